@@ -0,0 +1,104 @@
+use std::{fmt, sync::Arc};
+
+use futures::future::BoxFuture;
+use reqwest::{Request, RequestBuilder};
+
+use crate::Result;
+
+/// 请求使用的认证方式
+///
+/// 重定向到其他源时是否发送由 `RedirectPolicy::strip_auth` 决定
+#[derive(Clone)]
+pub enum Auth {
+    Basic { username: String, password: Option<String> },
+    Bearer(String),
+    /// 每次连接时向 provider 获取 Bearer token
+    Provider(Arc<dyn TokenProvider>),
+    /// 发送前由 signer 签名 例如 AWS SigV4
+    Signer(Arc<dyn Signer>),
+}
+
+/// 提供 Bearer token 例如 OAuth 的 access token
+///
+/// 每次建立连接 (包括失败后重连和切换镜像) 都会调用 `token` 可以在这里检查过期时间
+///
+/// 服务端返回 401 时调用 `refresh` 并用新的 token 重发一次请求
+pub trait TokenProvider: Send + Sync + 'static {
+    /// 当前可用的 token
+    fn token(&self) -> BoxFuture<'_, Result<String>>;
+
+    /// token 被服务端拒绝后获取新的 token
+    fn refresh(&self) -> BoxFuture<'_, Result<String>>;
+}
+
+/// 对完整的请求签名 每次发送 (包括重定向和重试) 前都会重新签名
+///
+/// 签名时请求已带上所有请求头 可以把签名加到请求头或查询参数中
+pub trait Signer: Send + Sync + 'static {
+    fn sign(&self, request: &mut Request) -> Result<()>;
+}
+
+impl Auth {
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self::Basic { username: username.into(), password: Some(password.into()) }
+    }
+
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Self::Bearer(token.into())
+    }
+
+    pub fn provider(provider: impl TokenProvider) -> Self {
+        Self::Provider(Arc::new(provider))
+    }
+
+    pub fn signer(signer: impl Signer) -> Self {
+        Self::Signer(Arc::new(signer))
+    }
+
+    /// provider 的 token refresh 为 true 时刷新 其他方式返回 None
+    pub(crate) async fn token(&self, refresh: bool) -> Result<Option<String>> {
+        let Self::Provider(provider) = self else {
+            return Ok(None);
+        };
+        match refresh {
+            true => provider.refresh().await.map(Some),
+            false => provider.token().await.map(Some),
+        }
+    }
+
+    /// token 为 `token` 的返回值
+    pub(crate) fn apply(
+        &self,
+        request: RequestBuilder,
+        token: Option<&str>,
+    ) -> Result<RequestBuilder> {
+        Ok(match (self, token) {
+            (Self::Basic { username, password }, _) => {
+                request.basic_auth(username, password.as_ref())
+            }
+            (Self::Bearer(token), _) => request.bearer_auth(token),
+            (Self::Provider(_), Some(token)) => request.bearer_auth(token),
+            (Self::Provider(_), None) => request,
+            (Self::Signer(signer), _) => {
+                let (client, request) = request.build_split();
+                let mut request = request?;
+                signer.sign(&mut request)?;
+                RequestBuilder::from_parts(client, request)
+            }
+        })
+    }
+}
+
+/// 不输出密码和 token
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Basic { username, .. } => {
+                f.debug_struct("Basic").field("username", username).finish_non_exhaustive()
+            }
+            Self::Bearer(_) => f.write_str("Bearer"),
+            Self::Provider(_) => f.write_str("Provider"),
+            Self::Signer(_) => f.write_str("Signer"),
+        }
+    }
+}
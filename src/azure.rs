@@ -0,0 +1,216 @@
+use std::{fmt, path::Path, time::SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Method, Request,
+};
+use sha2::Sha256;
+
+use crate::{
+    auth::{Auth, Signer},
+    cloud::{base64_hex, uri_encode, Utc},
+    hash::Algorithm,
+    http::HttpDownloader,
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// 请求使用的 REST API 版本
+const VERSION: &str = "2021-08-06";
+const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+/// Azure Blob Storage 中的 blob 通过带 Range 的 Get Blob 下载
+///
+/// 认证可以使用 [`SharedKey`] Entra ID 的 access token (`Auth::bearer`) 或 SAS token
+#[derive(Debug, Clone)]
+pub struct AzureBlob {
+    account:   String,
+    container: String,
+    blob:      String,
+    endpoint:  Option<String>,
+    sas:       Option<String>,
+}
+
+impl AzureBlob {
+    pub fn new(
+        account: impl Into<String>,
+        container: impl Into<String>,
+        blob: impl Into<String>,
+    ) -> Self {
+        Self {
+            account:   account.into(),
+            container: container.into(),
+            blob:      blob.into(),
+            endpoint:  None,
+            sas:       None,
+        }
+    }
+
+    /// 服务地址 例如 Azurite 的 `http://127.0.0.1:10000/devstoreaccount1`
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// SAS token 放在地址的查询参数中 不需要再设置认证
+    pub fn sas(mut self, token: impl Into<String>) -> Self {
+        self.sas = Some(token.into().trim_start_matches('?').to_string());
+        self
+    }
+
+    /// blob 的地址
+    pub fn url(&self) -> String {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("https://{}.blob.core.windows.net", self.account),
+        };
+        let blob = self.blob.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let url = format!("{endpoint}/{}/{blob}", self.container);
+        match &self.sas {
+            Some(sas) => format!("{url}?{sas}"),
+            None => url,
+        }
+    }
+
+    /// 下载到 path 的下载器
+    ///
+    /// 先用 Get Blob Properties 获取上传时设置的 Content-MD5 没有时返回 `DownloadError::NoChecksum`
+    pub async fn downloader(
+        &self,
+        path: impl AsRef<Path>,
+        auth: Option<Auth>,
+    ) -> Result<HttpDownloader> {
+        let mut http = self.downloader_with_hash(path, auth, "");
+        let response = http.send(Method::HEAD, &http.urls[0], &[]).await?.error_for_status()?;
+        let md5 = response.headers().get(CONTENT_MD5).and_then(|md5| md5.to_str().ok());
+        let hash = md5.and_then(base64_hex).ok_or(DownloadError::NoChecksum)?;
+        http.builder = http.builder.hash(hash);
+        Ok(http.algorithm(Algorithm::Md5))
+    }
+
+    /// 使用已知 hash 的下载器 默认按 SHA-256 校验
+    pub fn downloader_with_hash(
+        &self,
+        path: impl AsRef<Path>,
+        auth: Option<Auth>,
+        hash: impl Into<String>,
+    ) -> HttpDownloader {
+        let builder = DownloadBuilder::new(path).hash(hash);
+        let http = HttpDownloader::with_builder(self.url(), builder)
+            .header(HeaderName::from_static("x-ms-version"), VERSION);
+        match auth {
+            Some(auth) => http.auth(auth),
+            None => http,
+        }
+    }
+}
+
+/// 使用存储账户访问密钥的 Shared Key 认证 配合 `Auth::signer` 使用
+#[derive(Clone)]
+pub struct SharedKey {
+    account: String,
+    key:     Vec<u8>,
+}
+
+impl SharedKey {
+    /// key 为 Azure 门户中显示的 base64 访问密钥
+    pub fn new(account: impl Into<String>, key: &str) -> Result<Self> {
+        let key = STANDARD.decode(key.trim()).map_err(|e| invalid_header(format!("密钥: {e}")))?;
+        Ok(Self { account: account.into(), key })
+    }
+
+    fn sign_at(&self, request: &mut Request, now: SystemTime) -> Result<()> {
+        let headers = request.headers_mut();
+        headers.insert("x-ms-date", header_value(&Utc::new(now).rfc1123())?);
+        if !headers.contains_key("x-ms-version") {
+            headers.insert("x-ms-version", HeaderValue::from_static(VERSION));
+        }
+
+        let headers = request.headers();
+        let get = |name: HeaderName| {
+            headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default()
+        };
+        let length = match get(header::CONTENT_LENGTH) {
+            "0" => "",
+            length => length,
+        };
+        let standard = [
+            request.method().as_str(),
+            get(header::CONTENT_ENCODING),
+            get(header::CONTENT_LANGUAGE),
+            length,
+            get(CONTENT_MD5),
+            get(header::CONTENT_TYPE),
+            // 使用 x-ms-date 时 Date 为空
+            "",
+            get(header::IF_MODIFIED_SINCE),
+            get(header::IF_MATCH),
+            get(header::IF_NONE_MATCH),
+            get(header::IF_UNMODIFIED_SINCE),
+            get(header::RANGE),
+        ];
+        let mut to_sign: String = standard.iter().map(|value| format!("{value}\n")).collect();
+        to_sign.push_str(&canonical_headers(headers)?);
+        to_sign.push_str(&format!("/{}{}", self.account, request.url().path()));
+        let mut query: Vec<(String, String)> = vec![];
+        for (key, value) in request.url().query_pairs() {
+            query.push((key.to_lowercase(), value.into_owned()));
+        }
+        query.sort();
+        query.dedup_by(|(key, value), (prev_key, prev_value)| {
+            // 同名的参数合并为 `name:v1,v2`
+            let same = key == prev_key;
+            if same {
+                prev_value.push(',');
+                prev_value.push_str(value);
+            }
+            same
+        });
+        for (key, value) in query {
+            to_sign.push_str(&format!("\n{key}:{value}"));
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC 接受任意长度的密钥");
+        mac.update(to_sign.as_bytes());
+        let signature = STANDARD.encode(mac.finalize().into_bytes());
+        let authorization = format!("SharedKey {}:{signature}", self.account);
+        request.headers_mut().insert(header::AUTHORIZATION, header_value(&authorization)?);
+        Ok(())
+    }
+}
+
+impl Signer for SharedKey {
+    fn sign(&self, request: &mut Request) -> Result<()> {
+        self.sign_at(request, SystemTime::now())
+    }
+}
+
+/// 不输出密钥
+impl fmt::Debug for SharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedKey").field("account", &self.account).finish_non_exhaustive()
+    }
+}
+
+/// 按名称排序的 `x-ms-` 请求头 每个一行
+fn canonical_headers(headers: &HeaderMap) -> Result<String> {
+    let mut canonical = vec![];
+    for (name, value) in headers {
+        if name.as_str().starts_with("x-ms-") {
+            let value = value.to_str().map_err(invalid_header)?;
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            canonical.push(format!("{name}:{value}\n"));
+        }
+    }
+    canonical.sort();
+    Ok(canonical.concat())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(invalid_header)
+}
+
+fn invalid_header(e: impl fmt::Display) -> DownloadError {
+    DownloadError::InvalidHeader(e.to_string())
+}
@@ -0,0 +1,401 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::fs::File;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "decrypt")]
+use crate::decrypt::Decryption;
+#[cfg(feature = "encrypt")]
+use crate::encrypt::Key;
+use crate::{
+    hash::Algorithm, limit::RateLimiter, manager::Observer, post::PostProcess,
+    signature::Signature, AmendPolicy, BufferPool, DownloadError, Downloading, Metadata,
+    PersistPolicy, Pieces, Result, SpaceCheck, SyncPolicy,
+};
+
+/// 目标文件已存在时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// 返回 `DownloadError::TargetExists`
+    #[default]
+    Error,
+    /// 继续下载 完成时覆盖已存在的文件
+    Overwrite,
+    /// 在文件名后追加 (1) (2) ... 直到不冲突
+    RenameWithSuffix,
+    /// 已存在的文件 hash 一致时返回 `DownloadError::AlreadyDownloaded` 否则覆盖
+    SkipIfHashMatches(Algorithm),
+}
+
+/// downloading 文件的命名方式
+#[derive(Clone)]
+pub enum TempPath {
+    /// 在目标文件名后追加后缀 `a.txt` -> `a.txt.downloading`
+    Suffix(String),
+    /// 自定义 目标文件路径 -> 临时文件路径
+    Custom(Arc<dyn Fn(&Path) -> PathBuf + Send + Sync>),
+}
+
+impl TempPath {
+    /// 计算目标文件对应的临时文件路径
+    pub fn resolve(&self, target: &Path) -> PathBuf {
+        match self {
+            Self::Suffix(suffix) => match target.extension() {
+                Some(ext) => target.with_extension(format!("{}.{suffix}", ext.to_string_lossy())),
+                None => target.with_extension(suffix),
+            },
+            Self::Custom(f) => f(target),
+        }
+    }
+}
+
+impl Default for TempPath {
+    fn default() -> Self {
+        Self::Suffix("downloading".to_string())
+    }
+}
+
+impl fmt::Debug for TempPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Suffix(suffix) => f.debug_tuple("Suffix").field(suffix).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// `Downloading` 的构建器 新的选项统一在这里添加
+#[derive(Debug, Clone)]
+pub struct DownloadBuilder {
+    pub(crate) path:      PathBuf,
+    pub(crate) hash:      String,
+    pub(crate) size:      u64,
+    /// 大小未知
+    pub(crate) growing:   bool,
+    pub(crate) overwrite: OverwritePolicy,
+    pub(crate) amend:     AmendPolicy,
+    /// hash 使用的算法 保存在元数据中
+    pub(crate) algorithm: Option<Algorithm>,
+    pub(crate) track:     Option<Algorithm>,
+    pub(crate) temp:      TempPath,
+    pub(crate) sidecar:   bool,
+    pub(crate) limiters:  Vec<RateLimiter>,
+    pub(crate) cancel:    Option<CancellationToken>,
+    pub(crate) pieces:    Option<Pieces>,
+    pub(crate) signature: Option<Signature>,
+    /// 管理器中的任务向其报告进度
+    pub(crate) observer:  Option<Observer>,
+    pub(crate) post:      Option<PostProcess>,
+    pub(crate) space:     SpaceCheck,
+    pub(crate) sync:      SyncPolicy,
+    pub(crate) persist:   PersistPolicy,
+    /// `write_at` 缓冲的大小
+    pub(crate) buffer:    usize,
+    pub(crate) pool:      Option<BufferPool>,
+    /// 以 O_DIRECT 写入
+    pub(crate) direct:    bool,
+    #[cfg(feature = "decrypt")]
+    pub(crate) decrypt:   Option<Decryption>,
+    /// 加密存放的密钥
+    #[cfg(feature = "encrypt")]
+    pub(crate) at_rest:   Option<Key>,
+    #[cfg(feature = "mmap")]
+    pub(crate) mmap:      bool,
+}
+
+impl DownloadBuilder {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path:      path.as_ref().to_path_buf(),
+            hash:      String::new(),
+            size:      0,
+            growing:   false,
+            overwrite: OverwritePolicy::default(),
+            amend:     AmendPolicy::default(),
+            algorithm: None,
+            track:     None,
+            temp:      TempPath::default(),
+            sidecar:   false,
+            limiters:  vec![],
+            cancel:    None,
+            pieces:    None,
+            signature: None,
+            observer:  None,
+            post:      None,
+            space:     SpaceCheck::default(),
+            sync:      SyncPolicy::default(),
+            persist:   PersistPolicy::default(),
+            buffer:    0,
+            pool:      None,
+            direct:    false,
+            #[cfg(feature = "decrypt")]
+            decrypt:   None,
+            #[cfg(feature = "encrypt")]
+            at_rest:   None,
+            #[cfg(feature = "mmap")]
+            mmap:      false,
+        }
+    }
+
+    /// 期望的文件 hash 不设置时完成时不校验 见 `Downloading::set_expected_hash`
+    ///
+    /// 打开已有的 downloading 文件时不设置 沿用元数据中的 hash
+    pub fn hash(mut self, hash: impl Into<String>) -> Self {
+        self.hash = hash.into();
+        self
+    }
+
+    /// hash 使用的算法 保存在元数据中 `Downloading::complete_auto` 按它校验
+    ///
+    /// 继续下载时与元数据中记录的算法不同 视为 hash 不一致 见 `amend_policy`
+    pub fn hash_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = Some(algorithm);
+        self
+    }
+
+    /// 文件大小
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = size;
+        self.growing = false;
+        self
+    }
+
+    /// 文件大小未知 例如没有 Content-Length 的响应
+    ///
+    /// 只能顺序写入 元数据只记录已写入的字节数 `complete` 时以实际写入的长度为准
+    ///
+    /// 元数据跟在已写入的内容之后 每次写入都会移动 开启 `sidecar` 更可靠
+    pub fn unknown_size(mut self) -> Self {
+        self.size = 0;
+        self.growing = true;
+        self
+    }
+
+    /// 目标文件已存在时的处理方式
+    pub fn overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    /// downloading 文件中的 hash 或 size 与这次的不一致时是否保留进度 默认任意一个不一致都重置
+    pub fn amend_policy(mut self, policy: AmendPolicy) -> Self {
+        self.amend = policy;
+        self
+    }
+
+    /// 开启增量 hash 见 `Downloading::track_hash`
+    pub fn track_hash(mut self, algorithm: Algorithm) -> Self {
+        self.track = Some(algorithm);
+        self
+    }
+
+    /// 临时文件后缀 默认 `downloading` 例如 `part` `crdownload`
+    pub fn suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.temp = TempPath::Suffix(suffix.into());
+        self
+    }
+
+    /// 自定义目标文件到临时文件的路径映射
+    pub fn temp_path(mut self, f: impl Fn(&Path) -> PathBuf + Send + Sync + 'static) -> Self {
+        self.temp = TempPath::Custom(Arc::new(f));
+        self
+    }
+
+    /// 元数据单独存放在 `<临时文件>.meta` 中 临时文件只包含已下载的内容
+    ///
+    /// 下载过程中其他程序可以直接读取临时文件
+    pub fn sidecar(mut self, sidecar: bool) -> Self {
+        self.sidecar = sidecar;
+        self
+    }
+
+    /// 写入限速 可多次调用 共享同一个限速器的下载一起限速
+    pub fn limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiters.push(limiter);
+        self
+    }
+
+    /// 取消令牌 取消后停止写入 已下载的进度保留 见 `Downloading::set_cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// 分块 hash 见 [`Pieces`] 继续下载时沿用元数据中保存的
+    pub fn pieces(mut self, pieces: Pieces) -> Self {
+        self.pieces = Some(pieces);
+        self
+    }
+
+    /// 分离签名 见 [`Signature`] 不保存在元数据中 继续下载时需要重新设置
+    pub fn signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// 下载完成后的处理 见 [`PostProcess`] 只在 `HttpDownloader` 等下载器完成时执行
+    pub fn post_process(mut self, post: PostProcess) -> Self {
+        self.post = Some(post);
+        self
+    }
+
+    /// 打开前检查磁盘空间 默认 `SpaceCheck::Check`
+    pub fn space_check(mut self, policy: SpaceCheck) -> Self {
+        self.space = policy;
+        self
+    }
+
+    /// 何时同步到磁盘 默认 `SyncPolicy::Never`
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync = policy;
+        self
+    }
+
+    /// 何时把进度写入元数据 默认 `PersistPolicy::Always`
+    pub fn persist_policy(mut self, policy: PersistPolicy) -> Self {
+        self.persist = policy;
+        self
+    }
+
+    /// `write_at` 把紧接着的小块写入合并到 size 字节后对齐写入 默认 0 不合并
+    ///
+    /// 缓冲中的数据写入文件后才记录进度 `flush` `pause` 取消和完成时都会写入
+    /// 丢弃前没有写入的部分需要重新下载
+    ///
+    /// 多连接下载时每个连接各自合并 大小未知时不合并
+    pub fn write_buffer(mut self, size: usize) -> Self {
+        self.buffer = size;
+        self
+    }
+
+    /// 写入时复制数据和合并写入用的缓冲区从 pool 中取出 见 [`BufferPool`]
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// 管理器的缓冲区池 自己设置过时保持不变
+    pub(crate) fn default_buffer_pool(&mut self, pool: &BufferPool) {
+        self.pool.get_or_insert_with(|| pool.clone());
+    }
+
+    /// Linux 上对齐的写入以 O_DIRECT 绕过页缓存 大量下载时不会挤出其他程序的缓存
+    ///
+    /// 只对齐到 4096 字节的部分绕过 首尾不对齐的部分和元数据照常写入
+    /// 没有设置 `write_buffer` 时合并到 1 MiB 后写入 `AsyncWrite` 的顺序写入不受影响
+    ///
+    /// 同时设置 `mmap` 时写入映射 文件系统不支持或其他平台时忽略
+    pub fn direct_io(mut self, enabled: bool) -> Self {
+        self.direct = enabled;
+        self
+    }
+
+    /// 写入前解密 见 [`Decryption`] 不保存在元数据中 继续下载时需要重新设置
+    #[cfg(feature = "decrypt")]
+    pub fn decrypt(mut self, decryption: Decryption) -> Self {
+        self.decrypt = Some(decryption);
+        self
+    }
+
+    /// downloading 文件中的内容以 AES-256-CTR 加密存放 `complete` 时解密后校验
+    ///
+    /// 用于下载到共享或不可信的存储 nonce 保存在元数据中 密钥由调用方保存
+    ///
+    /// 继续下载时需要相同的密钥 否则返回 `DownloadError::WrongKey` 已有明文的进度会被丢弃
+    ///
+    /// 增量 hash 的状态由明文计算 不加密
+    #[cfg(feature = "encrypt")]
+    pub fn encrypt_at_rest(mut self, key: [u8; 32]) -> Self {
+        self.at_rest = Some(Key(key));
+        self
+    }
+
+    /// `write_at` 直接复制到 downloading 文件的内存映射中 省去每次写入的系统调用和复制
+    ///
+    /// 只用于大小已知的下载 元数据和 `AsyncWrite` 的顺序写入仍写入文件
+    ///
+    /// 下载期间 downloading 文件不能被其他程序截断 否则访问映射时进程崩溃
+    #[cfg(feature = "mmap")]
+    pub fn mmap(mut self, enabled: bool) -> Self {
+        self.mmap = enabled;
+        self
+    }
+
+    /// downloading 文件不存在创建并写入元数据
+    ///
+    /// 存在读取元数据 存在但信息不一致覆盖原来下载进度
+    pub async fn open(self) -> Result<Downloading> {
+        let mut target = self.path.clone();
+        if target.exists() {
+            match self.overwrite {
+                OverwritePolicy::Error => return Err(DownloadError::TargetExists),
+                OverwritePolicy::Overwrite => {}
+                OverwritePolicy::RenameWithSuffix => {
+                    target = (1..).map(|n| with_suffix(&target, n)).find(|p| !p.exists()).unwrap()
+                }
+                OverwritePolicy::SkipIfHashMatches(algorithm) => {
+                    let hash = algorithm.hash(&mut File::open(&target).await?).await?;
+                    if hash == self.hash {
+                        return Err(DownloadError::AlreadyDownloaded(target));
+                    }
+                }
+            }
+        }
+        let path = self.temp.resolve(&target);
+        let mut downloading = Downloading::create(&self, path, target).await?;
+        if let Some(algorithm) = self.track {
+            downloading.track_hash(algorithm).await?;
+        }
+        Ok(downloading)
+    }
+}
+
+impl DownloadBuilder {
+    /// 按命名方式打开已存在的临时文件继续下载 hash 和 size 从元数据中读取
+    pub async fn resume(self) -> Result<Downloading> {
+        let path = self.temp.resolve(&self.path);
+        let mut downloading = Downloading::reopen(path, self.path).await?;
+        downloading.limiters = self.limiters;
+        downloading.cancel = self.cancel;
+        downloading.signature = self.signature;
+        downloading.writer.sync = self.sync;
+        downloading.writer.persist = self.persist;
+        downloading.writer.buffer = self.buffer;
+        downloading.writer.pool = self.pool;
+        downloading.direct_io(self.direct);
+        #[cfg(feature = "mmap")]
+        {
+            downloading.writer.mmap = self.mmap;
+        }
+        #[cfg(feature = "encrypt")]
+        downloading.unlock(self.at_rest.as_ref()).await?;
+        #[cfg(not(feature = "encrypt"))]
+        downloading.unlock()?;
+        #[cfg(feature = "decrypt")]
+        if let Some(decryption) = &self.decrypt {
+            downloading.keystream = Some(decryption.keystream()?);
+        }
+        downloading.check_space(self.space).await?;
+        downloading.progress.observe(self.observer);
+        Ok(downloading)
+    }
+
+    /// 按命名方式只读读取临时文件的元数据
+    pub async fn metadata(&self) -> Result<Metadata> {
+        Metadata::load(&self.temp.resolve(&self.path)).await
+    }
+}
+
+/// `a.txt` -> `a (n).txt`
+fn with_suffix(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem} ({n}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({n})"),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use crate::{hash::Algorithm, Result};
+
+/// 校验文件中的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    /// 文件名 可能带有相对路径 只有 hash 的 `.sha256` 文件中为空
+    pub name:      String,
+    pub algorithm: Algorithm,
+    /// 小写十六进制
+    pub hash:      String,
+}
+
+/// SHA256SUMS MD5SUMS 和 `foo.iso.sha256` 等校验文件
+///
+/// 支持 GNU coreutils 的 `hash  name` `hash *name` BSD 的 `SHA256 (name) = hash`
+/// 以及只有 hash 的一行 空行 注释和无法识别的行会被忽略
+#[derive(Debug, Clone, Default)]
+pub struct Checksums {
+    entries: Vec<Checksum>,
+}
+
+impl Checksums {
+    /// 算法按 BSD 格式的标签或 hash 长度判断 长度为 64 时视为 SHA-256
+    pub fn parse(text: &str) -> Self {
+        Self::parse_as(text, None)
+    }
+
+    /// 按指定算法解析 用于 B3SUMS 等无法从长度判断的文件 BSD 格式的标签优先
+    pub fn parse_with(text: &str, algorithm: Algorithm) -> Self {
+        Self::parse_as(text, Some(algorithm))
+    }
+
+    /// 读取并解析 算法可以从文件名判断时使用文件名中的算法
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = tokio::fs::read_to_string(path).await?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(Self::parse_as(&text, algorithm_hint(&name)))
+    }
+
+    fn parse_as(text: &str, algorithm: Option<Algorithm>) -> Self {
+        let entries = text.lines().filter_map(|line| parse_line(line.trim(), algorithm)).collect();
+        Self { entries }
+    }
+
+    /// 按文件名查找 带有目录的项也按文件名匹配 完全一致的项优先
+    ///
+    /// 只有一项且没有文件名时总是返回该项
+    pub fn get(&self, name: &str) -> Option<&Checksum> {
+        if let [entry] = self.entries.as_slice() {
+            if entry.name.is_empty() {
+                return Some(entry);
+            }
+        }
+        let base = |name: &str| name.rsplit(['/', '\\']).next().unwrap_or(name).to_string();
+        self.entries
+            .iter()
+            .find(|entry| entry.name.trim_start_matches("./") == name)
+            .or_else(|| self.entries.iter().find(|entry| base(&entry.name) == base(name)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Checksum> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// 从校验文件的文件名判断算法 例如 `SHA1SUMS` `foo.iso.md5` `B3SUMS`
+pub(crate) fn algorithm_hint(name: &str) -> Option<Algorithm> {
+    let name = name.to_ascii_lowercase();
+    [
+        ("sha256", Algorithm::Sha256),
+        ("sha1", Algorithm::Sha1),
+        ("md5", Algorithm::Md5),
+        ("b3", Algorithm::Blake3),
+        ("blake3", Algorithm::Blake3),
+    ]
+    .into_iter()
+    .find(|(hint, _)| name.starts_with(hint) || name.ends_with(&format!(".{hint}")))
+    .map(|(_, algorithm)| algorithm)
+}
+
+fn parse_line(line: &str, algorithm: Option<Algorithm>) -> Option<Checksum> {
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    // BSD 格式 `SHA256 (name) = hash`
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((name, hash)) = rest.rsplit_once(") = ") {
+            let algorithm = match tag.to_ascii_uppercase().as_str() {
+                "SHA256" | "SHA2-256" => Algorithm::Sha256,
+                "SHA1" => Algorithm::Sha1,
+                "MD5" => Algorithm::Md5,
+                "BLAKE3" => Algorithm::Blake3,
+                _ => return None,
+            };
+            return checksum(name, algorithm, hash);
+        }
+    }
+
+    // GNU 格式 以 `\` 开头时文件名中的换行和反斜杠被转义
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (hash, name) = line.split_once([' ', '\t']).unwrap_or((line, ""));
+    let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+    let name = match escaped {
+        true => name.replace("\\n", "\n").replace("\\\\", "\\"),
+        false => name.to_string(),
+    };
+    let algorithm = algorithm.or(match hash.len() {
+        64 => Some(Algorithm::Sha256),
+        40 => Some(Algorithm::Sha1),
+        32 => Some(Algorithm::Md5),
+        _ => None,
+    })?;
+    checksum(&name, algorithm, hash)
+}
+
+/// hash 必须是与算法长度一致的十六进制
+fn checksum(name: &str, algorithm: Algorithm, hash: &str) -> Option<Checksum> {
+    let len = match algorithm {
+        Algorithm::Sha256 | Algorithm::Blake3 => 64,
+        Algorithm::Sha1 | Algorithm::GitSha1 => 40,
+        Algorithm::Md5 => 32,
+        Algorithm::Crc32c => 8,
+    };
+    if hash.len() != len || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let (name, hash) = (name.to_string(), hash.to_ascii_lowercase());
+    Some(Checksum { name, algorithm, hash })
+}
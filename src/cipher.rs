@@ -0,0 +1,46 @@
+use std::fmt;
+
+use aes::{Aes128, Aes192, Aes256};
+use ctr::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    Ctr128BE,
+};
+
+/// AES-CTR 的密钥流 按文件中的位置异或 加密和解密相同
+#[derive(Clone)]
+pub(crate) struct Keystream {
+    key: Vec<u8>,
+    iv:  [u8; 16],
+}
+
+impl Keystream {
+    /// key 为 16 24 或 32 字节 调用方检查长度
+    pub(crate) fn new(key: &[u8], iv: [u8; 16]) -> Self {
+        Self { key: key.to_vec(), iv }
+    }
+
+    pub(crate) fn bits(&self) -> usize {
+        self.key.len() * 8
+    }
+
+    /// 处理从 offset 开始的 buf
+    pub(crate) fn apply(&self, offset: u64, buf: &mut [u8]) {
+        match self.key.len() {
+            16 => self.xor::<Ctr128BE<Aes128>>(offset, buf),
+            24 => self.xor::<Ctr128BE<Aes192>>(offset, buf),
+            _ => self.xor::<Ctr128BE<Aes256>>(offset, buf),
+        }
+    }
+
+    fn xor<C: KeyIvInit + StreamCipher + StreamCipherSeek>(&self, offset: u64, buf: &mut [u8]) {
+        let mut cipher = C::new_from_slices(&self.key, &self.iv).expect("已检查密钥长度");
+        cipher.seek(offset);
+        cipher.apply_keystream(buf);
+    }
+}
+
+impl fmt::Debug for Keystream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keystream").field("bits", &self.bits()).finish_non_exhaustive()
+    }
+}
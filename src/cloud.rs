@@ -0,0 +1,82 @@
+#[cfg(any(feature = "s3", feature = "azure"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+
+use crate::hash::hex;
+
+#[cfg(feature = "azure")]
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+#[cfg(feature = "azure")]
+const MONTHS: [&str; 12] =
+    ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// 签名请求使用的 UTC 时间 精确到秒
+#[cfg(any(feature = "s3", feature = "azure"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Utc(u64);
+
+#[cfg(any(feature = "s3", feature = "azure"))]
+impl Utc {
+    pub(crate) fn new(time: SystemTime) -> Self {
+        Self(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+    }
+
+    /// `20130524`
+    #[cfg(feature = "s3")]
+    pub(crate) fn date(self) -> String {
+        let (year, month, day) = self.civil();
+        format!("{year:04}{month:02}{day:02}")
+    }
+
+    /// `20130524T000000Z`
+    #[cfg(feature = "s3")]
+    pub(crate) fn basic(self) -> String {
+        let (h, m, s) = self.time();
+        format!("{}T{h:02}{m:02}{s:02}Z", self.date())
+    }
+
+    /// `Fri, 24 May 2013 00:00:00 GMT`
+    #[cfg(feature = "azure")]
+    pub(crate) fn rfc1123(self) -> String {
+        let ((year, month, day), (h, m, s)) = (self.civil(), self.time());
+        let weekday = WEEKDAYS[(self.0 / 86400 % 7) as usize];
+        let month = MONTHS[month as usize - 1];
+        format!("{weekday}, {day:02} {month} {year:04} {h:02}:{m:02}:{s:02} GMT")
+    }
+
+    /// 由天数推算公历日期 见 http://howardhinnant.github.io/date_algorithms.html
+    fn civil(self) -> (u64, u64, u64) {
+        let z = self.0 / 86400 + 719468;
+        let (era, doe) = (z / 146097, z % 146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        (yoe + era * 400 + u64::from(month <= 2), month, day)
+    }
+
+    fn time(self) -> (u64, u64, u64) {
+        let secs = self.0 % 86400;
+        (secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// 按 SigV4 的规则编码 只保留字母 数字和 `-_.~`
+pub(crate) fn uri_encode(s: &str) -> String {
+    s.bytes().fold(String::with_capacity(s.len()), |mut encoded, b| {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{b:02X}")),
+        }
+        encoded
+    })
+}
+
+/// 请求头中 base64 编码的 hash 转为十六进制
+pub(crate) fn base64_hex(value: &str) -> Option<String> {
+    Some(hex(&base64::engine::general_purpose::STANDARD.decode(value.trim()).ok()?))
+}
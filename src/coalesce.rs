@@ -0,0 +1,100 @@
+use std::slice;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{Downloading, Result};
+
+/// 同时缓冲的区间数 超过时先写入最早的区间
+const MAX_RUNS: usize = 16;
+/// 缓冲满时写入到该对齐的位置 剩下的部分继续缓冲
+const ALIGN: u64 = 4096;
+
+impl Downloading {
+    /// 写入 `write_at` 缓冲中的数据并写入元数据
+    ///
+    /// 同 `AsyncWriteExt::flush` 并且包括 `DownloadBuilder::write_buffer` 的缓冲
+    pub async fn flush(&mut self) -> Result<()> {
+        self.flush_runs().await?;
+        AsyncWriteExt::flush(self).await?;
+        Ok(())
+    }
+
+    /// 顺序写入的下一个位置 包括缓冲中紧接着已下载部分的数据
+    pub(crate) fn position(&self) -> u64 {
+        let offset = self.meta.offset;
+        let run = self.writer.runs.iter().find(|(start, _)| *start == offset);
+        run.map_or(offset, |(start, run)| start + run.len() as u64)
+    }
+
+    /// 追加到紧接着的缓冲区间 缓冲满 区间之后已经下载或缓冲的数据补全了文件时写入文件
+    ///
+    /// 只有写入文件后才记录进度 缓冲中的数据丢失只需要重新下载
+    pub(crate) async fn coalesce(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        let end = offset + buf.len() as u64;
+        // 与缓冲中的区间重叠时先写入 保证写入的顺序
+        while let Some(i) = self.writer.runs.iter().position(|(start, run)| {
+            *start < end && offset < start + run.len() as u64
+        }) {
+            let (start, run) = self.writer.runs.remove(i);
+            self.write_run(start, slice::from_ref(&run)).await?;
+            self.recycle([run]);
+        }
+        let i = match self.writer.runs.iter().position(|(start, run)| {
+            start + run.len() as u64 == offset
+        }) {
+            Some(i) => i,
+            None => {
+                if self.writer.runs.len() >= MAX_RUNS {
+                    let (start, run) = self.writer.runs.remove(0);
+                    self.write_run(start, slice::from_ref(&run)).await?;
+                    self.recycle([run]);
+                }
+                let run = self.take_buffer(self.writer.buffer);
+                self.writer.runs.push((offset, run));
+                self.writer.runs.len() - 1
+            }
+        };
+        let (start, run) = &mut self.writer.runs[i];
+        run.extend_from_slice(buf);
+        let (start, len) = (*start, run.len());
+
+        // 之后已经下载或到达文件末尾 不会再有紧接着的写入
+        let closed = end >= self.meta.size || self.meta.ranges.iter().any(|r| r.start == end);
+        if closed {
+            let (start, run) = self.writer.runs.remove(i);
+            let result = self.write_run(start, slice::from_ref(&run)).await;
+            self.recycle([run]);
+            result?;
+        } else if len >= self.writer.buffer {
+            let cut = match end / ALIGN * ALIGN {
+                aligned if aligned > start => aligned - start,
+                _ => len as u64,
+            };
+            let mut run = self.writer.runs.remove(i).1;
+            let result = self.write_run(start, &[&run[..cut as usize]]).await;
+            // 剩下的部分移到开头继续缓冲
+            if cut < len as u64 {
+                run.drain(..cut as usize);
+                self.writer.runs.push((start + cut, run));
+            } else {
+                self.recycle([run]);
+            }
+            result?;
+        }
+        // 提供的数据已经覆盖整个文件 之后不会再有写入
+        let buffered: u64 = self.writer.runs.iter().map(|(_, run)| run.len() as u64).sum();
+        if buffered > 0 && self.meta.ranges.downloaded() + buffered >= self.meta.size {
+            return self.flush_runs().await;
+        }
+        Ok(())
+    }
+
+    /// 写入所有缓冲中的区间
+    pub(crate) async fn flush_runs(&mut self) -> Result<()> {
+        for (start, run) in std::mem::take(&mut self.writer.runs) {
+            self.write_run(start, slice::from_ref(&run)).await?;
+            self.recycle([run]);
+        }
+        Ok(())
+    }
+}
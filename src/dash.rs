@@ -0,0 +1,484 @@
+use std::{
+    fmt::Display,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use quick_xml::{
+    escape::unescape,
+    events::{BytesStart, Event},
+    Reader, XmlVersion,
+};
+use reqwest::{Method, Url};
+
+use crate::{
+    hls::{HlsDownloader, Segment},
+    hosts::HostLimits,
+    http::HttpDownloader,
+    limit::RateLimiter,
+    manager::Observer,
+    proxy::ProxyConfig,
+    BufferPool, CancellationToken, DownloadError, Result,
+};
+
+/// 一个 Representation 最多展开的分片数 防止很短的分片时长和很长的 Period 占满内存
+const MAX_SEGMENTS: u64 = 1 << 20;
+
+/// MPEG-DASH 的 MPD 清单
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mpd {
+    /// `type="dynamic"` 的直播清单 只包含当前可用的分片
+    pub dynamic: bool,
+    pub periods: Vec<Period>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Period {
+    pub adaptation_sets: Vec<AdaptationSet>,
+}
+
+/// 同一内容的多个码率
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptationSet {
+    /// `video` `audio` 或 `text` 没有 contentType 时由 mimeType 推导
+    pub content_type:    Option<String>,
+    pub representations: Vec<Representation>,
+}
+
+/// 一个码率 分片拼接后得到完整的文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Representation {
+    pub id:        String,
+    /// 每秒比特数
+    pub bandwidth: u64,
+    pub mime_type: Option<String>,
+    /// 初始化分片在最前面
+    pub segments:  Vec<Segment>,
+}
+
+/// MPD 中的元素 分片信息需要按 Period AdaptationSet Representation 逐层继承
+#[derive(Debug, Clone, Default)]
+struct Node {
+    name:     String,
+    attrs:    Vec<(String, String)>,
+    children: Vec<Node>,
+    text:     String,
+}
+
+impl Mpd {
+    /// 解析 MPD 相对地址按 base 解析
+    ///
+    /// 支持 SegmentBase SegmentList 和 SegmentTemplate 模板没有 SegmentTimeline 时由时长推算分片数
+    pub fn parse(xml: &str, base: &Url) -> Result<Self> {
+        let root = Node::parse(xml)?;
+        if root.name != "MPD" {
+            return Err(invalid("根元素不是 MPD"));
+        }
+        let dynamic = root.attr("type") == Some("dynamic");
+        let total = root.attr("mediaPresentationDuration").map(duration).transpose()?;
+        let base = root.base(base)?;
+
+        let periods: Vec<_> = root.children("Period").collect();
+        let mut starts = vec![];
+        for (i, period) in periods.iter().enumerate() {
+            let start = period.attr("start").map(duration).transpose()?;
+            starts.push(start.unwrap_or(if i == 0 { 0.0 } else { f64::NAN }));
+        }
+        let mut result = vec![];
+        for (i, period) in periods.iter().enumerate() {
+            let length = match period.attr("duration") {
+                Some(value) => Some(duration(value)?),
+                None => starts.get(i + 1).copied().or(total).map(|end| end - starts[i]),
+            };
+            let length = length.filter(|length| length.is_finite());
+            let base = period.base(&base)?;
+            let mut adaptation_sets = vec![];
+            for set in period.children("AdaptationSet") {
+                let base = set.base(&base)?;
+                let mut representations = vec![];
+                for rep in set.children("Representation") {
+                    let levels = [*period, set, rep];
+                    let rep = Representation::parse(&levels, &rep.base(&base)?, length)?;
+                    representations.push(rep);
+                }
+                let mime = set.children("Representation").find_map(|rep| rep.attr("mimeType"));
+                let mime = set.attr("mimeType").or(mime);
+                let content_type = mime.and_then(|mime| mime.split('/').next());
+                let content_type = set.attr("contentType").or(content_type);
+                let content_type = content_type.map(str::to_string);
+                adaptation_sets.push(AdaptationSet { content_type, representations });
+            }
+            result.push(Period { adaptation_sets });
+        }
+        Ok(Self { dynamic, periods: result })
+    }
+
+    /// 每个 Period 中选择 content_type 的 AdaptationSet 里不超过 max_bandwidth 的最高码率
+    ///
+    /// 都超过时选择最低的 没有该类型时使用第一个 AdaptationSet
+    pub fn select<'a>(&'a self, content_type: &str, max_bandwidth: u64) -> Vec<&'a Representation> {
+        let select = |period: &'a Period| {
+            let sets = &period.adaptation_sets;
+            let matches = |set: &&AdaptationSet| set.content_type.as_deref() == Some(content_type);
+            let set = sets.iter().find(matches).or(sets.first())?;
+            let reps = &set.representations;
+            let fits = reps.iter().filter(|rep| rep.bandwidth <= max_bandwidth);
+            fits.max_by_key(|rep| rep.bandwidth).or(reps.iter().min_by_key(|rep| rep.bandwidth))
+        };
+        self.periods.iter().filter_map(select).collect()
+    }
+}
+
+impl Representation {
+    /// levels 为 Period AdaptationSet Representation 下层的属性覆盖上层
+    fn parse(levels: &[&Node], base: &Url, length: Option<f64>) -> Result<Self> {
+        let rep = levels[levels.len() - 1];
+        let id = rep.attr("id").unwrap_or_default().to_string();
+        let bandwidth = rep.attr("bandwidth").and_then(|b| b.parse().ok()).unwrap_or(0);
+        let mime_type = rep.attr("mimeType").or(levels[1].attr("mimeType")).map(str::to_string);
+        let join = |uri: &str| base.join(uri).map(String::from).map_err(invalid);
+
+        let mut segments = vec![];
+        if let Some(template) = inherit(levels, "SegmentTemplate") {
+            let vars = |number: u64, time: u64, format: &str| {
+                expand(format, &id, bandwidth, number, time)
+            };
+            let get = |name| template.attr(name);
+            let timescale: u64 = get("timescale").and_then(|t| t.parse().ok()).unwrap_or(1);
+            let mut number: u64 = get("startNumber").and_then(|n| n.parse().ok()).unwrap_or(1);
+            if let Some(init) = get("initialization") {
+                segments.push(segment(join(&vars(number, 0, init)?)?, 0.0, None));
+            }
+            let media = get("media").ok_or_else(|| invalid("SegmentTemplate 缺少 media"))?;
+            let end = length.map(|length| (length * timescale as f64).round() as u64);
+            let timeline = template.children.iter().find(|node| node.name == "SegmentTimeline");
+            match (timeline, get("duration").and_then(|d| d.parse::<u64>().ok())) {
+                (Some(timeline), _) => {
+                    let mut time = 0;
+                    let entries: Vec<_> = timeline.children("S").collect();
+                    for (i, s) in entries.iter().enumerate() {
+                        time = s.attr("t").and_then(|t| t.parse().ok()).unwrap_or(time);
+                        let d: u64 = s.attr("d").and_then(|d| d.parse().ok()).unwrap_or(0);
+                        if d == 0 {
+                            return Err(invalid("SegmentTimeline 的 S 缺少 d"));
+                        }
+                        let r: i64 = s.attr("r").and_then(|r| r.parse().ok()).unwrap_or(0);
+                        // r 为负数时重复到下一个 S 或 Period 结束
+                        let next = entries.get(i + 1);
+                        let until = next.and_then(|next| next.attr("t")?.parse().ok());
+                        let count = match (r, until.or(end)) {
+                            (r, _) if r >= 0 => r as u64 + 1,
+                            (_, Some(until)) => until.saturating_sub(time).div_ceil(d),
+                            _ => return Err(invalid("SegmentTimeline 无法确定重复次数")),
+                        };
+                        if count.saturating_add(segments.len() as u64) > MAX_SEGMENTS {
+                            return Err(invalid(format!("分片超过 {MAX_SEGMENTS} 个")));
+                        }
+                        for _ in 0..count {
+                            let url = join(&vars(number, time, media)?)?;
+                            segments.push(segment(url, d as f64 / timescale as f64, None));
+                            (number, time) = (number + 1, time + d);
+                        }
+                    }
+                }
+                (None, Some(d)) if d > 0 => {
+                    let end = end.ok_or_else(|| invalid("无法确定 Period 的时长"))?;
+                    let count = end.div_ceil(d);
+                    if count > MAX_SEGMENTS {
+                        return Err(invalid(format!("分片超过 {MAX_SEGMENTS} 个")));
+                    }
+                    for i in 0..count {
+                        let url = join(&vars(number, i * d, media)?)?;
+                        segments.push(segment(url, d as f64 / timescale as f64, None));
+                        number += 1;
+                    }
+                }
+                _ => return Err(invalid("SegmentTemplate 缺少 duration 或 SegmentTimeline")),
+            }
+        } else if let Some(list) = inherit(levels, "SegmentList") {
+            let timescale: u64 = list.attr("timescale").and_then(|t| t.parse().ok()).unwrap_or(1);
+            let d: u64 = list.attr("duration").and_then(|d| d.parse().ok()).unwrap_or(0);
+            if let Some(init) = list.children("Initialization").next() {
+                let url = join(init.attr("sourceURL").unwrap_or_default())?;
+                segments.push(segment(url, 0.0, init.attr("range").map(range).transpose()?));
+            }
+            for item in list.children("SegmentURL") {
+                let url = join(item.attr("media").unwrap_or_default())?;
+                let range = item.attr("mediaRange").map(range).transpose()?;
+                segments.push(segment(url, d as f64 / timescale as f64, range));
+            }
+        } else {
+            // SegmentBase 或只有 BaseURL 整个文件就是一个分片
+            segments.push(segment(base.to_string(), length.unwrap_or(0.0), None));
+        }
+        Ok(Self { id, bandwidth, mime_type, segments })
+    }
+}
+
+/// 下载 DASH 清单中一个码率的所有分片 按顺序拼接成一个文件
+///
+/// ```ignore
+/// let http = HttpDownloader::new(mpd_url, "audio.mp4", "").retry(RetryPolicy::default());
+/// DashDownloader::new(http).content_type("audio").connections(4).download().await?;
+/// ```
+///
+/// 视频和音频在不同的 AdaptationSet 中 需要分别下载 之后再用其他工具合并
+///
+/// 下载 续传和重试与 [`HlsDownloader`] 相同 多个 Period 的分片依次拼接
+#[derive(Debug, Clone)]
+pub struct DashDownloader {
+    http:          HttpDownloader,
+    connections:   usize,
+    content_type:  String,
+    max_bandwidth: Option<u64>,
+}
+
+impl DashDownloader {
+    /// `http` 的地址为 MPD 默认单连接 下载视频
+    pub fn new(http: HttpDownloader) -> Self {
+        Self { http, connections: 1, content_type: "video".to_string(), max_bandwidth: None }
+    }
+
+    /// 同时下载的分片数
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// 下载的内容类型 例如 `audio`
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    /// 选择不超过该码率的最高码率 见 `Mpd::select` 默认选择最高码率
+    pub fn max_bandwidth(mut self, bandwidth: u64) -> Self {
+        self.max_bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// 代理 见 `HttpDownloader::proxy`
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.http = self.http.proxy(proxy);
+        self
+    }
+
+    pub(crate) fn default_proxy(&mut self, proxy: &ProxyConfig) {
+        self.http.default_proxy(proxy);
+    }
+
+    pub(crate) fn default_host_limits(&mut self, limits: &HostLimits) {
+        self.http.default_host_limits(limits);
+    }
+
+    pub(crate) fn add_limiter(&mut self, limiter: RateLimiter) {
+        self.http.builder.limiters.push(limiter);
+    }
+
+    pub(crate) fn default_buffer_pool(&mut self, pool: &BufferPool) {
+        self.http.builder.default_buffer_pool(pool);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Observer) {
+        self.http.builder.observer = Some(observer);
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.http.builder.path
+    }
+
+    /// 取消令牌 见 `DownloadBuilder::cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.http = self.http.cancel(token);
+        self
+    }
+
+    /// 获取并解析 MPD
+    pub async fn mpd(&self) -> Result<Mpd> {
+        let url = &self.http.urls[0];
+        let cancel = self.http.builder.cancel.as_ref();
+        let send = || self.http.send(Method::GET, url, &[]);
+        let observer = self.http.builder.observer.as_ref();
+        let response = self.http.retry.run(cancel, observer, send).await?.error_for_status()?;
+        let base = response.url().clone();
+        Mpd::parse(&response.text().await?, &base)
+    }
+
+    /// 下载并拼接 中断后再次调用会从没有写完的分片继续 返回目标文件路径
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "download",
+            skip_all,
+            fields(url = %self.http.urls[0], path = %self.http.builder.path.display()),
+            err(level = "warn"),
+        )
+    )]
+    pub async fn download(&self) -> Result<PathBuf> {
+        let download = self.http.within_deadline(self.attempt());
+        #[cfg(feature = "metrics")]
+        let download = crate::telemetry::measure(download);
+        download.await
+    }
+
+    async fn attempt(&self) -> Result<PathBuf> {
+        let mpd = self.mpd().await?;
+        let selected = mpd.select(&self.content_type, self.max_bandwidth.unwrap_or(u64::MAX));
+        let segments: Vec<_> = selected.into_iter().flat_map(|rep| rep.segments.clone()).collect();
+        if segments.is_empty() {
+            return Err(invalid("没有可以下载的分片"));
+        }
+        let hls = HlsDownloader::new(self.http.clone()).connections(self.connections);
+        hls.merge(segments).await
+    }
+}
+
+impl Node {
+    /// 整个文档解析为树 返回根元素
+    fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut stack = vec![Self::default()];
+        loop {
+            match reader.read_event().map_err(invalid)? {
+                // 只有 BaseURL 需要文本
+                Event::Start(e) if e.local_name().as_ref() == "BaseURL" => {
+                    let mut node = Self::from_start(&e)?;
+                    let text = reader.read_text(e.name()).map_err(invalid)?;
+                    node.text = unescape(&text).map_err(invalid)?.into_owned();
+                    stack.last_mut().expect("至少有根").children.push(node);
+                }
+                Event::Start(e) => stack.push(Self::from_start(&e)?),
+                Event::Empty(e) => {
+                    let node = Self::from_start(&e)?;
+                    stack.last_mut().expect("至少有根").children.push(node);
+                }
+                Event::End(_) => {
+                    let node = stack.pop().filter(|_| !stack.is_empty());
+                    let node = node.ok_or_else(|| invalid("多余的结束标签"))?;
+                    stack.last_mut().expect("至少有根").children.push(node);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        let document = stack.pop().filter(|_| stack.is_empty());
+        let document = document.ok_or_else(|| invalid("元素没有结束"))?;
+        document.children.into_iter().next().ok_or_else(|| invalid("空文档"))
+    }
+
+    fn from_start(e: &BytesStart) -> Result<Self> {
+        let mut attrs = vec![];
+        for attr in e.attributes() {
+            let attr = attr.map_err(invalid)?;
+            let value = attr.normalized_value(XmlVersion::default()).map_err(invalid)?;
+            attrs.push((attr.key.local_name().as_ref().to_string(), value.into_owned()));
+        }
+        let name = e.local_name().as_ref().to_string();
+        Ok(Self { name, attrs, ..Default::default() })
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Self> {
+        self.children.iter().filter(move |node| node.name == name)
+    }
+
+    /// 第一个 BaseURL 相对于上层解析
+    fn base(&self, base: &Url) -> Result<Url> {
+        match self.children("BaseURL").next() {
+            Some(node) => base.join(node.text.trim()).map_err(invalid),
+            None => Ok(base.clone()),
+        }
+    }
+}
+
+/// 逐层合并同名元素的属性 子元素使用最下层的
+fn inherit(levels: &[&Node], name: &str) -> Option<Node> {
+    let mut merged: Option<Node> = None;
+    for node in levels.iter().filter_map(|level| level.children(name).next()) {
+        let merged = merged.get_or_insert_with(Node::default);
+        for (key, value) in &node.attrs {
+            merged.attrs.retain(|(k, _)| k != key);
+            merged.attrs.push((key.clone(), value.clone()));
+        }
+        if !node.children.is_empty() {
+            merged.children = node.children.clone();
+        }
+    }
+    merged
+}
+
+/// 替换 `$RepresentationID$` `$Number%05d$` `$Bandwidth$` `$Time$` 和 `$$`
+fn expand(template: &str, id: &str, bandwidth: u64, number: u64, time: u64) -> Result<String> {
+    let mut out = String::new();
+    let mut parts = template.split('$');
+    out.push_str(parts.next().unwrap_or_default());
+    while let Some(var) = parts.next() {
+        let text = parts.next().ok_or_else(|| invalid(format!("模板缺少 $ {template}")))?;
+        let (name, format) = var.split_once('%').unwrap_or((var, ""));
+        let value = match name {
+            "" => "$".to_string(),
+            "RepresentationID" => id.to_string(),
+            "Number" | "Bandwidth" | "Time" => {
+                let value = match name {
+                    "Number" => number,
+                    "Bandwidth" => bandwidth,
+                    _ => time,
+                };
+                let width = format.trim_start_matches('0').trim_end_matches('d');
+                let width: usize = width.parse().unwrap_or(0);
+                format!("{value:0width$}")
+            }
+            _ => return Err(invalid(format!("不支持的模板变量 {name}"))),
+        };
+        out.push_str(&value);
+        out.push_str(text);
+    }
+    Ok(out)
+}
+
+fn segment(url: String, duration: f64, range: Option<Range<u64>>) -> Segment {
+    Segment { url, duration, range }
+}
+
+/// `a-b` 两端都包含
+fn range(value: &str) -> Result<Range<u64>> {
+    let (start, end) = value.split_once('-').ok_or_else(|| invalid(format!("无效的区间 {value}")))?;
+    let (start, end): (u64, u64) = (start.parse().map_err(invalid)?, end.parse().map_err(invalid)?);
+    match end.checked_add(1) {
+        Some(end) if start < end => Ok(start..end),
+        _ => Err(invalid(format!("无效的区间 {value}"))),
+    }
+}
+
+/// ISO 8601 时长 例如 `PT1H2M3.5S` 返回秒 不支持年和月
+fn duration(value: &str) -> Result<f64> {
+    let error = || invalid(format!("无效的时长 {value}"));
+    let rest = value.strip_prefix('P').ok_or_else(error)?;
+    let (mut seconds, mut number, mut time) = (0.0, String::new(), false);
+    for c in rest.chars() {
+        match c {
+            'T' => time = true,
+            '0'..='9' | '.' => number.push(c),
+            unit => {
+                let n: f64 = number.parse().map_err(|_| error())?;
+                seconds += n * match (unit, time) {
+                    ('D', false) => 86400.0,
+                    ('W', false) => 604800.0,
+                    ('H', true) => 3600.0,
+                    ('M', true) => 60.0,
+                    ('S', true) => 1.0,
+                    _ => return Err(error()),
+                };
+                number.clear();
+            }
+        }
+    }
+    Ok(seconds)
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Dash(e.to_string())
+}
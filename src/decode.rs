@@ -0,0 +1,53 @@
+use std::{io, pin::Pin};
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZstdDecoder};
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use reqwest::header::{self, HeaderMap};
+use tokio::io::AsyncRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{DownloadError, Result};
+
+/// 请求时声明接受的编码
+pub(crate) const ACCEPT: &str = "gzip, br, zstd";
+/// 每次读取解码后数据的大小
+const CHUNK: usize = 64 * 1024;
+
+/// 响应的 Content-Encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// 没有该响应头或为 identity 时返回 None
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Result<Option<Self>> {
+        let Some(value) = headers.get(header::CONTENT_ENCODING) else {
+            return Ok(None);
+        };
+        match value.to_str().unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(None),
+            "gzip" | "x-gzip" => Ok(Some(Self::Gzip)),
+            "br" => Ok(Some(Self::Brotli)),
+            "zstd" => Ok(Some(Self::Zstd)),
+            encoding => Err(DownloadError::UnsupportedEncoding(encoding.to_string())),
+        }
+    }
+}
+
+/// 边接收边解码响应体
+pub(crate) fn decode<'a>(
+    body: BoxStream<'a, Result<Bytes>>,
+    encoding: Encoding,
+) -> BoxStream<'a, Result<Bytes>> {
+    let reader = StreamReader::new(body.map_err(io::Error::other));
+    let reader: Pin<Box<dyn AsyncRead + Send + 'a>> = match encoding {
+        Encoding::Gzip => Box::pin(GzipDecoder::new(reader)),
+        Encoding::Brotli => Box::pin(BrotliDecoder::new(reader)),
+        Encoding::Zstd => Box::pin(ZstdDecoder::new(reader)),
+    };
+    ReaderStream::with_capacity(reader, CHUNK).map_err(DownloadError::from).boxed()
+}
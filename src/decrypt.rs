@@ -0,0 +1,126 @@
+use std::{fmt, sync::Arc};
+
+#[cfg(feature = "http")]
+use bytes::Bytes;
+#[cfg(feature = "http")]
+use futures::{stream::BoxStream, AsyncReadExt, StreamExt, TryStreamExt};
+
+use crate::{cipher::Keystream, DownloadError, Result};
+
+/// 每次读取明文的大小
+#[cfg(feature = "http")]
+const CHUNK: usize = 64 * 1024;
+
+/// 下载时解密 写入的是明文 hash 分块 hash 和签名都按明文校验
+///
+/// AES-CTR 可以按位置解密 续传和多连接下载不受影响
+///
+/// age 格式需要从头顺序解密 只支持 `HttpDownloader` 按大小未知下载 只使用一个连接
+/// 中断后从头重新下载 用于其他下载器时打开 downloading 文件返回 `DownloadError::Decrypt`
+///
+/// 密钥不保存在元数据中 继续下载时需要重新设置
+#[derive(Clone)]
+pub struct Decryption(Kind);
+
+#[derive(Clone)]
+enum Kind {
+    AesCtr(Keystream),
+    /// 只在 `HttpDownloader` 中使用
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    Age(Arc<dyn age::Identity + Send + Sync>),
+}
+
+impl Decryption {
+    /// key 为 16 24 或 32 字节 分别对应 AES-128 AES-192 AES-256
+    ///
+    /// iv 为第一个计数器块 按大端整体递增 同 `openssl enc -aes-256-ctr -iv`
+    pub fn aes_ctr(key: &[u8], iv: [u8; 16]) -> Result<Self> {
+        if ![16, 24, 32].contains(&key.len()) {
+            return Err(invalid(format!("AES 密钥长度为 {} 字节", key.len())));
+        }
+        Ok(Self(Kind::AesCtr(Keystream::new(key, iv))))
+    }
+
+    /// age 的 X25519 私钥 `AGE-SECRET-KEY-1...`
+    pub fn age(identity: &str) -> Result<Self> {
+        let identity: age::x25519::Identity = identity.trim().parse().map_err(invalid)?;
+        Ok(Self(Kind::Age(Arc::new(identity))))
+    }
+
+    /// 使用口令加密的 age 文件 `age -p`
+    pub fn age_passphrase(passphrase: impl Into<String>) -> Self {
+        let identity = age::scrypt::Identity::new(passphrase.into().into());
+        Self(Kind::Age(Arc::new(identity)))
+    }
+
+    /// 写入时使用的密钥流 age 格式返回错误
+    pub(crate) fn keystream(&self) -> Result<Keystream> {
+        match &self.0 {
+            Kind::AesCtr(keystream) => Ok(keystream.clone()),
+            Kind::Age(_) => Err(invalid("age 格式只支持 HttpDownloader")),
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn is_age(&self) -> bool {
+        matches!(self.0, Kind::Age(_))
+    }
+
+    #[cfg(feature = "http")]
+    fn identity(&self) -> Option<&(dyn age::Identity + Send + Sync)> {
+        match &self.0 {
+            Kind::Age(identity) => Some(identity.as_ref()),
+            Kind::AesCtr(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Decryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::AesCtr(keystream) => f
+                .debug_struct("AesCtr")
+                .field("bits", &keystream.bits())
+                .finish_non_exhaustive(),
+            Kind::Age(_) => f.debug_struct("Age").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// 解密 age 格式的响应体 先读取文件头解出文件密钥 不是 age 格式时原样返回
+#[cfg(feature = "http")]
+pub(crate) async fn decrypt<'a>(
+    body: BoxStream<'a, Result<Bytes>>,
+    decryption: &'a Decryption,
+) -> Result<BoxStream<'a, Result<Bytes>>> {
+    let Some(identity) = decryption.identity() else {
+        return Ok(body);
+    };
+    let reader = body.map_err(std::io::Error::other).into_async_read();
+    let decryptor = age::Decryptor::new_async_buffered(reader).await.map_err(fail)?;
+    let reader = decryptor.decrypt_async(std::iter::once(identity as _)).map_err(fail)?;
+    let stream = futures::stream::try_unfold(reader, |mut reader| async move {
+        let mut buf = vec![0; CHUNK];
+        match reader.read(&mut buf).await? {
+            0 => Ok(None),
+            n => {
+                buf.truncate(n);
+                Ok(Some((Bytes::from(buf), reader)))
+            }
+        }
+    });
+    Ok(stream.boxed())
+}
+
+/// 读取响应体的错误原样返回
+#[cfg(feature = "http")]
+fn fail(e: age::DecryptError) -> DownloadError {
+    match e {
+        age::DecryptError::Io(e) => e.into(),
+        e => invalid(e),
+    }
+}
+
+fn invalid(e: impl fmt::Display) -> DownloadError {
+    DownloadError::Decrypt(e.to_string())
+}
@@ -0,0 +1,73 @@
+use std::{borrow::Cow, fs::File, io, path::Path, sync::Arc};
+
+use crate::{writer::write_all_at, Downloading};
+
+/// O_DIRECT 要求的位置 长度和内存地址的对齐
+const ALIGN: usize = 4096;
+/// 没有设置 `write_buffer` 时合并写入的大小
+const BUFFER: usize = 1024 * 1024;
+
+impl Downloading {
+    /// 另外以 O_DIRECT 打开数据文件 文件系统不支持或其他平台时照常写入
+    ///
+    /// 没有设置 `write_buffer` 时按 `BUFFER` 合并 否则写入很少是对齐的
+    pub(crate) fn direct_io(&mut self, enabled: bool) {
+        self.writer.direct = if enabled { open(&self.path).map(Arc::new) } else { None };
+        if self.writer.direct.is_some() && self.writer.buffer == 0 {
+            self.writer.buffer = BUFFER;
+        }
+    }
+
+    /// 对齐的部分通过 O_DIRECT 写入 不经过页缓存 首尾不对齐的部分照常写入
+    ///
+    /// 没有以 O_DIRECT 打开或没有对齐的部分时返回 false 由调用方照常写入
+    pub(crate) async fn write_direct(
+        &self,
+        offset: u64,
+        bufs: &[Cow<'_, [u8]>],
+    ) -> io::Result<bool> {
+        let Some(direct) = self.writer.direct.clone() else {
+            return Ok(false);
+        };
+        let len = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        let end = offset + len as u64;
+        let start = offset.next_multiple_of(ALIGN as u64);
+        let stop = end / ALIGN as u64 * ALIGN as u64;
+        if start >= stop {
+            return Ok(false);
+        }
+        // 复制到缓冲区中 使 start 处的数据在内存中对齐 预留足够的容量 复制时不会重新分配
+        let head = (start - offset) as usize;
+        let mut buf = self.take_buffer(len + ALIGN);
+        buf.reserve(len + ALIGN);
+        let pad = (ALIGN - (buf.as_ptr() as usize + head) % ALIGN) % ALIGN;
+        buf.resize(pad, 0);
+        bufs.iter().for_each(|b| buf.extend_from_slice(b));
+
+        let data = self.writer.data.clone();
+        let task = move || {
+            let (first, rest) = buf[pad..].split_at(head);
+            let (aligned, last) = rest.split_at((stop - start) as usize);
+            write_all_at(&data, first, offset)?;
+            write_all_at(&direct, aligned, start)?;
+            write_all_at(&data, last, stop)?;
+            Ok::<_, io::Error>(buf)
+        };
+        let buf = tokio::task::spawn_blocking(task).await??;
+        self.recycle([buf]);
+        Ok(true)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open(path: &Path) -> Option<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let flags = rustix::fs::OFlags::DIRECT.bits() as i32;
+    File::options().write(true).custom_flags(flags).open(path).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open(_path: &Path) -> Option<File> {
+    None
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use crate::Downloading;
+
+/// 何时把内存中的进度写入元数据 内存中的进度总是准确的
+///
+/// 写入元数据前的进度在崩溃后丢失 需要重新下载 `flush` `pause` 取消和丢弃时都会写入
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PersistPolicy {
+    /// `write_at` 每次都写入
+    #[default]
+    Always,
+    /// 距上次写入元数据写入超过 n 字节后
+    EveryNBytes(u64),
+    /// 距上次写入元数据超过该时间后
+    Interval(Duration),
+    /// 只在 `flush` 时写入
+    OnFlush,
+}
+
+/// 何时调用 `sync_data` 把写入的数据和元数据同步到磁盘
+///
+/// 不同步时断电后元数据可能已经落盘而数据没有 再次打开后这些区间被当作已下载
+/// 只能在完成校验时发现
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// 不主动同步 由操作系统决定何时落盘
+    #[default]
+    Never,
+    /// 每次写入元数据前同步数据 写入后同步元数据 元数据记录的进度都已落盘
+    ///
+    /// `write_at` 每次都会写入元数据 写入较小时开销很大
+    OnMetadataUpdate,
+    /// 距上次同步写入超过 n 字节后 下一次写入元数据时同 `OnMetadataUpdate`
+    ///
+    /// 两次同步之间断电 元数据仍可能超过已落盘的数据
+    EveryNBytes(u64),
+    /// 只在完成时改名前同步整个文件
+    OnComplete,
+}
+
+impl Downloading {
+    /// 按 `PersistPolicy` 现在是否需要写入元数据
+    pub(crate) fn persist_due(&self) -> bool {
+        match self.writer.persist {
+            PersistPolicy::Always => true,
+            PersistPolicy::EveryNBytes(n) => self.writer.unsaved >= n,
+            PersistPolicy::Interval(interval) => self.writer.saved.elapsed() >= interval,
+            PersistPolicy::OnFlush => false,
+        }
+    }
+
+    /// 刚写入了元数据
+    pub(crate) fn persisted(&mut self) {
+        self.writer.unsaved = 0;
+        self.writer.saved = Instant::now();
+    }
+
+    /// 这次写入元数据前后是否需要同步 需要时清零未同步的字节数
+    pub(crate) fn sync_due(&mut self) -> bool {
+        let due = match self.writer.sync {
+            SyncPolicy::OnMetadataUpdate => true,
+            SyncPolicy::EveryNBytes(n) => self.writer.behind >= n,
+            SyncPolicy::Never | SyncPolicy::OnComplete => false,
+        };
+        if due {
+            self.writer.behind = 0;
+        }
+        due
+    }
+
+    /// 未同步的写入已超过 `EveryNBytes` 的限制 顺序写入时先 flush
+    pub(crate) fn sync_pending(&self) -> bool {
+        matches!(self.writer.sync, SyncPolicy::EveryNBytes(n) if self.writer.behind >= n)
+    }
+}
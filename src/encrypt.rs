@@ -0,0 +1,160 @@
+use std::{
+    fmt,
+    io::{self, Read, SeekFrom::Start},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{cipher::Keystream, DownloadError, Downloading, Encryption, Result};
+
+/// 解密时每次处理的大小
+const CHUNK: usize = 1024 * 1024;
+
+/// 加密存放使用的 AES-256 密钥
+#[derive(Clone)]
+pub(crate) struct Key(pub(crate) [u8; 32]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// 按位置加密和解密 downloading 文件中的内容
+#[derive(Debug, Clone)]
+pub(crate) struct Sealer {
+    key:       Key,
+    keystream: Keystream,
+}
+
+impl Sealer {
+    fn new(key: &Key, encrypted: &Encryption) -> Self {
+        Self { key: key.clone(), keystream: Keystream::new(&key.0, encrypted.nonce) }
+    }
+
+    pub(crate) fn apply(&self, offset: u64, buf: &mut [u8]) {
+        self.keystream.apply(offset, buf);
+    }
+}
+
+impl Encryption {
+    /// nonce 不需要保密 只要不重复
+    fn generate(key: &Key) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut hasher = Sha256::new();
+        hasher.update(now.as_nanos().to_le_bytes());
+        hasher.update(std::process::id().to_le_bytes());
+        hasher.update(COUNTER.fetch_add(1, Relaxed).to_le_bytes());
+        let nonce = hasher.finalize()[..16].try_into().unwrap();
+        Self { nonce, check: check(key, &nonce) }
+    }
+}
+
+/// 密钥和 nonce 的 SHA-256 的前 8 字节
+fn check(key: &Key, nonce: &[u8; 16]) -> [u8; 8] {
+    let digest = Sha256::new().chain_update(key.0).chain_update(nonce).finalize();
+    digest[..8].try_into().unwrap()
+}
+
+impl Downloading {
+    /// 按元数据使用 key 元数据中没有加密参数时生成 之前以明文下载的进度丢弃
+    ///
+    /// 文件已加密但没有提供 key 或 key 不一致时返回 `DownloadError::WrongKey`
+    pub(crate) async fn unlock(&mut self, key: Option<&Key>) -> Result<()> {
+        let encrypted = match (self.meta.encrypted, key) {
+            (None, None) => return Ok(()),
+            (Some(encrypted), Some(key)) if encrypted.check == check(key, &encrypted.nonce) => {
+                encrypted
+            }
+            (Some(_), _) => return Err(DownloadError::WrongKey),
+            (None, Some(key)) => {
+                if self.meta.ranges.downloaded() > 0 {
+                    self.restart().await?;
+                }
+                let encrypted = Encryption::generate(key);
+                self.meta.encrypted = Some(encrypted);
+                self.meta.resize();
+                encrypted
+            }
+        };
+        self.at_rest = key.map(|key| Sealer::new(key, &encrypted));
+        Ok(())
+    }
+
+    /// 重新开始时更换 nonce 同一位置不会用相同的密钥流加密不同的内容
+    pub(crate) fn renew(&mut self) {
+        if let Some(sealer) = &self.at_rest {
+            let encrypted = Encryption::generate(&sealer.key);
+            self.at_rest = Some(Sealer::new(&sealer.key, &encrypted));
+            self.meta.encrypted = Some(encrypted);
+        }
+    }
+
+    /// 解密从文件 offset 处读出的 buf
+    pub(crate) fn unseal(&self, offset: u64, buf: &mut [u8]) {
+        if let Some(sealer) = &self.at_rest {
+            sealer.apply(offset, buf);
+        }
+    }
+
+    /// 解密到 `<downloading 文件>.plain` 返回打开的文件和路径 没有加密存放时返回 None
+    ///
+    /// 原文件保留到校验通过 之前打开的读取者不受影响
+    pub(crate) async fn unseal_file(&mut self) -> Result<Option<(File, PathBuf)>> {
+        let Some(sealer) = &self.at_rest else {
+            return Ok(None);
+        };
+        let mut path = self.path.clone().into_os_string();
+        path.push(".plain");
+        let path = PathBuf::from(path);
+        let mut options = File::options();
+        options.create(true).truncate(true).write(true).read(true);
+        let mut plain = options.open(&path).await?;
+
+        let (mut pos, mut buf) = (0, vec![0; CHUNK]);
+        self.writer.cursor = None;
+        self.file.seek(Start(0)).await?;
+        while pos < self.meta.size {
+            let n = buf.len().min((self.meta.size - pos) as usize);
+            self.file.read_exact(&mut buf[..n]).await?;
+            sealer.apply(pos, &mut buf[..n]);
+            plain.write_all(&buf[..n]).await?;
+            pos += n as u64;
+        }
+        plain.flush().await?;
+        Ok(Some((plain, path)))
+    }
+}
+
+/// 读取时解密 用于需要 `Read` 的校验
+pub(crate) struct Unsealed<R> {
+    inner:  R,
+    sealer: Option<Sealer>,
+    pos:    u64,
+}
+
+impl<R> Unsealed<R> {
+    /// inner 从文件开头读取
+    pub(crate) fn new(inner: R, sealer: Option<Sealer>) -> Self {
+        Self { inner, sealer, pos: 0 }
+    }
+}
+
+impl<R: Read> Read for Unsealed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(sealer) = &self.sealer {
+            sealer.apply(self.pos, &mut buf[..n]);
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,270 @@
+use std::{error, fmt, path::PathBuf};
+
+use tokio::io;
+
+use crate::hash::Algorithm;
+
+pub type Result<T, E = DownloadError> = std::result::Result<T, E>;
+
+/// 下载过程中的错误
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DownloadError {
+    /// 文件读写错误
+    Io(io::Error),
+    /// HTTP 请求错误
+    #[cfg(feature = "http")]
+    Http(reqwest::Error),
+    /// downloading 文件不包含元数据
+    MetadataMissing,
+    /// 元数据无法解析
+    MetadataCorrupt,
+    /// 不支持的元数据版本
+    UnsupportedVersion(u8),
+    /// 要下载的文件已存在
+    TargetExists,
+    /// 已存在的文件 hash 一致 无需下载
+    AlreadyDownloaded(PathBuf),
+    /// 写入的内容超过文件长度
+    Overflow,
+    /// 文件还未下载完成
+    Incomplete,
+    /// 文件校验失败
+    HashMismatch { expected: String, actual: String },
+    /// 不支持的 hash 算法
+    UnsupportedAlgorithm(String),
+    /// 该算法不支持持久化增量 hash 状态
+    NotResumable(Algorithm),
+    /// 未开启增量 hash
+    NotTracked,
+    /// 元数据中没有记录 hash 算法
+    UnknownAlgorithm,
+    /// 无法获取远程文件大小
+    UnknownSize,
+    /// 服务端不支持 Range 请求
+    RangeNotSupported,
+    /// 连接在数据收完前结束
+    ConnectionClosed,
+    /// 下载管理器中不存在该任务
+    TaskNotFound(u64),
+    /// 下载已取消
+    Cancelled,
+    /// 超过设定的时间没有收到数据
+    Stalled,
+    /// Metalink 文档无法解析
+    Metalink(String),
+    /// 下载过程中远程资源发生变化
+    ResourceChanged,
+    /// 无法解析的地址
+    InvalidUrl(String),
+    /// 重定向次数超过限制
+    TooManyRedirects,
+    /// 重定向策略不允许跳转到该地址
+    RedirectRejected(String),
+    /// TLS 证书或私钥无效
+    Tls(String),
+    /// 超过整个下载的最长时间
+    DeadlineExceeded,
+    /// 大小未知时只能顺序写入
+    OutOfOrder,
+    /// 第 n 块校验失败 已丢弃需要重新下载
+    PieceMismatch(usize),
+    /// 整个文件校验失败 这些块的 hash 不一致 已丢弃需要重新下载
+    Corrupted(Vec<usize>),
+    /// hash 不是 BLAKE3 或 outboard 与文件大小不一致
+    InvalidOutboard,
+    /// 校验文件中没有该文件
+    ChecksumNotFound(String),
+    /// 签名无效或校验失败
+    InvalidSignature(String),
+    /// 无法放入请求头的值
+    InvalidHeader(String),
+    /// 远程没有提供可以校验整个文件的 hash
+    NoChecksum,
+    /// 改名模板展开后不是有效的文件名
+    InvalidTemplate(String),
+    /// downloading 文件加密存放 没有提供密钥或密钥不正确
+    WrongKey,
+    /// 磁盘剩余空间不足以写完 单位字节
+    InsufficientSpace { required: u64, available: u64 },
+    /// FTP 服务器返回的错误响应
+    #[cfg(feature = "ftp")]
+    Ftp { code: u16, message: String },
+    /// DASH 清单无法解析或不支持
+    #[cfg(feature = "dash")]
+    Dash(String),
+    /// 响应使用了不支持的 Content-Encoding
+    #[cfg(feature = "decompress")]
+    UnsupportedEncoding(String),
+    /// 密钥不正确或密文损坏
+    #[cfg(feature = "decrypt")]
+    Decrypt(String),
+    /// 压缩包损坏或格式不支持
+    #[cfg(feature = "extract")]
+    Extract(String),
+    /// Hugging Face Hub 返回的错误 例如仓库不存在或需要授权
+    #[cfg(feature = "hf")]
+    HuggingFace(String),
+    /// HLS 播放列表无法解析或不支持
+    #[cfg(feature = "hls")]
+    Hls(String),
+    /// 无法解析的 IPFS 内容标识符
+    #[cfg(feature = "ipfs")]
+    InvalidCid(String),
+    /// 导出的 JSON 文档无效 或任务不能导出
+    #[cfg(feature = "json")]
+    Json(String),
+    /// Git LFS 的指针或 batch 响应无效 或服务返回了对象的错误
+    #[cfg(feature = "lfs")]
+    Lfs(String),
+    /// OCI 仓库的引用无效或认证失败
+    #[cfg(feature = "oci")]
+    Oci(String),
+    /// SSH 连接 登录或 SFTP 请求失败
+    #[cfg(feature = "sftp")]
+    Ssh(String),
+    /// 队列数据库读写失败
+    #[cfg(feature = "sqlite")]
+    Database(rusqlite::Error),
+    /// 种子文件或磁力链接无效
+    #[cfg(feature = "torrent")]
+    Torrent(String),
+    /// WebDAV 响应无法解析
+    #[cfg(feature = "webdav")]
+    WebDav(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "http")]
+            Self::Http(e) => write!(f, "{e}"),
+            Self::MetadataMissing => f.write_str("文件不包含元数据"),
+            Self::MetadataCorrupt => f.write_str("解析 downloading 元数据失败"),
+            Self::UnsupportedVersion(version) => write!(f, "不支持的元数据版本: {version}"),
+            Self::TargetExists => f.write_str("要下载的文件已存在"),
+            Self::AlreadyDownloaded(path) => write!(f, "文件已下载: {}", path.display()),
+            Self::Overflow => f.write_str("写入的文本长度超过文件长度"),
+            Self::Incomplete => f.write_str("文件还未下载完成"),
+            Self::HashMismatch { expected, actual } => {
+                write!(f, "文件检验失败 期望 {expected} 实际 {actual}")
+            }
+            Self::UnsupportedAlgorithm(name) => write!(f, "不支持的 hash 算法: {name}"),
+            Self::NotResumable(algorithm) => {
+                write!(f, "{} 不支持持久化 hash 状态", algorithm.name())
+            }
+            Self::NotTracked => f.write_str("未开启增量 hash"),
+            Self::UnknownAlgorithm => f.write_str("元数据中没有记录 hash 算法"),
+            Self::UnknownSize => f.write_str("无法获取文件大小"),
+            Self::RangeNotSupported => f.write_str("服务端不支持分段下载"),
+            Self::ConnectionClosed => f.write_str("连接提前结束"),
+            Self::TaskNotFound(id) => write!(f, "任务不存在: {id}"),
+            Self::Cancelled => f.write_str("下载已取消"),
+            Self::Stalled => f.write_str("长时间没有收到数据"),
+            Self::Metalink(e) => write!(f, "解析 Metalink 失败: {e}"),
+            Self::ResourceChanged => f.write_str("远程文件已变化"),
+            Self::InvalidUrl(e) => write!(f, "无效的地址: {e}"),
+            Self::TooManyRedirects => f.write_str("重定向次数过多"),
+            Self::RedirectRejected(url) => write!(f, "不允许重定向到 {url}"),
+            Self::Tls(e) => write!(f, "TLS 配置错误: {e}"),
+            Self::DeadlineExceeded => f.write_str("下载超时"),
+            Self::OutOfOrder => f.write_str("大小未知的文件只能顺序写入"),
+            Self::PieceMismatch(index) => write!(f, "第 {index} 块校验失败"),
+            Self::Corrupted(pieces) => write!(f, "{} 块已损坏 需要重新下载", pieces.len()),
+            Self::InvalidOutboard => f.write_str("outboard 与文件的 BLAKE3 hash 或大小不一致"),
+            Self::ChecksumNotFound(name) => write!(f, "校验文件中没有 {name}"),
+            Self::InvalidSignature(e) => write!(f, "签名校验失败: {e}"),
+            Self::InvalidHeader(e) => write!(f, "无效的请求头: {e}"),
+            Self::NoChecksum => f.write_str("远程没有提供可以校验的 hash"),
+            Self::InvalidTemplate(name) => write!(f, "模板展开后不是有效的文件名: {name}"),
+            Self::WrongKey => f.write_str("downloading 文件已加密 密钥不正确"),
+            Self::InsufficientSpace { required, available } => {
+                write!(f, "磁盘空间不足 需要 {required} 字节 剩余 {available} 字节")
+            }
+            #[cfg(feature = "ftp")]
+            Self::Ftp { code, message } => write!(f, "FTP 服务器返回 {code} {message}"),
+            #[cfg(feature = "dash")]
+            Self::Dash(e) => write!(f, "解析 DASH 清单失败: {e}"),
+            #[cfg(feature = "decompress")]
+            Self::UnsupportedEncoding(e) => write!(f, "不支持的内容编码: {e}"),
+            #[cfg(feature = "decrypt")]
+            Self::Decrypt(e) => write!(f, "解密失败: {e}"),
+            #[cfg(feature = "extract")]
+            Self::Extract(e) => write!(f, "解压失败: {e}"),
+            #[cfg(feature = "hf")]
+            Self::HuggingFace(e) => write!(f, "Hugging Face 错误: {e}"),
+            #[cfg(feature = "hls")]
+            Self::Hls(e) => write!(f, "解析 HLS 播放列表失败: {e}"),
+            #[cfg(feature = "ipfs")]
+            Self::InvalidCid(cid) => write!(f, "无效的 CID: {cid}"),
+            #[cfg(feature = "json")]
+            Self::Json(e) => write!(f, "导入导出失败: {e}"),
+            #[cfg(feature = "lfs")]
+            Self::Lfs(e) => write!(f, "Git LFS 错误: {e}"),
+            #[cfg(feature = "oci")]
+            Self::Oci(e) => write!(f, "OCI 仓库错误: {e}"),
+            #[cfg(feature = "sftp")]
+            Self::Ssh(e) => write!(f, "SSH 错误: {e}"),
+            #[cfg(feature = "sqlite")]
+            Self::Database(e) => write!(f, "队列数据库错误: {e}"),
+            #[cfg(feature = "torrent")]
+            Self::Torrent(e) => write!(f, "解析种子失败: {e}"),
+            #[cfg(feature = "webdav")]
+            Self::WebDav(e) => write!(f, "解析 WebDAV 响应失败: {e}"),
+        }
+    }
+}
+
+impl error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            #[cfg(feature = "http")]
+            Self::Http(e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            Self::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// 经过 `io::Error` 传递的 `DownloadError` 原样取出
+impl From<io::Error> for DownloadError {
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().is_some_and(|inner| inner.is::<DownloadError>()) {
+            true => *e.into_inner().and_then(|inner| inner.downcast().ok()).expect("已检查类型"),
+            false => Self::Io(e),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for DownloadError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for DownloadError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Self::Io(io::Error::other(e))
+    }
+}
+
+/// 需要 io::Error 的场景直接转换 原始错误可通过 `into_inner` 取回
+impl From<DownloadError> for io::Error {
+    fn from(e: DownloadError) -> Self {
+        match e {
+            DownloadError::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use tokio::io::AsyncRead;
+use tokio_util::io::SyncIoBridge;
+
+use crate::{DownloadError, Result};
+
+/// 支持的压缩包格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    /// `.tar.gz` 或 `.tgz`
+    TarGz,
+    /// `.tar.zst` 或 `.tzst`
+    TarZstd,
+    /// 按本地文件头顺序读取 不使用末尾的中央目录
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// 按扩展名判断 不区分大小写
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let formats = [
+            (".tar", Self::Tar),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZstd),
+            (".tzst", Self::TarZstd),
+            (".zip", Self::Zip),
+        ];
+        formats.into_iter().find(|(ext, _)| name.ends_with(ext)).map(|(_, format)| format)
+    }
+}
+
+/// 从 reader 流式解压到 dir 返回解出的文件数 dir 不存在时创建
+///
+/// ```ignore
+/// let reader = downloading.reader().await?;
+/// let unpack = tokio::spawn(extract::extract(reader, ArchiveFormat::TarGz, "model"));
+/// // 继续写入 downloading
+/// unpack.await??;
+/// ```
+///
+/// reader 可以是正在下载的 `DownloadReader` 边下载边解压 解压在阻塞线程池中进行
+///
+/// 路径跳出 dir 的条目被跳过 已存在的文件被覆盖
+pub async fn extract<R>(reader: R, format: ArchiveFormat, dir: impl AsRef<Path>) -> Result<u64>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let dir = dir.as_ref().to_path_buf();
+    let reader = SyncIoBridge::new(reader);
+    let task = tokio::task::spawn_blocking(move || {
+        fs::create_dir_all(&dir)?;
+        match format {
+            ArchiveFormat::Tar => untar(reader, &dir),
+            ArchiveFormat::TarGz => untar(flate2::read::GzDecoder::new(reader), &dir),
+            ArchiveFormat::TarZstd => untar(zstd::Decoder::new(reader)?, &dir),
+            ArchiveFormat::Zip => unzip(reader, &dir),
+        }
+    });
+    task.await?
+}
+
+/// 解压下载完成的 path 格式按扩展名判断
+pub async fn extract_file(path: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let format = ArchiveFormat::from_path(path);
+    let format = format.ok_or_else(|| invalid(format!("不支持的格式 {}", path.display())))?;
+    extract(tokio::fs::File::open(path).await?, format, dir).await
+}
+
+fn untar(reader: impl Read, dir: &Path) -> Result<u64> {
+    let mut count = 0;
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        // 路径跳出 dir 时返回 false
+        if entry.unpack_in(dir)? && entry.header().entry_type().is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn unzip(mut reader: impl Read, dir: &Path) -> Result<u64> {
+    let mut count = 0;
+    while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut reader).map_err(invalid)? {
+        // 丢弃 file 时会读完剩余的数据
+        let Some(path) = file.enclosed_name().map(|name| dir.join(name)) else {
+            continue;
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut file, &mut fs::File::create(&path)?)?;
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Extract(e.to_string())
+}
@@ -0,0 +1,68 @@
+use reqwest::{header, header::HeaderMap, Url};
+
+/// 无法得到文件名时使用
+const FALLBACK: &str = "download";
+
+/// 从 Content-Disposition 或最终 URL 的路径推导文件名 优先使用 Content-Disposition
+pub(crate) fn resolve(headers: &HeaderMap, url: &Url) -> String {
+    let name = headers
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|value| content_disposition(value.to_str().ok()?))
+        .or_else(|| percent_decode(url.path_segments()?.next_back()?));
+    name.map(|name| sanitize(&name)).unwrap_or_else(|| FALLBACK.to_string())
+}
+
+/// 解析 `filename*=UTF-8''...` 或 `filename="..."` 前者优先
+fn content_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    for param in value.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                let (charset, value) = value.trim().split_once('\'')?;
+                let (_, value) = value.split_once('\'')?;
+                if charset.eq_ignore_ascii_case("utf-8") {
+                    return percent_decode(value);
+                }
+            }
+            "filename" => plain = Some(value.trim().trim_matches('"').replace("\\\"", "\"")),
+            _ => {}
+        }
+    }
+    plain
+}
+
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// 只保留最后一段路径 替换控制字符和 Windows 不允许的字符 去掉首尾的空白和点
+pub(crate) fn sanitize(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_matches(|c: char| c.is_whitespace() || c == '.');
+    match name.is_empty() {
+        true => FALLBACK.to_string(),
+        false => name.to_string(),
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    fmt,
+    io::ErrorKind,
+    net::SocketAddr,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, Future, StreamExt};
+use reqwest::Url;
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::{
+    filename::percent_decode, tls::TlsConfig, transport::Transport, DownloadError, Result,
+};
+
+/// 每次读取的最大字节数
+const CHUNK: usize = 64 * 1024;
+/// 响应的最大行数 防止服务器一直发送多行响应
+const MAX_LINES: usize = 1024;
+
+/// FTP 和 FTPS 的 [`Transport`] 交给 `TransportDownloader` 下载
+///
+/// ```ignore
+/// let ftp = FtpTransport::new("ftp://ftp.example.com/pub/firmware.bin")?;
+/// let builder = DownloadBuilder::new("firmware.bin").hash(hash);
+/// TransportDownloader::new(ftp, builder).download().await?;
+/// ```
+///
+/// 使用被动模式和二进制传输 每次读取建立新的控制连接 通过 `REST` 从 `Metadata::offset` 续传
+///
+/// 服务器不支持 `REST` 时中断后从头下载 `MDTM` 返回的修改时间变化时丢弃已下载的进度
+#[derive(Clone)]
+pub struct FtpTransport {
+    host:     String,
+    port:     u16,
+    path:     String,
+    user:     String,
+    password: String,
+    security: Security,
+    timeout:  Duration,
+    /// `open` 时获取的大小 修改时间和是否支持 `REST`
+    probe:    Arc<Mutex<(Option<u64>, Option<String>)>>,
+    resume:   Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+enum Security {
+    Plain,
+    /// 连接后发送 `AUTH TLS` 升级
+    Explicit(TlsConnector),
+    /// 连接后直接握手 默认端口 990
+    Implicit(TlsConnector),
+}
+
+impl FtpTransport {
+    /// 解析 `ftp://` `ftps://` (隐式 TLS) 或 `ftpes://` (显式 TLS) 地址
+    ///
+    /// 地址中没有用户名时匿名登录 TLS 只使用系统的根证书 需要其他选项时使用 `explicit_tls`
+    pub fn new(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url).map_err(|_| DownloadError::InvalidUrl(url.to_string()))?;
+        let security = match parsed.scheme() {
+            "ftp" => Security::Plain,
+            "ftpes" => Security::Explicit(connector(&TlsConfig::default())?),
+            "ftps" => Security::Implicit(connector(&TlsConfig::default())?),
+            _ => return Err(DownloadError::InvalidUrl(url.to_string())),
+        };
+        let default_port = match security {
+            Security::Implicit(_) => 990,
+            _ => 21,
+        };
+        let invalid = || DownloadError::InvalidUrl(url.to_string());
+        let decode = |s: &str| percent_decode(s).filter(|s| !splits(s)).ok_or_else(invalid);
+        let (user, password) = match parsed.username() {
+            "" => ("anonymous".to_string(), "anonymous@".to_string()),
+            user => (decode(user)?, decode(parsed.password().unwrap_or_default())?),
+        };
+        Ok(Self {
+            host: parsed.host_str().ok_or_else(invalid)?.to_string(),
+            port: parsed.port().unwrap_or(default_port),
+            path: decode(parsed.path())?,
+            user,
+            password,
+            security,
+            timeout: Duration::from_secs(60),
+            probe: Arc::default(),
+            resume: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// 用户名和密码 替换地址中的用户名 包含 CR LF 或 NUL 时返回 `DownloadError::InvalidUrl`
+    pub fn login(mut self, user: impl Into<String>, password: impl Into<String>) -> Result<Self> {
+        let (user, password) = (user.into(), password.into());
+        if splits(&user) || splits(&password) {
+            let url = format!("ftp://{}{}", self.host, self.path);
+            return Err(DownloadError::InvalidUrl(url));
+        }
+        self.user = user;
+        self.password = password;
+        Ok(self)
+    }
+
+    /// 连接后发送 `AUTH TLS` 升级为 TLS 数据连接同样加密
+    pub fn explicit_tls(mut self, tls: &TlsConfig) -> Result<Self> {
+        self.security = Security::Explicit(connector(tls)?);
+        Ok(self)
+    }
+
+    /// 连接后直接进行 TLS 握手 不会修改端口
+    pub fn implicit_tls(mut self, tls: &TlsConfig) -> Result<Self> {
+        self.security = Security::Implicit(connector(tls)?);
+        Ok(self)
+    }
+
+    /// 建立连接 等待响应或单次读取数据的超时 默认 60 秒 超时返回 `DownloadError::Stalled`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 建立控制连接并登录 切换为二进制传输
+    async fn connect(&self) -> Result<Control> {
+        let stream = self.within(TcpStream::connect((self.host.as_str(), self.port))).await??;
+        let peer = stream.peer_addr()?;
+        let mut control = Control {
+            stream:  BufReader::new(Box::new(stream)),
+            peer,
+            timeout: self.timeout,
+            tls:     None,
+        };
+        match &self.security {
+            Security::Plain => control.expect("", 2).await?,
+            Security::Explicit(tls) => {
+                control.expect("", 2).await?;
+                control.expect("AUTH TLS", 2).await?;
+                control.upgrade(tls.clone(), &self.host).await?;
+            }
+            Security::Implicit(tls) => {
+                control.upgrade(tls.clone(), &self.host).await?;
+                control.expect("", 2).await?;
+            }
+        }
+
+        let (code, message) = control.command(&format!("USER {}", self.user)).await?;
+        match code {
+            230 => {}
+            331 => control.expect(&format!("PASS {}", self.password), 2).await?,
+            _ => return Err(DownloadError::Ftp { code, message }),
+        }
+        if control.tls.is_some() {
+            control.expect("PBSZ 0", 2).await?;
+            control.expect("PROT P", 2).await?;
+        }
+        control.expect("TYPE I", 2).await?;
+        Ok(control)
+    }
+
+    async fn within<F: Future>(&self, future: F) -> Result<F::Output> {
+        within(self.timeout, future).await
+    }
+}
+
+impl Transport for FtpTransport {
+    /// 获取大小和修改时间 检查是否支持 `REST`
+    fn open(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut control = self.connect().await?;
+            let size = match control.command(&format!("SIZE {}", self.path)).await? {
+                (213, size) => size.trim().parse().ok(),
+                _ => None,
+            };
+            let modified = match control.command(&format!("MDTM {}", self.path)).await? {
+                (213, modified) => Some(modified.trim().to_string()),
+                _ => None,
+            };
+            let (code, _) = control.command("REST 0").await?;
+            self.resume.store(code == 350, Ordering::Relaxed);
+            *self.probe.lock().unwrap() = (size, modified);
+            control.quit().await;
+            Ok(())
+        })
+    }
+
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(self.probe.lock().unwrap().0) })
+    }
+
+    /// 从 `REST` 的位置开始 `RETR` 读到 end 后直接断开
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let mut control = self.connect().await?;
+            let addr = control.passive().await?;
+            if range.start > 0 {
+                control.expect(&format!("REST {}", range.start), 3).await?;
+            }
+            let data = self.within(TcpStream::connect(addr)).await??;
+            control.expect(&format!("RETR {}", self.path), 1).await?;
+            let data: Box<dyn Io> = match control.tls.clone() {
+                Some(tls) => {
+                    let name = server_name(&self.host)?;
+                    Box::new(self.within(tls.connect(name, data)).await??)
+                }
+                None => Box::new(data),
+            };
+
+            let retr = Retr { control, data, remain: range.end - range.start };
+            let stream = futures::stream::try_unfold(retr, |mut retr| async move {
+                let chunk = retr.next().await?;
+                Ok(chunk.map(|chunk| (chunk, retr)))
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    fn supports_resume(&self) -> bool {
+        self.resume.load(Ordering::Relaxed)
+    }
+
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.probe.lock().unwrap().1.clone()) })
+    }
+}
+
+impl fmt::Debug for FtpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let security = match self.security {
+            Security::Plain => "plain",
+            Security::Explicit(_) => "explicit",
+            Security::Implicit(_) => "implicit",
+        };
+        f.debug_struct("FtpTransport")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("path", &self.path)
+            .field("user", &self.user)
+            .field("security", &security)
+            .finish_non_exhaustive()
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// 控制连接
+struct Control {
+    stream:  BufReader<Box<dyn Io>>,
+    /// 被动模式的数据连接使用同一个地址
+    peer:    SocketAddr,
+    timeout: Duration,
+    /// 已升级为 TLS 时数据连接也使用 TLS
+    tls:     Option<TlsConnector>,
+}
+
+impl Control {
+    /// 发送命令并读取响应 cmd 为空时只读取响应
+    async fn command(&mut self, cmd: &str) -> Result<(u16, String)> {
+        if !cmd.is_empty() {
+            let stream = self.stream.get_mut();
+            within(self.timeout, stream.write_all(format!("{cmd}\r\n").as_bytes())).await??;
+        }
+        within(self.timeout, self.reply()).await?
+    }
+
+    /// 响应码不是 class 开头时返回 `DownloadError::Ftp`
+    async fn expect(&mut self, cmd: &str, class: u16) -> Result<()> {
+        match self.command(cmd).await? {
+            (code, _) if code / 100 == class => Ok(()),
+            (code, message) => Err(DownloadError::Ftp { code, message }),
+        }
+    }
+
+    /// 读取一个响应 多行响应以 `123-` 开始 `123 ` 结束
+    async fn reply(&mut self) -> Result<(u16, String)> {
+        let mut line = String::new();
+        let mut first = None;
+        for _ in 0..MAX_LINES {
+            line.clear();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(DownloadError::ConnectionClosed);
+            }
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let first = *first.get_or_insert(code);
+            if code.is_some() && code == first && line.as_bytes().get(3) != Some(&b'-') {
+                let message = line.get(4..).unwrap_or_default().trim_end().to_string();
+                return code.map(|code| (code, message)).ok_or(DownloadError::ConnectionClosed);
+            }
+        }
+        Err(DownloadError::Ftp { code: 0, message: "响应过长".to_string() })
+    }
+
+    /// 进入被动模式 优先 `EPSV` 只使用端口 地址与控制连接相同
+    async fn passive(&mut self) -> Result<SocketAddr> {
+        let port = match self.command("EPSV").await? {
+            (229, message) => message.split('|').nth(3).and_then(|port| port.parse().ok()),
+            _ => match self.command("PASV").await? {
+                (227, message) => {
+                    let start = message.find('(').map_or(0, |i| i + 1);
+                    let numbers: Option<Vec<u8>> = message[start..]
+                        .split(|c: char| !c.is_ascii_digit())
+                        .filter(|n| !n.is_empty())
+                        .map(|n| n.parse().ok())
+                        .collect();
+                    numbers
+                        .filter(|numbers| numbers.len() >= 6)
+                        .map(|numbers| u16::from_be_bytes([numbers[4], numbers[5]]))
+                }
+                (code, message) => return Err(DownloadError::Ftp { code, message }),
+            },
+        };
+        let port = port.ok_or(DownloadError::Ftp { code: 0, message: "无法解析被动模式端口".into() })?;
+        Ok(SocketAddr::new(self.peer.ip(), port))
+    }
+
+    /// 在当前连接上进行 TLS 握手
+    async fn upgrade(&mut self, tls: TlsConnector, host: &str) -> Result<()> {
+        let empty = BufReader::new(Box::new(tokio::io::empty()) as Box<dyn Io>);
+        let stream = std::mem::replace(&mut self.stream, empty).into_inner();
+        let stream = within(self.timeout, tls.connect(server_name(host)?, stream));
+        self.stream = BufReader::new(Box::new(stream.await??));
+        self.tls = Some(tls);
+        Ok(())
+    }
+
+    /// 尽量正常退出 忽略错误
+    async fn quit(mut self) {
+        let _ = self.command("QUIT").await;
+    }
+}
+
+/// 一次 `RETR` 的数据连接
+struct Retr {
+    control: Control,
+    data:    Box<dyn Io>,
+    remain:  u64,
+}
+
+impl Retr {
+    /// 读到区间结束或数据连接关闭 关闭时确认服务器已完整发送
+    async fn next(&mut self) -> Result<Option<Bytes>> {
+        if self.remain == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; CHUNK.min(self.remain.try_into().unwrap_or(CHUNK))];
+        let n = match within(self.control.timeout, self.data.read(&mut buf)).await? {
+            // 部分服务器关闭 TLS 数据连接时不发送 close_notify 以控制连接的响应为准
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && self.control.tls.is_some() => 0,
+            result => result?,
+        };
+        if n == 0 {
+            self.remain = 0;
+            return match self.control.command("").await? {
+                (code, _) if code / 100 == 2 => Ok(None),
+                (code, message) => Err(DownloadError::Ftp { code, message }),
+            };
+        }
+        buf.truncate(n);
+        self.remain -= n as u64;
+        Ok(Some(Bytes::from(buf)))
+    }
+}
+
+/// 超时返回 `DownloadError::Stalled`
+async fn within<F: Future>(timeout: Duration, future: F) -> Result<F::Output> {
+    tokio::time::timeout(timeout, future).await.map_err(|_| DownloadError::Stalled)
+}
+
+/// 包含会拆分控制连接上的命令的 CR LF 或 NUL
+fn splits(s: &str) -> bool {
+    s.contains(['\r', '\n', '\0'])
+}
+
+fn connector(tls: &TlsConfig) -> Result<TlsConnector> {
+    let mut config = tls.build()?;
+    config.alpn_protocols.clear();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+fn server_name(host: &str) -> Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string()).map_err(|e| DownloadError::Tls(e.to_string()))
+}
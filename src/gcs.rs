@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use reqwest::{header::HeaderMap, Method};
+
+use crate::{
+    auth::Auth,
+    cloud::{base64_hex, uri_encode},
+    hash::Algorithm,
+    http::HttpDownloader,
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// Google Cloud Storage 中的对象 通过 XML API 带 Range 下载
+///
+/// 认证使用 OAuth 2.0 的 access token 即 `Auth::bearer` 或 `Auth::provider`
+/// 公开的对象不需要认证
+#[derive(Debug, Clone)]
+pub struct GcsObject {
+    bucket:     String,
+    object:     String,
+    endpoint:   String,
+    generation: Option<u64>,
+}
+
+impl GcsObject {
+    pub fn new(bucket: impl Into<String>, object: impl Into<String>) -> Self {
+        Self {
+            bucket:     bucket.into(),
+            object:     object.into(),
+            endpoint:   "https://storage.googleapis.com".to_string(),
+            generation: None,
+        }
+    }
+
+    /// 服务地址 例如模拟器的 `http://localhost:4443`
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// 下载指定版本
+    pub fn generation(mut self, generation: u64) -> Self {
+        self.generation = Some(generation);
+        self
+    }
+
+    /// 对象的地址
+    pub fn url(&self) -> String {
+        let object = self.object.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let mut url = format!("{}/{}/{object}", self.endpoint, uri_encode(&self.bucket));
+        if let Some(generation) = self.generation {
+            url.push_str(&format!("?generation={generation}"));
+        }
+        url
+    }
+
+    /// 下载到 path 的下载器
+    ///
+    /// 先请求对象的元数据 优先使用 MD5 组合对象没有 MD5 时使用 CRC32C
+    pub async fn downloader(
+        &self,
+        path: impl AsRef<Path>,
+        auth: Option<Auth>,
+    ) -> Result<HttpDownloader> {
+        let mut http = self.downloader_with_hash(path, auth, "");
+        let response = http.send(Method::HEAD, &http.urls[0], &[]).await?.error_for_status()?;
+        let (algorithm, hash) = checksum(response.headers()).ok_or(DownloadError::NoChecksum)?;
+        http.builder = http.builder.hash(hash);
+        Ok(http.algorithm(algorithm))
+    }
+
+    /// 使用已知 hash 的下载器 默认按 SHA-256 校验
+    pub fn downloader_with_hash(
+        &self,
+        path: impl AsRef<Path>,
+        auth: Option<Auth>,
+        hash: impl Into<String>,
+    ) -> HttpDownloader {
+        let http = HttpDownloader::with_builder(self.url(), DownloadBuilder::new(path).hash(hash));
+        match auth {
+            Some(auth) => http.auth(auth),
+            None => http,
+        }
+    }
+}
+
+/// `x-goog-hash: crc32c=n8HvTQ==,md5=...` 可能有多个同名的请求头
+fn checksum(headers: &HeaderMap) -> Option<(Algorithm, String)> {
+    let (mut md5, mut crc32c) = (None, None);
+    let values = headers.get_all("x-goog-hash").iter().filter_map(|value| value.to_str().ok());
+    for (name, value) in values.flat_map(|value| value.split(',')).filter_map(|v| v.split_once('='))
+    {
+        match name.trim() {
+            "md5" => md5 = base64_hex(value),
+            "crc32c" => crc32c = base64_hex(value),
+            _ => {}
+        }
+    }
+    md5.map(|hash| (Algorithm::Md5, hash)).or(crc32c.map(|hash| (Algorithm::Crc32c, hash)))
+}
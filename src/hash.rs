@@ -0,0 +1,316 @@
+use std::{
+    fmt::Write,
+    io::{Read, SeekFrom::Start},
+    str::FromStr,
+};
+
+use sha2::{digest::common::hazmat::SerializableState, Digest};
+use tokio::{fs::File, io::AsyncSeekExt};
+
+use crate::{progress::Verifier, DownloadError, Result};
+
+/// 分块读取大小
+const CHUNK: usize = 1024 * 1024;
+
+/// 内置的 hash 算法 序列化为 `name` 返回的名称
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Algorithm {
+    Sha256,
+    Sha1,
+    Blake3,
+    /// 只用于兼容旧的校验文件 不要用于安全校验
+    Md5,
+    /// 只能发现传输错误 用于对象存储提供的 CRC32C
+    Crc32c,
+    /// Git 对象的 SHA-1 即 `blob <大小>\0` 加上内容 用于 Git 仓库中的普通文件
+    GitSha1,
+}
+
+impl Algorithm {
+    /// 算法名称 用于写入元数据
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha1 => "sha1",
+            Self::Blake3 => "blake3",
+            Self::Md5 => "md5",
+            Self::Crc32c => "crc32c",
+            Self::GitSha1 => "git-sha1",
+        }
+    }
+
+    /// 是否支持持久化增量 hash 状态 Git SHA-1 需要先知道大小
+    pub fn resumable(self) -> bool {
+        !matches!(self, Self::Blake3 | Self::GitSha1)
+    }
+
+    /// 在阻塞线程池中从头分块读取文件计算 hash 返回小写十六进制
+    ///
+    /// `Downloading::complete` 调用 verify 前已截去元数据 不会读到尾部的元数据
+    ///
+    /// 开启 `rayon` feature 时 BLAKE3 在 rayon 线程池中并行计算
+    pub async fn hash(self, file: &mut File) -> Result<String> {
+        self.hash_reporting(file, None).await
+    }
+
+    /// 同 `hash` 每读取一块通过 verifier 报告进度
+    pub(crate) async fn hash_reporting(
+        self,
+        file: &mut File,
+        mut verifier: Option<Verifier>,
+    ) -> Result<String> {
+        file.seek(Start(0)).await?;
+        let mut file = file.try_clone().await?.into_std().await;
+        let task = tokio::task::spawn_blocking(move || -> Result<String> {
+            let mut hasher = Hasher::new(self);
+            if self == Self::GitSha1 {
+                hasher.update(format!("blob {}\0", file.metadata()?.len()).as_bytes());
+            }
+            // 每块足够大时并行计算才有收益
+            let parallel = cfg!(feature = "rayon") && self == Self::Blake3;
+            let chunk = if parallel { CHUNK * 16 } else { CHUNK };
+            let (mut buf, mut verified) = (vec![0; chunk], 0);
+            loop {
+                let n = read_full(&mut file, &mut buf)?;
+                if n == 0 {
+                    return Ok(hasher.finalize());
+                }
+                hasher.update_parallel(&buf[..n]);
+                verified += n as u64;
+                if let Some(verifier) = &mut verifier {
+                    verifier.update(verified);
+                }
+            }
+        });
+        task.await?
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = DownloadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "sha1" => Ok(Self::Sha1),
+            "blake3" => Ok(Self::Blake3),
+            "md5" => Ok(Self::Md5),
+            "crc32c" => Ok(Self::Crc32c),
+            "git-sha1" => Ok(Self::GitSha1),
+            _ => Err(DownloadError::UnsupportedAlgorithm(s.to_string())),
+        }
+    }
+}
+
+/// 计算 SHA-256 可直接传给 `Downloading::complete`
+pub async fn sha256(file: &mut File) -> Result<String> {
+    Algorithm::Sha256.hash(file).await
+}
+
+/// 计算 SHA-1 可直接传给 `Downloading::complete`
+pub async fn sha1(file: &mut File) -> Result<String> {
+    Algorithm::Sha1.hash(file).await
+}
+
+/// 计算 BLAKE3 可直接传给 `Downloading::complete`
+pub async fn blake3(file: &mut File) -> Result<String> {
+    Algorithm::Blake3.hash(file).await
+}
+
+/// 计算 MD5 可直接传给 `Downloading::complete`
+pub async fn md5(file: &mut File) -> Result<String> {
+    Algorithm::Md5.hash(file).await
+}
+
+/// 计算 CRC32C 可直接传给 `Downloading::complete`
+pub async fn crc32c(file: &mut File) -> Result<String> {
+    Algorithm::Crc32c.hash(file).await
+}
+
+/// 可持久化的增量 hash 状态
+///
+/// 随写入推进 序列化后保存在元数据中 续传时从中断处继续计算
+///
+/// serde 序列化为算法和 `to_bytes` 的内容
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "StateRepr", try_from = "StateRepr")
+)]
+pub struct State(Hasher);
+
+/// 序列化时的 `State`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StateRepr {
+    algorithm: Algorithm,
+    bytes:     Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl From<State> for StateRepr {
+    fn from(state: State) -> Self {
+        Self { algorithm: state.algorithm(), bytes: state.to_bytes() }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<StateRepr> for State {
+    type Error = DownloadError;
+
+    fn try_from(state: StateRepr) -> Result<Self> {
+        Self::from_bytes(state.algorithm, &state.bytes)
+    }
+}
+
+impl State {
+    pub fn new(algorithm: Algorithm) -> Result<Self> {
+        if !algorithm.resumable() {
+            return Err(DownloadError::NotResumable(algorithm));
+        }
+        Ok(Self(Hasher::new(algorithm)))
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self.0 {
+            Hasher::Sha256(_) => Algorithm::Sha256,
+            Hasher::Sha1(_) => Algorithm::Sha1,
+            Hasher::Blake3(_) => Algorithm::Blake3,
+            Hasher::Md5(_) => Algorithm::Md5,
+            Hasher::Crc32c(_) => Algorithm::Crc32c,
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf)
+    }
+
+    /// 返回小写十六进制 hash
+    pub fn finalize(self) -> String {
+        self.0.finalize()
+    }
+
+    /// 序列化内部状态
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.0 {
+            Hasher::Sha256(hasher) => hasher.serialize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.serialize().to_vec(),
+            Hasher::Md5(hasher) => hasher.serialize().to_vec(),
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            Hasher::Blake3(_) => unreachable!("blake3 不支持持久化"),
+        }
+    }
+
+    /// 从序列化的内部状态恢复
+    pub fn from_bytes(algorithm: Algorithm, bytes: &[u8]) -> Result<Self> {
+        let hasher = match algorithm {
+            Algorithm::Sha256 => bytes
+                .try_into()
+                .ok()
+                .and_then(|state| sha2::Sha256::deserialize(state).ok())
+                .map(Hasher::Sha256),
+            Algorithm::Sha1 => bytes
+                .try_into()
+                .ok()
+                .and_then(|state| sha1::Sha1::deserialize(state).ok())
+                .map(Hasher::Sha1),
+            Algorithm::Md5 => bytes
+                .try_into()
+                .ok()
+                .and_then(|state| md5::Md5::deserialize(state).ok())
+                .map(Hasher::Md5),
+            Algorithm::Crc32c => bytes.try_into().ok().map(u32::from_be_bytes).map(Hasher::Crc32c),
+            Algorithm::Blake3 | Algorithm::GitSha1 => None,
+        };
+        hasher.map(Self).ok_or(DownloadError::MetadataCorrupt)
+    }
+}
+
+/// 增量 hash 计算
+#[derive(Debug, Clone)]
+pub(crate) enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Blake3(Box<blake3::Hasher>),
+    Md5(md5::Md5),
+    Crc32c(u32),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            Algorithm::Sha1 | Algorithm::GitSha1 => Self::Sha1(sha1::Sha1::new()),
+            Algorithm::Blake3 => Self::Blake3(Box::default()),
+            Algorithm::Md5 => Self::Md5(md5::Md5::new()),
+            Algorithm::Crc32c => Self::Crc32c(0),
+        }
+    }
+
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(buf),
+            Self::Sha1(hasher) => hasher.update(buf),
+            Self::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+            Self::Md5(hasher) => hasher.update(buf),
+            Self::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, buf),
+        }
+    }
+
+    /// 同 `update` 开启 `rayon` feature 时 BLAKE3 并行计算
+    fn update_parallel(&mut self, buf: &[u8]) {
+        match self {
+            #[cfg(feature = "rayon")]
+            Self::Blake3(hasher) => {
+                hasher.update_rayon(buf);
+            }
+            _ => self.update(buf),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> String {
+        match self {
+            Self::Sha256(hasher) => hex(&hasher.finalize()),
+            Self::Sha1(hasher) => hex(&hasher.finalize()),
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Md5(hasher) => hex(&hasher.finalize()),
+            Self::Crc32c(crc) => hex(&crc.to_be_bytes()),
+        }
+    }
+}
+
+/// 读满 buf 或读到文件末尾 返回读取的字节数
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
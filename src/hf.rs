@@ -0,0 +1,179 @@
+use std::{fmt::Display, path::Path};
+
+use reqwest::{
+    header::{self, HeaderMap},
+    Client, Url,
+};
+
+use crate::{auth::Auth, hash::Algorithm, http::HttpDownloader, DownloadError, Result};
+
+/// 默认的服务地址 可以用环境变量 `HF_ENDPOINT` 替换
+const ENDPOINT: &str = "https://huggingface.co";
+/// 跟随改名后的仓库的重定向的最多次数
+const MAX_HOPS: usize = 5;
+
+/// 仓库的类型 决定地址的前缀
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepoType {
+    #[default]
+    Model,
+    Dataset,
+    Space,
+}
+
+/// Hugging Face Hub 仓库中的一个文件
+///
+/// ```ignore
+/// let file = HfFile::new("Qwen/Qwen2.5-7B", "model-00001-of-00004.safetensors");
+/// Segmented::new(file.downloader("model.safetensors").await?).connections(8).download().await?;
+/// ```
+///
+/// 下载前先解析 revision 对应的提交 之后固定使用该提交的地址 分支更新不会混入新的内容
+///
+/// LFS 文件按 SHA-256 校验 普通文件按 Git 对象的 SHA-1 校验
+#[derive(Debug, Clone)]
+pub struct HfFile {
+    endpoint:  String,
+    repo_type: RepoType,
+    repo:      String,
+    revision:  String,
+    filename:  String,
+    token:     Option<String>,
+}
+
+/// HEAD 请求获取到的文件信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HfFileInfo {
+    /// revision 对应的提交
+    pub commit:    String,
+    pub algorithm: Algorithm,
+    pub hash:      String,
+    pub size:      Option<u64>,
+}
+
+impl HfFile {
+    /// repo 为 `owner/name` filename 为仓库中的路径 revision 默认为 main
+    ///
+    /// 设置了环境变量 `HF_TOKEN` 时作为默认的 token
+    pub fn new(repo: impl Into<String>, filename: impl Into<String>) -> Self {
+        let endpoint = std::env::var("HF_ENDPOINT").unwrap_or_else(|_| ENDPOINT.to_string());
+        Self {
+            endpoint:  endpoint.trim_end_matches('/').to_string(),
+            repo_type: RepoType::Model,
+            repo:      repo.into(),
+            revision:  "main".to_string(),
+            filename:  filename.into(),
+            token:     std::env::var("HF_TOKEN").ok().filter(|token| !token.is_empty()),
+        }
+    }
+
+    pub fn repo_type(mut self, repo_type: RepoType) -> Self {
+        self.repo_type = repo_type;
+        self
+    }
+
+    /// 分支 标签 提交或 `refs/pr/1`
+    pub fn revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = revision.into();
+        self
+    }
+
+    /// 访问令牌 私有和需要授权的仓库必须设置 重定向到 CDN 时不会发送
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// 替换服务地址 例如镜像站
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// revision 下文件的地址 `<endpoint>/<repo>/resolve/<revision>/<filename>`
+    pub fn url(&self, revision: &str) -> Result<String> {
+        let mut url = Url::parse(&self.endpoint).map_err(invalid_url)?;
+        let prefix = match self.repo_type {
+            RepoType::Model => None,
+            RepoType::Dataset => Some("datasets"),
+            RepoType::Space => Some("spaces"),
+        };
+        url.path_segments_mut()
+            .map_err(|_| invalid_url(&self.endpoint))?
+            .pop_if_empty()
+            .extend(prefix)
+            .extend(self.repo.split('/'))
+            .push("resolve")
+            // revision 中的 `/` 需要转义
+            .push(revision)
+            .extend(self.filename.split('/'));
+        Ok(url.into())
+    }
+
+    /// 解析 revision 对应的提交和文件的 hash 不跟随到 CDN 的重定向
+    pub async fn info(&self) -> Result<HfFileInfo> {
+        let client = Client::builder().redirect(reqwest::redirect::Policy::none()).build()?;
+        let mut url = Url::parse(&self.url(&self.revision)?).map_err(invalid_url)?;
+        for _ in 0..MAX_HOPS {
+            // 压缩后的 Content-Length 不是文件大小
+            let mut request = client.head(url.clone()).header(header::ACCEPT_ENCODING, "identity");
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await?;
+            let headers = response.headers();
+            if let Some(code) = text(headers, "x-error-code") {
+                let message = text(headers, "x-error-message").unwrap_or_default();
+                return Err(invalid(format!("{code} {message}")));
+            }
+            // 改名后的仓库重定向到相对地址 LFS 文件重定向到 CDN 的绝对地址
+            let location = text(headers, header::LOCATION.as_str());
+            if let Some(location) = location.filter(|_| response.status().is_redirection()) {
+                if Url::parse(location).is_err() {
+                    url = url.join(location).map_err(invalid_url)?;
+                    continue;
+                }
+            }
+            if !response.status().is_redirection() {
+                response.error_for_status_ref()?;
+            }
+
+            let etag = text(headers, "x-linked-etag").or_else(|| text(headers, "etag"));
+            let etag = etag.map(|etag| etag.trim_start_matches("W/").trim_matches('"'));
+            let etag = etag.ok_or(DownloadError::NoChecksum)?.to_ascii_lowercase();
+            let algorithm = match etag.len() {
+                64 => Algorithm::Sha256,
+                40 => Algorithm::GitSha1,
+                _ => return Err(DownloadError::NoChecksum),
+            };
+            let size = text(headers, "x-linked-size").or_else(|| text(headers, "content-length"));
+            let commit = text(headers, "x-repo-commit").unwrap_or(&self.revision).to_string();
+            let size = size.and_then(|size| size.parse().ok());
+            return Ok(HfFileInfo { commit, algorithm, hash: etag, size });
+        }
+        Err(DownloadError::TooManyRedirects)
+    }
+
+    /// 下载到 path 的下载器 使用解析出的提交的地址和 hash
+    pub async fn downloader(&self, path: impl AsRef<Path>) -> Result<HttpDownloader> {
+        let info = self.info().await?;
+        let http = HttpDownloader::new(self.url(&info.commit)?, path, info.hash);
+        let http = http.algorithm(info.algorithm);
+        match &self.token {
+            Some(token) => Ok(http.auth(Auth::bearer(token))),
+            None => Ok(http),
+        }
+    }
+}
+
+fn text<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::HuggingFace(e.to_string())
+}
+
+fn invalid_url(e: impl Display) -> DownloadError {
+    DownloadError::InvalidUrl(e.to_string())
+}
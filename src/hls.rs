@@ -0,0 +1,430 @@
+use std::{
+    future::Future,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{header, Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{
+    hash::hex, hosts::HostLimits, http::HttpDownloader, limit::RateLimiter, manager::Observer,
+    proxy::ProxyConfig, scheduler, BufferPool, CancellationToken, DownloadError, Downloading,
+    Result,
+};
+
+/// 主播放列表最多指向其他主播放列表的次数 防止互相指向时一直请求
+const MAX_HOPS: usize = 8;
+
+/// m3u8 播放列表
+#[derive(Debug, Clone, PartialEq)]
+pub enum Playlist {
+    /// 多码率的主播放列表
+    Master(Vec<Variant>),
+    Media(MediaPlaylist),
+}
+
+/// 主播放列表中的一个码率
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub url:        String,
+    /// 每秒比特数
+    pub bandwidth:  u64,
+    /// 例如 `1920x1080`
+    pub resolution: Option<String>,
+}
+
+/// 媒体播放列表
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaPlaylist {
+    /// `#EXT-X-MAP` 的初始化分片在它之后的分片之前
+    pub segments: Vec<Segment>,
+    /// 有 `#EXT-X-ENDLIST` 直播的播放列表没有 只下载当前列出的分片
+    pub ended:    bool,
+}
+
+/// 一个分片 拼接后得到完整的文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub url:      String,
+    /// 秒 初始化分片为 0
+    pub duration: f64,
+    /// `#EXT-X-BYTERANGE` 指定的区间
+    pub range:    Option<Range<u64>>,
+}
+
+impl Playlist {
+    /// 解析 m3u8 相对地址按 base 解析 加密的分片不支持
+    pub fn parse(text: &str, base: &Url) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(invalid("缺少 #EXTM3U"));
+        }
+        let join = |uri: &str| base.join(uri).map(String::from).map_err(invalid);
+
+        let (mut variants, mut segments, mut ended) = (vec![], vec![], false);
+        let (mut stream, mut duration, mut range) = (None, 0.0, None);
+        // 不带 @ 的 BYTERANGE 接着同一地址上一个区间的结束位置
+        let mut last: Option<(String, u64)> = None;
+        for line in lines {
+            let Some(tag) = line.strip_prefix('#') else {
+                let url = join(line)?;
+                match stream.take() {
+                    Some((bandwidth, resolution)) => {
+                        variants.push(Variant { url, bandwidth, resolution })
+                    }
+                    None => {
+                        let range = byterange(range.take(), &url, &last)?;
+                        if let Some(range) = &range {
+                            last = Some((url.clone(), range.end));
+                        }
+                        segments.push(Segment { url, duration, range });
+                        duration = 0.0;
+                    }
+                }
+                continue;
+            };
+            let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+            match name {
+                "EXT-X-STREAM-INF" => {
+                    let attrs = attributes(value);
+                    let get = |key| attrs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+                    let bandwidth = get("BANDWIDTH").and_then(|v| v.parse().ok()).unwrap_or(0);
+                    stream = Some((bandwidth, get("RESOLUTION").map(str::to_string)));
+                }
+                "EXTINF" => {
+                    let value = value.split(',').next().unwrap_or_default();
+                    duration = value.trim().parse().map_err(invalid)?;
+                }
+                "EXT-X-BYTERANGE" => range = Some(value.to_string()),
+                "EXT-X-KEY" => {
+                    let attrs = attributes(value);
+                    let method = attrs.iter().find(|(k, _)| *k == "METHOD").map(|(_, v)| *v);
+                    if method.is_some_and(|method| method != "NONE") {
+                        return Err(invalid(format!("不支持加密的分片 {value}")));
+                    }
+                }
+                "EXT-X-MAP" => {
+                    let attrs = attributes(value);
+                    let get = |key| attrs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+                    let url = join(get("URI").ok_or_else(|| invalid("EXT-X-MAP 缺少 URI"))?)?;
+                    let range = byterange(get("BYTERANGE").map(str::to_string), &url, &None)?;
+                    segments.push(Segment { url, duration: 0.0, range });
+                }
+                "EXT-X-ENDLIST" => ended = true,
+                _ => {}
+            }
+        }
+        match variants.is_empty() {
+            true => Ok(Self::Media(MediaPlaylist { segments, ended })),
+            false => Ok(Self::Master(variants)),
+        }
+    }
+}
+
+/// 下载 HLS 播放列表中的所有分片 按顺序拼接成一个文件
+///
+/// ```ignore
+/// let http = HttpDownloader::new(m3u8_url, "video.ts", "").retry(RetryPolicy::default());
+/// HlsDownloader::new(http).connections(4).download().await?;
+/// ```
+///
+/// 先用 BYTERANGE 或 HEAD 请求得到每个分片的大小 再通过 `Downloading::write_at` 写入各自的位置
+///
+/// 每个分片按 `HttpDownloader::retry` 的策略单独重试 中断后只重新下载没有写完的分片
+///
+/// 构建器没有 hash 时不校验 播放列表的分片变化时丢弃已下载的进度
+#[derive(Debug, Clone)]
+pub struct HlsDownloader {
+    http:          HttpDownloader,
+    connections:   usize,
+    max_bandwidth: Option<u64>,
+}
+
+/// 分片和它在文件中的位置
+struct Part {
+    segment: Segment,
+    offset:  u64,
+    size:    u64,
+}
+
+impl HlsDownloader {
+    /// `http` 的地址为播放列表 默认单连接
+    pub fn new(http: HttpDownloader) -> Self {
+        Self { http, connections: 1, max_bandwidth: None }
+    }
+
+    /// 同时下载的分片数
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// 主播放列表中选择不超过该码率的最高码率 都超过时选择最低的 默认选择最高码率
+    pub fn max_bandwidth(mut self, bandwidth: u64) -> Self {
+        self.max_bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// 代理 见 `HttpDownloader::proxy`
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.http = self.http.proxy(proxy);
+        self
+    }
+
+    pub(crate) fn default_proxy(&mut self, proxy: &ProxyConfig) {
+        self.http.default_proxy(proxy);
+    }
+
+    pub(crate) fn default_host_limits(&mut self, limits: &HostLimits) {
+        self.http.default_host_limits(limits);
+    }
+
+    pub(crate) fn add_limiter(&mut self, limiter: RateLimiter) {
+        self.http.builder.limiters.push(limiter);
+    }
+
+    pub(crate) fn default_buffer_pool(&mut self, pool: &BufferPool) {
+        self.http.builder.default_buffer_pool(pool);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Observer) {
+        self.http.builder.observer = Some(observer);
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.http.builder.path
+    }
+
+    /// 取消令牌 见 `DownloadBuilder::cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.http = self.http.cancel(token);
+        self
+    }
+
+    /// 获取媒体播放列表 主播放列表按码率选择一个
+    pub async fn playlist(&self) -> Result<MediaPlaylist> {
+        let mut url = self.http.urls[0].clone();
+        for _ in 0..MAX_HOPS {
+            let response = self.retry(|| self.http.send(Method::GET, &url, &[])).await?;
+            let base = response.url().clone();
+            let text = response.error_for_status()?.text().await?;
+            match Playlist::parse(&text, &base)? {
+                Playlist::Media(playlist) => return Ok(playlist),
+                Playlist::Master(variants) => {
+                    let max = self.max_bandwidth.unwrap_or(u64::MAX);
+                    let variant = variants.iter().filter(|v| v.bandwidth <= max);
+                    let variant = variant.max_by_key(|v| v.bandwidth);
+                    let variant = variant.or_else(|| variants.iter().min_by_key(|v| v.bandwidth));
+                    let next = variant.expect("至少有一个码率").url.clone();
+                    if next == url {
+                        return Err(invalid("主播放列表指向自身"));
+                    }
+                    url = next;
+                }
+            }
+        }
+        Err(invalid("主播放列表嵌套过多"))
+    }
+
+    /// 下载并拼接 中断后再次调用会从没有写完的分片继续 返回目标文件路径
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "download",
+            skip_all,
+            fields(url = %self.http.urls[0], path = %self.http.builder.path.display()),
+            err(level = "warn"),
+        )
+    )]
+    pub async fn download(&self) -> Result<PathBuf> {
+        let download = self.http.within_deadline(self.attempt());
+        #[cfg(feature = "metrics")]
+        let download = crate::telemetry::measure(download);
+        download.await
+    }
+
+    async fn attempt(&self) -> Result<PathBuf> {
+        self.merge(self.playlist().await?.segments).await
+    }
+
+    /// 下载分片并按顺序拼接到构建器的路径 DASH 也使用
+    pub(crate) async fn merge(&self, segments: Vec<Segment>) -> Result<PathBuf> {
+        let parts = self.layout(segments).await?;
+        let size = parts.last().map_or(0, |part| part.offset + part.size);
+        let mut downloading = self.http.builder.clone().size(size).open().await?;
+
+        let mut digest = Sha256::new();
+        for part in &parts {
+            digest.update(format!("{} {:?}\n", part.segment.url, part.segment.range));
+        }
+        let validator = Some(hex(&digest.finalize()));
+        let stored = &downloading.meta().validator;
+        if stored.is_some() && *stored != validator {
+            downloading.restart().await?;
+        }
+        downloading.set_validator(validator).await?;
+
+        // 没有写完的分片整个重新下载
+        let missing = downloading.meta().ranges.missing(size);
+        let overlaps = |part: &Part| {
+            let end = part.offset + part.size;
+            missing.iter().any(|range| range.start < end && part.offset < range.end)
+        };
+        let pending: Arc<Vec<_>> = Arc::new(parts.into_iter().filter(overlaps).collect());
+        let path = downloading.target().to_path_buf();
+        let downloading = Arc::new(Mutex::new(downloading));
+
+        let (next, mut workers) = (Arc::new(AtomicUsize::new(0)), JoinSet::new());
+        for _ in 0..self.connections.min(pending.len()) {
+            let worker = Worker {
+                hls:         self.clone(),
+                parts:       pending.clone(),
+                next:        next.clone(),
+                downloading: downloading.clone(),
+            };
+            workers.spawn(worker.run());
+        }
+        scheduler::join(workers, &downloading).await?;
+
+        let downloading = Arc::into_inner(downloading).expect("所有连接都已结束").into_inner();
+        downloading.complete_with(self.http.algorithm).await?;
+        self.http.builder.finish(path, self.http.algorithm, None).await
+    }
+
+    /// 得到每个分片的大小和位置 没有 BYTERANGE 时发送 HEAD 请求
+    async fn layout(&self, segments: Vec<Segment>) -> Result<Vec<Part>> {
+        let sizes: Vec<_> = segments.iter().map(|segment| self.size(segment)).collect();
+        let sizes = futures::stream::iter(sizes);
+        let sizes: Vec<u64> = sizes.buffered(self.connections.max(4)).try_collect().await?;
+
+        let mut offset = 0;
+        let parts = segments.into_iter().zip(sizes).map(|(segment, size)| {
+            offset += size;
+            Part { segment, offset: offset - size, size }
+        });
+        Ok(parts.collect())
+    }
+
+    async fn size(&self, segment: &Segment) -> Result<u64> {
+        if let Some(range) = &segment.range {
+            return Ok(range.end - range.start);
+        }
+        let response = self.retry(|| self.http.send(Method::HEAD, &segment.url, &[])).await?;
+        let length = response.error_for_status()?.headers().get(header::CONTENT_LENGTH).cloned();
+        let length = length.and_then(|len| len.to_str().ok()?.parse().ok());
+        length.ok_or(DownloadError::UnknownSize)
+    }
+
+    /// 下载一个分片写入 [offset, offset + size) 提前结束时返回 `DownloadError::ConnectionClosed`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "segment",
+            skip_all,
+            fields(url = %part.segment.url, offset = part.offset, bytes = part.size),
+            err(level = "debug"),
+        )
+    )]
+    async fn fetch(&self, part: &Part, downloading: &Mutex<Downloading>) -> Result<()> {
+        let segment = &part.segment;
+        let value = segment.range.as_ref().map(|r| format!("bytes={}-{}", r.start, r.end - 1));
+        let headers: Vec<_> = value.iter().map(|value| (header::RANGE, value.as_str())).collect();
+        let _permit = self.http.host_permit(&segment.url).await?;
+        let response = self.http.send(Method::GET, &segment.url, &headers).await?;
+        let mut response = response.error_for_status()?;
+        // 服务端忽略 Range 从头返回时跳过区间之前的部分
+        let mut skip = match (&segment.range, response.status()) {
+            (Some(range), status) if status != StatusCode::PARTIAL_CONTENT => range.start,
+            _ => 0,
+        };
+
+        let (mut pos, end) = (part.offset, part.offset + part.size);
+        while pos < end {
+            let chunk = self.http.chunk(&mut response).await?;
+            let chunk = chunk.ok_or(DownloadError::ConnectionClosed)?;
+            let n = skip.min(chunk.len() as u64) as usize;
+            skip -= n as u64;
+            let chunk = &chunk[n..chunk.len().min(n + (end - pos) as usize)];
+            if !chunk.is_empty() {
+                downloading.lock().await.write_at(pos, chunk).await?;
+                pos += chunk.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// 按 `HttpDownloader::retry` 的策略重试
+    async fn retry<T, F: Future<Output = Result<T>>>(&self, f: impl Fn() -> F) -> Result<T> {
+        let builder = &self.http.builder;
+        self.http.retry.run(builder.cancel.as_ref(), builder.observer.as_ref(), f).await
+    }
+}
+
+struct Worker {
+    hls:         HlsDownloader,
+    parts:       Arc<Vec<Part>>,
+    /// 下一个要下载的分片
+    next:        Arc<AtomicUsize>,
+    downloading: Arc<Mutex<Downloading>>,
+}
+
+impl Worker {
+    async fn run(self) -> Result<()> {
+        while let Some(part) = self.parts.get(self.next.fetch_add(1, Ordering::Relaxed)) {
+            self.hls.retry(|| self.hls.fetch(part, &self.downloading)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// `n[@o]` 没有 o 时接着同一地址上一个区间
+fn byterange(
+    value: Option<String>,
+    url: &str,
+    last: &Option<(String, u64)>,
+) -> Result<Option<Range<u64>>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let (len, start) = value.split_once('@').map_or((value.as_str(), None), |(n, o)| (n, Some(o)));
+    let len: u64 = len.trim().parse().map_err(invalid)?;
+    let start = match start {
+        Some(start) => start.trim().parse().map_err(invalid)?,
+        None => match last {
+            Some((last, end)) if last == url => *end,
+            _ => return Err(invalid(format!("BYTERANGE 缺少起始位置 {value}"))),
+        },
+    };
+    let end = start.checked_add(len).filter(|_| len > 0);
+    let end = end.ok_or_else(|| invalid(format!("无效的 BYTERANGE {value}")))?;
+    Ok(Some(start..end))
+}
+
+/// `KEY=VALUE,KEY="VALUE"` 引号中可以有逗号
+fn attributes(value: &str) -> Vec<(&str, &str)> {
+    let mut attrs = vec![];
+    let mut rest = value;
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, next.split_once(',').map_or("", |(_, next)| next))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        attrs.push((key.trim(), value));
+        rest = next;
+    }
+    attrs
+}
+
+fn invalid(e: impl std::fmt::Display) -> DownloadError {
+    DownloadError::Hls(e.to_string())
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use reqwest::Url;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::{cancellable, CancellationToken, Result};
+
+/// 按主机限制同时进行的连接数和请求的间隔 避免批量下载时被镜像站封禁
+///
+/// clone 出的限制共享计数 同一个限制交给多个下载或 `DownloadManager::host_limits` 即为全局限制
+///
+/// 主机按 `域名:端口` 区分 重定向到其他主机后仍计入原地址的主机
+#[derive(Debug, Clone)]
+pub struct HostLimits {
+    hosts:       Arc<Mutex<HashMap<String, Host>>>,
+    connections: usize,
+    /// 同一主机相邻两个请求开始的最小间隔
+    delay:       Duration,
+}
+
+#[derive(Debug)]
+struct Host {
+    connections: Arc<Semaphore>,
+    /// 下一个请求最早可以开始的时间
+    next:        Instant,
+}
+
+impl HostLimits {
+    /// 每个主机最多 connections 个连接
+    pub fn new(connections: usize) -> Self {
+        Self {
+            hosts:       Arc::default(),
+            connections: connections.max(1),
+            delay:       Duration::ZERO,
+        }
+    }
+
+    /// 同一主机的请求之间至少间隔 delay 默认不等待
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// 等待 url 所在主机的连接名额 传输结束后释放
+    pub(crate) async fn acquire(
+        &self,
+        url: &str,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(key) = key(url) else {
+            return Ok(None);
+        };
+        let connections = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let host = hosts.entry(key).or_insert_with(|| self.host());
+            host.connections.clone()
+        };
+        Ok(cancellable(cancel, connections.acquire_owned()).await?.ok())
+    }
+
+    /// 等到 url 所在主机可以发送下一个请求
+    pub(crate) async fn pace(&self, url: &str, cancel: Option<&CancellationToken>) -> Result<()> {
+        let Some(key) = key(url).filter(|_| !self.delay.is_zero()) else {
+            return Ok(());
+        };
+        let start = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let host = hosts.entry(key).or_insert_with(|| self.host());
+            let start = host.next.max(Instant::now());
+            host.next = start + self.delay;
+            start
+        };
+        cancellable(cancel, tokio::time::sleep_until(start)).await
+    }
+
+    fn host(&self) -> Host {
+        Host { connections: Arc::new(Semaphore::new(self.connections)), next: Instant::now() }
+    }
+}
+
+/// 没有主机的地址不限制
+fn key(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    Some(format!("{}:{}", url.host_str()?, url.port_or_known_default().unwrap_or_default()))
+}
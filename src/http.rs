@@ -0,0 +1,922 @@
+#[cfg(feature = "http3")]
+use std::sync::atomic::AtomicBool;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use reqwest::{
+    cookie::Jar,
+    header::{self, HeaderMap, HeaderName},
+    redirect, Client, Method, Response, StatusCode, Url,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[cfg(feature = "decompress")]
+use crate::decode::{self, Encoding};
+#[cfg(feature = "decrypt")]
+use crate::decrypt::{self, Decryption};
+use crate::{
+    auth::Auth,
+    cancellable,
+    checksums::{self, Checksums},
+    filename,
+    hash::Algorithm,
+    hosts::HostLimits,
+    limit::RateLimiter,
+    manager::TaskSpec,
+    post::PostProcess,
+    proxy::ProxyConfig,
+    redirect::RedirectPolicy,
+    retry::RetryPolicy,
+    signature::Signature,
+    tls::TlsConfig,
+    AmendPolicy, BufferPool, CancellationToken, DownloadBuilder, DownloadError, Downloading,
+    Metadata, Outboard, PersistPolicy, Result, SpaceCheck, SyncPolicy,
+};
+
+/// 基于 reqwest 的 HTTP 下载器
+///
+/// 先用 HEAD 获取文件大小 再根据 downloading 的进度发送 Range 请求续传
+#[derive(Debug, Clone)]
+pub struct HttpDownloader {
+    pub(crate) client:    Client,
+    /// 内置客户端的选项 使用自定义客户端时为 None
+    pub(crate) config:    Option<ClientConfig>,
+    /// 每个请求都带上的请求头
+    pub(crate) headers:   Vec<(HeaderName, String)>,
+    pub(crate) auth:      Option<Auth>,
+    /// 第一个为主地址 之后是镜像
+    pub(crate) urls:      Vec<String>,
+    pub(crate) builder:   DownloadBuilder,
+    pub(crate) algorithm: Algorithm,
+    pub(crate) retry:     RetryPolicy,
+    /// 超过该时间没有收到数据时重新连接
+    pub(crate) stall:     Option<Duration>,
+    /// 构建器中的路径为目录 文件名由响应推导
+    pub(crate) auto_name: bool,
+    pub(crate) redirect:  RedirectPolicy,
+    /// 整个下载 (包括重试) 的最长时间
+    pub(crate) deadline:  Option<Duration>,
+    /// 续传前校验已下载的部分
+    pub(crate) outboard:  Option<Outboard>,
+    /// HTTP/2 同时进行的流的上限 共享连接的下载器之间共享
+    pub(crate) streams:   Option<Arc<Semaphore>>,
+    /// 每个主机的连接数和请求间隔
+    pub(crate) hosts:     Option<HostLimits>,
+    /// 接受压缩的响应并边下载边解压
+    #[cfg(feature = "decompress")]
+    pub(crate) decode:    bool,
+    /// 请求使用 HTTP/3 QUIC 连接失败后改为 false
+    #[cfg(feature = "http3")]
+    pub(crate) http3:     Option<Arc<AtomicBool>>,
+    /// 明文 HTTP 的响应体 splice 到文件
+    #[cfg(feature = "splice")]
+    pub(crate) zero_copy: bool,
+}
+
+/// 默认的连接超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// 默认的读取超时
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 内置客户端的选项 修改后重新创建客户端
+#[derive(Debug, Clone)]
+pub(crate) struct ClientConfig {
+    cookies: Option<Arc<Jar>>,
+    /// 未设置时读取环境变量
+    proxy:   Option<ProxyConfig>,
+    /// 由 `TlsConfig` 创建的 rustls 配置
+    tls:     Option<Arc<rustls::ClientConfig>>,
+    connect: Option<Duration>,
+    read:    Option<Duration>,
+    /// 多个流共享一个连接时按吞吐调整流控窗口
+    http2:   bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            cookies: None,
+            proxy:   None,
+            tls:     None,
+            connect: Some(CONNECT_TIMEOUT),
+            read:    Some(READ_TIMEOUT),
+            http2:   false,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// 没有代理和 cookies 时可以自己建立连接 返回连接和读取的超时
+    ///
+    /// 读取代理环境变量时 设置了任何一个都视为有代理
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    pub(crate) fn direct(&self) -> Option<(Option<Duration>, Option<Duration>)> {
+        let proxied = match &self.proxy {
+            None | Some(ProxyConfig::Env) => ["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+                .iter()
+                .any(|key| std::env::var_os(key).is_some()),
+            Some(ProxyConfig::Direct) => false,
+            Some(ProxyConfig::Custom(_)) => true,
+        };
+        (!proxied && self.cookies.is_none()).then_some((self.connect, self.read))
+    }
+
+    /// 不自动跟随重定向 重定向由 `RedirectPolicy` 处理
+    fn build(&self) -> Client {
+        let mut builder = Client::builder().redirect(redirect::Policy::none());
+        if let Some(cookies) = &self.cookies {
+            builder = builder.cookie_provider(cookies.clone());
+        }
+        if let Some(timeout) = self.connect {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read {
+            builder = builder.read_timeout(timeout);
+        }
+        if self.http2 {
+            builder = builder.http2_adaptive_window(true);
+        }
+        if let Some(tls) = &self.tls {
+            builder = builder.tls_backend_preconfigured(rustls::ClientConfig::clone(tls));
+        }
+        match &self.proxy {
+            None | Some(ProxyConfig::Env) => {}
+            Some(ProxyConfig::Direct) => builder = builder.no_proxy(),
+            Some(ProxyConfig::Custom(proxies)) => {
+                builder = proxies.iter().fold(builder.no_proxy(), |b, p| b.proxy(p.clone()));
+            }
+        }
+        builder.build().expect("创建 HTTP 客户端失败")
+    }
+}
+
+/// HEAD 请求获取到的远程文件信息
+#[derive(Debug, Clone)]
+pub(crate) struct Probe {
+    /// 没有 Content-Length 时为 None
+    pub(crate) size:      Option<u64>,
+    /// 是否支持 Range 请求
+    pub(crate) ranges:    bool,
+    /// ETag 或 Last-Modified
+    pub(crate) validator: Option<String>,
+    /// 从 Content-Disposition 或重定向后的 URL 推导出的文件名
+    pub(crate) filename:  String,
+    /// 重定向后的最终地址
+    pub(crate) url:       String,
+    /// Last-Modified
+    pub(crate) modified:  Option<SystemTime>,
+}
+
+/// 下载完成的文件
+#[derive(Debug, Clone)]
+pub struct Downloaded {
+    pub path: PathBuf,
+    /// 重定向后的最终地址
+    pub url:  String,
+    pub size: u64,
+}
+
+impl HttpDownloader {
+    /// hash 默认按 SHA-256 校验
+    pub fn new(url: impl Into<String>, path: impl AsRef<Path>, hash: impl Into<String>) -> Self {
+        Self::with_builder(url, DownloadBuilder::new(path).hash(hash))
+    }
+
+    /// 使用构建器中的选项创建 downloading 文件 文件大小由 HEAD 请求获取
+    pub fn with_builder(url: impl Into<String>, builder: DownloadBuilder) -> Self {
+        let config = ClientConfig::default();
+        Self {
+            client:    config.build(),
+            config:    Some(config),
+            headers:   vec![],
+            auth:      None,
+            urls:      vec![url.into()],
+            builder,
+            algorithm: Algorithm::Sha256,
+            retry:     RetryPolicy::none(),
+            stall:     None,
+            auto_name: false,
+            redirect:  RedirectPolicy::default(),
+            deadline:  None,
+            outboard:  None,
+            streams:   None,
+            hosts:     None,
+            #[cfg(feature = "decompress")]
+            decode:    false,
+            #[cfg(feature = "http3")]
+            http3:     None,
+            #[cfg(feature = "splice")]
+            zero_copy: false,
+        }
+    }
+
+    /// 使用自定义的 reqwest 客户端 客户端自动跟随的重定向不受 `redirect` 控制
+    ///
+    /// 之后 `cookies` 等内置客户端的选项不再生效
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self.config = None;
+        self
+    }
+
+    /// 每个请求都带上的请求头 同名的请求头会被替换 例如 Referer
+    pub fn header(mut self, name: HeaderName, value: impl Into<String>) -> Self {
+        self.headers.retain(|(n, _)| *n != name);
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn user_agent(self, user_agent: impl Into<String>) -> Self {
+        self.header(header::USER_AGENT, user_agent)
+    }
+
+    /// 认证方式 见 `Auth`
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// 使用 cookie jar 保存和发送 cookie 可以预先放入登录后的 cookie
+    pub fn cookies(mut self, jar: Arc<Jar>) -> Self {
+        if let Some(config) = &mut self.config {
+            config.cookies = Some(jar);
+            self.client = config.build();
+        }
+        self
+    }
+
+    /// 代理 默认读取环境变量 见 `ProxyConfig`
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        if let Some(config) = &mut self.config {
+            config.proxy = Some(proxy);
+            self.client = config.build();
+        }
+        self
+    }
+
+    /// TLS 选项 见 `TlsConfig`
+    pub fn tls(mut self, tls: TlsConfig) -> Result<Self> {
+        if let Some(config) = &mut self.config {
+            config.tls = Some(Arc::new(tls.build()?));
+            self.client = config.build();
+        }
+        Ok(self)
+    }
+
+    /// 分段下载的各个区间作为同一个 HTTP/2 连接上的流 不再各自建立连接
+    ///
+    /// max_streams 为同时进行的流的上限 超出时区间等待其他流结束
+    ///
+    /// 需要服务端通过 ALPN 协商 HTTP/2 否则仍是 HTTP/1.1 的多个连接 只受 max_streams 限制
+    pub fn http2(mut self, max_streams: usize) -> Self {
+        if let Some(config) = &mut self.config {
+            config.http2 = true;
+            self.client = config.build();
+        }
+        self.streams = Some(Arc::new(Semaphore::new(max_streams.max(1))));
+        self
+    }
+
+    /// 通过 QUIC 使用 HTTP/3 丢包较多的网络上吞吐更稳定
+    ///
+    /// 服务端不支持或 UDP 被阻断导致请求失败时改用 TCP 之后的请求不再尝试
+    /// 没有收到任何 UDP 响应时要等 QUIC 的空闲超时 (30 秒) 后才会改用 TCP
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self) -> Self {
+        self.http3 = Some(Arc::new(AtomicBool::new(true)));
+        self
+    }
+
+    /// 与 other 使用同一个客户端 同一主机的多个文件复用连接 也共享 `http2` 的流的上限
+    ///
+    /// 之后修改 cookies 代理等客户端选项会创建新的客户端 不再共享
+    pub fn share_connections(mut self, other: &HttpDownloader) -> Self {
+        self.client = other.client.clone();
+        self.config = other.config.clone();
+        self.streams = other.streams.clone();
+        #[cfg(feature = "http3")]
+        {
+            self.http3 = other.http3.clone();
+        }
+        self
+    }
+
+    /// 等待可用的流 没有设置 `http2` 时不限制
+    pub(crate) async fn stream_permit(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(streams) = &self.streams else {
+            return Ok(None);
+        };
+        let permit = cancellable(self.builder.cancel.as_ref(), streams.clone().acquire_owned());
+        Ok(permit.await?.ok())
+    }
+
+    /// 按主机限制连接数和请求间隔 见 `HostLimits`
+    pub fn host_limits(mut self, limits: HostLimits) -> Self {
+        self.hosts = Some(limits);
+        self
+    }
+
+    /// 等待 url 所在主机的连接名额 没有设置 `host_limits` 时不限制
+    pub(crate) async fn host_permit(&self, url: &str) -> Result<Option<OwnedSemaphorePermit>> {
+        match &self.hosts {
+            Some(hosts) => hosts.acquire(url, self.builder.cancel.as_ref()).await,
+            None => Ok(None),
+        }
+    }
+
+    /// 管理器的主机限制 没有单独设置过时使用
+    pub(crate) fn default_host_limits(&mut self, limits: &HostLimits) {
+        self.hosts.get_or_insert_with(|| limits.clone());
+    }
+
+    /// 管理器保存和导出任务时的选项
+    pub(crate) fn task_spec(&self, connections: usize) -> TaskSpec {
+        let headers = self.headers.iter().map(|(name, value)| (name.to_string(), value.clone()));
+        TaskSpec {
+            urls: self.urls.clone(),
+            path: self.builder.path.clone(),
+            hash: self.builder.hash.clone(),
+            algorithm: self.algorithm,
+            headers: headers.collect(),
+            connections,
+            auto_name: self.auto_name,
+        }
+    }
+
+    /// 管理器的代理 没有单独设置过代理时使用
+    pub(crate) fn default_proxy(&mut self, proxy: &ProxyConfig) {
+        if let Some(config) = self.config.as_mut().filter(|config| config.proxy.is_none()) {
+            config.proxy = Some(proxy.clone());
+            self.client = config.build();
+        }
+    }
+
+    /// 校验使用的 hash 算法
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// BLAKE3 的 outboard 见 `Outboard` 同时使用 BLAKE3 校验
+    ///
+    /// 每次续传前先校验已下载的部分 丢弃损坏的部分后再继续 hash 需要是 BLAKE3
+    pub fn outboard(mut self, outboard: Outboard) -> Self {
+        self.outboard = Some(outboard);
+        self.algorithm = Algorithm::Blake3;
+        self
+    }
+
+    /// 从校验文件中查找 hash 和算法 见 `Checksums`
+    ///
+    /// 按构建器中的文件名查找 开启 `auto_filename` 时按地址中的文件名查找
+    pub fn checksum(mut self, checksums: &Checksums) -> Result<Self> {
+        let name = match self.auto_name {
+            true => Url::parse(&self.urls[0])
+                .ok()
+                .and_then(|url| Some(url.path_segments()?.next_back()?.to_string())),
+            false => self.builder.path.file_name().map(|name| name.to_string_lossy().into_owned()),
+        }
+        .unwrap_or_default();
+        let checksum = checksums.get(&name).ok_or(DownloadError::ChecksumNotFound(name))?;
+        self.builder = self.builder.hash(&checksum.hash);
+        self.algorithm = checksum.algorithm;
+        Ok(self)
+    }
+
+    /// 下载并解析校验文件 例如发布目录中的 SHA256SUMS 使用相同的认证和代理
+    pub async fn fetch_checksums(&self, url: &str) -> Result<Checksums> {
+        let response = self.send(Method::GET, url, &[]).await?.error_for_status()?;
+        let name = response.url().path_segments().and_then(|mut segments| segments.next_back());
+        let algorithm = name.and_then(checksums::algorithm_hint);
+        let text = response.text().await?;
+        Ok(match algorithm {
+            Some(algorithm) => Checksums::parse_with(&text, algorithm),
+            None => Checksums::parse(&text),
+        })
+    }
+
+    /// 添加镜像 某个地址出错或停滞时换下一个地址从同一位置继续
+    pub fn mirror(mut self, url: impl Into<String>) -> Self {
+        self.urls.push(url.into());
+        self
+    }
+
+    /// 添加多个镜像 见 `mirror`
+    pub fn mirrors(mut self, urls: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.urls.extend(urls.into_iter().map(Into::into));
+        self
+    }
+
+    /// 下载限速 见 `DownloadBuilder::limit`
+    pub fn limit(mut self, limiter: RateLimiter) -> Self {
+        self.builder = self.builder.limit(limiter);
+        self
+    }
+
+    /// 取消令牌 见 `DownloadBuilder::cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.builder = self.builder.cancel(token);
+        self
+    }
+
+    /// 分离签名 见 `DownloadBuilder::signature`
+    pub fn signature(mut self, signature: Signature) -> Self {
+        self.builder = self.builder.signature(signature);
+        self
+    }
+
+    /// 下载完成后的处理 见 `DownloadBuilder::post_process`
+    pub fn post_process(mut self, post: PostProcess) -> Self {
+        self.builder = self.builder.post_process(post);
+        self
+    }
+
+    /// 写入前解密 见 `DownloadBuilder::decrypt`
+    #[cfg(feature = "decrypt")]
+    pub fn decrypt(mut self, decryption: Decryption) -> Self {
+        self.builder = self.builder.decrypt(decryption);
+        self
+    }
+
+    /// 开始前检查磁盘空间或预先分配 见 `DownloadBuilder::space_check`
+    pub fn space_check(mut self, policy: SpaceCheck) -> Self {
+        self.builder = self.builder.space_check(policy);
+        self
+    }
+
+    /// 何时同步到磁盘 见 `DownloadBuilder::sync_policy`
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.builder = self.builder.sync_policy(policy);
+        self
+    }
+
+    /// 何时把进度写入元数据 见 `DownloadBuilder::persist_policy`
+    pub fn persist_policy(mut self, policy: PersistPolicy) -> Self {
+        self.builder = self.builder.persist_policy(policy);
+        self
+    }
+
+    /// hash 或 size 不一致时是否保留进度 见 `DownloadBuilder::amend_policy`
+    pub fn amend_policy(mut self, policy: AmendPolicy) -> Self {
+        self.builder = self.builder.amend_policy(policy);
+        self
+    }
+
+    /// 合并小块写入 见 `DownloadBuilder::write_buffer`
+    pub fn write_buffer(mut self, size: usize) -> Self {
+        self.builder = self.builder.write_buffer(size);
+        self
+    }
+
+    /// 共享的缓冲区池 见 `DownloadBuilder::buffer_pool`
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.builder = self.builder.buffer_pool(pool);
+        self
+    }
+
+    /// 以 O_DIRECT 写入 见 `DownloadBuilder::direct_io`
+    pub fn direct_io(mut self, enabled: bool) -> Self {
+        self.builder = self.builder.direct_io(enabled);
+        self
+    }
+
+    /// 写入内存映射 见 `DownloadBuilder::mmap`
+    #[cfg(feature = "mmap")]
+    pub fn mmap(mut self, enabled: bool) -> Self {
+        self.builder = self.builder.mmap(enabled);
+        self
+    }
+
+    /// 失败时的重试策略 默认不重试
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// 超过 timeout 没有收到数据时从当前位置重新连接 默认不检测
+    ///
+    /// 重新连接后仍然没有收到任何数据时返回 `DownloadError::Stalled`
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall = Some(timeout);
+        self
+    }
+
+    /// 建立连接的超时 默认 30 秒 None 为不限制
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        if let Some(config) = &mut self.config {
+            config.connect = timeout;
+            self.client = config.build();
+        }
+        self
+    }
+
+    /// 等待响应头或单次读取数据的超时 默认 60 秒 None 为不限制
+    ///
+    /// 超时后切换镜像或按重试策略重试 与 `stall_timeout` 不同 不会在同一地址直接重连
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        if let Some(config) = &mut self.config {
+            config.read = timeout;
+            self.client = config.build();
+        }
+        self
+    }
+
+    /// 整个下载 (包括重试和等待) 的最长时间 超过后返回 `DownloadError::DeadlineExceeded`
+    ///
+    /// 已下载的进度会保留 默认不限制
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// 重定向策略
+    pub fn redirect(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect = policy;
+        self
+    }
+
+    /// 把构建器中的路径当作目录 文件名从 Content-Disposition 或重定向后的 URL 推导
+    ///
+    /// 文件名会去掉路径和不安全的字符 实际路径可以通过 `resolve_path` 获取
+    pub fn auto_filename(mut self) -> Self {
+        self.auto_name = true;
+        self
+    }
+
+    /// 请求时发送 `Accept-Encoding: gzip, br, zstd` 按 `Content-Encoding` 边下载边解压
+    ///
+    /// 保存的是解压后的内容 hash 也按解压后的内容计算 压缩的响应没有可用的大小
+    /// 按大小未知下载 只使用一个连接 中断后从头重新下载
+    #[cfg(feature = "decompress")]
+    pub fn decompress(mut self) -> Self {
+        self.decode = true;
+        self
+    }
+
+    /// Linux 上明文 HTTP 的响应体通过 splice 直接从 socket 转移到文件 不经过用户态
+    ///
+    /// 只用于单连接的 `download` 需要没有代理 认证和 cookies 写入前不需要解密或加密
+    /// 大小已知 其他情况以及重定向 分块编码等响应仍由 reqwest 处理 其他平台忽略
+    ///
+    /// 无法更新增量 hash 完成时重新读取文件计算
+    #[cfg(feature = "splice")]
+    pub fn zero_copy(mut self, enabled: bool) -> Self {
+        self.zero_copy = enabled;
+        self
+    }
+
+    /// 下载的目标文件路径 开启 `auto_filename` 时需要请求远程获取
+    pub async fn resolve_path(&self) -> Result<PathBuf> {
+        match self.auto_name {
+            true => Ok(self.builder_for(&self.probe().await?).path),
+            false => Ok(self.builder.path.clone()),
+        }
+    }
+
+    /// 下载并校验 中断后再次调用会从已下载的位置继续
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "download",
+            skip_all,
+            fields(url = %self.urls[0], path = %self.builder.path.display()),
+            err(level = "warn"),
+        )
+    )]
+    pub async fn download(&self) -> Result<Downloaded> {
+        let (cancel, observer) = (self.builder.cancel.as_ref(), self.builder.observer.as_ref());
+        let download = self.within_deadline(self.retry.run(cancel, observer, || self.attempt()));
+        #[cfg(feature = "metrics")]
+        let download = crate::telemetry::measure(download);
+        download.await
+    }
+
+    /// 超过 `deadline` 时放弃 future
+    pub(crate) async fn within_deadline<T>(
+        &self,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout(deadline, future)
+                .await
+                .unwrap_or(Err(DownloadError::DeadlineExceeded)),
+            None => future.await,
+        }
+    }
+
+    /// 单次下载 远程出错时轮流切换镜像 所有地址都没有进展时返回最后的错误
+    pub(crate) async fn attempt(&self) -> Result<Downloaded> {
+        let probe = self.probe().await?;
+        let _permit = self.stream_permit().await?;
+        let mut downloading = self.open(&probe).await?;
+        let (mut mirror, mut failures) = (0, 0);
+        // 之前已经写完 再请求只会得到 416
+        while !downloading.is_finished() || downloading.meta().growing {
+            let offset = downloading.meta().offset;
+            let result = self.transfer(&self.urls[mirror], &mut downloading).await;
+            let progressed = downloading.meta().offset > offset;
+            match result {
+                // 修复损坏的块后还有别的缺口
+                Ok(()) if !downloading.is_finished() => failures = 0,
+                Ok(()) => break,
+                Err(DownloadError::Stalled) if progressed => {}
+                Err(e) if is_remote(&e) => {
+                    failures = if progressed { 0 } else { failures + 1 };
+                    if failures >= self.urls.len() {
+                        return Err(e);
+                    }
+                    mirror = (mirror + 1) % self.urls.len();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.complete(probe, downloading).await
+    }
+
+    /// 校验并完成下载
+    pub(crate) async fn complete(
+        &self,
+        probe: Probe,
+        downloading: Downloading,
+    ) -> Result<Downloaded> {
+        let (path, size) = (downloading.target().to_path_buf(), downloading.meta().size);
+        downloading.complete_with(self.algorithm).await?;
+        let path = self.builder.finish(path, self.algorithm, probe.modified).await?;
+        Ok(Downloaded { path, url: probe.url, size })
+    }
+
+    /// 从当前位置请求并写入 连接提前结束时返回 `DownloadError::ConnectionClosed`
+    ///
+    /// 带上保存的 ETag 或 Last-Modified 作为 If-Range 资源变化时从头下载
+    ///
+    /// 丢弃损坏的块后中间会有缺口 只请求到第一个缺口结束
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(url = %url, offset = downloading.meta().offset),
+            err(level = "debug"),
+        )
+    )]
+    async fn transfer(&self, url: &str, downloading: &mut Downloading) -> Result<()> {
+        #[cfg(feature = "decompress")]
+        let decode = self.decode && downloading.meta().growing;
+        if self.sequential(downloading.meta()) && downloading.meta().offset > 0 {
+            downloading.restart().await?;
+        }
+        let meta = downloading.meta();
+        let (offset, stored) = (meta.offset, meta.validator.clone());
+        let end = meta.ranges.iter().map(|range| range.start).find(|&start| start > offset);
+        let range = match end {
+            Some(end) => format!("bytes={offset}-{}", end - 1),
+            None => format!("bytes={offset}-"),
+        };
+        let mut headers = vec![];
+        if offset > 0 || end.is_some() {
+            headers.push((header::RANGE, range.as_str()));
+            if let Some(stored) = &stored {
+                headers.push((header::IF_RANGE, stored.as_str()));
+            }
+        }
+        #[cfg(feature = "decompress")]
+        if decode {
+            headers.push((header::ACCEPT_ENCODING, decode::ACCEPT));
+        }
+        let _permit = self.host_permit(url).await?;
+        #[cfg(all(feature = "splice", target_os = "linux"))]
+        if self.splice(url, downloading, &headers).await? {
+            let meta = downloading.meta();
+            if meta.offset < end.unwrap_or(meta.size) {
+                return Err(DownloadError::ConnectionClosed);
+            }
+            return Ok(());
+        }
+        let response = self.send(Method::GET, url, &headers).await?.error_for_status()?;
+        #[cfg(feature = "tracing")]
+        let downloaded = downloading.meta().ranges.downloaded();
+
+        // 服务端不支持 Range 时从头返回 跳过已下载的部分
+        let skip = match response.status() {
+            // 代理或 CDN 返回的区间可能与请求的不同 写入会错位
+            StatusCode::PARTIAL_CONTENT => match content_range(response.headers()) {
+                Some((start, last)) if start == offset && end.is_none_or(|end| last < end) => 0,
+                _ => return Err(DownloadError::RangeNotSupported),
+            },
+            _ if offset > 0 && stored.is_some() => {
+                downloading.restart().await?;
+                0
+            }
+            _ => offset,
+        };
+        downloading.set_validator(validator(response.headers()).or(stored)).await?;
+        #[cfg(feature = "decompress")]
+        let encoding = match decode {
+            true => Encoding::from_headers(response.headers())?,
+            false => None,
+        };
+        #[cfg_attr(not(any(feature = "decompress", feature = "decrypt")), allow(unused_mut))]
+        let mut body = self.stream(response, skip);
+        #[cfg(feature = "decompress")]
+        if let Some(encoding) = encoding {
+            body = decode::decode(body, encoding);
+        }
+        #[cfg(feature = "decrypt")]
+        if let Some(decryption) = &self.builder.decrypt {
+            body = decrypt::decrypt(body, decryption).await?;
+        }
+        // 从头返回时会经过缺口之后已下载的部分 按收到的位置写入
+        let mut pos = downloading.meta().offset;
+        while let Some(chunk) = futures::TryStreamExt::try_next(&mut body).await? {
+            downloading.write_at(pos, &chunk).await?;
+            pos += chunk.len() as u64;
+        }
+        let meta = downloading.meta();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = meta.ranges.downloaded() - downloaded, "连接结束");
+        if meta.offset < end.unwrap_or(meta.size) && !meta.growing {
+            return Err(DownloadError::ConnectionClosed);
+        }
+        Ok(())
+    }
+
+    /// 解压或解密 age 后的内容不对应远程的字节位置 只能从头顺序下载 无法续传
+    #[cfg_attr(not(any(feature = "decompress", feature = "decrypt")), allow(unused_variables))]
+    fn sequential(&self, meta: &Metadata) -> bool {
+        #[cfg(feature = "decompress")]
+        if self.decode {
+            return meta.growing;
+        }
+        #[cfg(feature = "decrypt")]
+        if self.builder.decrypt.as_ref().is_some_and(Decryption::is_age) {
+            return meta.growing;
+        }
+        false
+    }
+
+    /// 读取下一块数据 取消时返回 `DownloadError::Cancelled` 超时返回 `DownloadError::Stalled`
+    pub(crate) async fn chunk(&self, response: &mut Response) -> Result<Option<Bytes>> {
+        let cancel = self.builder.cancel.as_ref();
+        let chunk = match self.stall {
+            Some(stall) => {
+                let chunk = tokio::time::timeout(stall, response.chunk());
+                cancellable(cancel, chunk).await?.map_err(|_| DownloadError::Stalled)?
+            }
+            None => cancellable(cancel, response.chunk()).await?,
+        };
+        Ok(chunk?)
+    }
+
+    /// 响应体作为数据流 跳过开头的 skip 字节 用于服务端忽略 Range 从头返回的情况
+    pub(crate) fn stream(
+        &self,
+        response: Response,
+        skip: u64,
+    ) -> futures::stream::BoxStream<'_, Result<Bytes>> {
+        let stream = futures::stream::try_unfold((response, skip), move |(mut response, mut skip)| {
+            async move {
+                while let Some(chunk) = self.chunk(&mut response).await? {
+                    let n = skip.min(chunk.len() as u64);
+                    skip -= n;
+                    if n as usize != chunk.len() {
+                        return Ok(Some((chunk.slice(n as usize..), (response, skip))));
+                    }
+                }
+                Ok(None)
+            }
+        });
+        futures::StreamExt::boxed(stream)
+    }
+
+    /// 打开 downloading 文件 设置了 outboard 时先校验已下载的部分
+    pub(crate) async fn open(&self, probe: &Probe) -> Result<Downloading> {
+        let mut downloading = self.builder_for(probe).open().await?;
+        if let Some(outboard) = self.outboard.as_ref().filter(|_| downloading.meta().offset > 0) {
+            downloading.verify_outboard(outboard).await?;
+        }
+        Ok(downloading)
+    }
+
+    /// 按探测结果设置文件大小和文件名 大小未知时边下载边确定 记录校验使用的算法
+    pub(crate) fn builder_for(&self, probe: &Probe) -> DownloadBuilder {
+        let builder = self.builder.clone().hash_algorithm(self.algorithm);
+        let mut builder = match probe.size {
+            Some(size) => builder.size(size),
+            None => builder.unknown_size(),
+        };
+        if self.auto_name {
+            builder.path = builder.path.join(&probe.filename);
+        }
+        // age 格式在接收时解密 不经过 downloading
+        #[cfg(feature = "decrypt")]
+        if builder.decrypt.as_ref().is_some_and(Decryption::is_age) {
+            builder.decrypt = None;
+        }
+        builder
+    }
+
+    /// 依次向各个地址发送 HEAD 请求 返回第一个成功的结果
+    pub(crate) async fn probe(&self) -> Result<Probe> {
+        let mut error = None;
+        for url in &self.urls {
+            match self.probe_url(url).await {
+                Ok(probe) => return Ok(probe),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.expect("至少有一个地址"))
+    }
+
+    /// 通过 HEAD 请求获取文件大小和 Range 支持情况 没有 Content-Length 时按大小未知下载
+    async fn probe_url(&self, url: &str) -> Result<Probe> {
+        #[cfg_attr(not(feature = "decompress"), allow(unused_mut))]
+        let mut headers = vec![];
+        #[cfg(feature = "decompress")]
+        if self.decode {
+            headers.push((header::ACCEPT_ENCODING, decode::ACCEPT));
+        }
+        let response = self.send(Method::HEAD, url, &headers).await?.error_for_status()?;
+        let headers = response.headers();
+        let size = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse().ok());
+        let ranges = headers.get(header::ACCEPT_RANGES).is_some_and(|v| v == "bytes");
+        // 压缩后的长度和 Range 都不对应解压后的内容
+        #[cfg(feature = "decompress")]
+        let (size, ranges) = match self.decode && Encoding::from_headers(headers)?.is_some() {
+            true => (None, false),
+            false => (size, ranges),
+        };
+        // age 加密后的长度不同 只能从头顺序解密
+        #[cfg(feature = "decrypt")]
+        let (size, ranges) = match self.builder.decrypt.as_ref().is_some_and(Decryption::is_age) {
+            true => (None, false),
+            false => (size, ranges),
+        };
+        let (validator, url) = (validator(headers), response.url().to_string());
+        let filename = filename::resolve(headers, response.url());
+        let modified = headers.get(header::LAST_MODIFIED).and_then(|v| http_date(v.to_str().ok()?));
+        Ok(Probe { size, ranges, validator, filename, url, modified })
+    }
+}
+
+/// 优先使用强 ETag 否则使用 Last-Modified
+pub(crate) fn validator(headers: &HeaderMap) -> Option<String> {
+    let etag = headers.get(header::ETAG).filter(|etag| !etag.as_bytes().starts_with(b"W/"));
+    let validator = etag.or_else(|| headers.get(header::LAST_MODIFIED))?;
+    Some(validator.to_str().ok()?.to_string())
+}
+
+/// `bytes 100-199/1000` 中的 (100, 199)
+pub(crate) fn content_range(headers: &HeaderMap) -> Option<(u64, u64)> {
+    let value = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (start, last) = value.strip_prefix("bytes ")?.split('/').next()?.split_once('-')?;
+    let (start, last) = (start.trim().parse().ok()?, last.trim().parse().ok()?);
+    (start <= last).then_some((start, last))
+}
+
+/// 解析 `Last-Modified` 的 `Sun, 06 Nov 1994 08:49:37 GMT`
+fn http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] =
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (_, rest) = value.split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<u64>().ok());
+    let (h, m, s) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+    let secs = (days(year, month, day) * 86400) + h * 3600 + m * 60 + s;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// 公历日期距 1970-01-01 的天数 见 http://howardhinnant.github.io/date_algorithms.html
+fn days(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, yoe) = (year / 400, year % 400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 错误来自远程 可以换一个地址继续
+pub(crate) fn is_remote(e: &DownloadError) -> bool {
+    matches!(
+        e,
+        DownloadError::Http(_)
+            | DownloadError::Stalled
+            | DownloadError::ConnectionClosed
+            | DownloadError::RangeNotSupported
+            | DownloadError::PieceMismatch(_)
+    )
+}
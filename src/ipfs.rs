@@ -0,0 +1,197 @@
+use std::{fmt, path::Path};
+
+use crate::{
+    hash::{hex, Algorithm},
+    http::HttpDownloader,
+    DownloadError, Result,
+};
+
+/// 默认的公共网关
+const GATEWAYS: [&str; 2] = ["https://ipfs.io", "https://dweb.link"];
+
+/// multicodec 中的 raw 内容就是文件本身
+const RAW: u64 = 0x55;
+/// multicodec 中的 dag-pb 即 UnixFS
+const DAG_PB: u64 = 0x70;
+
+/// IPFS 内容标识符 支持 CIDv0 和 base32 base58btc base16 编码的 CIDv1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cid {
+    text:   String,
+    codec:  u64,
+    /// multihash 的算法编号
+    code:   u64,
+    digest: Vec<u8>,
+}
+
+/// 通过 HTTP 网关下载的 IPFS 内容 `ipfs://<CID>`
+///
+/// 网关按顺序使用 某个网关出错或停滞时换下一个从同一位置继续
+///
+/// 只支持 raw 编码的 CID 它的 multihash 就是文件内容的 hash 完成时按它校验
+/// UnixFS (dag-pb) 包括所有 `Qm` 开头的 CIDv0 的 multihash 是 DAG 根节点的 hash
+/// 无法按文件内容校验 `new` 返回 `DownloadError::InvalidCid`
+#[derive(Debug, Clone)]
+pub struct IpfsObject {
+    cid:      Cid,
+    gateways: Vec<String>,
+}
+
+impl Cid {
+    pub fn parse(text: &str) -> Result<Self> {
+        let bytes = match text.as_bytes() {
+            // CIDv0 是 base58btc 编码的 sha2-256 multihash
+            [b'Q', b'm', ..] if text.len() == 46 => {
+                base58(text).map(|multihash| [vec![0, DAG_PB as u8], multihash].concat())
+            }
+            [b'b', ..] => base32(&text[1..]),
+            [b'B', ..] => base32(&text[1..].to_ascii_lowercase()),
+            [b'z', ..] => base58(&text[1..]),
+            [b'f' | b'F', ..] => unhex(&text[1..]),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(text))?;
+
+        let mut input = bytes.as_slice();
+        let mut read = || varint(&mut input).ok_or_else(|| invalid(text));
+        let (version, codec, code, len) = (read()?, read()?, read()?, read()?);
+        if !matches!(version, 0 | 1) || input.len() as u64 != len {
+            return Err(invalid(text));
+        }
+        Ok(Self { text: text.to_string(), codec, code, digest: input.to_vec() })
+    }
+
+    /// 文件内容的 hash 只有 raw 编码并且算法受支持时才有
+    pub fn content_hash(&self) -> Option<(Algorithm, String)> {
+        let algorithm = match (self.code, self.digest.len()) {
+            (0x11, 20) => Algorithm::Sha1,
+            (0x12, 32) => Algorithm::Sha256,
+            (0x1e, 32) => Algorithm::Blake3,
+            (0xd5, 16) => Algorithm::Md5,
+            _ => return None,
+        };
+        (self.codec == RAW).then(|| (algorithm, hex(&self.digest)))
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl IpfsObject {
+    /// 接受 `ipfs://<CID>` `/ipfs/<CID>` 或单独的 CID
+    ///
+    /// CID 不是 raw 编码或带有路径时返回 `DownloadError::InvalidCid`
+    pub fn new(uri: &str) -> Result<Self> {
+        let rest = uri.strip_prefix("ipfs://").or_else(|| uri.strip_prefix("/ipfs/"));
+        let rest = rest.unwrap_or(uri);
+        let (cid, path) = rest.find(['/', '?', '#']).map_or((rest, ""), |i| rest.split_at(i));
+        let path = path.split(['?', '#']).next().unwrap_or_default().trim_end_matches('/');
+        let cid = Cid::parse(cid)?;
+        if cid.codec != RAW || !path.is_empty() {
+            return Err(invalid(&format!("{cid}{path} 不是 raw 编码的内容")));
+        }
+        let gateways = GATEWAYS.iter().map(ToString::to_string).collect();
+        Ok(Self { cid, gateways })
+    }
+
+    pub fn cid(&self) -> &Cid {
+        &self.cid
+    }
+
+    /// 替换默认的网关 例如本地节点的 `http://127.0.0.1:8080`
+    pub fn gateways(mut self, gateways: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let gateways = gateways.into_iter().map(|url| url.into().trim_end_matches('/').to_string());
+        self.gateways = gateways.collect();
+        self
+    }
+
+    /// 在网关上的地址 请求原始数据 也适用于只提供 trustless 接口的网关
+    pub fn url(&self, gateway: &str) -> String {
+        format!("{gateway}/ipfs/{}?format=raw", self.cid)
+    }
+
+    /// 下载到 path 的下载器 按 CID 的 multihash 校验
+    ///
+    /// multihash 的算法不受支持时返回 `DownloadError::NoChecksum` 用 `downloader_with_hash`
+    pub fn downloader(&self, path: impl AsRef<Path>) -> Result<HttpDownloader> {
+        let (algorithm, hash) = self.cid.content_hash().ok_or(DownloadError::NoChecksum)?;
+        Ok(self.downloader_with_hash(path, hash).algorithm(algorithm))
+    }
+
+    /// 使用已知 hash 的下载器 默认按 SHA-256 校验
+    pub fn downloader_with_hash(
+        &self,
+        path: impl AsRef<Path>,
+        hash: impl Into<String>,
+    ) -> HttpDownloader {
+        let mut urls = self.gateways.iter().map(|gateway| self.url(gateway));
+        let first = urls.next().unwrap_or_else(|| self.url(GATEWAYS[0]));
+        HttpDownloader::new(first, path, hash).mirrors(urls)
+    }
+}
+
+/// 无符号 LEB128 最多 9 字节
+fn varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &b) in input.iter().enumerate().take(9) {
+        value |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// RFC 4648 小写 没有填充
+fn base32(s: &str) -> Option<Vec<u8>> {
+    let (mut bits, mut acc, mut out) = (0, 0u32, vec![]);
+    for b in s.bytes() {
+        let v = match b {
+            b'a'..=b'z' => b - b'a',
+            b'2'..=b'7' => b - b'2' + 26,
+            _ => return None,
+        };
+        acc = (acc << 5 | u32::from(v)) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// 比特币的字母表
+fn base58(s: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut out: Vec<u8> = vec![];
+    for b in s.bytes() {
+        let mut carry = ALPHABET.iter().position(|&c| c == b)? as u32;
+        for byte in out.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            out.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|&b| b == b'1').count();
+    Some([vec![0; zeros], out].concat())
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn invalid(cid: &str) -> DownloadError {
+    DownloadError::InvalidCid(cid.to_string())
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use serde_json::{json, Value};
+use tokio::fs::{File, OpenOptions};
+
+#[cfg(feature = "http")]
+use crate::{
+    manager::{DownloadManager, TaskId, TaskSpec},
+    segments::Segmented,
+};
+use crate::{
+    hash::{hex, unhex, State},
+    sidecar_path, temp_path, DownloadError, Encryption, Metadata, Pieces, Ranges, Result,
+};
+
+/// 导出文档的格式版本
+const FORMAT: u64 = 1;
+
+impl Metadata {
+    /// 导出为 JSON 和 downloading 文件一起复制到其他机器后用 `import` 继续下载
+    ///
+    /// 整数按原值保存 增量 hash 状态为十六进制
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    /// 解析 `to_json` 导出的文档
+    pub fn from_json(json: &str) -> Result<Self> {
+        Self::from_value(&serde_json::from_str(json).map_err(invalid)?)
+    }
+
+    /// 把元数据写入 path 对应的 downloading 文件 已下载的内容需要先复制过去
+    ///
+    /// 有单独的元数据文件时写入其中 否则追加在内容之后 复制过来的文件带着旧的元数据也可以
+    pub async fn import(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = temp_path(path.as_ref());
+        let mut file = OpenOptions::new().write(true).open(&path).await?;
+        let end = self.ranges.iter().map(|range| range.end).max().unwrap_or_default();
+        if file.metadata().await?.len() < end.max(self.offset) {
+            return Err(invalid("downloading 文件比已下载的部分短"));
+        }
+        let sidecar = sidecar_path(&path);
+        match sidecar.exists() {
+            true => self.update_sidecar(&mut File::create(sidecar).await?).await,
+            false => self.update(&mut file).await,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let ranges: Vec<_> = self.ranges.iter().map(|range| [range.start, range.end]).collect();
+        let state = self.state.as_ref().map(|state| {
+            json!({"algorithm": state.algorithm().name(), "bytes": hex(&state.to_bytes())})
+        });
+        let pieces = self.pieces.as_ref().map(|pieces| {
+            json!({
+                "algorithm": pieces.algorithm.name(),
+                "length": pieces.length,
+                "hashes": pieces.hashes,
+            })
+        });
+        let encrypted = self.encrypted.as_ref().map(|encrypted| {
+            json!({"nonce": hex(&encrypted.nonce), "check": hex(&encrypted.check)})
+        });
+        json!({
+            "format": FORMAT,
+            "hash": self.hash,
+            "size": self.size,
+            "offset": self.offset,
+            "ranges": ranges,
+            "validator": self.validator,
+            "growing": self.growing,
+            "state": state,
+            "pieces": pieces,
+            "encrypted": encrypted,
+        })
+    }
+
+    fn from_value(value: &Value) -> Result<Self> {
+        version(value)?;
+        let mut meta = Self::new(text(&value["hash"])?, number(&value["size"])?);
+        meta.offset = number(&value["offset"])?;
+        meta.ranges = Ranges::new();
+        for range in value["ranges"].as_array().ok_or_else(|| invalid("缺少 ranges"))? {
+            let (start, end) = (number(&range[0])?, number(&range[1])?);
+            if start > end || end > meta.size {
+                return Err(invalid(format!("无效的区间 {start}..{end}")));
+            }
+            meta.ranges.insert(start..end);
+        }
+        if meta.ranges.offset() != meta.offset {
+            return Err(invalid("offset 与 ranges 不一致"));
+        }
+        meta.validator = value["validator"].as_str().map(str::to_string);
+        meta.growing = value["growing"].as_bool().unwrap_or_default();
+        if let Some(state) = value.get("state").filter(|state| !state.is_null()) {
+            let algorithm = text(&state["algorithm"])?.parse()?;
+            let bytes = unhex(&text(&state["bytes"])?).ok_or_else(|| invalid("无效的 hash 状态"))?;
+            meta.state = Some(State::from_bytes(algorithm, &bytes)?);
+        }
+        if let Some(pieces) = value.get("pieces").filter(|pieces| !pieces.is_null()) {
+            let hashes = pieces["hashes"].as_array().ok_or_else(|| invalid("缺少 hashes"))?;
+            let length = number(&pieces["length"])?;
+            if length == 0 {
+                return Err(invalid("分块大小为 0"));
+            }
+            meta.pieces = Some(Pieces {
+                algorithm: text(&pieces["algorithm"])?.parse()?,
+                length,
+                hashes:    hashes.iter().map(text).collect::<Result<_>>()?,
+            });
+        }
+        if let Some(encrypted) = value.get("encrypted").filter(|value| !value.is_null()) {
+            let (nonce, check) = (array(&encrypted["nonce"])?, array(&encrypted["check"])?);
+            meta.encrypted = Some(Encryption { nonce, check });
+        }
+        meta.resize();
+        Ok(meta)
+    }
+}
+
+#[cfg(feature = "http")]
+impl DownloadManager {
+    /// 导出任务的选项和下载进度 运行中的任务应先暂停 还没开始时不包含进度
+    ///
+    /// 只能导出 `Task::spec` 返回 Some 的任务 认证和代理等不导出
+    pub async fn export(&self, id: TaskId) -> Result<String> {
+        let (spec, priority) = self.spec(id)?;
+        let spec = spec.ok_or_else(|| invalid(format!("任务 {id} 不能导出")))?;
+        let metadata = match Metadata::from_path(&spec.path).await {
+            Ok(meta) => meta.to_value(),
+            Err(DownloadError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Value::Null,
+            Err(e) => return Err(e),
+        };
+        let headers: Vec<_> = spec.headers.iter().map(|(name, value)| [name, value]).collect();
+        let task = json!({
+            "urls": spec.urls,
+            "hash": spec.hash,
+            "algorithm": spec.algorithm.name(),
+            "headers": headers,
+            "connections": spec.connections,
+            "auto_name": spec.auto_name,
+            "priority": priority,
+        });
+        Ok(json!({"format": FORMAT, "task": task, "metadata": metadata}).to_string())
+    }
+
+    /// 导入 `export` 导出的任务 下载到本机的 path 后开始排队
+    ///
+    /// 带有进度时 downloading 文件需要先复制到 path 对应的位置 见 `Metadata::import`
+    pub async fn import(&self, json: &str, path: impl AsRef<Path>) -> Result<TaskId> {
+        let value: Value = serde_json::from_str(json).map_err(invalid)?;
+        version(&value)?;
+        let task = &value["task"];
+        let urls = task["urls"].as_array().ok_or_else(|| invalid("缺少 urls"))?;
+        let headers = task["headers"].as_array().into_iter().flatten();
+        let headers = headers.map(|header| Ok((text(&header[0])?, text(&header[1])?)));
+        let spec = TaskSpec {
+            urls:        urls.iter().map(text).collect::<Result<_>>()?,
+            path:        path.as_ref().to_path_buf(),
+            hash:        text(&task["hash"])?,
+            algorithm:   text(&task["algorithm"])?.parse()?,
+            headers:     headers.collect::<Result<_>>()?,
+            connections: number(&task["connections"])? as usize,
+            auto_name:   task["auto_name"].as_bool().unwrap_or_default(),
+        };
+        if spec.urls.is_empty() {
+            return Err(invalid("缺少 urls"));
+        }
+        // 先检查所有选项 出错时不改动已有的文件
+        let http = spec.http()?;
+        if !value["metadata"].is_null() {
+            Metadata::from_value(&value["metadata"])?.import(&spec.path).await?;
+        }
+        let priority = task["priority"].as_i64().unwrap_or_default() as i32;
+        Ok(match spec.connections {
+            0 | 1 => self.add(http, priority),
+            n => self.add(Segmented::new(http).connections(n), priority),
+        })
+    }
+}
+
+fn version(value: &Value) -> Result<()> {
+    match value["format"].as_u64() {
+        Some(FORMAT) => Ok(()),
+        format => Err(invalid(format!("不支持的格式 {format:?}"))),
+    }
+}
+
+fn text(value: &Value) -> Result<String> {
+    value.as_str().map(str::to_string).ok_or_else(|| invalid(format!("应为字符串 {value}")))
+}
+
+fn number(value: &Value) -> Result<u64> {
+    value.as_u64().ok_or_else(|| invalid(format!("应为整数 {value}")))
+}
+
+/// 固定长度的十六进制
+fn array<const N: usize>(value: &Value) -> Result<[u8; N]> {
+    let bytes = unhex(&text(value)?).and_then(|bytes| bytes.try_into().ok());
+    bytes.ok_or_else(|| invalid(format!("应为 {N} 字节的十六进制 {value}")))
+}
+
+fn invalid(e: impl std::fmt::Display) -> DownloadError {
+    DownloadError::Json(e.to_string())
+}
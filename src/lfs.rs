@@ -0,0 +1,204 @@
+use std::{fmt::Display, ops::Range, path::Path};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream};
+use reqwest::{
+    header::{self, HeaderName},
+    Client, Method, StatusCode,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::Auth,
+    http::HttpDownloader,
+    transport::{Transport, TransportDownloader},
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// Git LFS 的媒体类型
+const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// 仓库中代替大文件提交的指针文件
+///
+/// ```text
+/// version https://git-lfs.github.com/spec/v1
+/// oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+/// size 12345
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// SHA-256 小写十六进制 也是下载完成时校验的 hash
+    pub oid:  String,
+    pub size: u64,
+}
+
+/// Git LFS 服务 通过 batch API 获取对象的下载地址
+///
+/// ```ignore
+/// let lfs = LfsRepo::new("https://github.com/owner/repo").auth(Auth::basic(user, token));
+/// let pointer = Pointer::from_path("assets/model.bin").await?;
+/// lfs.downloader(&pointer, "model.bin").await?.connections(4).download().await?;
+/// ```
+///
+/// 下载地址通常有有效期 过期后重新调用 `downloader` 从已下载的位置继续
+#[derive(Debug, Clone)]
+pub struct LfsRepo {
+    endpoint: String,
+    client:   Client,
+    auth:     Option<Auth>,
+}
+
+impl Pointer {
+    /// 解析指针文件的内容
+    pub fn parse(text: &str) -> Result<Self> {
+        let (mut version, mut oid, mut size) = (None, None, None);
+        for line in text.lines() {
+            match line.split_once(' ') {
+                Some(("version", value)) => version = Some(value),
+                Some(("oid", value)) => oid = value.strip_prefix("sha256:"),
+                Some(("size", value)) => size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if !version.is_some_and(|v| v.ends_with("/spec/v1")) {
+            return Err(invalid("不是 Git LFS 指针文件"));
+        }
+        let oid = oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()));
+        let oid = oid.ok_or_else(|| invalid("缺少 sha256 oid"))?.to_ascii_lowercase();
+        Ok(Self { oid, size: size.ok_or_else(|| invalid("缺少 size"))? })
+    }
+
+    /// 读取并解析指针文件
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&tokio::fs::read_to_string(path).await?)
+    }
+}
+
+impl LfsRepo {
+    /// 仓库的克隆地址 例如 `https://github.com/owner/repo` 服务地址为 `<仓库>.git/info/lfs`
+    pub fn new(remote: &str) -> Self {
+        let remote = remote.trim_end_matches('/');
+        let remote = remote.strip_suffix(".git").unwrap_or(remote);
+        Self::with_endpoint(format!("{remote}.git/info/lfs"))
+    }
+
+    /// 直接指定服务地址 即 `lfs.url` 的值
+    pub fn with_endpoint(endpoint: impl Into<String>) -> Self {
+        let endpoint = endpoint.into().trim_end_matches('/').to_string();
+        Self { endpoint, client: Client::new(), auth: None }
+    }
+
+    /// 请求 batch API 时的认证 下载对象时使用服务返回的请求头
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// 使用自定义的 reqwest 客户端 也用于下载对象
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// 下载到 path 的下载器 大小和 hash 来自指针 不再发送 HEAD 请求
+    pub async fn downloader(
+        &self,
+        pointer: &Pointer,
+        path: impl AsRef<Path>,
+    ) -> Result<TransportDownloader> {
+        let path = path.as_ref();
+        let mut downloaders = self.downloaders([(pointer.clone(), path)]).await?;
+        downloaders.pop().expect("一个对象一个结果")
+    }
+
+    /// 一次 batch 请求获取多个对象的下载器 单个对象的错误不影响其他对象
+    pub async fn downloaders<P: AsRef<Path>>(
+        &self,
+        objects: impl IntoIterator<Item = (Pointer, P)>,
+    ) -> Result<Vec<Result<TransportDownloader>>> {
+        let objects: Vec<_> = objects.into_iter().collect();
+        let list = objects.iter().map(|(pointer, _)| {
+            json!({"oid": pointer.oid, "size": pointer.size})
+        });
+        let list: Vec<_> = list.collect();
+        let body = json!({"operation": "download", "transfers": ["basic"], "objects": list});
+
+        let mut request = self
+            .client
+            .post(format!("{}/objects/batch", self.endpoint))
+            .header(header::ACCEPT, MEDIA_TYPE)
+            .header(header::CONTENT_TYPE, MEDIA_TYPE)
+            .body(body.to_string());
+        if let Some(auth) = &self.auth {
+            let token = auth.token(false).await?;
+            request = auth.apply(request, token.as_deref())?;
+        }
+        let response = request.send().await?.error_for_status()?;
+        let batch: Value = serde_json::from_str(&response.text().await?).map_err(invalid)?;
+        if let Some(transfer) = batch["transfer"].as_str().filter(|t| *t != "basic") {
+            return Err(invalid(format!("不支持的传输方式 {transfer}")));
+        }
+        let returned = batch["objects"].as_array().ok_or_else(|| invalid("响应中没有 objects"))?;
+
+        let result = objects.into_iter().map(|(pointer, path)| {
+            let object = returned.iter().find(|o| o["oid"].as_str() == Some(&pointer.oid));
+            let object = object.ok_or_else(|| invalid(format!("响应中没有 {}", pointer.oid)))?;
+            self.object(pointer, path.as_ref(), object)
+        });
+        Ok(result.collect())
+    }
+
+    /// 由 batch 响应中的一个对象创建下载器
+    fn object(&self, pointer: Pointer, path: &Path, object: &Value) -> Result<TransportDownloader> {
+        if let Some(error) = object.get("error") {
+            let code = error["code"].as_u64().unwrap_or_default();
+            let message = error["message"].as_str().unwrap_or_default();
+            return Err(invalid(format!("{} {code} {message}", pointer.oid)));
+        }
+        let action = &object["actions"]["download"];
+        let href = action["href"].as_str().ok_or_else(|| {
+            invalid(format!("{} 没有下载地址", pointer.oid))
+        })?;
+
+        let builder = DownloadBuilder::new(path).hash(&pointer.oid).size(pointer.size);
+        let http = HttpDownloader::with_builder(href, builder.clone());
+        let mut http = http.client(self.client.clone());
+        for (name, value) in action["header"].as_object().into_iter().flatten() {
+            let name = HeaderName::from_bytes(name.as_bytes());
+            let name = name.map_err(|e| DownloadError::InvalidHeader(e.to_string()))?;
+            http = http.header(name, value.as_str().unwrap_or_default());
+        }
+        let transport = LfsObject { http, size: pointer.size };
+        Ok(TransportDownloader::new(transport, builder))
+    }
+}
+
+/// 按 batch API 返回的地址和请求头读取对象
+struct LfsObject {
+    http: HttpDownloader,
+    size: u64,
+}
+
+impl Transport for LfsObject {
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(Some(self.size)) })
+    }
+
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let value = format!("bytes={}-{}", range.start, range.end.min(self.size) - 1);
+            let headers = [(header::RANGE, value.as_str())];
+            let response = self.http.send(Method::GET, &self.http.urls[0], &headers).await?;
+            let response = response.error_for_status()?;
+            let skip = match response.status() {
+                StatusCode::PARTIAL_CONTENT => 0,
+                _ => range.start,
+            };
+            Ok(self.http.stream(response, skip))
+        })
+    }
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Lfs(e.to_string())
+}
@@ -1,147 +1,805 @@
+#[cfg(feature = "http")]
+pub mod auth;
+#[cfg(feature = "azure")]
+pub mod azure;
+mod builder;
+pub mod checksums;
+#[cfg(any(feature = "decrypt", feature = "encrypt"))]
+mod cipher;
+#[cfg(any(feature = "s3", feature = "gcs", feature = "azure"))]
+mod cloud;
+mod coalesce;
+#[cfg(feature = "dash")]
+pub mod dash;
+#[cfg(feature = "decompress")]
+mod decode;
+#[cfg(feature = "decrypt")]
+pub mod decrypt;
+mod direct;
+mod durable;
+#[cfg(feature = "encrypt")]
+mod encrypt;
+mod error;
+#[cfg(feature = "extract")]
+pub mod extract;
+#[cfg(feature = "http")]
+mod filename;
+#[cfg(feature = "ftp")]
+pub mod ftp;
+#[cfg(feature = "gcs")]
+pub mod gcs;
+pub mod hash;
+#[cfg(feature = "hf")]
+pub mod hf;
+#[cfg(feature = "hls")]
+pub mod hls;
+#[cfg(feature = "http")]
+pub mod hosts;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "ipfs")]
+pub mod ipfs;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "lfs")]
+pub mod lfs;
+pub mod limit;
+#[cfg(feature = "local")]
+pub mod local;
+pub mod manager;
+mod metadata;
+mod outboard;
+mod outcome;
+#[cfg(feature = "metalink")]
+pub mod metalink;
+#[cfg(feature = "http")]
+pub mod mirror;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "oci")]
+pub mod oci;
+mod pause;
+mod pieces;
+mod pool;
+#[cfg(feature = "http")]
+pub mod pipe;
+pub mod post;
+mod progress;
+#[cfg(feature = "http")]
+pub mod proxy;
+#[cfg(feature = "sqlite")]
+pub mod queue;
+mod ranges;
+mod reader;
+#[cfg(feature = "http")]
+pub mod redirect;
+pub mod retry;
+#[cfg(feature = "s3")]
+pub mod s3;
+mod scan;
+mod scheduler;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "http")]
+pub mod segments;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod splice;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod signature;
+mod sink;
+mod space;
+mod stats;
+mod tee;
+#[cfg(feature = "metrics")]
+mod telemetry;
+#[cfg(feature = "http")]
+pub mod tls;
+#[cfg(feature = "torrent")]
+pub mod torrent;
+pub mod transport;
+#[cfg(feature = "http")]
+mod tuner;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+mod writer;
+
 use std::{
+    borrow::Cow,
     fmt::Debug,
-    io::SeekFrom::*,
+    future::Future,
+    io::{Read, Seek, SeekFrom::*},
+    ops::{Deref, Range},
     path::{Path, PathBuf},
 };
 
+pub use builder::{DownloadBuilder, OverwritePolicy, TempPath};
+pub use durable::{PersistPolicy, SyncPolicy};
+pub use error::{DownloadError, Result};
+use hash::{Algorithm, State};
+use limit::RateLimiter;
+pub use metadata::{AmendPolicy, Encryption, Metadata, VERSION};
+pub use outboard::Outboard;
+pub use outcome::WriteOutcome;
+pub use pause::PausedDownload;
+pub use pieces::Pieces;
+pub use pool::BufferPool;
+pub use progress::Progress;
+use progress::Reporter;
+pub use ranges::Ranges;
+pub use reader::DownloadReader;
+pub use scan::{scan_dir, ResumableEntry};
+use signature::Signature;
+pub use sink::DownloadSink;
+pub use space::SpaceCheck;
+pub use stats::Stats;
+pub use tee::Tee;
+use stats::Sampler;
 use tokio::{
     fs::File,
-    io,
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
+pub use tokio_util::sync::CancellationToken;
+use writer::Writer;
+
+/// `Downloading::copy_from` 每次复制的大小 之间保存进度
+const COPY_CHUNK: u64 = 8 * 1024 * 1024;
 
 #[derive(Debug)]
-pub struct Metadata {
-    pub hash:   String,
-    pub size:   u64,
-    pub offset: u64,
-    pub len:    u64,
+pub struct Downloading {
+    path:      PathBuf,
+    target:    PathBuf,
+    file:      File,
+    /// 单独存放的元数据文件
+    sidecar:   Option<File>,
+    meta:      Metadata,
+    writer:    Writer,
+    progress:  Reporter,
+    stats:     Sampler,
+    limiters:  Vec<RateLimiter>,
+    cancel:    Option<CancellationToken>,
+    signature: Option<Signature>,
+    /// 写入前解密
+    #[cfg(feature = "decrypt")]
+    keystream: Option<cipher::Keystream>,
+    /// 加密存放
+    #[cfg(feature = "encrypt")]
+    at_rest:   Option<encrypt::Sealer>,
+    #[cfg(feature = "metrics")]
+    meter:     telemetry::Meter,
 }
 
-impl Metadata {
-    pub fn new(hash: impl Into<String>, size: u64) -> Self {
-        let hash = hash.into();
-        let len = size + 40 + hash.len() as u64;
-        Self { hash, size, offset: 0, len }
+impl Downloading {
+    /// downloading 文件不存在创建并写入元数据
+    ///
+    /// 存在读取元数据 存在但信息不一致覆盖原来下载进度
+    ///
+    /// hash 可以为空 完成时不校验 或之后通过 `set_expected_hash` 设置
+    pub async fn new<P, H>(path: P, hash: H, size: u64) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        H: Into<String>,
+    {
+        Self::builder(path).hash(hash).size(size).open().await
     }
-    pub async fn from_file(file: &mut File) -> io::Result<Self> {
-        let len = file.metadata().await?.len();
-        if len < 40 {
-            return Err(io::Error::other("文件不包含元数据"));
-        }
 
-        file.seek(End(-40)).await?;
-        let mut buf = [0; 40];
-        file.read_exact(&mut buf).await?;
-        let error = |_| io::Error::other("解析 downloading 元数据失败");
-        let size: u64 = String::from_utf8_lossy(&buf[..20]).parse().map_err(error)?;
-        let offset: u64 = String::from_utf8_lossy(&buf[20..]).parse().map_err(error)?;
+    /// 更多选项通过构建器设置
+    pub fn builder(path: impl AsRef<Path>) -> DownloadBuilder {
+        DownloadBuilder::new(path)
+    }
+
+    /// 已存在单独的元数据文件时沿用 不论是否开启 sidecar
+    pub(crate) async fn create(
+        builder: &DownloadBuilder,
+        path: PathBuf,
+        target: PathBuf,
+    ) -> Result<Self> {
+        let (hash, size) = (builder.hash.as_str(), builder.size);
+        let mut file = open(&path).await?;
+        let sidecar = sidecar_path(&path);
+        let mut sidecar = match builder.sidecar || sidecar.exists() {
+            true => Some(open(&sidecar).await?),
+            false => None,
+        };
 
-        let mut buf = vec![0; (len - size - 40) as usize];
-        file.seek(Start(size)).await?;
-        file.read_exact(&mut buf).await?;
-        let hash = String::from_utf8_lossy(&buf).to_string();
-        Ok(Self { hash, size, offset, len })
+        let meta = match &mut sidecar {
+            Some(sidecar) => Metadata::from_sidecar(sidecar).await,
+            None => Metadata::from_file(&mut file).await,
+        };
+        let algorithm = builder.algorithm;
+        let mut meta = match meta {
+            Ok(meta) if builder.growing => meta.amend_growing(hash, algorithm),
+            Ok(meta) => meta.amend(hash, algorithm, size, builder.amend),
+            Err(DownloadError::MetadataMissing) if builder.growing => Metadata::growing(hash),
+            Err(DownloadError::MetadataMissing) => Metadata::new(hash, size),
+            Err(e) => return Err(e),
+        };
+        meta.algorithm = meta.algorithm.or(algorithm);
+        if builder.pieces.is_some() {
+            meta.pieces = builder.pieces.clone();
+        }
+        let mut writer = Writer::new(&file, sidecar.as_ref()).await?;
+        writer.sync = builder.sync;
+        writer.persist = builder.persist;
+        writer.buffer = builder.buffer;
+        writer.pool = builder.pool.clone();
+        #[cfg(feature = "mmap")]
+        {
+            writer.mmap = builder.mmap;
+        }
+        let (mut progress, stats) = (Reporter::new(&meta), Sampler::new());
+        progress.observe(builder.observer.clone());
+        let mut downloading = Self {
+            path,
+            target,
+            file,
+            sidecar,
+            meta,
+            writer,
+            progress,
+            stats,
+            limiters: builder.limiters.clone(),
+            cancel: builder.cancel.clone(),
+            signature: builder.signature.clone(),
+            #[cfg(feature = "decrypt")]
+            keystream: builder.decrypt.as_ref().map(decrypt::Decryption::keystream).transpose()?,
+            #[cfg(feature = "encrypt")]
+            at_rest: None,
+            #[cfg(feature = "metrics")]
+            meter: telemetry::Meter::new(),
+        };
+        #[cfg(feature = "encrypt")]
+        downloading.unlock(builder.at_rest.as_ref()).await?;
+        #[cfg(not(feature = "encrypt"))]
+        downloading.unlock()?;
+        downloading.direct_io(builder.direct);
+        downloading.check_space(builder.space).await?;
+        downloading.save().await?;
+        Ok(downloading)
     }
 
-    pub async fn update(&self, file: &mut File) -> io::Result<()> {
-        let meta = format!("{}{:020}{:020}", self.hash, self.size, self.offset);
-        file.set_len(self.len).await?;
-        file.seek(Start(self.size)).await?;
-        file.write_all(meta.as_bytes()).await
+    /// 打开已存在的 downloading 文件继续下载 hash 和 size 从元数据中读取
+    ///
+    /// path 可以是目标文件路径 也可以是 downloading 文件本身的路径
+    ///
+    /// 其他命名方式使用 `DownloadBuilder::resume`
+    pub async fn resume(path: impl AsRef<Path>) -> Result<Self> {
+        let path = temp_path(path.as_ref());
+        let target = path.with_extension("");
+        let mut downloading = Self::reopen(path, target).await?;
+        #[cfg(feature = "encrypt")]
+        downloading.unlock(None).await?;
+        #[cfg(not(feature = "encrypt"))]
+        downloading.unlock()?;
+        downloading.check_space(SpaceCheck::default()).await?;
+        Ok(downloading)
     }
 
-    /// hash 和 size 一致保留下载进度 否则重置下载进度并更新
-    pub fn amend(mut self, hash: &str, size: u64) -> Self {
-        if self.hash != hash && self.size != size {
-            self.offset = 0;
-            self.size = size;
-            self.hash.truncate(0);
-            self.hash.push_str(hash);
-            self.len = self.size + 40 + self.hash.len() as u64;
+    pub(crate) async fn reopen(path: PathBuf, target: PathBuf) -> Result<Self> {
+        if target.exists() {
+            return Err(DownloadError::TargetExists);
         }
-        self
+
+        let mut file = File::options().write(true).read(true).open(&path).await?;
+        let sidecar = sidecar_path(&path);
+        let (sidecar, meta) = match sidecar.exists() {
+            true => {
+                let mut sidecar = File::options().write(true).read(true).open(sidecar).await?;
+                let meta = Metadata::from_sidecar(&mut sidecar).await?;
+                (Some(sidecar), meta)
+            }
+            false => (None, Metadata::from_file(&mut file).await?),
+        };
+        let writer = Writer::new(&file, sidecar.as_ref()).await?;
+        let (progress, stats) = (Reporter::new(&meta), Sampler::new());
+        Ok(Self {
+            path,
+            target,
+            file,
+            sidecar,
+            meta,
+            writer,
+            progress,
+            stats,
+            limiters: vec![],
+            cancel: None,
+            signature: None,
+            #[cfg(feature = "decrypt")]
+            keystream: None,
+            #[cfg(feature = "encrypt")]
+            at_rest: None,
+            #[cfg(feature = "metrics")]
+            meter: telemetry::Meter::new(),
+        })
     }
-}
 
-#[derive(Debug)]
-pub struct Downloading {
-    path: PathBuf,
-    file: File,
-    meta: Metadata,
-}
+    /// 顺序写入 成功后返回当前位置和剩余的字节数 见 [`WriteOutcome`]
+    ///
+    /// 完整写入后返回 `WriteOutcome::Complete` 大小未知时总是返回 `InProgress`
+    pub async fn write(&mut self, buf: &[u8]) -> Result<WriteOutcome> {
+        self.write_at(self.position(), buf).await
+    }
 
-impl Downloading {
-    /// downloading 文件不存在创建并写入元数据
+    /// 在指定位置写入 用于多个区间同时下载
     ///
-    /// 存在读取元数据 存在但信息不一致覆盖原来下载进度
-    pub async fn new<P, H>(path: P, hash: H, size: u64) -> io::Result<Self>
+    /// 写入成功后返回本次写入的结束位置 所有区间写满后返回 `WriteOutcome::Complete`
+    ///
+    /// 增量 hash 只支持顺序写入 乱序写入时会放弃增量 hash
+    ///
+    /// 已取消时不再写入 返回 `DownloadError::Cancelled` 之前的进度已经保存
+    ///
+    /// 大小未知时只能从 `offset` 继续写入 否则返回 `DownloadError::OutOfOrder`
+    ///
+    /// 设置了分块 hash 时校验因此下载完整的块 不一致时丢弃该块 返回 `DownloadError::PieceMismatch`
+    pub async fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<WriteOutcome> {
+        self.write_vectored_at(offset, &[buf]).await
+    }
+
+    /// 依次写入多个缓冲区 不需要先拼接成连续的缓冲区 见 `write`
+    ///
+    /// 可以传入 `&[IoSlice]` `&[Bytes]` 或 `&[Vec<u8>]`
+    pub async fn write_vectored<B>(&mut self, bufs: &[B]) -> Result<WriteOutcome>
     where
-        P: AsRef<Path>,
-        H: Into<String>,
+        B: Deref<Target = [u8]> + Sync,
+    {
+        self.write_vectored_at(self.position(), bufs).await
+    }
+
+    /// 从 offset 开始依次写入多个缓冲区 见 `write_at`
+    ///
+    /// Linux 上一次 pwritev 写入 其他平台逐个定位写入
+    pub async fn write_vectored_at<B>(&mut self, offset: u64, bufs: &[B]) -> Result<WriteOutcome>
+    where
+        B: Deref<Target = [u8]> + Sync,
     {
-        if path.as_ref().exists() {
-            return Err(io::Error::other("要下载的文件已存在"));
+        let len = bufs.iter().map(|buf| buf.len() as u64).sum::<u64>();
+        let end = offset + len;
+        if self.meta.growing && offset != self.meta.offset {
+            return Err(DownloadError::OutOfOrder);
+        }
+        if end > self.meta.size && !self.meta.growing {
+            return Err(DownloadError::Overflow);
+        }
+        if self.is_cancelled() {
+            self.flush_runs().await?;
+            if self.writer.dirty {
+                self.save().await?;
+            }
+            return Err(DownloadError::Cancelled);
         }
 
-        let ext = path.as_ref().extension().unwrap_or_default();
-        let ext = format!("{}.downloading", ext.to_string_lossy());
-        let path = path.as_ref().with_extension(ext);
-        let mut file = File::options().create(true).write(true).read(true).open(&path).await?;
-        let len = file.metadata().await?.len();
-        let hash = hash.into();
+        let delay = self.reserve(len);
+        if !delay.is_zero() {
+            cancellable(self.cancel.as_ref(), tokio::time::sleep(delay)).await?;
+        }
+        if self.writer.buffer > 0 && !self.meta.growing && len > 0 {
+            let mut pos = offset;
+            for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+                self.coalesce(pos, buf).await?;
+                pos += buf.len() as u64;
+            }
+            return Ok(self.outcome(end));
+        }
+        self.write_run(offset, bufs).await
+    }
 
-        let meta = if len < 40 {
-            Metadata::new(hash, size)
-        } else {
-            Metadata::from_file(&mut file).await?.amend(&hash, size)
-        };
-        meta.update(&mut file).await?;
+    /// 写入文件后记录进度 见 `write_vectored_at`
+    async fn write_run<B: Deref<Target = [u8]>>(
+        &mut self,
+        offset: u64,
+        bufs: &[B],
+    ) -> Result<WriteOutcome> {
+        // 顺序写入在后台的写入先完成
+        self.file.flush().await?;
+        let (mut end, mut plain, mut written) = (offset, vec![], vec![]);
+        for buf in bufs {
+            let (buf, data) = self.transform(end, buf);
+            end += buf.len() as u64;
+            plain.push(buf);
+            written.push(data);
+        }
+        // 定位写入 写入完成后读取者才能读到
+        self.write_data(offset, written).await?;
+        // 非 Unix 平台会移动文件位置
+        self.writer.cursor = None;
+        if offset != self.meta.offset {
+            self.meta.state = None;
+        }
+        if let Some(state) = &mut self.meta.state {
+            plain.iter().for_each(|buf| state.update(buf));
+        }
+        self.commit(offset..end).await
+    }
+
+    /// 写入文件前的处理 返回明文和写入文件的内容 明文用于增量 hash
+    ///
+    /// 解密和加密存放都按位置处理 同一位置重复调用结果相同
+    #[cfg_attr(not(any(feature = "decrypt", feature = "encrypt")), allow(unused_variables))]
+    fn transform<'a>(&self, offset: u64, buf: &'a [u8]) -> (Cow<'a, [u8]>, Cow<'a, [u8]>) {
+        #[cfg_attr(not(feature = "decrypt"), allow(unused_mut))]
+        let mut plain = Cow::Borrowed(buf);
+        #[cfg(feature = "decrypt")]
+        if let Some(keystream) = &self.keystream {
+            keystream.apply(offset, plain.to_mut());
+        }
+        #[cfg_attr(not(feature = "encrypt"), allow(unused_mut))]
+        let mut written = plain.clone();
+        #[cfg(feature = "encrypt")]
+        if let Some(sealer) = &self.at_rest {
+            sealer.apply(offset, written.to_mut());
+        }
+        (plain, written)
+    }
 
-        Ok(Self { path: path.to_path_buf(), file, meta })
+    /// 写入前需要解密或加密 不能直接复制
+    pub(crate) fn transforms(&self) -> bool {
+        #[cfg(feature = "decrypt")]
+        if self.keystream.is_some() {
+            return true;
+        }
+        #[cfg(feature = "encrypt")]
+        if self.at_rest.is_some() {
+            return true;
+        }
+        false
     }
 
-    /// 写入成功后返回当前位置 Some(offset)
+    /// 加密存放的文件需要开启 `encrypt` 并提供密钥
+    #[cfg(not(feature = "encrypt"))]
+    fn unlock(&self) -> Result<()> {
+        match self.meta.encrypted {
+            Some(_) => Err(DownloadError::WrongKey),
+            None => Ok(()),
+        }
+    }
+
+    /// 从本地文件复制 range 到相同的位置 数据不经过内存
     ///
-    /// 完整写入后返回 None
-    pub async fn write(&mut self, buf: &[u8]) -> io::Result<Option<u64>> {
-        let offset = self.meta.offset + buf.len() as u64;
-        if offset > self.meta.size {
-            return Err(io::Error::other("写入的文本长度超过文件长度"));
+    /// Linux 上由 `std::io::copy` 使用 copy_file_range 其他平台退回普通的读写
+    ///
+    /// 无法更新增量 hash 完成时重新读取文件计算 需要解密或加密存放时读入内存后写入
+    pub(crate) async fn copy_from(
+        &mut self,
+        source: &std::fs::File,
+        range: Range<u64>,
+    ) -> Result<()> {
+        let mut pos = range.start;
+        while pos < range.end {
+            if self.is_cancelled() {
+                return Err(DownloadError::Cancelled);
+            }
+            let n = COPY_CHUNK.min(range.end - pos);
+            if self.transforms() {
+                let mut source = source.try_clone()?;
+                let task = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+                    let mut buf = vec![];
+                    source.seek(Start(pos))?;
+                    source.take(n).read_to_end(&mut buf)?;
+                    Ok(buf)
+                });
+                let buf = task.await??;
+                if buf.is_empty() {
+                    return Err(DownloadError::ConnectionClosed);
+                }
+                self.write_at(pos, &buf).await?;
+                pos += buf.len() as u64;
+                continue;
+            }
+            let delay = self.reserve(n);
+            if !delay.is_zero() {
+                cancellable(self.cancel.as_ref(), tokio::time::sleep(delay)).await?;
+            }
+            let mut source = source.try_clone()?;
+            let mut target = self.file.try_clone().await?.into_std().await;
+            let task = tokio::task::spawn_blocking(move || -> std::io::Result<u64> {
+                source.seek(Start(pos))?;
+                target.seek(Start(pos))?;
+                std::io::copy(&mut source.take(n), &mut target)
+            });
+            let copied = task.await??;
+            // 与数据文件共享位置
+            self.writer.cursor = None;
+            // 源文件变短了
+            if copied == 0 {
+                return Err(DownloadError::ConnectionClosed);
+            }
+            self.meta.state = None;
+            self.commit(pos..pos + copied).await?;
+            pos += copied;
         }
+        Ok(())
+    }
 
-        self.file.seek(Start(self.meta.offset)).await?;
-        self.file.write_all(buf).await?;
-        self.file.seek(End(-20)).await?;
-        self.file.write_all(format!("{:020}", offset).as_bytes()).await?;
-        self.meta.offset = offset;
+    /// 记录已写入的区间 保存元数据后校验涉及的块
+    async fn commit(&mut self, range: Range<u64>) -> Result<WriteOutcome> {
+        let (offset, end) = (range.start, range.end);
+        self.meta.ranges.insert(offset..end);
+        self.meta.offset = self.meta.ranges.offset();
+        self.meta.size = self.meta.size.max(end);
+        self.record(end - offset);
 
-        if offset != self.meta.size {
-            Ok(Some(offset))
-        } else {
-            Ok(None)
+        self.meta.resize();
+        match self.persist_due() {
+            true => self.save().await?,
+            false => self.writer.dirty = true,
         }
+        self.verify_written(offset..end).await?;
+        self.progress.readable(self.meta.offset);
+        Ok(self.outcome(end))
     }
 
     /// 完成下载
-    pub async fn complete(mut self, verify: impl Fn(&mut File) -> String) -> io::Result<()> {
+    ///
+    /// 截去元数据后交给 verify 计算 hash 校验失败时恢复元数据 设置了签名时再校验签名
+    ///
+    /// 没有期望的 hash 时不调用 verify 跳过 hash 校验 签名照常校验
+    ///
+    /// 大小未知时以已写入的长度作为文件大小
+    ///
+    /// 有分块 hash 时校验失败会逐块校验 丢弃损坏的块并返回 `DownloadError::Corrupted`
+    /// 再次打开后只需要重新下载这些块
+    pub async fn complete(
+        mut self,
+        verify: impl AsyncFnOnce(&mut File) -> Result<String>,
+    ) -> Result<()> {
+        self.flush_runs().await?;
         if self.meta.offset != self.meta.size {
-            return Err(io::Error::other("文件还未下载完成"));
+            return Err(DownloadError::Incomplete);
         }
-        self.file.seek(Start(0)).await?;
+        // 校验通过后不再需要元数据 丢弃时不要写回
+        self.writer.dirty = false;
+        #[cfg(feature = "mmap")]
+        self.unmap()?;
         self.file.set_len(self.meta.size).await?;
+        // 加密存放时解密到新文件后校验 原文件保留到校验通过
+        #[cfg(feature = "encrypt")]
+        let mut plain = self.unseal_file().await?;
+        #[cfg(feature = "encrypt")]
+        let (file, path) = match &mut plain {
+            Some((file, path)) => (file, &*path),
+            None => (&mut self.file, &self.path),
+        };
+        #[cfg(not(feature = "encrypt"))]
+        let (file, path) = (&mut self.file, &self.path);
+        file.seek(Start(0)).await?;
+
+        let verified = match self.meta.hash.is_empty() {
+            true => Ok(String::new()),
+            false => verify(file).await,
+        };
+        let error = match verified {
+            Ok(hash) if hash == self.meta.hash => match &self.signature {
+                Some(signature) => signature.verify(file, path).await.err(),
+                None => None,
+            },
+            Ok(actual) => {
+                Some(DownloadError::HashMismatch { expected: self.meta.hash.clone(), actual })
+            }
+            Err(e) => Some(e),
+        };
+        if let Some(e) = error {
+            #[cfg(feature = "encrypt")]
+            if let Some((_, path)) = &plain {
+                tokio::fs::remove_file(path).await?;
+            }
+            if let DownloadError::HashMismatch { .. } = e {
+                let corrupted = self.verify_pieces().await?;
+                if !corrupted.is_empty() {
+                    return Err(DownloadError::Corrupted(corrupted));
+                }
+            }
+            self.save().await?;
+            return Err(e);
+        }
 
-        if verify(&mut self.file) != self.meta.hash {
-            self.meta.update(&mut self.file).await?;
-            return Err(io::Error::other("文件检验失败"));
+        if self.writer.sync != SyncPolicy::Never {
+            file.sync_all().await?;
+        }
+        tokio::fs::rename(path, &self.target).await?;
+        #[cfg(feature = "encrypt")]
+        if plain.is_some() {
+            tokio::fs::remove_file(&self.path).await?;
         }
+        if self.sidecar.take().is_some() {
+            tokio::fs::remove_file(sidecar_path(&self.path)).await?;
+        }
+        Ok(())
+    }
 
-        tokio::fs::rename(&self.path, self.path.with_extension("")).await?;
+    /// 放弃下载 删除 downloading 文件和单独存放的元数据文件
+    pub async fn abort(mut self) -> Result<()> {
+        self.writer.dirty = false;
+        tokio::fs::remove_file(&self.path).await?;
+        if self.sidecar.take().is_some() {
+            tokio::fs::remove_file(sidecar_path(&self.path)).await?;
+        }
         Ok(())
     }
 
+    /// 开启增量 hash 写入时同步计算并保存到元数据
+    ///
+    /// 已下载的部分会先读取计算一次
+    pub async fn track_hash(&mut self, algorithm: Algorithm) -> Result<()> {
+        if matches!(&self.meta.state, Some(state) if state.algorithm() == algorithm) {
+            return Ok(());
+        }
+
+        let mut state = State::new(algorithm)?;
+        let mut buf = vec![0; 1024 * 1024];
+        let mut remain = self.meta.offset;
+        self.file.seek(Start(0)).await?;
+        while remain > 0 {
+            let n = buf.len().min(remain as usize);
+            self.file.read_exact(&mut buf[..n]).await?;
+            #[cfg(feature = "encrypt")]
+            self.unseal(self.meta.offset - remain, &mut buf[..n]);
+            state.update(&buf[..n]);
+            remain -= n as u64;
+        }
+
+        self.meta.state = Some(state);
+        self.meta.resize();
+        self.save().await
+    }
+
+    /// 远程资源已变化 丢弃已下载的进度从头开始
+    pub(crate) async fn restart(&mut self) -> Result<()> {
+        self.meta.offset = 0;
+        self.meta.ranges = Ranges::new();
+        self.meta.state = self.meta.state.take().and_then(|s| State::new(s.algorithm()).ok());
+        #[cfg(feature = "encrypt")]
+        self.renew();
+        self.progress.readable(0);
+        self.meta.resize();
+        self.save().await
+    }
+
+    /// 记录远程资源的 ETag 或 Last-Modified
+    pub(crate) async fn set_validator(&mut self, validator: Option<String>) -> Result<()> {
+        if self.meta.validator == validator {
+            return Ok(());
+        }
+        self.meta.validator = validator;
+        self.meta.resize();
+        self.save().await
+    }
+
+    /// 使用增量 hash 完成下载 无需重新读取整个文件
+    pub async fn complete_tracked(self) -> Result<()> {
+        let state = self.meta.state.clone().ok_or(DownloadError::NotTracked)?;
+        let hash = state.finalize();
+        self.complete(async |_| Ok(hash)).await
+    }
+
+    /// 下载完成后的文件路径
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// 使用 algorithm 校验并完成下载 增量 hash 的算法一致时直接使用增量结果
+    ///
+    /// 重新读取文件时通过 `progress` 报告已校验的字节数 见 `Progress::verified`
+    pub async fn complete_with(self, algorithm: Algorithm) -> Result<()> {
+        match &self.meta.state {
+            Some(state) if state.algorithm() == algorithm => self.complete_tracked().await,
+            _ => {
+                let verifier = self.progress.verifier();
+                self.complete(async |file| algorithm.hash_reporting(file, Some(verifier)).await)
+                    .await
+            }
+        }
+    }
+
+    /// 按元数据中记录的算法校验并完成下载 见 `DownloadBuilder::hash_algorithm`
+    ///
+    /// 没有期望的 hash 时不校验 有 hash 但没有记录算法时返回 `DownloadError::UnknownAlgorithm`
+    pub async fn complete_auto(self) -> Result<()> {
+        match self.meta.algorithm {
+            Some(algorithm) => self.complete_with(algorithm).await,
+            None if self.meta.hash.is_empty() => self.complete(async |_| Ok(String::new())).await,
+            None => Err(DownloadError::UnknownAlgorithm),
+        }
+    }
+
+    /// 设置取消令牌 取消后的写入返回 `DownloadError::Cancelled`
+    pub fn set_cancel(&mut self, token: CancellationToken) {
+        self.cancel = Some(token);
+    }
+
+    /// 设置分离签名 见 `DownloadBuilder::signature`
+    pub fn set_signature(&mut self, signature: Signature) {
+        self.signature = Some(signature);
+    }
+
+    /// 设置期望的 hash 用于开始下载时还不知道 hash 的情况 保存到元数据中 继续下载时沿用
+    ///
+    /// 设置为空时完成时不校验
+    pub async fn set_expected_hash(&mut self, hash: impl Into<String>) -> Result<()> {
+        let hash = hash.into();
+        if self.meta.hash == hash {
+            return Ok(());
+        }
+        self.meta.hash = hash;
+        self.meta.resize();
+        self.save().await
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
     /// 查看元数据
     pub fn meta(&self) -> &Metadata {
         &self.meta
     }
+
+    /// 所有区间都已写入 大小未知时以已写入的长度作为大小
+    pub fn is_finished(&self) -> bool {
+        self.meta.offset == self.meta.size
+    }
+
+    /// 写入 n 字节后更新进度和速度统计
+    fn record(&mut self, n: u64) {
+        self.writer.behind += n;
+        self.writer.unsaved += n;
+        self.progress.update(&self.meta, n);
+        self.stats.record(n);
+        #[cfg(feature = "metrics")]
+        self.meter.record(n);
+    }
+
+    /// 写入完整的元数据
+    async fn save(&mut self) -> Result<()> {
+        self.writer.cursor = None;
+        self.writer.dirty = false;
+        let sync = self.sync_due();
+        if sync {
+            #[cfg(feature = "mmap")]
+            self.flush_mapping()?;
+            self.file.sync_data().await?;
+        }
+        match &mut self.sidecar {
+            Some(sidecar) => self.meta.update_sidecar(sidecar).await?,
+            None => self.meta.update(&mut self.file).await?,
+        }
+        self.meta.written(if self.sidecar.is_some() { 0 } else { self.meta.size });
+        self.persisted();
+        if sync {
+            self.sidecar.as_ref().unwrap_or(&self.file).sync_data().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn open(path: &Path) -> Result<File> {
+    let mut options = File::options();
+    Ok(options.create(true).truncate(false).write(true).read(true).open(path).await?)
+}
+
+/// 等待 future 完成 token 先被取消时返回 `DownloadError::Cancelled`
+pub(crate) async fn cancellable<F: Future>(
+    token: Option<&CancellationToken>,
+    future: F,
+) -> Result<F::Output> {
+    match token {
+        Some(token) => token.run_until_cancelled(future).await.ok_or(DownloadError::Cancelled),
+        None => Ok(future.await),
+    }
+}
+
+/// `a.txt.downloading` -> `a.txt.downloading.meta`
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".meta");
+    path.into()
+}
+
+/// 目标文件对应的默认 downloading 文件路径 已经是 downloading 文件时原样返回
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    if path.extension().is_some_and(|ext| ext == "downloading") {
+        return path.to_path_buf();
+    }
+    TempPath::default().resolve(path)
 }
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::Downloading;
+
+/// 令牌桶限速器 每秒补充 rate 个字节的令牌 最多积攒 1 秒
+///
+/// clone 出的限速器共享同一个桶 同一个限速器挂到多个下载上即为全局限速
+///
+/// 可以同时挂多个限速器 例如单个下载限速加全局限速 写入时等待最久的那个
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate:   f64,
+    /// 可能为负 表示已经透支的字节数
+    tokens: f64,
+    last:   Instant,
+    /// `Bandwidth::share` 创建时为所在的总带宽和份额的 id
+    share:  Option<(Arc<Mutex<Pool>>, u64)>,
+}
+
+/// 最近这段时间内写入过的份额参与分配
+const ACTIVE: Duration = Duration::from_secs(1);
+
+/// 多个下载按权重分配的总带宽
+///
+/// ```ignore
+/// let bandwidth = Bandwidth::new(10 << 20);
+/// let urgent = HttpDownloader::new(url, path, hash).limit(bandwidth.share(4));
+/// let other = HttpDownloader::new(url2, path2, hash2).limit(bandwidth.share(1));
+/// ```
+///
+/// 每个份额的速率为 总速率 × 权重 / 正在写入的份额的权重之和 上例中 urgent 约占 80%
+/// 空闲或已结束的份额不占用带宽
+#[derive(Debug, Clone)]
+pub struct Bandwidth {
+    pool: Arc<Mutex<Pool>>,
+}
+
+#[derive(Debug)]
+struct Pool {
+    rate:    f64,
+    next_id: u64,
+    shares:  HashMap<u64, Share>,
+}
+
+#[derive(Debug)]
+struct Share {
+    weight: f64,
+    /// 最近一次写入的时间
+    active: Instant,
+}
+
+impl RateLimiter {
+    /// rate 为每秒字节数
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        let bucket = Bucket { rate, tokens: rate, last: Instant::now(), share: None };
+        Self { bucket: Arc::new(Mutex::new(bucket)) }
+    }
+
+    /// 调整速率 对共享该桶的所有下载生效 份额的速率由 `Bandwidth` 决定 调整无效
+    pub fn set_rate(&self, rate: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate.max(1) as f64;
+        bucket.tokens = bucket.tokens.min(bucket.rate);
+    }
+
+    /// 当前速率 字节/秒
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate as u64
+    }
+
+    /// 取走 n 个令牌 返回需要等待的时间
+    ///
+    /// 令牌不足时允许透支 之后的写入等待补齐
+    pub fn reserve(&self, n: u64) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        if let Some((pool, id)) = &bucket.share {
+            let rate = pool.lock().unwrap().rate_of(*id);
+            bucket.rate = rate;
+            bucket.tokens = bucket.tokens.min(rate);
+        }
+        bucket.tokens -= n as f64;
+        match bucket.tokens < 0.0 {
+            true => Duration::from_secs_f64(-bucket.tokens / bucket.rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// 等待 n 个字节的令牌
+    pub async fn acquire(&self, n: u64) {
+        let delay = self.reserve(n);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// 调整份额的权重 只对 `Bandwidth::share` 创建的限速器有效
+    pub fn set_weight(&self, weight: u32) {
+        let bucket = self.bucket.lock().unwrap();
+        if let Some((pool, id)) = &bucket.share {
+            if let Some(share) = pool.lock().unwrap().shares.get_mut(id) {
+                share.weight = weight.max(1) as f64;
+            }
+        }
+    }
+}
+
+impl Bandwidth {
+    /// rate 为所有份额合计的每秒字节数
+    pub fn new(rate: u64) -> Self {
+        let pool = Pool { rate: rate.max(1) as f64, next_id: 0, shares: HashMap::new() };
+        Self { pool: Arc::new(Mutex::new(pool)) }
+    }
+
+    /// 调整总速率 各份额下次写入时按新的速率分配
+    pub fn set_rate(&self, rate: u64) {
+        self.pool.lock().unwrap().rate = rate.max(1) as f64;
+    }
+
+    /// 总速率 字节/秒
+    pub fn rate(&self) -> u64 {
+        self.pool.lock().unwrap().rate as u64
+    }
+
+    /// 按 weight 分配带宽的限速器 clone 出的限速器属于同一个份额 全部丢弃后份额移除
+    pub fn share(&self, weight: u32) -> RateLimiter {
+        let mut pool = self.pool.lock().unwrap();
+        let id = pool.next_id;
+        pool.next_id += 1;
+        let share = Share { weight: weight.max(1) as f64, active: Instant::now() };
+        pool.shares.insert(id, share);
+        let rate = pool.rate_of(id);
+        let share = Some((self.pool.clone(), id));
+        let bucket = Bucket { rate, tokens: rate, last: Instant::now(), share };
+        RateLimiter { bucket: Arc::new(Mutex::new(bucket)) }
+    }
+}
+
+/// 按一天中的时段限制总带宽 交给 `DownloadManager::bandwidth_schedule`
+///
+/// ```ignore
+/// // 北京时间 01:00 到 07:00 不限速 其余时间 1 MB/s
+/// let schedule = BandwidthSchedule::new(Some(1 << 20))
+///     .between((1, 0), (7, 0), None)
+///     .utc_offset(8 * 3600);
+/// ```
+///
+/// 时段按添加的顺序匹配 结束早于开始时跨过午夜 时间默认为 UTC
+#[derive(Debug, Clone)]
+pub struct BandwidthSchedule {
+    /// 不在任何时段内时的速率 None 为不限速
+    default: Option<u64>,
+    /// 从零点开始的分钟数 [start, end) 和该时段的速率
+    rules:   Vec<(u32, u32, Option<u64>)>,
+    /// 本地时间比 UTC 快的秒数
+    offset:  i64,
+}
+
+impl BandwidthSchedule {
+    /// default 为其余时间的速率 字节/秒 None 为不限速
+    pub fn new(default: Option<u64>) -> Self {
+        Self { default, rules: vec![], offset: 0 }
+    }
+
+    /// start 到 end 之间使用 rate 时间为 (时, 分)
+    pub fn between(mut self, start: (u32, u32), end: (u32, u32), rate: Option<u64>) -> Self {
+        let minutes = |(hour, minute): (u32, u32)| (hour * 60 + minute) % MINUTES_PER_DAY;
+        self.rules.push((minutes(start), minutes(end), rate));
+        self
+    }
+
+    /// 时段所用的时区 例如东八区为 `8 * 3600`
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.offset = seconds.into();
+        self
+    }
+
+    /// time 时的速率 None 为不限速
+    pub fn rate_at(&self, time: SystemTime) -> Option<u64> {
+        let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let minute = ((seconds + self.offset) / 60).rem_euclid(MINUTES_PER_DAY.into()) as u32;
+        let rule = self.rules.iter().find(|&&(start, end, _)| match start <= end {
+            true => (start..end).contains(&minute),
+            false => minute >= start || minute < end,
+        });
+        rule.map_or(self.default, |&(_, _, rate)| rate)
+    }
+}
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+impl Pool {
+    /// 标记 id 正在写入 返回其当前的速率
+    fn rate_of(&mut self, id: u64) -> f64 {
+        let now = Instant::now();
+        let Some(share) = self.shares.get_mut(&id) else {
+            return self.rate;
+        };
+        share.active = now;
+        let weight = share.weight;
+        let active = self.shares.values().filter(|share| now - share.active < ACTIVE);
+        let total: f64 = active.map(|share| share.weight).sum();
+        (self.rate * weight / total).max(1.0)
+    }
+}
+
+impl Drop for Bucket {
+    fn drop(&mut self) {
+        if let Some((pool, id)) = &self.share {
+            pool.lock().unwrap().shares.remove(id);
+        }
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+    }
+}
+
+impl Downloading {
+    /// 挂上限速器 之后的写入按其速率等待
+    pub fn limit(&mut self, limiter: RateLimiter) {
+        self.limiters.push(limiter);
+    }
+
+    /// 从所有限速器中取走 n 个令牌 返回需要等待的最长时间
+    pub(crate) fn reserve(&self, n: u64) -> Duration {
+        self.limiters.iter().map(|limiter| limiter.reserve(n)).max().unwrap_or_default()
+    }
+}
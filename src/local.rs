@@ -0,0 +1,176 @@
+use std::{
+    io::SeekFrom::Start,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    hash::hex,
+    transport::{Transport, TransportDownloader},
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// `read_range` 每次读取的大小
+const CHUNK: usize = 64 * 1024;
+
+/// 按地址创建下载器 支持 `file://` 和 `data:`
+///
+/// ```ignore
+/// let builder = DownloadBuilder::new("model.bin").hash(hash);
+/// local::downloader("file:///var/cache/models/model.bin", builder)?.download().await?;
+/// ```
+///
+/// `data:` 的内容就在地址中 构建器没有 hash 时使用内容的 SHA-256
+pub fn downloader(uri: &str, builder: DownloadBuilder) -> Result<TransportDownloader> {
+    if uri.starts_with("data:") {
+        let data = DataUri::parse(uri)?;
+        let builder = match builder.hash.is_empty() {
+            true => builder.hash(hex(&Sha256::digest(&data.data))),
+            false => builder,
+        };
+        return Ok(TransportDownloader::new(data, builder));
+    }
+    Ok(TransportDownloader::new(LocalFile::from_url(uri)?, builder))
+}
+
+/// 本地文件 复制时使用 `Downloading::copy_from` Linux 上数据不经过用户态
+///
+/// 以修改时间和大小作为版本 源文件变化后从头复制
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    path: PathBuf,
+}
+
+impl LocalFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `file:///path` 或 `file://localhost/path` 路径按百分号编码解码
+    pub fn from_url(url: &str) -> Result<Self> {
+        let invalid = || DownloadError::InvalidUrl(url.to_string());
+        let rest = url.strip_prefix("file://").ok_or_else(invalid)?;
+        let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+        if !rest.starts_with('/') {
+            return Err(invalid());
+        }
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        let path = String::from_utf8(percent_decode(path).ok_or_else(invalid)?);
+        let path = path.map_err(|_| invalid())?;
+        // Windows 上为 `/C:/path`
+        let path = match path.as_bytes() {
+            [b'/', drive, b':', ..] if cfg!(windows) && drive.is_ascii_alphabetic() => &path[1..],
+            _ => &path,
+        };
+        Ok(Self::new(path))
+    }
+}
+
+impl Transport for LocalFile {
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(Some(tokio::fs::metadata(&self.path).await?.len())) })
+    }
+
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let mut file = tokio::fs::File::open(&self.path).await?;
+            file.seek(Start(range.start)).await?;
+            let remain = range.end - range.start;
+            let stream = futures::stream::try_unfold((file, remain), |(mut file, remain)| {
+                async move {
+                    let mut buf = vec![0; CHUNK.min(remain.try_into().unwrap_or(CHUNK))];
+                    match file.read(&mut buf).await? {
+                        0 => Ok(None),
+                        n => {
+                            buf.truncate(n);
+                            Ok(Some((Bytes::from(buf), (file, remain - n as u64))))
+                        }
+                    }
+                }
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let metadata = tokio::fs::metadata(&self.path).await?;
+            let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+            Ok(Some(format!("{}-{}", modified.as_nanos(), metadata.len())))
+        })
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// RFC 2397 的 `data:[<媒体类型>][;base64],<数据>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataUri {
+    /// 没有时为 `text/plain;charset=US-ASCII`
+    pub mime: String,
+    pub data: Bytes,
+}
+
+impl DataUri {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let invalid = || DownloadError::InvalidUrl(uri.chars().take(64).collect());
+        let rest = uri.strip_prefix("data:").ok_or_else(invalid)?;
+        let (header, data) = rest.split_once(',').ok_or_else(invalid)?;
+        let (mime, base64) = match header.strip_suffix(";base64") {
+            Some(mime) => (mime, true),
+            None => (header, false),
+        };
+        let data = percent_decode(data).ok_or_else(invalid)?;
+        let data = match base64 {
+            true => {
+                let data: Vec<_> = data.into_iter().filter(|b| !b.is_ascii_whitespace()).collect();
+                STANDARD.decode(data).map_err(|_| invalid())?
+            }
+            false => data,
+        };
+        let mime = match mime {
+            "" => "text/plain;charset=US-ASCII".to_string(),
+            mime => mime.to_string(),
+        };
+        Ok(Self { mime, data: data.into() })
+    }
+}
+
+impl Transport for DataUri {
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(Some(self.data.len() as u64)) })
+    }
+
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let end = range.end.min(self.data.len() as u64) as usize;
+            let chunk = self.data.slice((range.start as usize).min(end)..end);
+            Ok(futures::stream::once(async move { Ok(chunk) }).boxed())
+        })
+    }
+}
+
+/// 解码为字节 data URI 的内容不一定是 UTF-8
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
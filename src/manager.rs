@@ -0,0 +1,1009 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{future::BoxFuture, Stream};
+#[cfg(feature = "http")]
+use reqwest::header::HeaderName;
+use tokio::{sync::broadcast, task::JoinHandle};
+
+#[cfg(feature = "http")]
+use crate::{
+    hash::Algorithm, hosts::HostLimits, http::HttpDownloader, proxy::ProxyConfig,
+    segments::Segmented, DownloadBuilder,
+};
+#[cfg(feature = "sqlite")]
+use crate::queue::Store;
+use crate::{
+    limit::{Bandwidth, BandwidthSchedule, RateLimiter},
+    BufferPool, CancellationToken, DownloadError, Progress, Result,
+};
+
+pub type TaskId = u64;
+
+/// 可以交给 `DownloadManager` 调度的下载任务
+///
+/// 暂停后恢复会再次调用 `run` 需要能从已下载的进度继续
+///
+/// 暂停和取消时 cancel 被取消 任务应保存进度后尽快结束
+pub trait Task: Send + Sync + 'static {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>>;
+
+    /// 管理器设置了总带宽时 在添加任务前调用 写入时应同时受 limiter 限速
+    fn use_limiter(&mut self, _limiter: RateLimiter) {}
+
+    /// 管理器设置了代理时 在添加任务前调用 任务自己设置过代理时应保持不变
+    #[cfg(feature = "http")]
+    fn use_proxy(&mut self, _proxy: &ProxyConfig) {}
+
+    /// 管理器设置了主机限制时 在添加任务前调用 任务自己设置过时应保持不变
+    #[cfg(feature = "http")]
+    fn use_host_limits(&mut self, _limits: &HostLimits) {}
+
+    /// 管理器设置了缓冲区池时 在添加任务前调用 任务自己设置过时应保持不变
+    fn use_buffer_pool(&mut self, _pool: &BufferPool) {}
+
+    /// 添加任务时调用 通过 observer 报告进度 分段完成和重试 状态变化由管理器发送
+    fn use_observer(&mut self, _observer: Observer) {}
+
+    /// 下载到的路径 交给结束时的钩子 见 `DownloadManager::on_finish`
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// 保存和导出时的选项 返回 None 的任务不会保存到队列数据库 也不能导出
+    #[cfg(feature = "http")]
+    fn spec(&self) -> Option<TaskSpec> {
+        None
+    }
+}
+
+#[cfg(feature = "http")]
+impl Task for crate::http::HttpDownloader {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>> {
+        let this = self.clone().cancel(cancel);
+        Box::pin(async move { this.download().await.map(drop) })
+    }
+
+    fn use_limiter(&mut self, limiter: RateLimiter) {
+        self.builder.limiters.push(limiter);
+    }
+
+    fn use_observer(&mut self, observer: Observer) {
+        self.builder.observer = Some(observer);
+    }
+
+    /// 自动命名时为目录
+    fn path(&self) -> Option<&Path> {
+        Some(&self.builder.path)
+    }
+
+    fn spec(&self) -> Option<TaskSpec> {
+        Some(self.task_spec(1))
+    }
+
+    fn use_proxy(&mut self, proxy: &ProxyConfig) {
+        self.default_proxy(proxy);
+    }
+
+    fn use_host_limits(&mut self, limits: &HostLimits) {
+        self.default_host_limits(limits);
+    }
+
+    fn use_buffer_pool(&mut self, pool: &BufferPool) {
+        self.builder.default_buffer_pool(pool);
+    }
+}
+
+#[cfg(feature = "http")]
+impl Task for crate::segments::Segmented {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>> {
+        let this = self.clone().cancel(cancel);
+        Box::pin(async move { this.download().await.map(drop) })
+    }
+
+    fn spec(&self) -> Option<TaskSpec> {
+        Some(self.task_spec())
+    }
+
+    fn use_limiter(&mut self, limiter: RateLimiter) {
+        self.add_limiter(limiter);
+    }
+
+    fn use_observer(&mut self, observer: Observer) {
+        self.set_observer(observer);
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.path())
+    }
+
+    fn use_proxy(&mut self, proxy: &ProxyConfig) {
+        self.default_proxy(proxy);
+    }
+
+    fn use_host_limits(&mut self, limits: &HostLimits) {
+        self.default_host_limits(limits);
+    }
+
+    fn use_buffer_pool(&mut self, pool: &BufferPool) {
+        self.default_buffer_pool(pool);
+    }
+}
+
+#[cfg(feature = "dash")]
+impl Task for crate::dash::DashDownloader {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>> {
+        let this = self.clone().cancel(cancel);
+        Box::pin(async move { this.download().await.map(drop) })
+    }
+
+    fn use_limiter(&mut self, limiter: RateLimiter) {
+        self.add_limiter(limiter);
+    }
+
+    fn use_observer(&mut self, observer: Observer) {
+        self.set_observer(observer);
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.path())
+    }
+
+    fn use_proxy(&mut self, proxy: &ProxyConfig) {
+        self.default_proxy(proxy);
+    }
+
+    fn use_host_limits(&mut self, limits: &HostLimits) {
+        self.default_host_limits(limits);
+    }
+
+    fn use_buffer_pool(&mut self, pool: &BufferPool) {
+        self.default_buffer_pool(pool);
+    }
+}
+
+#[cfg(feature = "hls")]
+impl Task for crate::hls::HlsDownloader {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>> {
+        let this = self.clone().cancel(cancel);
+        Box::pin(async move { this.download().await.map(drop) })
+    }
+
+    fn use_limiter(&mut self, limiter: RateLimiter) {
+        self.add_limiter(limiter);
+    }
+
+    fn use_observer(&mut self, observer: Observer) {
+        self.set_observer(observer);
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.path())
+    }
+
+    fn use_proxy(&mut self, proxy: &ProxyConfig) {
+        self.default_proxy(proxy);
+    }
+
+    fn use_host_limits(&mut self, limits: &HostLimits) {
+        self.default_host_limits(limits);
+    }
+
+    fn use_buffer_pool(&mut self, pool: &BufferPool) {
+        self.default_buffer_pool(pool);
+    }
+}
+
+impl Task for crate::transport::TransportDownloader {
+    fn run(&self, cancel: CancellationToken) -> BoxFuture<'static, Result<()>> {
+        let this = self.clone().cancel(cancel);
+        Box::pin(async move { this.download().await.map(drop) })
+    }
+
+    fn use_limiter(&mut self, limiter: RateLimiter) {
+        self.add_limiter(limiter);
+    }
+
+    fn use_observer(&mut self, observer: Observer) {
+        self.set_observer(observer);
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.path())
+    }
+
+    fn use_buffer_pool(&mut self, pool: &BufferPool) {
+        self.default_buffer_pool(pool);
+    }
+}
+
+/// 任务的选项 用于保存队列和导出任务 按它重新创建下载器
+///
+/// 认证 代理 cookies 等不包含在内 需要时重新设置
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskSpec {
+    /// 第一个为主地址 之后是镜像
+    pub urls:        Vec<String>,
+    pub path:        PathBuf,
+    pub hash:        String,
+    pub algorithm:   Algorithm,
+    /// 每个请求都带上的请求头
+    pub headers:     Vec<(String, String)>,
+    /// 大于 1 时按 `Segmented` 分段下载
+    pub connections: usize,
+    /// path 为目录 文件名由响应推导
+    pub auto_name:   bool,
+}
+
+#[cfg(feature = "http")]
+impl TaskSpec {
+    /// 单连接下载器 请求头无效时返回错误
+    pub fn http(&self) -> Result<HttpDownloader> {
+        let builder = DownloadBuilder::new(&self.path).hash(&self.hash);
+        let mut http = HttpDownloader::with_builder(&self.urls[0], builder)
+            .algorithm(self.algorithm)
+            .mirrors(self.urls[1..].iter().cloned());
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes());
+            let name = name.map_err(|e| DownloadError::InvalidHeader(e.to_string()))?;
+            http = http.header(name, value);
+        }
+        Ok(if self.auto_name { http.auto_filename() } else { http })
+    }
+
+    /// connections 大于 1 时的分段下载器
+    pub fn segmented(&self) -> Result<Segmented> {
+        Ok(Segmented::new(self.http()?).connections(self.connections))
+    }
+}
+
+/// 任务状态
+///
+/// 序列化时 `Failed` 中的错误只保留消息 反序列化后为带有该消息的 `DownloadError::Io`
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "TaskStateRepr", from = "TaskStateRepr")
+)]
+pub enum TaskState {
+    /// 等到该时间后开始排队
+    Scheduled(SystemTime),
+    /// 等待空闲的并发名额
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed(Arc<DownloadError>),
+    Cancelled,
+}
+
+impl TaskState {
+    /// 已结束的任务不会再被调度
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed(_) | Self::Cancelled)
+    }
+}
+
+/// 序列化时的 `TaskState`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "TaskState")]
+enum TaskStateRepr {
+    Scheduled(SystemTime),
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+
+#[cfg(feature = "serde")]
+impl From<TaskState> for TaskStateRepr {
+    fn from(state: TaskState) -> Self {
+        match state {
+            TaskState::Scheduled(start) => Self::Scheduled(start),
+            TaskState::Queued => Self::Queued,
+            TaskState::Running => Self::Running,
+            TaskState::Paused => Self::Paused,
+            TaskState::Completed => Self::Completed,
+            TaskState::Failed(e) => Self::Failed(e.to_string()),
+            TaskState::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<TaskStateRepr> for TaskState {
+    fn from(state: TaskStateRepr) -> Self {
+        match state {
+            TaskStateRepr::Scheduled(start) => Self::Scheduled(start),
+            TaskStateRepr::Queued => Self::Queued,
+            TaskStateRepr::Running => Self::Running,
+            TaskStateRepr::Paused => Self::Paused,
+            TaskStateRepr::Completed => Self::Completed,
+            TaskStateRepr::Failed(e) => {
+                Self::Failed(Arc::new(DownloadError::Io(std::io::Error::other(e))))
+            }
+            TaskStateRepr::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+/// 管理器中任务的事件 状态变化和任务报告的进度
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DownloadEvent {
+    /// 等到 start 后开始排队
+    Scheduled { id: TaskId, start: SystemTime },
+    Queued { id: TaskId },
+    Started { id: TaskId },
+    /// 运行中按间隔发送 不是每次写入都有
+    Progress { id: TaskId, progress: Progress },
+    /// `Segmented` 的一个连接下载完 range
+    SegmentCompleted { id: TaskId, range: Range<u64> },
+    /// 第 attempt 次重试之前 error 为上次失败的原因
+    Retrying { id: TaskId, attempt: u32, error: String },
+    Paused { id: TaskId },
+    Completed { id: TaskId },
+    /// 序列化时只保留错误消息 同 `TaskState::Failed`
+    Failed {
+        id:    TaskId,
+        #[cfg_attr(feature = "serde", serde(with = "message"))]
+        error: Arc<DownloadError>,
+    },
+    Cancelled { id: TaskId },
+}
+
+impl DownloadEvent {
+    pub fn id(&self) -> TaskId {
+        match self {
+            Self::Scheduled { id, .. }
+            | Self::Queued { id }
+            | Self::Started { id }
+            | Self::Progress { id, .. }
+            | Self::SegmentCompleted { id, .. }
+            | Self::Retrying { id, .. }
+            | Self::Paused { id }
+            | Self::Completed { id }
+            | Self::Failed { id, .. }
+            | Self::Cancelled { id } => *id,
+        }
+    }
+
+    /// 状态变化的事件对应的新状态 进度 分段和重试为 None
+    pub fn state(&self) -> Option<TaskState> {
+        match self {
+            Self::Scheduled { start, .. } => Some(TaskState::Scheduled(*start)),
+            Self::Queued { .. } => Some(TaskState::Queued),
+            Self::Started { .. } => Some(TaskState::Running),
+            Self::Paused { .. } => Some(TaskState::Paused),
+            Self::Completed { .. } => Some(TaskState::Completed),
+            Self::Failed { error, .. } => Some(TaskState::Failed(error.clone())),
+            Self::Cancelled { .. } => Some(TaskState::Cancelled),
+            Self::Progress { .. } | Self::SegmentCompleted { .. } | Self::Retrying { .. } => None,
+        }
+    }
+
+    fn from_state(id: TaskId, state: TaskState) -> Self {
+        match state {
+            TaskState::Scheduled(start) => Self::Scheduled { id, start },
+            TaskState::Queued => Self::Queued { id },
+            TaskState::Running => Self::Started { id },
+            TaskState::Paused => Self::Paused { id },
+            TaskState::Completed => Self::Completed { id },
+            TaskState::Failed(error) => Self::Failed { id, error },
+            TaskState::Cancelled => Self::Cancelled { id },
+        }
+    }
+}
+
+/// 错误只序列化消息
+#[cfg(feature = "serde")]
+mod message {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::DownloadError;
+
+    pub(super) fn serialize<S: Serializer>(
+        error: &Arc<DownloadError>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(error)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<DownloadError>, D::Error> {
+        let message = String::deserialize(deserializer)?;
+        Ok(Arc::new(DownloadError::Io(std::io::Error::other(message))))
+    }
+}
+
+/// 任务向管理器报告事件 由管理器在添加任务时交给 `Task::use_observer`
+#[derive(Debug, Clone)]
+pub struct Observer {
+    id:     TaskId,
+    events: broadcast::Sender<DownloadEvent>,
+}
+
+impl Observer {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 调用方自行控制频率 内置的下载器每 200ms 报告一次
+    pub fn progress(&self, progress: Progress) {
+        self.send(DownloadEvent::Progress { id: self.id, progress });
+    }
+
+    pub fn segment_completed(&self, range: Range<u64>) {
+        self.send(DownloadEvent::SegmentCompleted { id: self.id, range });
+    }
+
+    /// 第 attempt 次重试之前 从 1 开始
+    pub fn retrying(&self, attempt: u32, error: &DownloadError) {
+        self.send(DownloadEvent::Retrying { id: self.id, attempt, error: error.to_string() });
+    }
+
+    fn send(&self, event: DownloadEvent) {
+        // 没有订阅者时丢弃
+        let _ = self.events.send(event);
+    }
+}
+
+/// 任务结束时运行的钩子 见 `DownloadManager::on_finish`
+pub type Hook = Arc<dyn Fn(Finished) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// 交给钩子的任务信息
+#[derive(Debug, Clone)]
+pub struct Finished {
+    pub id:       TaskId,
+    /// `Completed` 或 `Failed`
+    pub state:    TaskState,
+    pub priority: i32,
+    /// 见 `Task::path`
+    pub path:     Option<PathBuf>,
+    /// 见 `Task::spec`
+    #[cfg(feature = "http")]
+    pub spec:     Option<TaskSpec>,
+}
+
+impl Finished {
+    fn new(id: TaskId, entry: &Entry) -> Self {
+        Self {
+            id,
+            state: entry.state.clone(),
+            priority: entry.priority,
+            path: entry.task.path().map(Path::to_path_buf),
+            #[cfg(feature = "http")]
+            spec: entry.task.spec(),
+        }
+    }
+}
+
+/// 下载管理器 按优先级排队 同时最多运行 concurrency 个任务
+///
+/// 优先级高的先运行 优先级相同时先添加的先运行 clone 出的管理器共享同一个队列
+#[derive(Clone)]
+pub struct DownloadManager {
+    inner:  Arc<Mutex<Inner>>,
+    events: broadcast::Sender<DownloadEvent>,
+}
+
+struct Inner {
+    concurrency: usize,
+    next_id:     TaskId,
+    tasks:       HashMap<TaskId, Entry>,
+    bandwidth:   Option<Bandwidth>,
+    /// 按时段调整总带宽的后台任务
+    schedule:    Option<JoinHandle<()>>,
+    #[cfg(feature = "http")]
+    proxy:       Option<ProxyConfig>,
+    #[cfg(feature = "http")]
+    hosts:       Option<HostLimits>,
+    /// 保存队列的数据库
+    #[cfg(feature = "sqlite")]
+    store:       Option<Store>,
+    /// 之后添加的任务共享的缓冲区池
+    pool:        Option<BufferPool>,
+    /// 所有任务结束时运行的钩子
+    hooks:       Vec<Hook>,
+}
+
+struct Entry {
+    task:     Arc<dyn Task>,
+    priority: i32,
+    state:    TaskState,
+    /// 运行中的任务 暂停和取消时取消
+    cancel:   Option<CancellationToken>,
+    /// 最近一次运行 再次启动前等待其结束 避免同时写入同一个文件
+    handle:   Option<JoinHandle<()>>,
+    /// 每次启动加一 忽略已被中止的旧运行结果
+    run:      u64,
+    /// 在总带宽中的份额
+    share:    Option<RateLimiter>,
+    /// 在总带宽中的权重 保存到队列数据库
+    weight:   u32,
+    /// 不早于该时间开始
+    start:    Option<SystemTime>,
+    /// 只对该任务运行的钩子
+    hooks:    Vec<Hook>,
+}
+
+impl DownloadManager {
+    pub fn new(concurrency: usize) -> Self {
+        let inner = Inner {
+            concurrency: concurrency.max(1),
+            next_id:     0,
+            tasks:       HashMap::new(),
+            bandwidth:   None,
+            schedule:    None,
+            #[cfg(feature = "http")]
+            proxy:       None,
+            #[cfg(feature = "http")]
+            hosts:       None,
+            #[cfg(feature = "sqlite")]
+            store:       None,
+            pool:        None,
+            hooks:       vec![],
+        };
+        let (events, _) = broadcast::channel(256);
+        Self { inner: Arc::new(Mutex::new(inner)), events }
+    }
+
+    /// 之后添加的任务默认使用的代理 任务自己设置的代理优先
+    #[cfg(feature = "http")]
+    pub fn proxy(&self, proxy: ProxyConfig) {
+        self.inner.lock().unwrap().proxy = Some(proxy);
+    }
+
+    /// 之后添加的任务共享的主机限制 同一主机的任务合计不超过其连接数
+    ///
+    /// 任务的名额在连接时获取 排队时不占用 任务自己设置的限制优先
+    #[cfg(feature = "http")]
+    pub fn host_limits(&self, limits: HostLimits) {
+        self.inner.lock().unwrap().hosts = Some(limits);
+    }
+
+    /// 之后添加的任务共享的缓冲区池 任务自己设置的池优先
+    pub fn buffer_pool(&self, pool: BufferPool) {
+        self.inner.lock().unwrap().pool = Some(pool);
+    }
+
+    /// 之后添加的任务共享的总带宽 每个任务的权重默认为 1 见 `set_weight`
+    pub fn bandwidth(&self, bandwidth: Bandwidth) {
+        self.inner.lock().unwrap().bandwidth = Some(bandwidth);
+    }
+
+    /// 按时段调整总带宽 每分钟按当前时间重新计算 运行中的下载不需要重启
+    ///
+    /// 没有设置 `bandwidth` 时创建一个 之后添加的任务都参与分配 再次调用时替换之前的时段
+    pub fn bandwidth_schedule(&self, schedule: BandwidthSchedule) {
+        let mut inner = self.inner.lock().unwrap();
+        let bandwidth = inner.bandwidth.get_or_insert_with(|| Bandwidth::new(u64::MAX));
+        bandwidth.set_rate(schedule.rate_at(SystemTime::now()).unwrap_or(u64::MAX));
+        let weak = Arc::downgrade(&self.inner);
+        let handle = tokio::spawn(async move {
+            loop {
+                // 对齐到整分钟 时段的边界都在整分钟上
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let wait = 60_000 - (now.as_millis() % 60_000) as u64;
+                tokio::time::sleep(Duration::from_millis(wait)).await;
+                // 管理器已经丢弃
+                let Some(inner) = weak.upgrade() else {
+                    return;
+                };
+                let bandwidth = inner.lock().unwrap().bandwidth.clone();
+                if let Some(bandwidth) = bandwidth {
+                    bandwidth.set_rate(schedule.rate_at(SystemTime::now()).unwrap_or(u64::MAX));
+                }
+            }
+        });
+        if let Some(previous) = inner.schedule.replace(handle) {
+            previous.abort();
+        }
+    }
+
+    /// 任务完成或失败时运行 hook 取消的任务不运行
+    ///
+    /// ```ignore
+    /// manager.on_finish(move |finished| {
+    ///     let client = client.clone();
+    ///     async move {
+    ///         let body = format!("{} {:?} {:?}", finished.id, finished.state, finished.path);
+    ///         let _ = client.post(WEBHOOK).body(body).send().await;
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// 钩子在后台按注册顺序依次运行 先运行任务自己的 不占用并发名额
+    /// 任务的 `Completed` 或 `Failed` 事件在钩子之前发送
+    pub fn on_finish<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Finished) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.lock().unwrap().hooks.push(boxed(hook));
+    }
+
+    /// 只对 id 运行的钩子 例如把文件移动到各自的位置 任务已经完成或失败时立即运行
+    pub fn on_task_finish<F, Fut>(&self, id: TaskId, hook: F) -> Result<()>
+    where
+        F: Fn(Finished) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.tasks.get_mut(&id).ok_or(DownloadError::TaskNotFound(id))?;
+        match entry.state {
+            TaskState::Completed | TaskState::Failed(_) => {
+                tokio::spawn(hook(Finished::new(id, entry)));
+            }
+            _ => entry.hooks.push(boxed(hook)),
+        }
+        Ok(())
+    }
+
+    /// 添加任务 有空闲名额时立即开始
+    pub fn add(&self, task: impl Task, priority: i32) -> TaskId {
+        let mut inner = self.inner.lock().unwrap();
+        let id = self.insert(&mut inner, task, priority, None, TaskState::Queued);
+        self.schedule(&mut inner);
+        id
+    }
+
+    /// 添加到 start 才开始排队的任务 例如只在夜间计费较低时下载
+    ///
+    /// 按墙上时间计算等待的时长 之后修改系统时间不会提前或推迟
+    pub fn add_at(&self, task: impl Task, priority: i32, start: SystemTime) -> TaskId {
+        let mut inner = self.inner.lock().unwrap();
+        let id = self.insert(&mut inner, task, priority, Some(start), waiting(Some(start)));
+        self.schedule(&mut inner);
+        id
+    }
+
+    /// 把队列保存到 path 的 SQLite 数据库 之后的每次状态变化都立即写入
+    ///
+    /// 数据库中已有的任务先恢复 返回它们的新 id 运行中的任务恢复为排队 已结束的任务不恢复
+    ///
+    /// 只保存 `Task::spec` 返回 Some 的任务 即 `HttpDownloader` 和 `Segmented`
+    /// 认证和代理等不保存 由管理器的 `proxy` 等设置提供
+    #[cfg(feature = "sqlite")]
+    pub fn persist(&self, path: impl AsRef<Path>) -> Result<Vec<TaskId>> {
+        let store = Store::open(path.as_ref())?;
+        // 先重建所有任务 出错时数据库保持不变
+        let rows = store.load()?;
+        let rows = rows.into_iter().map(|row| Ok((row.spec.http()?, row)));
+        let rows = rows.collect::<Result<Vec<_>>>()?;
+        // 恢复的任务重新分配 id
+        store.clear()?;
+        let mut inner = self.inner.lock().unwrap();
+        for (&id, entry) in inner.tasks.iter().filter(|(_, e)| !e.state.is_finished()) {
+            if let Some(spec) = entry.task.spec() {
+                store.insert(id, &spec, entry.priority, entry.weight, entry.start, &entry.state)?;
+            }
+        }
+        inner.store = Some(store);
+
+        let mut ids = vec![];
+        for (http, row) in rows {
+            let (priority, start) = (row.priority, row.start);
+            let id = match row.spec.connections {
+                0 | 1 => self.insert(&mut inner, http, priority, start, row.state),
+                n => {
+                    let segmented = Segmented::new(http).connections(n);
+                    self.insert(&mut inner, segmented, priority, start, row.state)
+                }
+            };
+            self.weigh(&mut inner, id, row.weight)?;
+            ids.push(id);
+        }
+        self.schedule(&mut inner);
+        Ok(ids)
+    }
+
+    /// 添加为 state 状态 等待中的任务到时间后开始排队
+    fn insert(
+        &self,
+        inner: &mut Inner,
+        mut task: impl Task,
+        priority: i32,
+        start: Option<SystemTime>,
+        state: TaskState,
+    ) -> TaskId {
+        let id = inner.next_id;
+        inner.next_id += 1;
+        task.use_observer(Observer { id, events: self.events.clone() });
+        let share = inner.bandwidth.as_ref().map(|bandwidth| bandwidth.share(1));
+        if let Some(share) = &share {
+            task.use_limiter(share.clone());
+        }
+        if let Some(pool) = &inner.pool {
+            task.use_buffer_pool(pool);
+        }
+        #[cfg(feature = "http")]
+        {
+            if let Some(proxy) = &inner.proxy {
+                task.use_proxy(proxy);
+            }
+            if let Some(limits) = &inner.hosts {
+                task.use_host_limits(limits);
+            }
+        }
+        // 写入失败时任务照常运行 只是重启后不会恢复
+        #[cfg(feature = "sqlite")]
+        if let (Some(store), Some(spec)) = (&inner.store, task.spec()) {
+            let _ = store.insert(id, &spec, priority, 1, start, &state);
+        }
+        if let TaskState::Scheduled(start) = state {
+            let wait = start.duration_since(SystemTime::now()).unwrap_or_default();
+            let manager = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(wait).await;
+                manager.due(id);
+            });
+        }
+        let entry = Entry {
+            task:     Arc::new(task),
+            priority,
+            state:    state.clone(),
+            cancel:   None,
+            handle:   None,
+            run:      0,
+            share,
+            weight:   1,
+            start,
+            hooks:    vec![],
+        };
+        inner.tasks.insert(id, entry);
+        self.emit(inner, id, state);
+        id
+    }
+
+    /// 暂停等待中 排队中或运行中的任务 运行中的任务会被取消 已写入的进度保留
+    pub fn pause(&self, id: TaskId) -> Result<()> {
+        let from = |state: &TaskState| {
+            matches!(state, TaskState::Scheduled(_) | TaskState::Queued | TaskState::Running)
+        };
+        self.transition(id, from, TaskState::Paused)
+    }
+
+    /// 恢复暂停的任务 重新排队 还没到开始时间时继续等待
+    pub fn resume(&self, id: TaskId) -> Result<()> {
+        let start = self.inner.lock().unwrap().tasks.get(&id).and_then(|entry| entry.start);
+        self.transition(id, |state| matches!(state, TaskState::Paused), waiting(start))
+    }
+
+    /// 取消未结束的任务
+    pub fn cancel(&self, id: TaskId) -> Result<()> {
+        self.transition(id, |state| !state.is_finished(), TaskState::Cancelled)
+    }
+
+    /// 修改优先级 只影响还在排队的任务
+    pub fn set_priority(&self, id: TaskId, priority: i32) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tasks.get_mut(&id).ok_or(DownloadError::TaskNotFound(id))?.priority = priority;
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &inner.store {
+            store.set_priority(id, priority)?;
+        }
+        Ok(())
+    }
+
+    /// 修改任务在总带宽中的权重 例如一个任务设为 4 另一个为 1 时前者约占 80%
+    ///
+    /// 没有设置 `bandwidth` 时不起作用
+    pub fn set_weight(&self, id: TaskId, weight: u32) -> Result<()> {
+        self.weigh(&mut self.inner.lock().unwrap(), id, weight)
+    }
+
+    fn weigh(&self, inner: &mut Inner, id: TaskId, weight: u32) -> Result<()> {
+        let entry = inner.tasks.get_mut(&id).ok_or(DownloadError::TaskNotFound(id))?;
+        entry.weight = weight;
+        if let Some(share) = &entry.share {
+            share.set_weight(weight);
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &inner.store {
+            store.set_weight(id, weight)?;
+        }
+        Ok(())
+    }
+
+    /// 修改并发数 调小时不会中止已经运行的任务
+    pub fn set_concurrency(&self, concurrency: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.concurrency = concurrency.max(1);
+        self.schedule(&mut inner);
+    }
+
+    /// 导出时任务的选项和优先级
+    #[cfg(all(feature = "http", feature = "json"))]
+    pub(crate) fn spec(&self, id: TaskId) -> Result<(Option<TaskSpec>, i32)> {
+        let inner = self.inner.lock().unwrap();
+        let entry = inner.tasks.get(&id).ok_or(DownloadError::TaskNotFound(id))?;
+        Ok((entry.task.spec(), entry.priority))
+    }
+
+    pub fn state(&self, id: TaskId) -> Option<TaskState> {
+        self.inner.lock().unwrap().tasks.get(&id).map(|entry| entry.state.clone())
+    }
+
+    /// 移除已结束的任务 返回其最终状态
+    pub fn remove(&self, id: TaskId) -> Option<TaskState> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.tasks.get(&id)?.state.is_finished() {
+            return None;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &inner.store {
+            let _ = store.remove(id);
+        }
+        inner.tasks.remove(&id).map(|entry| entry.state)
+    }
+
+    /// 订阅之后的事件 接收过慢时会跳过积压的事件 界面可以据此显示而不用轮询 `state`
+    pub fn events(&self) -> impl Stream<Item = DownloadEvent> {
+        futures::stream::unfold(self.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// from 满足时切换到 to 并取消运行中的任务
+    fn transition(
+        &self,
+        id: TaskId,
+        from: impl FnOnce(&TaskState) -> bool,
+        to: TaskState,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.tasks.get_mut(&id).ok_or(DownloadError::TaskNotFound(id))?;
+        if !from(&entry.state) {
+            return Ok(());
+        }
+        if let Some(cancel) = entry.cancel.take() {
+            cancel.cancel();
+        }
+        entry.state = to.clone();
+        self.emit(&inner, id, to);
+        self.schedule(&mut inner);
+        Ok(())
+    }
+
+    /// 按优先级启动排队中的任务直到用满并发数
+    fn schedule(&self, inner: &mut Inner) {
+        let running = inner.tasks.values().filter(|e| matches!(e.state, TaskState::Running));
+        let mut free = inner.concurrency.saturating_sub(running.count());
+        while free > 0 {
+            let next = inner
+                .tasks
+                .iter()
+                .filter(|(_, e)| matches!(e.state, TaskState::Queued))
+                .max_by_key(|(&id, e)| (e.priority, std::cmp::Reverse(id)))
+                .map(|(&id, _)| id);
+            let Some(id) = next else {
+                break;
+            };
+
+            let entry = inner.tasks.get_mut(&id).unwrap();
+            entry.run += 1;
+            entry.state = TaskState::Running;
+            let cancel = CancellationToken::new();
+            let (run, future) = (entry.run, entry.task.run(cancel.clone()));
+            let previous = entry.handle.take();
+            let manager = self.clone();
+            let handle = tokio::spawn(async move {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+                let result = future.await;
+                manager.finish(id, run, result);
+            });
+            entry.cancel = Some(cancel);
+            entry.handle = Some(handle);
+            self.emit(inner, id, TaskState::Running);
+            free -= 1;
+        }
+    }
+
+    /// 到了开始时间 等待中的任务开始排队
+    fn due(&self, id: TaskId) {
+        let from = |state: &TaskState| matches!(state, TaskState::Scheduled(_));
+        let _ = self.transition(id, from, TaskState::Queued);
+    }
+
+    /// 任务运行结束
+    fn finish(&self, id: TaskId, run: u64, result: Result<()>) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(entry) = inner.tasks.get_mut(&id) else {
+            return;
+        };
+        if entry.run != run || !matches!(entry.state, TaskState::Running) {
+            return;
+        }
+        let state = match result {
+            Ok(()) => TaskState::Completed,
+            Err(e) => TaskState::Failed(Arc::new(e)),
+        };
+        entry.cancel = None;
+        entry.state = state.clone();
+        self.emit(&inner, id, state);
+        self.hooks(&mut inner, id);
+        self.schedule(&mut inner);
+    }
+
+    /// 在后台运行任务结束时的钩子
+    fn hooks(&self, inner: &mut Inner, id: TaskId) {
+        let Some(entry) = inner.tasks.get_mut(&id) else {
+            return;
+        };
+        let finished = Finished::new(id, entry);
+        let mut hooks = std::mem::take(&mut entry.hooks);
+        hooks.extend(inner.hooks.iter().cloned());
+        if hooks.is_empty() {
+            return;
+        }
+        tokio::spawn(async move {
+            for hook in hooks {
+                hook(finished.clone()).await;
+            }
+        });
+    }
+
+    /// 同时写入队列数据库
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn emit(&self, inner: &Inner, id: TaskId, state: TaskState) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(id, ?state, "任务状态变化");
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &inner.store {
+            let _ = store.set_state(id, &state);
+        }
+        // 没有订阅者时丢弃
+        let _ = self.events.send(DownloadEvent::from_state(id, state));
+    }
+}
+
+fn boxed<F, Fut>(hook: F) -> Hook
+where
+    F: Fn(Finished) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |finished| Box::pin(hook(finished)))
+}
+
+/// 添加或恢复时的状态 没到 start 时等待
+fn waiting(start: Option<SystemTime>) -> TaskState {
+    match start {
+        Some(start) if start > SystemTime::now() => TaskState::Scheduled(start),
+        _ => TaskState::Queued,
+    }
+}
+
+impl fmt::Debug for DownloadManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("DownloadManager")
+            .field("concurrency", &inner.concurrency)
+            .field("tasks", &inner.tasks.len())
+            .finish()
+    }
+}
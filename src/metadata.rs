@@ -0,0 +1,716 @@
+use std::{io::SeekFrom::*, path::Path};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{
+    hash::{self, Algorithm, State},
+    sidecar_path, temp_path, DownloadError, Pieces, Ranges, Result,
+};
+
+/// 当前写入的元数据版本
+pub const VERSION: u8 = 3;
+/// v2 v3 元数据末尾的魔数
+const MAGIC: &[u8; 8] = b"DOWNLOAD";
+/// v2 末尾的固定部分 payload 长度(4) crc32(4) 版本(1) 魔数(8)
+const FOOTER: usize = 17;
+/// v3 槽末尾的固定部分 payload 长度(4) 序号(8) 槽大小(4) crc32(4) 版本(1) 魔数(8)
+const SLOT_FOOTER: usize = 29;
+/// 槽的最小大小
+const MIN_SLOT: u64 = 4096;
+/// v1 末尾的固定部分 size(20) offset(20)
+const V1_TAIL: usize = 40;
+
+const TAG_STATE: u8 = 1;
+const TAG_RANGES: u8 = 2;
+const TAG_VALIDATOR: u8 = 3;
+const TAG_GROWING: u8 = 4;
+const TAG_PIECES: u8 = 5;
+const TAG_ENCRYPTION: u8 = 6;
+const TAG_ALGORITHM: u8 = 7;
+
+/// 打开已有的 downloading 文件时 元数据中的 hash 或 size 与传入的不一致时的处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmendPolicy {
+    /// hash 或 size 任意一个不一致都重置下载进度
+    #[default]
+    ResetOnAnyMismatch,
+    /// 只在 hash 不一致时重置 size 不一致时保留进度并使用新的 size
+    ResetOnHashMismatch,
+    /// 总是保留进度 使用新的 hash 和 size 用于确定内容没有变化 只是 hash 的写法或来源变了
+    KeepAlways,
+}
+
+/// 下载文件的元数据
+///
+/// v3 格式 内容之后是两个同样大小的槽 每个槽的内容靠槽的末尾存放
+/// `[payload][payload 长度][序号][槽大小][crc32][版本][魔数]` 整数均为小端
+///
+/// 两个槽交替写入 读取时取校验通过且序号较大的 写入中断时另一个槽仍然有效
+/// 槽大小为 2 的幂 不够时加倍 先写入位于文件末尾的槽
+///
+/// payload 依次为 size(8) offset(8) hash 长度(4) hash 之后是 `[tag(1)][长度(4)][内容]` 扩展字段
+///
+/// 读取时兼容 v1 的十进制格式和 v2 的单份格式 下次写入时升级为 v3
+///
+/// 大小未知时 size 为已写入的字节数 元数据跟在已写入的内容之后 位置随写入移动
+/// 写入中断时可能丢失
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    pub hash:         String,
+    /// hash 使用的算法 旧版本写入的元数据中没有
+    pub algorithm:    Option<Algorithm>,
+    pub size:         u64,
+    pub offset:       u64,
+    pub len:          u64,
+    /// 增量 hash 状态
+    pub state:        Option<State>,
+    /// 已下载的区间 顺序下载时只有 [0, offset)
+    pub ranges:       Ranges,
+    /// 远程资源的 ETag 或 Last-Modified 续传时用于 If-Range
+    pub validator:    Option<String>,
+    /// 大小未知 完成时才确定
+    pub growing:      bool,
+    /// 分块 hash
+    pub pieces:       Option<Pieces>,
+    /// 内容加密存放
+    pub encrypted:    Option<Encryption>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) slots: Slots,
+}
+
+/// 两个槽在文件中的布局
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Slots {
+    /// 每个槽的大小
+    capacity: u64,
+    /// 最近一次写入的序号
+    seq:      u64,
+    /// 磁盘上的布局 (元数据开始的位置, 槽大小, 最近写入的槽) 未知时为 None
+    disk:     Option<(u64, u64, u64)>,
+}
+
+/// 加密存放的参数 密钥由调用方保存 见 `DownloadBuilder::encrypt_at_rest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Encryption {
+    /// AES-256-CTR 的初始计数器块
+    pub nonce: [u8; 16],
+    /// 由密钥和 nonce 计算 用于检查密钥是否正确
+    pub check: [u8; 8],
+}
+
+impl Metadata {
+    pub fn new(hash: impl Into<String>, size: u64) -> Self {
+        let hash = hash.into();
+        let mut meta = Self {
+            hash,
+            algorithm: None,
+            size,
+            offset: 0,
+            len: 0,
+            state: None,
+            ranges: Ranges::new(),
+            validator: None,
+            growing: false,
+            pieces: None,
+            encrypted: None,
+            slots: Slots::default(),
+        };
+        meta.resize();
+        meta
+    }
+
+    /// 大小未知 只能顺序写入
+    pub fn growing(hash: impl Into<String>) -> Self {
+        let mut meta = Self::new(hash, 0);
+        meta.growing = true;
+        meta.resize();
+        meta
+    }
+
+    /// 读取追加在文件末尾的元数据
+    pub async fn from_file(file: &mut File) -> Result<Self> {
+        let len = file.metadata().await?.len();
+        match version(file, len).await? {
+            Some(2) => {
+                let mut footer = [0; FOOTER];
+                file.seek(End(-(FOOTER as i64))).await?;
+                file.read_exact(&mut footer).await?;
+                let payload = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+                let trailer = payload + FOOTER as u64;
+                if trailer > len {
+                    return Err(DownloadError::MetadataCorrupt);
+                }
+                let mut buf = vec![0; trailer as usize];
+                file.seek(Start(len - trailer)).await?;
+                file.read_exact(&mut buf).await?;
+                Self::decode(&buf, len)
+            }
+            version => match Self::from_slots(file, len, true).await? {
+                Some(meta) => Ok(meta),
+                None if version.is_some() => Err(DownloadError::MetadataCorrupt),
+                None => Self::from_file_v1(file, len).await,
+            },
+        }
+    }
+
+    /// 从单独存放的元数据文件读取
+    pub async fn from_sidecar(file: &mut File) -> Result<Self> {
+        let len = file.metadata().await?.len();
+        match version(file, len).await? {
+            Some(2) => {
+                let mut buf = vec![];
+                file.seek(Start(0)).await?;
+                file.read_to_end(&mut buf).await?;
+                let size = buf.get(..8).ok_or(DownloadError::MetadataCorrupt)?;
+                let size = u64::from_le_bytes(size.try_into().unwrap());
+                let len = size.checked_add(buf.len() as u64);
+                Self::decode(&buf, len.ok_or(DownloadError::MetadataCorrupt)?)
+            }
+            version => match Self::from_slots(file, len, false).await? {
+                Some(meta) => Ok(meta),
+                None if version.is_some() => Err(DownloadError::MetadataCorrupt),
+                None if len < V1_TAIL as u64 => Err(DownloadError::MetadataMissing),
+                None => {
+                    let mut buf = vec![];
+                    file.seek(Start(0)).await?;
+                    file.read_to_end(&mut buf).await?;
+                    Self::parse_v1(&buf)
+                }
+            },
+        }
+    }
+
+    /// 读取 v3 的两个槽 取有效且序号较大的 没有有效的槽时返回 None
+    ///
+    /// 末尾的槽无效时按可能的槽大小查找另一个槽 inline 为 false 时是单独存放的元数据文件
+    async fn from_slots(file: &mut File, len: u64, inline: bool) -> Result<Option<Self>> {
+        // 槽大小一致 元数据之前是 size 字节的内容 单独存放时 size 同样不能溢出
+        let fits = |meta: &Self, capacity: u64| {
+            let total = meta.size.checked_add(2 * capacity);
+            match inline {
+                true => total == Some(len),
+                false => total.is_some() && 2 * capacity == len,
+            }
+        };
+        let last = read_slot(file, len).await?.filter(|(meta, _, capacity)| fits(meta, *capacity));
+        let capacities = match &last {
+            Some((_, _, capacity)) => vec![*capacity],
+            None => (MIN_SLOT.trailing_zeros()..63)
+                .map(|n| 1 << n)
+                .take_while(|capacity| 2 * capacity <= len)
+                .collect(),
+        };
+        let mut first = None;
+        for capacity in capacities {
+            if let Some((meta, seq, stored)) = read_slot(file, len - capacity).await? {
+                if stored == capacity && fits(&meta, capacity) {
+                    first = Some((meta, seq, capacity));
+                    break;
+                }
+            }
+        }
+        let (mut meta, seq, capacity, slot) = match (first, last) {
+            (Some((first, s0, c)), Some((_, s1, _))) if s0 > s1 => (first, s0, c, 0),
+            (_, Some((last, seq, capacity))) => (last, seq, capacity, 1),
+            (Some((first, seq, capacity)), None) => (first, seq, capacity, 0),
+            (None, None) => return Self::from_grown_slots(file, len, inline).await,
+        };
+        let base = if inline { meta.size } else { 0 };
+        meta.len = meta.size + 2 * capacity;
+        meta.slots = Slots { capacity, seq, disk: Some((base, capacity, slot)) };
+        Ok(Some(meta))
+    }
+
+    /// 槽大小增加时先扩展文件再写入新的槽 写入完成前中断 有效的只有原来位置上较小的两个槽
+    ///
+    /// 按可能的新旧槽大小查找 槽中记录的大小和内容的长度都要与位置一致
+    async fn from_grown_slots(file: &mut File, len: u64, inline: bool) -> Result<Option<Self>> {
+        let sizes = || (MIN_SLOT.trailing_zeros()..63).map(|n| 1u64 << n);
+        let mut found: Option<(Self, u64, u64, u64, u64)> = None;
+        for grown in sizes().take_while(|grown| 2 * grown <= len) {
+            let base = len - 2 * grown;
+            if !inline && base != 0 {
+                continue;
+            }
+            for capacity in sizes().take_while(|capacity| *capacity < grown) {
+                for slot in [0, 1] {
+                    let Some((meta, seq, stored)) =
+                        read_slot(file, base + (slot + 1) * capacity).await?
+                    else {
+                        continue;
+                    };
+                    let fits = stored == capacity
+                        && meta.size.checked_add(2 * capacity).is_some()
+                        && (!inline || meta.size == base);
+                    if fits && found.as_ref().is_none_or(|found| seq > found.1) {
+                        found = Some((meta, seq, base, capacity, slot));
+                    }
+                }
+            }
+        }
+        Ok(found.map(|(mut meta, seq, base, capacity, slot)| {
+            meta.len = meta.size + 2 * capacity;
+            meta.slots = Slots { capacity, seq, disk: Some((base, capacity, slot)) };
+            meta
+        }))
+    }
+
+    /// 存在单独的元数据文件时优先读取
+    pub(crate) async fn load(path: &Path) -> Result<Self> {
+        let sidecar = sidecar_path(path);
+        if sidecar.exists() {
+            Self::from_sidecar(&mut File::open(sidecar).await?).await
+        } else {
+            Self::from_file(&mut File::open(path).await?).await
+        }
+    }
+
+    /// 只读打开 downloading 文件读取元数据 不会修改文件
+    ///
+    /// path 可以是目标文件路径 也可以是 downloading 文件本身的路径
+    ///
+    /// 其他命名方式使用 `DownloadBuilder::metadata`
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(&temp_path(path.as_ref())).await
+    }
+
+    /// 下载进度百分比 分段下载时按已下载的总字节数计算 大小未知时为 0
+    pub fn percent(&self) -> f64 {
+        match self.size {
+            _ if self.growing => 0.0,
+            0 => 100.0,
+            size => self.ranges.downloaded() as f64 * 100.0 / size as f64,
+        }
+    }
+
+    /// 写入追加在内容之后的元数据 之前写入的槽保持不变
+    pub async fn update(&self, file: &mut File) -> Result<()> {
+        self.write_slots(file, self.size).await
+    }
+
+    /// 写入单独存放的元数据文件
+    pub async fn update_sidecar(&self, file: &mut File) -> Result<()> {
+        self.write_slots(file, 0).await
+    }
+
+    async fn write_slots(&self, file: &mut File, base: u64) -> Result<()> {
+        let (len, writes) = self.encode(base);
+        file.set_len(len).await?;
+        for (n, (pos, slot)) in writes.into_iter().enumerate() {
+            if n > 0 && self.grows(base) {
+                file.sync_data().await?;
+            }
+            file.seek(Start(pos)).await?;
+            file.write_all(&slot).await?;
+        }
+        Ok(())
+    }
+
+    /// 按 policy 保留或重置下载进度 保留时更新为传入的 hash 算法和 size
+    ///
+    /// 之前大小未知 hash 一致时视为 size 一致 已写入的超过 size 时总是重置
+    ///
+    /// 双方都记录了算法且不同时视为 hash 不一致 任意一方没有 hash 时视为一致 沿用已有的 hash
+    pub fn amend(
+        mut self,
+        hash: &str,
+        algorithm: Option<Algorithm>,
+        size: u64,
+        policy: AmendPolicy,
+    ) -> Self {
+        let written = self.ranges.iter().map(|range| range.end).max().unwrap_or(0);
+        let same = self.same_hash(hash, algorithm);
+        let keep = written <= size
+            && match policy {
+                AmendPolicy::ResetOnAnyMismatch => same && (self.size == size || self.growing),
+                AmendPolicy::ResetOnHashMismatch => same,
+                AmendPolicy::KeepAlways => true,
+            };
+        if !keep {
+            self.algorithm = algorithm;
+            self.offset = 0;
+            self.size = size;
+            self.hash.truncate(0);
+            self.hash.push_str(hash);
+            self.state = self.state.and_then(|state| State::new(state.algorithm()).ok());
+            self.ranges = Ranges::new();
+            self.validator = None;
+            self.growing = false;
+            self.pieces = None;
+            self.encrypted = None;
+            self.resize();
+        } else {
+            self.adopt(hash, algorithm);
+            self.growing = false;
+            self.size = size;
+            self.resize();
+        }
+        self
+    }
+
+    /// 大小未知时使用 hash 一致且同样是大小未知时保留下载进度 否则重新开始
+    pub fn amend_growing(mut self, hash: &str, algorithm: Option<Algorithm>) -> Self {
+        if !self.growing || !self.same_hash(hash, algorithm) {
+            let mut meta = Self::growing(hash);
+            meta.algorithm = algorithm;
+            return meta;
+        }
+        self.adopt(hash, algorithm);
+        self
+    }
+
+    /// 保留进度时使用传入的 hash 和算法 没有传入 hash 时沿用之前的
+    fn adopt(&mut self, hash: &str, algorithm: Option<Algorithm>) {
+        if hash.is_empty() && !self.hash.is_empty() {
+            return;
+        }
+        self.hash.truncate(0);
+        self.hash.push_str(hash);
+        self.algorithm = algorithm.or(self.algorithm);
+    }
+
+    /// hash 一致 且没有记录不同的算法 任意一方没有 hash 时视为一致
+    fn same_hash(&self, hash: &str, algorithm: Option<Algorithm>) -> bool {
+        if self.hash.is_empty() || hash.is_empty() {
+            return true;
+        }
+        let conflict = matches!((self.algorithm, algorithm), (Some(a), Some(b)) if a != b);
+        self.hash == hash && !conflict
+    }
+
+    /// 重新计算包含元数据的文件总长度 槽大小只增不减
+    pub(crate) fn resize(&mut self) {
+        self.slots.capacity = self.capacity();
+        self.len = self.size + 2 * self.slots.capacity;
+    }
+
+    /// 放下当前元数据需要的槽大小
+    fn capacity(&self) -> u64 {
+        let len = (self.payload().len() + SLOT_FOOTER) as u64;
+        self.slots.capacity.max(len.next_power_of_two()).max(MIN_SLOT)
+    }
+
+    /// 编码下一次写入的槽 元数据开始于 base
+    ///
+    /// 返回 (元数据所在文件的长度, [(位置, 内容)]) 先设置长度再依次写入
+    ///
+    /// 磁盘上的布局未知 位置变化或槽大小增加时写入两个槽 否则写入较旧的槽
+    pub(crate) fn encode(&self, base: u64) -> (u64, Vec<(u64, Vec<u8>)>) {
+        let capacity = self.capacity();
+        let mut slot = self.payload();
+        let len = slot.len() as u32;
+        slot.extend(len.to_le_bytes());
+        slot.extend((self.slots.seq + 1).to_le_bytes());
+        slot.extend((capacity as u32).to_le_bytes());
+        let crc = crc32fast::hash(&slot);
+        slot.extend(crc.to_le_bytes());
+        slot.push(VERSION);
+        slot.extend(MAGIC);
+
+        let end = |n: u64| base + (n + 1) * capacity - slot.len() as u64;
+        let writes = match self.next_slot(base, capacity) {
+            Some(n) => vec![(end(n), slot)],
+            None => vec![(end(1), slot.clone()), (end(0), slot)],
+        };
+        (base + 2 * capacity, writes)
+    }
+
+    /// 下一次写入的槽 None 表示两个都写入 先写入末尾的槽
+    ///
+    /// 槽大小增加时也写入两个 末尾的槽不会覆盖原来的两个槽 写完后原来的槽才会被另一个槽覆盖
+    fn next_slot(&self, base: u64, capacity: u64) -> Option<u64> {
+        match self.slots.disk {
+            Some((disk, _, _)) if disk != base => None,
+            Some((_, disk, _)) if disk < capacity => None,
+            Some((_, _, last)) => Some(1 - last),
+            None => None,
+        }
+    }
+
+    /// 槽大小是否增加 增加时写完末尾的槽要先同步 再写会覆盖原来的槽的另一个槽
+    pub(crate) fn grows(&self, base: u64) -> bool {
+        let capacity = self.capacity();
+        matches!(self.slots.disk, Some((disk, old, _)) if disk == base && old < capacity)
+    }
+
+    /// `encode` 的内容写入后记录磁盘上的布局
+    pub(crate) fn written(&mut self, base: u64) {
+        let capacity = self.capacity();
+        let (seq, slot) = (self.slots.seq + 1, self.next_slot(base, capacity).unwrap_or(1));
+        self.slots = Slots { capacity, seq, disk: Some((base, capacity, slot)) };
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        payload.extend(self.size.to_le_bytes());
+        payload.extend(self.offset.to_le_bytes());
+        payload.extend((self.hash.len() as u32).to_le_bytes());
+        payload.extend(self.hash.as_bytes());
+
+        let mut field = |tag: u8, value: &[u8]| {
+            payload.push(tag);
+            payload.extend((value.len() as u32).to_le_bytes());
+            payload.extend(value);
+        };
+        if let Some(state) = &self.state {
+            let name = state.algorithm().name();
+            let value = [&[name.len() as u8], name.as_bytes(), &state.to_bytes()].concat();
+            field(TAG_STATE, &value);
+        }
+        if !self.ranges.is_prefix() {
+            let value: Vec<u8> = self
+                .ranges
+                .iter()
+                .flat_map(|r| [r.start.to_le_bytes(), r.end.to_le_bytes()])
+                .flatten()
+                .collect();
+            field(TAG_RANGES, &value);
+        }
+        if let Some(validator) = &self.validator {
+            field(TAG_VALIDATOR, validator.as_bytes());
+        }
+        if self.growing {
+            field(TAG_GROWING, &[]);
+        }
+        if let Some(pieces) = &self.pieces {
+            field(TAG_PIECES, &pieces.encode());
+        }
+        if let Some(encrypted) = &self.encrypted {
+            field(TAG_ENCRYPTION, &[&encrypted.nonce[..], &encrypted.check].concat());
+        }
+        if let Some(algorithm) = self.algorithm {
+            field(TAG_ALGORITHM, algorithm.name().as_bytes());
+        }
+        payload
+    }
+
+    /// 解析 v2 元数据 buf 为 payload 加末尾固定部分 len 为包含元数据的文件总长度
+    fn decode(buf: &[u8], len: u64) -> Result<Self> {
+        let (payload, footer) = buf.split_at(buf.len() - FOOTER);
+        let crc = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+        if crc32fast::hash(payload) != crc {
+            return Err(DownloadError::MetadataCorrupt);
+        }
+        let meta = Self::decode_payload(payload, len).ok_or(DownloadError::MetadataCorrupt)?;
+        if meta.size.checked_add(buf.len() as u64) != Some(len) {
+            return Err(DownloadError::MetadataCorrupt);
+        }
+        Ok(meta)
+    }
+
+    /// 解析 v2 v3 共用的 payload
+    fn decode_payload(payload: &[u8], len: u64) -> Option<Self> {
+        let mut reader = Reader(payload);
+        let size = reader.u64()?;
+        let offset = reader.u64()?;
+        let hash = reader.block()?;
+        let hash = String::from_utf8(hash.to_vec()).ok()?;
+        let mut algorithm = None;
+        let mut state = None;
+        let mut ranges = Ranges::prefix(offset);
+        let mut validator = None;
+        let mut growing = false;
+        let mut pieces = None;
+        let mut encrypted = None;
+        while !reader.0.is_empty() {
+            let tag = reader.u8()?;
+            let mut value = Reader(reader.block()?);
+            match tag {
+                TAG_STATE => {
+                    let n = value.u8()? as usize;
+                    let name = value.bytes(n)?;
+                    let algorithm = std::str::from_utf8(name).ok()?.parse().ok()?;
+                    state = Some(State::from_bytes(algorithm, value.0).ok()?);
+                }
+                TAG_RANGES => {
+                    ranges = Ranges::new();
+                    while !value.0.is_empty() {
+                        ranges.insert(value.u64()?..value.u64()?);
+                    }
+                }
+                TAG_VALIDATOR => validator = Some(String::from_utf8(value.0.to_vec()).ok()?),
+                TAG_GROWING => growing = true,
+                TAG_PIECES => {
+                    let n = value.u8()? as usize;
+                    let name = value.bytes(n)?;
+                    let algorithm = std::str::from_utf8(name).ok()?.parse().ok()?;
+                    let length = value.u64().filter(|&length| length > 0)?;
+                    let mut hashes = vec![];
+                    while !value.0.is_empty() {
+                        hashes.push(String::from_utf8(value.block()?.to_vec()).ok()?);
+                    }
+                    pieces = Some(Pieces { algorithm, length, hashes });
+                }
+                TAG_ENCRYPTION => {
+                    let nonce = value.bytes(16)?.try_into().ok()?;
+                    let check = value.bytes(8)?.try_into().ok()?;
+                    encrypted = Some(Encryption { nonce, check });
+                }
+                // 之后版本新增的算法当作没有记录
+                TAG_ALGORITHM => {
+                    algorithm = std::str::from_utf8(value.0).ok().and_then(|name| name.parse().ok())
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            hash,
+            algorithm,
+            size,
+            offset,
+            len,
+            state,
+            ranges,
+            validator,
+            growing,
+            pieces,
+            encrypted,
+            slots: Slots::default(),
+        })
+    }
+
+    async fn from_file_v1(file: &mut File, len: u64) -> Result<Self> {
+        if len < V1_TAIL as u64 {
+            return Err(DownloadError::MetadataMissing);
+        }
+
+        file.seek(End(-(V1_TAIL as i64))).await?;
+        let mut buf = [0; 20];
+        file.read_exact(&mut buf).await?;
+        let size: u64 =
+            String::from_utf8_lossy(&buf).parse().map_err(|_| DownloadError::MetadataCorrupt)?;
+        if size.checked_add(V1_TAIL as u64).is_none_or(|end| len < end) {
+            return Err(DownloadError::MetadataCorrupt);
+        }
+
+        let mut buf = vec![0; (len - size) as usize];
+        file.seek(Start(size)).await?;
+        file.read_exact(&mut buf).await?;
+        Self::parse_v1(&buf)
+    }
+
+    /// 解析 v1 元数据 hash 以 \0 分隔的扩展字段 size(20) offset(20)
+    fn parse_v1(buf: &[u8]) -> Result<Self> {
+        let (body, tail) = buf.split_at(buf.len() - V1_TAIL);
+        let size: u64 = String::from_utf8_lossy(&tail[..20])
+            .parse()
+            .map_err(|_| DownloadError::MetadataCorrupt)?;
+        let offset: u64 = String::from_utf8_lossy(&tail[20..])
+            .parse()
+            .map_err(|_| DownloadError::MetadataCorrupt)?;
+
+        let body = String::from_utf8_lossy(body);
+        let mut fields = body.split('\0');
+        let hash = fields.next().unwrap_or_default().to_string();
+        let mut state = None;
+        let mut ranges = Ranges::prefix(offset);
+        for field in fields {
+            match field.split_once('=') {
+                Some(("state", value)) => {
+                    state = Some(parse_state(value).ok_or(DownloadError::MetadataCorrupt)?)
+                }
+                Some(("ranges", value)) => ranges = value.parse()?,
+                _ => {}
+            }
+        }
+        let len = size.checked_add(buf.len() as u64).ok_or(DownloadError::MetadataCorrupt)?;
+        let (validator, growing, pieces, encrypted) = (None, false, None, None);
+        let slots = Slots::default();
+        Ok(Self {
+            hash,
+            algorithm: None,
+            size,
+            offset,
+            len,
+            state,
+            ranges,
+            validator,
+            growing,
+            pieces,
+            encrypted,
+            slots,
+        })
+    }
+}
+
+/// 末尾魔数之前的版本号 没有魔数时返回 None
+async fn version(file: &mut File, len: u64) -> Result<Option<u8>> {
+    if len < FOOTER as u64 {
+        return Ok(None);
+    }
+    let mut tail = [0; 9];
+    file.seek(End(-9)).await?;
+    file.read_exact(&mut tail).await?;
+    match tail[0] {
+        _ if !tail.ends_with(MAGIC) => Ok(None),
+        version @ 2..=VERSION => Ok(Some(version)),
+        version => Err(DownloadError::UnsupportedVersion(version)),
+    }
+}
+
+/// 读取结束于 end 的 v3 槽 返回 (元数据, 序号, 写入时的槽大小) 无效时返回 None
+async fn read_slot(file: &mut File, end: u64) -> Result<Option<(Metadata, u64, u64)>> {
+    if end < SLOT_FOOTER as u64 {
+        return Ok(None);
+    }
+    let mut footer = [0; SLOT_FOOTER];
+    file.seek(Start(end - SLOT_FOOTER as u64)).await?;
+    file.read_exact(&mut footer).await?;
+    if !footer.ends_with(MAGIC) || footer[20] != 3 {
+        return Ok(None);
+    }
+    let payload = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+    let Some(start) = end.checked_sub(payload + SLOT_FOOTER as u64) else {
+        return Ok(None);
+    };
+    let mut buf = vec![0; (end - start) as usize];
+    file.seek(Start(start)).await?;
+    file.read_exact(&mut buf).await?;
+    let (slot, crc) = buf.split_at(payload as usize + 16);
+    if crc32fast::hash(slot) != u32::from_le_bytes(crc[..4].try_into().unwrap()) {
+        return Ok(None);
+    }
+    let seq = u64::from_le_bytes(slot[payload as usize + 4..][..8].try_into().unwrap());
+    let capacity = u32::from_le_bytes(slot[payload as usize + 12..].try_into().unwrap()) as u64;
+    let meta = Metadata::decode_payload(&slot[..payload as usize], 0);
+    Ok(meta.map(|meta| (meta, seq, capacity)))
+}
+
+fn parse_state(value: &str) -> Option<State> {
+    let (algorithm, bytes) = value.split_once(':')?;
+    State::from_bytes(algorithm.parse().ok()?, &hash::unhex(bytes)?).ok()
+}
+
+/// 按小端顺序读取 payload
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (bytes, rest) = self.0.split_at_checked(n)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    /// 4 字节长度前缀的内容
+    fn block(&mut self) -> Option<&'a [u8]> {
+        let n = self.u32()? as usize;
+        self.bytes(n)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+}
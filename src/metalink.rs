@@ -0,0 +1,148 @@
+use std::{
+    fmt::Display,
+    path::{Component, Path},
+};
+
+use quick_xml::{
+    escape::unescape,
+    events::{BytesStart, Event},
+    Reader, XmlVersion,
+};
+
+use crate::{hash::Algorithm, http::HttpDownloader, DownloadBuilder, DownloadError, Result};
+
+/// Metalink v4 (RFC 5854) 文档
+#[derive(Debug, Clone, Default)]
+pub struct Metalink {
+    pub files: Vec<MetalinkFile>,
+}
+
+/// 文档中描述的一个文件
+#[derive(Debug, Clone, Default)]
+pub struct MetalinkFile {
+    /// 相对路径
+    pub name:   String,
+    pub size:   Option<u64>,
+    /// (类型, hash) 类型如 `sha-256`
+    pub hashes: Vec<(String, String)>,
+    pub urls:   Vec<MetalinkUrl>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetalinkUrl {
+    pub url:      String,
+    /// 越小越优先 未设置时排在最后
+    pub priority: Option<u32>,
+    /// ISO 3166 国家代码
+    pub location: Option<String>,
+}
+
+impl Metalink {
+    /// 解析 `.meta4` 文档
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut metalink = Self::default();
+        let mut file: Option<MetalinkFile> = None;
+        loop {
+            match reader.read_event().map_err(invalid)? {
+                Event::Start(e) => match (e.local_name().as_ref(), &mut file) {
+                    ("file", _) => {
+                        let name = attr(&e, "name")?.ok_or_else(|| invalid("file 缺少 name"))?;
+                        file = Some(MetalinkFile { name, ..Default::default() });
+                    }
+                    ("size", Some(file)) => {
+                        let size = text(&mut reader, &e)?;
+                        file.size = Some(size.trim().parse().map_err(invalid)?);
+                    }
+                    ("hash", Some(file)) => {
+                        let Some(kind) = attr(&e, "type")? else {
+                            continue;
+                        };
+                        let hash = text(&mut reader, &e)?.trim().to_lowercase();
+                        file.hashes.push((kind, hash));
+                    }
+                    ("url", Some(file)) => {
+                        let priority = attr(&e, "priority")?.and_then(|p| p.parse().ok());
+                        let location = attr(&e, "location")?;
+                        let url = text(&mut reader, &e)?.trim().to_string();
+                        file.urls.push(MetalinkUrl { url, priority, location });
+                    }
+                    _ => {}
+                },
+                Event::End(e) if e.local_name().as_ref() == "file" => {
+                    metalink.files.extend(file.take());
+                }
+                Event::Eof => return Ok(metalink),
+                _ => {}
+            }
+        }
+    }
+
+    /// 读取并解析 `.meta4` 文件
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&tokio::fs::read_to_string(path).await?)
+    }
+}
+
+impl MetalinkFile {
+    /// 支持的算法中最强的 hash
+    pub fn hash(&self) -> Option<(Algorithm, &str)> {
+        let algorithm = |kind: &str| match kind {
+            "sha-256" => Some(Algorithm::Sha256),
+            "sha-1" => Some(Algorithm::Sha1),
+            "blake3" => Some(Algorithm::Blake3),
+            "md5" => Some(Algorithm::Md5),
+            _ => None,
+        };
+        let rank = |algorithm: Algorithm| match algorithm {
+            Algorithm::Blake3 => 4,
+            Algorithm::Sha256 => 3,
+            Algorithm::Sha1 => 2,
+            Algorithm::Md5 => 1,
+            Algorithm::Crc32c | Algorithm::GitSha1 => 0,
+        };
+        self.hashes
+            .iter()
+            .filter_map(|(kind, hash)| Some((algorithm(kind)?, hash.as_str())))
+            .max_by_key(|(algorithm, _)| rank(*algorithm))
+    }
+
+    /// 下载到 dir 下的 HTTP 下载器 按优先级排列地址 使用文档中的 hash 校验
+    ///
+    /// name 为绝对路径或包含 `..` 时拒绝
+    pub fn downloader(&self, dir: impl AsRef<Path>) -> Result<HttpDownloader> {
+        let name = Path::new(&self.name);
+        if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid(format!("不安全的文件名 {}", self.name)));
+        }
+        let (algorithm, hash) = self.hash().ok_or_else(|| {
+            let kinds: Vec<_> = self.hashes.iter().map(|(kind, _)| kind.as_str()).collect();
+            DownloadError::UnsupportedAlgorithm(kinds.join(","))
+        })?;
+
+        let mut urls: Vec<_> = self.urls.iter().collect();
+        urls.sort_by_key(|url| url.priority.unwrap_or(u32::MAX));
+        let mut urls = urls.into_iter().map(|url| url.url.clone());
+        let url = urls.next().ok_or_else(|| invalid(format!("{} 没有下载地址", self.name)))?;
+
+        let builder = DownloadBuilder::new(dir.as_ref().join(name)).hash(hash);
+        Ok(HttpDownloader::with_builder(url, builder).mirrors(urls).algorithm(algorithm))
+    }
+}
+
+/// 读取元素内的文本
+fn text(reader: &mut Reader<&[u8]>, start: &BytesStart) -> Result<String> {
+    let text = reader.read_text(start.name()).map_err(invalid)?;
+    Ok(unescape(&text).map_err(invalid)?.into_owned())
+}
+
+fn attr(start: &BytesStart, name: &str) -> Result<Option<String>> {
+    let Some(attr) = start.try_get_attribute(name).map_err(invalid)? else {
+        return Ok(None);
+    };
+    Ok(Some(attr.normalized_value(XmlVersion::default()).map_err(invalid)?.into_owned()))
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Metalink(e.to_string())
+}
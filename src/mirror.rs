@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use reqwest::{header, Method};
+
+use crate::{http::HttpDownloader, Result};
+
+/// 测速时下载的字节数
+const SAMPLE: u64 = 64 * 1024;
+/// 单个地址测速的最长时间
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 单个地址的测速结果
+#[derive(Debug, Clone)]
+pub struct MirrorStats {
+    pub url:        String,
+    /// 发出请求到收到响应头的时间
+    pub latency:    Duration,
+    /// 收到响应头之后的下载速度 字节/秒
+    pub throughput: f64,
+}
+
+impl HttpDownloader {
+    /// 同时向所有地址请求开头的一小段数据测速 按速度从快到慢返回 失败的地址不返回
+    pub async fn measure_mirrors(&self) -> Vec<MirrorStats> {
+        let probes = self.urls.iter().map(|url| async move {
+            let stats = tokio::time::timeout(TIMEOUT, self.measure(url)).await;
+            stats.ok()?.ok()
+        });
+        let mut stats: Vec<_> =
+            futures::future::join_all(probes).await.into_iter().flatten().collect();
+        stats.sort_by(|a, b| {
+            b.throughput.total_cmp(&a.throughput).then_with(|| a.latency.cmp(&b.latency))
+        });
+        stats
+    }
+
+    /// 测速后按速度重新排列地址 只保留最快的 top 个
+    ///
+    /// 单连接下载从最快的开始 分段下载的连接分散到这 top 个地址
+    ///
+    /// 所有地址测速都失败时保持原样
+    pub async fn fastest_mirrors(mut self, top: usize) -> Self {
+        let stats = self.measure_mirrors().await;
+        if !stats.is_empty() {
+            self.urls = stats.into_iter().take(top.max(1)).map(|stats| stats.url).collect();
+        }
+        self
+    }
+
+    async fn measure(&self, url: &str) -> Result<MirrorStats> {
+        let start = Instant::now();
+        let range = format!("bytes=0-{}", SAMPLE - 1);
+        let headers = [(header::RANGE, range.as_str())];
+        let mut response = self.send(Method::GET, url, &headers).await?.error_for_status()?;
+        let latency = start.elapsed();
+
+        // 不支持 Range 的服务端会返回整个文件 收够 SAMPLE 即停止
+        let (start, mut received) = (Instant::now(), 0);
+        while received < SAMPLE {
+            let Some(chunk) = response.chunk().await? else {
+                break;
+            };
+            received += chunk.len() as u64;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let throughput = match elapsed > 0.0 {
+            true => received as f64 / elapsed,
+            false => f64::INFINITY,
+        };
+        Ok(MirrorStats { url: url.to_string(), latency, throughput })
+    }
+}
@@ -0,0 +1,70 @@
+use std::{borrow::Cow, fs::File, io};
+
+use memmap2::{MmapOptions, MmapRaw};
+
+use crate::Downloading;
+
+/// downloading 文件中数据部分的可写映射 不包含之后的元数据
+#[derive(Debug)]
+pub(crate) struct Mapping(MmapRaw);
+
+impl Mapping {
+    /// 映射 [0, len) 文件不够长时先扩展 访问超出文件末尾的映射会导致 SIGBUS
+    fn new(file: &File, len: u64) -> io::Result<Self> {
+        if file.metadata()?.len() < len {
+            file.set_len(len)?;
+        }
+        Ok(Self(MmapOptions::new().len(len as usize).map_raw(file)?))
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    /// 从 pos 开始依次复制到映射中 超出映射范围时返回 false
+    pub(crate) fn write(&self, mut pos: u64, bufs: &[Cow<'_, [u8]>]) -> bool {
+        if pos + bufs.iter().map(|buf| buf.len() as u64).sum::<u64>() > self.len() {
+            return false;
+        }
+        for buf in bufs {
+            // SAFETY: 范围已检查 文件不短于映射 downloading 文件只由持有者写入 不会被截断
+            unsafe {
+                let dst = self.0.as_mut_ptr().add(pos as usize);
+                std::ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len());
+            }
+            pos += buf.len() as u64;
+        }
+        true
+    }
+}
+
+impl Downloading {
+    /// 按当前大小映射 大小未知时不映射 大小变化后重新映射
+    pub(crate) fn mapping(&mut self) -> io::Result<Option<&Mapping>> {
+        let size = self.meta.size;
+        if !self.writer.mmap || self.meta.growing || size == 0 || usize::try_from(size).is_err() {
+            return Ok(None);
+        }
+        if self.writer.mapping.as_ref().is_none_or(|mapping| mapping.len() != size) {
+            self.writer.mapping = Some(Mapping::new(&self.writer.data, size)?);
+        }
+        Ok(self.writer.mapping.as_ref())
+    }
+
+    /// 开始写回映射中的修改 之后的 `sync_data` 才包含这些修改
+    ///
+    /// Linux 上 `sync_data` 本来就包含 Windows 上需要先 FlushViewOfFile
+    pub(crate) fn flush_mapping(&self) -> io::Result<()> {
+        match &self.writer.mapping {
+            Some(mapping) => mapping.0.flush_async(),
+            None => Ok(()),
+        }
+    }
+
+    /// 截断或关闭文件前解除映射 Windows 上存在映射时不能截断
+    pub(crate) fn unmap(&mut self) -> io::Result<()> {
+        self.flush_mapping()?;
+        self.writer.mapping = None;
+        Ok(())
+    }
+}
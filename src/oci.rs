@@ -0,0 +1,216 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::BoxFuture;
+use reqwest::{header, Client, Method, StatusCode, Url};
+
+use crate::{
+    auth::{Auth, TokenProvider},
+    hash::Algorithm,
+    http::HttpDownloader,
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// Docker Hub 的仓库地址
+const DOCKER_HUB: &str = "https://registry-1.docker.io";
+
+/// OCI 镜像仓库中的 blob 例如镜像的层 `/v2/<name>/blobs/<digest>`
+///
+/// ```ignore
+/// let blob = OciBlob::parse("ghcr.io/owner/app@sha256:4b84...")?;
+/// blob.downloader("layer.tar.gz", Some(Auth::basic(user, token))).await?.download().await?;
+/// ```
+///
+/// blob 按内容寻址 digest 就是文件的 hash 完成时按它校验
+#[derive(Debug, Clone)]
+pub struct OciBlob {
+    registry: String,
+    name:     String,
+    digest:   String,
+}
+
+impl OciBlob {
+    /// registry 为 `ghcr.io` 或 `http://10.0.0.2:5000` 这样的地址 没有协议时使用 https
+    pub fn new(
+        registry: impl Into<String>,
+        name: impl Into<String>,
+        digest: impl Into<String>,
+    ) -> Self {
+        let registry = registry.into();
+        let registry = match registry.as_str() {
+            "docker.io" | "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB.to_string(),
+            r if r.contains("://") => r.trim_end_matches('/').to_string(),
+            // 与 Docker 一样 本机的仓库默认不使用 TLS
+            r if r.starts_with("localhost") || r.starts_with("127.") => format!("http://{r}"),
+            r => format!("https://{}", r.trim_end_matches('/')),
+        };
+        let mut name = name.into();
+        // Docker Hub 的官方镜像在 library 下
+        if registry == DOCKER_HUB && !name.contains('/') {
+            name = format!("library/{name}");
+        }
+        Self { registry, name, digest: digest.into() }
+    }
+
+    /// 解析 `[registry/]name@sha256:<hex>` 没有 registry 时为 Docker Hub
+    pub fn parse(reference: &str) -> Result<Self> {
+        let (repository, digest) = reference.split_once('@').ok_or_else(|| {
+            invalid(format!("缺少 digest {reference}"))
+        })?;
+        let (first, rest) = repository.split_once('/').unwrap_or(("", repository));
+        match first.contains(['.', ':']) || first == "localhost" {
+            true => Ok(Self::new(first, rest, digest)),
+            false => Ok(Self::new("docker.io", repository, digest)),
+        }
+    }
+
+    /// blob 的地址
+    pub fn url(&self) -> String {
+        format!("{}/v2/{}/blobs/{}", self.registry, self.name, self.digest)
+    }
+
+    /// digest 对应的算法和十六进制 hash
+    pub fn hash(&self) -> Result<(Algorithm, String)> {
+        let (algorithm, hash) = self.digest.split_once(':').ok_or_else(|| {
+            invalid(format!("无效的 digest {}", self.digest))
+        })?;
+        match algorithm {
+            "sha256" => Ok((Algorithm::Sha256, hash.to_ascii_lowercase())),
+            algorithm => Err(DownloadError::UnsupportedAlgorithm(algorithm.to_string())),
+        }
+    }
+
+    /// 下载到 path 的下载器 按 digest 校验
+    ///
+    /// 先不带认证请求一次 仓库要求 Bearer token 时向它指定的地址获取 pull 权限的 token
+    /// auth 用于获取 token 通常是 `Auth::basic` 公开的镜像不需要
+    ///
+    /// 仓库要求 Basic 认证时直接使用 auth
+    pub async fn downloader(
+        &self,
+        path: impl AsRef<Path>,
+        auth: Option<Auth>,
+    ) -> Result<HttpDownloader> {
+        let (algorithm, hash) = self.hash()?;
+        let builder = DownloadBuilder::new(path).hash(hash);
+        let http = HttpDownloader::with_builder(self.url(), builder).algorithm(algorithm);
+
+        let response = http.send(Method::HEAD, &http.urls[0], &[]).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            response.error_for_status()?;
+            return Ok(http);
+        }
+        let challenge = response.headers().get(header::WWW_AUTHENTICATE);
+        let challenge = challenge.and_then(|value| value.to_str().ok()).unwrap_or_default();
+        let Some(params) = challenge.strip_prefix("Bearer ") else {
+            let auth = auth.ok_or_else(|| invalid(format!("需要认证 {challenge}")))?;
+            return Ok(http.auth(auth));
+        };
+
+        let params = parameters(params);
+        let get = |key: &str| params.iter().find(|(k, _)| k.eq_ignore_ascii_case(key));
+        let realm = get("realm").ok_or_else(|| invalid(format!("缺少 realm {challenge}")))?;
+        let mut url = Url::parse(&realm.1).map_err(invalid)?;
+        let scope = format!("repository:{}:pull", self.name);
+        url.query_pairs_mut()
+            .extend_pairs(get("service").map(|(_, service)| ("service", service.as_str())))
+            .append_pair("scope", &scope);
+        let provider = RegistryToken {
+            client: http.client.clone(),
+            url,
+            auth,
+            token: Arc::default(),
+        };
+        Ok(http.auth(Auth::provider(provider)))
+    }
+}
+
+/// 向仓库的认证服务获取 Bearer token 过期后服务端返回 401 时重新获取
+struct RegistryToken {
+    client: Client,
+    /// realm 加上 service 和 scope
+    url:    Url,
+    auth:   Option<Auth>,
+    token:  Arc<Mutex<Option<String>>>,
+}
+
+impl RegistryToken {
+    async fn fetch(&self) -> Result<String> {
+        let mut request = self.client.get(self.url.clone());
+        if let Some(auth) = &self.auth {
+            let token = auth.token(false).await?;
+            request = auth.apply(request, token.as_deref())?;
+        }
+        let body = request.send().await?.error_for_status()?.text().await?;
+        // 规范中是 token 有的实现只返回 access_token
+        let token = json_string(&body, "token").or_else(|| json_string(&body, "access_token"));
+        let token = token.ok_or_else(|| invalid("认证服务的响应中没有 token"))?;
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(token)
+    }
+}
+
+impl TokenProvider for RegistryToken {
+    fn token(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let token = self.token.lock().unwrap().clone();
+            match token {
+                Some(token) => Ok(token),
+                None => self.fetch().await,
+            }
+        })
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(self.fetch())
+    }
+}
+
+/// `realm="...",service="..."` 值可能没有引号
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = value.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, next.split_once(',').map_or("", |(_, next)| next))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.push((key.trim().to_string(), value.to_string()));
+        rest = next.trim_start();
+    }
+    params
+}
+
+/// JSON 对象中顶层字符串字段的值 只处理常见的转义
+fn json_string(body: &str, key: &str) -> Option<String> {
+    let pattern = format!("\"{key}\"");
+    let after = &body[body.find(&pattern)? + pattern.len()..];
+    let after = after.trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    let (mut value, mut chars) = (String::new(), after.chars());
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Oci(e.to_string())
+}
@@ -0,0 +1,111 @@
+use std::{
+    fmt,
+    io::{self, Cursor, Read, Seek, SeekFrom::Start},
+    sync::Arc,
+};
+
+use tokio::fs::File;
+
+use crate::{DownloadError, Downloading, Result};
+
+/// BLAKE3 的块大小 只能校验到块的边界
+const CHUNK: u64 = 1024;
+/// 读取时的缓冲大小
+const BUFFER: usize = 1024 * 1024;
+
+/// BLAKE3 树的 bao outboard 编码 即不含文件内容的中间节点 hash
+///
+/// 根 hash 就是文件的 BLAKE3 hash 有了 outboard 可以逐块校验 不用下载完整个文件
+/// 就能证明已下载的前缀是正确的 大小约为文件的 1/16
+#[derive(Clone)]
+pub struct Outboard {
+    data: Arc<Vec<u8>>,
+}
+
+impl Outboard {
+    /// 由 `bao encode --outboard` 等工具生成的数据
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Self { data: Arc::new(data.into()) }
+    }
+
+    /// 为完整的文件生成 outboard 返回 outboard 和文件的 BLAKE3 hash
+    pub async fn encode(file: &mut File) -> Result<(Self, String)> {
+        let mut file = file.try_clone().await?.into_std().await;
+        let task = tokio::task::spawn_blocking(move || -> Result<(Self, String)> {
+            file.seek(Start(0))?;
+            let mut encoder = bao::encode::Encoder::new_outboard(Cursor::new(vec![]));
+            io::copy(&mut file, &mut encoder)?;
+            let hash = encoder.finalize()?;
+            Ok((Self::new(encoder.into_inner().into_inner()), hash.to_hex().to_string()))
+        });
+        task.await?
+    }
+
+    /// 头部记录的文件大小
+    pub fn size(&self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.data.get(..8)?.try_into().ok()?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl fmt::Debug for Outboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Outboard").field("size", &self.size()).finish_non_exhaustive()
+    }
+}
+
+impl Downloading {
+    /// 用 outboard 校验从头连续下载的部分 返回证明正确的长度
+    ///
+    /// 未下载完时只校验到最后一个完整的块 其余部分不校验 损坏之后的部分会被丢弃 续传时重新下载
+    ///
+    /// hash 不是 BLAKE3 或 outboard 与文件大小不一致时返回 `DownloadError::InvalidOutboard`
+    pub async fn verify_outboard(&mut self, outboard: &Outboard) -> Result<u64> {
+        let hash = blake3::Hash::from_hex(&self.meta.hash)
+            .ok()
+            .filter(|_| !self.meta.growing && outboard.size() == Some(self.meta.size))
+            .ok_or(DownloadError::InvalidOutboard)?;
+        let end = match self.meta.offset == self.meta.size {
+            true => self.meta.size,
+            false => self.meta.offset / CHUNK * CHUNK,
+        };
+
+        self.writer.cursor = None;
+        let mut file = self.file.try_clone().await?.into_std().await;
+        let data = outboard.data.clone();
+        #[cfg(feature = "encrypt")]
+        let sealer = self.at_rest.clone();
+        let task = tokio::task::spawn_blocking(move || -> Result<u64> {
+            file.seek(Start(0))?;
+            #[cfg(feature = "encrypt")]
+            let file = crate::encrypt::Unsealed::new(file, sealer);
+            let mut decoder = bao::decode::Decoder::new_outboard(file, Cursor::new(&*data), &hash);
+            let (mut pos, mut buf) = (0, vec![0; BUFFER]);
+            while pos < end {
+                let n = buf.len().min((end - pos) as usize);
+                match decoder.read(&mut buf[..n]) {
+                    Ok(0) => break,
+                    Ok(n) => pos += n as u64,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(pos)
+        });
+        let verified = task.await??;
+
+        if verified < end {
+            // 增量 hash 已经包含了错误的数据
+            self.meta.state = None;
+            self.meta.ranges.remove(verified..self.meta.offset);
+            self.meta.offset = self.meta.ranges.offset();
+            self.progress.readable(self.meta.offset);
+            self.meta.resize();
+            self.save().await?;
+        }
+        Ok(verified)
+    }
+}
@@ -0,0 +1,42 @@
+use crate::Downloading;
+
+/// `Downloading::write` 等方法写入成功后的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// 还有未写入的区间
+    InProgress {
+        /// 本次写入的结束位置
+        offset:    u64,
+        /// 还没有写入文件的字节数 `write_buffer` 缓冲中的数据也算在内 大小未知时为 None
+        remaining: Option<u64>,
+    },
+    /// 所有区间都已写满 可以调用 `complete`
+    Complete,
+}
+
+impl WriteOutcome {
+    pub fn is_complete(&self) -> bool {
+        matches!(self, Self::Complete)
+    }
+
+    /// 本次写入的结束位置 写满后为 None
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Self::InProgress { offset, .. } => Some(*offset),
+            Self::Complete => None,
+        }
+    }
+}
+
+impl Downloading {
+    /// 写入到 end 后的状态 大小未知时总是 `InProgress`
+    pub(crate) fn outcome(&self, end: u64) -> WriteOutcome {
+        if self.meta.growing {
+            return WriteOutcome::InProgress { offset: end, remaining: None };
+        }
+        match self.meta.size - self.meta.ranges.downloaded() {
+            0 => WriteOutcome::Complete,
+            remaining => WriteOutcome::InProgress { offset: end, remaining: Some(remaining) },
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    mem,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    limit::RateLimiter, sidecar_path, BufferPool, CancellationToken, Downloading, Metadata,
+    PersistPolicy, Result, SyncPolicy,
+};
+
+/// 已暂停的下载 不持有文件句柄
+#[derive(Debug)]
+pub struct PausedDownload {
+    path:     PathBuf,
+    target:   PathBuf,
+    limiters: Vec<RateLimiter>,
+    cancel:   Option<CancellationToken>,
+    sync:     SyncPolicy,
+    persist:  PersistPolicy,
+    buffer:   usize,
+    pool:     Option<BufferPool>,
+    direct:   bool,
+    #[cfg(feature = "mmap")]
+    mmap:     bool,
+}
+
+impl Downloading {
+    /// 暂停下载 写入元数据并同步到磁盘后关闭文件
+    pub async fn pause(mut self) -> Result<PausedDownload> {
+        self.flush().await?;
+        self.save().await?;
+        #[cfg(feature = "mmap")]
+        self.unmap()?;
+        self.file.sync_all().await?;
+        if let Some(sidecar) = &self.sidecar {
+            sidecar.sync_all().await?;
+        }
+        Ok(PausedDownload {
+            path:     mem::take(&mut self.path),
+            target:   mem::take(&mut self.target),
+            limiters: mem::take(&mut self.limiters),
+            cancel:   self.cancel.take(),
+            sync:     self.writer.sync,
+            persist:  self.writer.persist,
+            buffer:   self.writer.buffer,
+            pool:     self.writer.pool.take(),
+            direct:   self.writer.direct.is_some(),
+            #[cfg(feature = "mmap")]
+            mmap:     self.writer.mmap,
+        })
+    }
+}
+
+impl PausedDownload {
+    /// 重新打开文件继续下载 保留暂停前的限速器 取消令牌 同步和写入元数据的策略 写入缓冲 缓冲区池 O_DIRECT 和映射
+    pub async fn resume(self) -> Result<Downloading> {
+        let mut downloading = Downloading::reopen(self.path, self.target).await?;
+        downloading.limiters = self.limiters;
+        downloading.cancel = self.cancel;
+        downloading.writer.sync = self.sync;
+        downloading.writer.persist = self.persist;
+        downloading.writer.buffer = self.buffer;
+        downloading.writer.pool = self.pool;
+        downloading.direct_io(self.direct);
+        #[cfg(feature = "mmap")]
+        {
+            downloading.writer.mmap = self.mmap;
+        }
+        Ok(downloading)
+    }
+
+    /// 放弃下载 删除 downloading 文件和单独存放的元数据文件
+    pub async fn abort(self) -> Result<()> {
+        tokio::fs::remove_file(&self.path).await?;
+        let sidecar = sidecar_path(&self.path);
+        if sidecar.exists() {
+            tokio::fs::remove_file(sidecar).await?;
+        }
+        Ok(())
+    }
+
+    /// 只读读取暂停时保存的元数据
+    pub async fn meta(&self) -> Result<Metadata> {
+        Metadata::load(&self.path).await
+    }
+
+    /// 下载完成后的文件路径
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+}
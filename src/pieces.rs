@@ -0,0 +1,154 @@
+use std::{io::SeekFrom::Start, ops::Range};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    hash::{Algorithm, Hasher},
+    DownloadError, Downloading, Result,
+};
+
+/// 读取分块时的缓冲大小
+const CHUNK: usize = 1024 * 1024;
+
+/// 分块 hash 类似 BitTorrent 和 aria2 的 piece hash
+///
+/// 每块下载完成后立即校验 不一致时丢弃该块重新下载 不用等到 `complete` 才发现损坏
+///
+/// 保存在元数据中 每次写入元数据都会写入全部 hash 块不宜太小
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pieces {
+    pub algorithm: Algorithm,
+    /// 每块的长度 最后一块可能更短
+    pub length:    u64,
+    /// 按顺序的小写十六进制 hash
+    pub hashes:    Vec<String>,
+}
+
+impl Pieces {
+    pub fn new(
+        algorithm: Algorithm,
+        length: u64,
+        hashes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        let hashes = hashes.into_iter().map(|hash| hash.as_ref().to_lowercase()).collect();
+        Self { algorithm, length: length.max(1), hashes }
+    }
+
+    /// 第 index 块在文件中的范围
+    pub fn range(&self, index: usize, size: u64) -> Range<u64> {
+        let start = index as u64 * self.length;
+        start.min(size)..(start + self.length).min(size)
+    }
+
+    /// 与 range 相交的块
+    pub(crate) fn overlapping(&self, range: &Range<u64>) -> Range<usize> {
+        if range.is_empty() {
+            return 0..0;
+        }
+        let end = ((range.end - 1) / self.length + 1) as usize;
+        (range.start / self.length) as usize..end.min(self.hashes.len())
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let name = self.algorithm.name();
+        let mut buf = [&[name.len() as u8], name.as_bytes()].concat();
+        buf.extend(self.length.to_le_bytes());
+        for hash in &self.hashes {
+            buf.extend((hash.len() as u32).to_le_bytes());
+            buf.extend(hash.as_bytes());
+        }
+        buf
+    }
+}
+
+impl Downloading {
+    /// 校验所有已下载完整的块 丢弃不一致的块 返回这些块的序号
+    ///
+    /// 没有分块 hash 时返回空
+    pub async fn verify_pieces(&mut self) -> Result<Vec<usize>> {
+        let Some(count) = self.meta.pieces.as_ref().map(|pieces| pieces.hashes.len()) else {
+            return Ok(vec![]);
+        };
+        let mut bad = vec![];
+        for index in 0..count {
+            if !self.verify_piece(index).await? {
+                bad.push(index);
+            }
+        }
+        if !bad.is_empty() {
+            self.save().await?;
+        }
+        Ok(bad)
+    }
+
+    /// 只用分块 hash 校验并完成下载 用于没有整个文件 hash 的来源 例如 BitTorrent
+    ///
+    /// 没有分块 hash 时返回 `DownloadError::NoChecksum` 损坏的块丢弃后返回 `DownloadError::Corrupted`
+    pub async fn complete_pieces(mut self) -> Result<()> {
+        if self.meta.pieces.is_none() {
+            return Err(DownloadError::NoChecksum);
+        }
+        if self.meta.offset != self.meta.size {
+            return Err(DownloadError::Incomplete);
+        }
+        let corrupted = self.verify_pieces().await?;
+        if !corrupted.is_empty() {
+            return Err(DownloadError::Corrupted(corrupted));
+        }
+        let hash = self.meta.hash.clone();
+        self.complete(async |_| Ok(hash)).await
+    }
+
+    /// 写入 range 后校验因此下载完整的块 不一致时丢弃并返回 `DownloadError::PieceMismatch`
+    pub(crate) async fn verify_written(&mut self, range: Range<u64>) -> Result<()> {
+        let Some(pieces) = self.meta.pieces.as_ref().map(|pieces| pieces.overlapping(&range)) else {
+            return Ok(());
+        };
+        for index in pieces {
+            if !self.verify_piece(index).await? {
+                self.save().await?;
+                return Err(DownloadError::PieceMismatch(index));
+            }
+        }
+        Ok(())
+    }
+
+    /// 块未下载完整时跳过 不一致时从已下载的区间中移除 不写入元数据
+    async fn verify_piece(&mut self, index: usize) -> Result<bool> {
+        let Some(pieces) = &self.meta.pieces else {
+            return Ok(true);
+        };
+        let range = pieces.range(index, self.meta.size);
+        if range.is_empty() || !self.meta.ranges.contains(&range) {
+            return Ok(true);
+        }
+
+        let mut hasher = Hasher::new(pieces.algorithm);
+        let mut buf = vec![0; CHUNK.min((range.end - range.start) as usize)];
+        let mut remain = range.end - range.start;
+        self.writer.cursor = None;
+        self.file.seek(Start(range.start)).await?;
+        while remain > 0 {
+            let n = buf.len().min(remain as usize);
+            self.file.read_exact(&mut buf[..n]).await?;
+            #[cfg(feature = "encrypt")]
+            self.unseal(range.end - remain, &mut buf[..n]);
+            hasher.update(&buf[..n]);
+            remain -= n as u64;
+        }
+        if hasher.finalize() == pieces.hashes[index] {
+            return Ok(true);
+        }
+
+        // 增量 hash 已经包含了错误的数据
+        if range.start < self.meta.offset {
+            self.meta.state = None;
+        }
+        self.meta.ranges.remove(range);
+        self.meta.offset = self.meta.ranges.offset();
+        self.progress.readable(self.meta.offset);
+        self.meta.resize();
+        Ok(false)
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use reqwest::{header, Method, StatusCode};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{watch, Mutex},
+};
+
+use crate::{
+    cancellable,
+    hash::Hasher,
+    http::{is_remote, validator, HttpDownloader},
+    progress::Reporter,
+    DownloadError, Metadata, Progress, Result,
+};
+
+/// 下载到任意 `AsyncWrite` 例如标准输出 管道或内存 不创建文件
+///
+/// 使用下载器的重试 镜像 限速和取消设置 重试时从已写出的位置继续
+///
+/// 写出的数据无法撤回 全部写出后才校验 hash 校验失败时由调用方丢弃已写出的数据
+pub struct Pipe<'a, W> {
+    http:     &'a HttpDownloader,
+    state:    Mutex<State<W>>,
+    receiver: watch::Receiver<Progress>,
+}
+
+struct State<W> {
+    writer:    W,
+    hasher:    Hasher,
+    /// 只用于记录进度 大小在第一次请求后确定
+    meta:      Metadata,
+    progress:  Reporter,
+    validator: Option<String>,
+}
+
+impl HttpDownloader {
+    /// 下载到 writer 见 `Pipe`
+    pub fn pipe<W: AsyncWrite + Unpin>(&self, writer: W) -> Pipe<'_, W> {
+        let meta = Metadata::growing(self.builder.hash.as_str());
+        let progress = Reporter::new(&meta);
+        let receiver = progress.subscribe();
+        let hasher = Hasher::new(self.algorithm);
+        let state = State { writer, hasher, meta, progress, validator: None };
+        Pipe { http: self, state: Mutex::new(state), receiver }
+    }
+
+    /// 下载到 writer 并校验 返回写出的字节数 需要进度时使用 `pipe`
+    pub async fn download_to<W: AsyncWrite + Unpin>(&self, writer: W) -> Result<u64> {
+        self.pipe(writer).run().await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Pipe<'_, W> {
+    /// 订阅下载进度
+    pub fn progress(&self) -> watch::Receiver<Progress> {
+        self.receiver.clone()
+    }
+
+    /// 下载并校验 返回写出的字节数 hash 为空时不校验
+    pub async fn run(self) -> Result<u64> {
+        let http = self.http;
+        let (cancel, observer) = (http.builder.cancel.as_ref(), http.builder.observer.as_ref());
+        let download = http.retry.run(cancel, observer, || self.attempt());
+        http.within_deadline(download).await?;
+
+        let State { mut writer, hasher, meta, .. } = self.state.into_inner();
+        writer.flush().await?;
+        let (expected, actual) = (&http.builder.hash, hasher.finalize());
+        if !expected.is_empty() && *expected != actual {
+            return Err(DownloadError::HashMismatch { expected: expected.clone(), actual });
+        }
+        Ok(meta.offset)
+    }
+
+    /// 单次下载 远程出错时轮流切换镜像 所有地址都没有进展时返回最后的错误
+    async fn attempt(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let probe = self.http.probe().await?;
+        if state.meta.offset == 0 {
+            let hash = self.http.builder.hash.as_str();
+            state.meta = match probe.size {
+                Some(size) => Metadata::new(hash, size),
+                None => Metadata::growing(hash),
+            };
+        }
+
+        let urls = &self.http.urls;
+        let (mut mirror, mut failures) = (0, 0);
+        loop {
+            let offset = state.meta.offset;
+            let result = self.transfer(&urls[mirror], &mut state).await;
+            let progressed = state.meta.offset > offset;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(DownloadError::Stalled) if progressed => {}
+                Err(e) if is_remote(&e) => {
+                    failures = if progressed { 0 } else { failures + 1 };
+                    if failures >= urls.len() {
+                        return Err(e);
+                    }
+                    mirror = (mirror + 1) % urls.len();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 从已写出的位置请求 服务端不支持 Range 时跳过已写出的部分
+    ///
+    /// 已写出的数据无法撤回 资源变化时返回 `DownloadError::ResourceChanged`
+    async fn transfer(&self, url: &str, state: &mut State<W>) -> Result<()> {
+        let offset = state.meta.offset;
+        let range = format!("bytes={offset}-");
+        let mut headers = vec![];
+        if offset > 0 {
+            headers.push((header::RANGE, range.as_str()));
+            if let Some(stored) = &state.validator {
+                headers.push((header::IF_RANGE, stored.as_str()));
+            }
+        }
+        let mut response = self.http.send(Method::GET, url, &headers).await?.error_for_status()?;
+
+        let mut skip = match response.status() {
+            StatusCode::PARTIAL_CONTENT => 0,
+            _ if offset > 0 && state.validator.is_some() => {
+                return Err(DownloadError::ResourceChanged);
+            }
+            _ => offset,
+        };
+        state.validator = validator(response.headers()).or(state.validator.take());
+        while let Some(chunk) = self.http.chunk(&mut response).await? {
+            let n = skip.min(chunk.len() as u64);
+            skip -= n;
+            if n as usize != chunk.len() {
+                self.write(state, &chunk[n as usize..]).await?;
+            }
+        }
+        if state.meta.offset != state.meta.size && !state.meta.growing {
+            return Err(DownloadError::ConnectionClosed);
+        }
+        Ok(())
+    }
+
+    async fn write(&self, state: &mut State<W>, buf: &[u8]) -> Result<()> {
+        let (end, n) = (state.meta.offset + buf.len() as u64, buf.len() as u64);
+        if end > state.meta.size && !state.meta.growing {
+            return Err(DownloadError::Overflow);
+        }
+        let builder = &self.http.builder;
+        let delay = builder.limiters.iter().map(|limiter| limiter.reserve(n)).max();
+        if let Some(delay) = delay.filter(|delay| !delay.is_zero()) {
+            cancellable(builder.cancel.as_ref(), tokio::time::sleep(delay)).await?;
+        }
+        state.writer.write_all(buf).await?;
+        state.hasher.update(buf);
+        state.meta.ranges.insert(state.meta.offset..end);
+        state.meta.offset = end;
+        state.meta.size = state.meta.size.max(end);
+        state.progress.update(&state.meta, n);
+        Ok(())
+    }
+}
+
+impl<W> fmt::Debug for Pipe<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipe").field("progress", &*self.receiver.borrow()).finish_non_exhaustive()
+    }
+}
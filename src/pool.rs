@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+use crate::Downloading;
+
+/// 多个下载共享的缓冲区池 写入时复制数据和合并写入用的缓冲区从池中取出 用完放回
+///
+/// ```ignore
+/// let pool = BufferPool::new(64 << 10, 1024);
+/// manager.buffer_pool(pool.clone());
+/// ```
+///
+/// clone 出的池共享同一组缓冲区 同时下载大量文件时避免每个数据块都分配和释放
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    buffers:  Mutex<Vec<Vec<u8>>>,
+    /// 新分配的缓冲区预留的容量
+    capacity: usize,
+    /// 池中最多保留的缓冲区数
+    max:      usize,
+}
+
+impl BufferPool {
+    /// 每个缓冲区预留 capacity 字节 最多保留 max 个
+    pub fn new(capacity: usize, max: usize) -> Self {
+        let buffers = Mutex::new(Vec::with_capacity(max.min(1024)));
+        Self { inner: Arc::new(Inner { buffers, capacity, max }) }
+    }
+
+    /// 取出一个清空的缓冲区 池中没有时新分配
+    pub fn get(&self) -> Vec<u8> {
+        let buf = self.inner.buffers.lock().unwrap().pop();
+        buf.unwrap_or_else(|| Vec::with_capacity(self.inner.capacity))
+    }
+
+    /// 清空后放回 池已满或容量超过预留的 4 倍时丢弃 避免一直占用偶尔的大块内存
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > self.inner.capacity.saturating_mul(4) {
+            return;
+        }
+        buf.clear();
+        let mut buffers = self.inner.buffers.lock().unwrap();
+        if buffers.len() < self.inner.max {
+            buffers.push(buf);
+        }
+    }
+
+    /// 池中空闲的缓冲区数
+    pub fn idle(&self) -> usize {
+        self.inner.buffers.lock().unwrap().len()
+    }
+}
+
+/// 64 KiB 的缓冲区 最多保留 256 个
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(64 << 10, 256)
+    }
+}
+
+impl Downloading {
+    /// 从池中取出缓冲区 没有设置池时新分配 capacity 字节
+    pub(crate) fn take_buffer(&self, capacity: usize) -> Vec<u8> {
+        match &self.writer.pool {
+            Some(pool) => pool.get(),
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    /// 用完的缓冲区放回池中
+    pub(crate) fn recycle(&self, bufs: impl IntoIterator<Item = Vec<u8>>) {
+        if let Some(pool) = &self.writer.pool {
+            bufs.into_iter().for_each(|buf| pool.put(buf));
+        }
+    }
+}
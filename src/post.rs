@@ -0,0 +1,139 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{hash::Algorithm, DownloadBuilder, DownloadError, Result};
+
+/// 下载完成并校验后对文件的处理 依次为改名 设置权限 修改时间 写入 checksum 文件 解压
+///
+/// ```ignore
+/// let post = PostProcess::new()
+///     .rename("{stem}-{hash8}.{ext}")
+///     .permissions(0o644)
+///     .preserve_mtime()
+///     .checksum_file();
+/// HttpDownloader::new(url, "model.bin", hash).post_process(post).download().await?;
+/// ```
+///
+/// 返回的路径为处理后的路径 某一步失败时之前的步骤不会撤销
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PostProcess {
+    template: Option<String>,
+    mode:     Option<u32>,
+    mtime:    bool,
+    checksum: bool,
+    /// 解压到的目录
+    #[cfg(feature = "extract")]
+    extract:  Option<PathBuf>,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按模板在同一目录下改名 已存在的同名文件会被覆盖
+    ///
+    /// `{name}` 文件名 `{stem}` 去掉扩展名的文件名 `{ext}` 扩展名 没有时为空
+    /// `{hash}` 期望的 hash `{hash8}` hash 的前 8 位
+    pub fn rename(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    /// Unix 上的权限 例如 `0o644` 其他平台忽略
+    pub fn permissions(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// 修改时间设为响应的 `Last-Modified` 没有该响应头时不修改
+    pub fn preserve_mtime(mut self) -> Self {
+        self.mtime = true;
+        self
+    }
+
+    /// 在文件旁写入 `<文件名>.<算法>` 格式同 `sha256sum` 的输出 没有期望的 hash 时不写入
+    pub fn checksum_file(mut self) -> Self {
+        self.checksum = true;
+        self
+    }
+
+    /// 解压到 dir 格式按扩展名判断 见 `extract::extract_file` 压缩包保留
+    #[cfg(feature = "extract")]
+    pub fn extract(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.extract = Some(dir.into());
+        self
+    }
+
+    async fn apply(
+        &self,
+        path: PathBuf,
+        hash: &str,
+        algorithm: Algorithm,
+        modified: Option<SystemTime>,
+    ) -> Result<PathBuf> {
+        let path = match &self.template {
+            Some(template) => {
+                let renamed = path.with_file_name(render(template, &path, hash)?);
+                tokio::fs::rename(&path, &renamed).await?;
+                renamed
+            }
+            None => path,
+        };
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).await?;
+        }
+        if let Some(modified) = modified.filter(|_| self.mtime) {
+            let file = path.clone();
+            let task = tokio::task::spawn_blocking(move || {
+                std::fs::File::options().write(true).open(file)?.set_modified(modified)
+            });
+            task.await??;
+        }
+        if self.checksum && !hash.is_empty() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let mut sums = path.clone().into_os_string();
+            sums.push(format!(".{}", algorithm.name()));
+            tokio::fs::write(sums, format!("{hash}  {name}\n")).await?;
+        }
+        #[cfg(feature = "extract")]
+        if let Some(dir) = &self.extract {
+            crate::extract::extract_file(&path, dir).await?;
+        }
+        Ok(path)
+    }
+}
+
+impl DownloadBuilder {
+    /// 按构建器的 `PostProcess` 处理下载完成的 path 返回处理后的路径
+    pub(crate) async fn finish(
+        &self,
+        path: PathBuf,
+        algorithm: Algorithm,
+        modified: Option<SystemTime>,
+    ) -> Result<PathBuf> {
+        match &self.post {
+            Some(post) => post.apply(path, &self.hash, algorithm, modified).await,
+            None => Ok(path),
+        }
+    }
+}
+
+/// 展开模板 结果不能包含路径分隔符
+fn render(template: &str, path: &Path, hash: &str) -> Result<String> {
+    let text = |s: Option<&std::ffi::OsStr>| s.unwrap_or_default().to_string_lossy().into_owned();
+    let name = template
+        .replace("{name}", &text(path.file_name()))
+        .replace("{stem}", &text(path.file_stem()))
+        .replace("{ext}", &text(path.extension()))
+        .replace("{hash8}", hash.get(..8).unwrap_or(hash))
+        .replace("{hash}", hash);
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(DownloadError::InvalidTemplate(name));
+    }
+    Ok(name)
+}
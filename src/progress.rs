@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::{manager::Observer, Downloading, Metadata};
+
+/// 向管理器报告进度的最小间隔
+const REPORT: Duration = Duration::from_millis(200);
+
+/// 下载进度 每次写入后更新
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Progress {
+    /// 已下载的字节数
+    pub downloaded: u64,
+    /// 文件大小
+    pub total:      u64,
+    /// 进度百分比
+    pub percent:    f64,
+    /// 距上次写入的瞬时速度 字节/秒
+    pub speed:      f64,
+    /// 完成前校验 hash 时已读取的字节数 下载时为 None
+    pub verified:   Option<u64>,
+}
+
+/// 向 `watch` 通道发送进度
+#[derive(Debug)]
+pub(crate) struct Reporter {
+    sender:   watch::Sender<Progress>,
+    /// 已经写入文件可以读取的连续位置 见 `Downloading::reader`
+    readable: watch::Sender<u64>,
+    last:     Instant,
+    observer: Option<Observer>,
+    /// 上次向 observer 报告的时间
+    reported: Instant,
+}
+
+impl Reporter {
+    pub(crate) fn new(meta: &Metadata) -> Self {
+        let progress = Progress {
+            downloaded: meta.ranges.downloaded(),
+            total:      meta.size,
+            percent:    meta.percent(),
+            speed:      0.0,
+            verified:   None,
+        };
+        Self {
+            sender:   watch::Sender::new(progress),
+            readable: watch::Sender::new(meta.offset),
+            last:     Instant::now(),
+            observer: None,
+            reported: Instant::now(),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, observer: Option<Observer>) {
+        self.observer = observer;
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Progress> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn subscribe_readable(&self) -> watch::Receiver<u64> {
+        self.readable.subscribe()
+    }
+
+    /// offset 之前的数据已经落到文件中
+    pub(crate) fn readable(&self, offset: u64) {
+        self.readable.send_if_modified(|readable| {
+            let modified = *readable != offset;
+            *readable = offset;
+            modified
+        });
+    }
+
+    /// 写入 n 字节后更新进度 没有接收者时同样更新 之后订阅的可以拿到最新值
+    pub(crate) fn update(&mut self, meta: &Metadata, n: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.sender.send_modify(|progress| {
+            progress.downloaded = meta.ranges.downloaded();
+            progress.total = meta.size;
+            progress.percent = meta.percent();
+            if elapsed > 0.0 {
+                progress.speed = n as f64 / elapsed;
+            }
+            progress.verified = None;
+        });
+        if let Some(observer) = &self.observer {
+            // 写完时总是报告
+            let progress = *self.sender.borrow();
+            let finished = progress.downloaded == progress.total;
+            if finished || now.duration_since(self.reported) >= REPORT {
+                self.reported = now;
+                observer.progress(progress);
+            }
+        }
+    }
+}
+
+/// 在阻塞线程中报告校验进度 通过同一个通道和 observer
+#[derive(Debug, Clone)]
+pub(crate) struct Verifier {
+    sender:   watch::Sender<Progress>,
+    observer: Option<Observer>,
+    reported: Instant,
+}
+
+impl Reporter {
+    pub(crate) fn verifier(&self) -> Verifier {
+        Verifier {
+            sender:   self.sender.clone(),
+            observer: self.observer.clone(),
+            reported: Instant::now(),
+        }
+    }
+}
+
+impl Verifier {
+    /// 已读取 verified 字节 observer 每 200ms 报告一次 读完时总是报告
+    pub(crate) fn update(&mut self, verified: u64) {
+        self.sender.send_modify(|progress| progress.verified = Some(verified));
+        if let Some(observer) = &self.observer {
+            let progress = *self.sender.borrow();
+            let now = Instant::now();
+            if verified >= progress.total || now.duration_since(self.reported) >= REPORT {
+                self.reported = now;
+                observer.progress(progress);
+            }
+        }
+    }
+}
+
+impl Downloading {
+    /// 订阅下载进度
+    pub fn progress(&self) -> watch::Receiver<Progress> {
+        self.progress.subscribe()
+    }
+}
@@ -0,0 +1,30 @@
+pub use reqwest::Proxy;
+
+use crate::Result;
+
+/// 代理设置
+///
+/// 支持 `http://` `https://` `socks5://` `socks5h://` 代理 用户名密码可以写在地址中
+/// 也可以通过 `Proxy::basic_auth` 设置
+#[derive(Debug, Clone, Default)]
+pub enum ProxyConfig {
+    /// 读取 `HTTP_PROXY` `HTTPS_PROXY` `ALL_PROXY` `NO_PROXY` 环境变量 (包括小写)
+    #[default]
+    Env,
+    /// 直接连接 忽略环境变量
+    Direct,
+    /// 按顺序匹配的代理 都不匹配时直接连接
+    Custom(Vec<Proxy>),
+}
+
+impl ProxyConfig {
+    /// 所有请求都通过 url 代理
+    pub fn url(url: &str) -> Result<Self> {
+        Ok(Self::Custom(vec![Proxy::all(url)?]))
+    }
+
+    /// 所有请求都通过 url 代理 并使用用户名密码认证
+    pub fn with_auth(url: &str, username: &str, password: &str) -> Result<Self> {
+        Ok(Self::Custom(vec![Proxy::all(url)?.basic_auth(username, password)]))
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{
+    params,
+    types::{Value, ValueRef},
+    Connection,
+};
+
+use crate::{
+    hash::Algorithm,
+    manager::{TaskId, TaskSpec, TaskState},
+    Result,
+};
+
+/// 队列数据库中的一个任务 恢复时重新分配 id
+pub(crate) struct Row {
+    pub(crate) spec:     TaskSpec,
+    pub(crate) priority: i32,
+    pub(crate) weight:   u32,
+    pub(crate) start:    Option<SystemTime>,
+    /// 只会是 Scheduled Queued 或 Paused 运行中的任务恢复为排队
+    pub(crate) state:    TaskState,
+}
+
+/// SQLite 中的任务队列 每次状态变化都立即写入
+pub(crate) struct Store {
+    conn: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS tasks (
+    id          INTEGER PRIMARY KEY,
+    urls        TEXT NOT NULL,
+    path        TEXT NOT NULL,
+    hash        TEXT NOT NULL,
+    algorithm   TEXT NOT NULL,
+    headers     TEXT NOT NULL,
+    connections INTEGER NOT NULL,
+    auto_name   INTEGER NOT NULL,
+    priority    INTEGER NOT NULL,
+    weight      INTEGER NOT NULL,
+    start       INTEGER,
+    state       TEXT NOT NULL
+)";
+
+/// 不再恢复的状态
+const FINISHED: &str = "('completed', 'failed', 'cancelled')";
+
+impl Store {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// 删除已结束的任务 返回其余的任务 按 id 排序
+    pub(crate) fn load(&self) -> Result<Vec<Row>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(&format!("DELETE FROM tasks WHERE state IN {FINISHED}"), [])?;
+        let mut statement = conn.prepare(
+            "SELECT urls, path, hash, algorithm, headers, connections, auto_name,
+                    priority, weight, start, state
+             FROM tasks ORDER BY id",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let headers: String = row.get(4)?;
+            let headers = headers.lines().filter_map(|line| line.split_once(": "));
+            let headers = headers.map(|(name, value)| (name.to_string(), value.to_string()));
+            let spec = TaskSpec {
+                urls:        row.get::<_, String>(0)?.lines().map(str::to_string).collect(),
+                path:        path_from(row.get_ref(1)?)?,
+                hash:        row.get(2)?,
+                algorithm:   row.get::<_, String>(3)?.parse().unwrap_or(Algorithm::Sha256),
+                headers:     headers.collect(),
+                connections: row.get(5)?,
+                auto_name:   row.get(6)?,
+            };
+            let start: Option<u64> = row.get(9)?;
+            let start = start.map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+            let state = match row.get::<_, String>(10)?.as_str() {
+                "scheduled" => TaskState::Scheduled(start.unwrap_or(UNIX_EPOCH)),
+                "paused" => TaskState::Paused,
+                _ => TaskState::Queued,
+            };
+            Ok(Row { spec, priority: row.get(7)?, weight: row.get(8)?, start, state })
+        })?;
+        let rows: Vec<_> = rows.collect::<Result<_, _>>()?;
+        // 地址为空的记录无法恢复
+        Ok(rows.into_iter().filter(|row| !row.spec.urls.is_empty()).collect())
+    }
+
+    pub(crate) fn insert(
+        &self,
+        id: TaskId,
+        spec: &TaskSpec,
+        priority: i32,
+        weight: u32,
+        start: Option<SystemTime>,
+        state: &TaskState,
+    ) -> Result<()> {
+        let headers = spec.headers.iter().map(|(name, value)| format!("{name}: {value}"));
+        let headers = headers.collect::<Vec<_>>().join("\n");
+        let start = start.map(|start| {
+            start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+        });
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO tasks
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                id,
+                spec.urls.join("\n"),
+                path_value(&spec.path),
+                spec.hash,
+                spec.algorithm.name(),
+                headers,
+                spec.connections,
+                spec.auto_name,
+                priority,
+                weight,
+                start,
+                name(state),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn set_state(&self, id: TaskId, state: &TaskState) -> Result<()> {
+        self.update(id, "state", name(state))
+    }
+
+    pub(crate) fn set_priority(&self, id: TaskId, priority: i32) -> Result<()> {
+        self.update(id, "priority", priority)
+    }
+
+    pub(crate) fn set_weight(&self, id: TaskId, weight: u32) -> Result<()> {
+        self.update(id, "weight", weight)
+    }
+
+    pub(crate) fn clear(&self) -> Result<()> {
+        self.conn.lock().unwrap().execute("DELETE FROM tasks", [])?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, id: TaskId) -> Result<()> {
+        self.conn.lock().unwrap().execute("DELETE FROM tasks WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// 不保存的任务没有对应的行 更新不会生效
+    fn update(&self, id: TaskId, column: &str, value: impl rusqlite::ToSql) -> Result<()> {
+        let sql = format!("UPDATE tasks SET {column} = ?1 WHERE id = ?2");
+        self.conn.lock().unwrap().execute(&sql, params![value, id])?;
+        Ok(())
+    }
+}
+
+/// 路径是 UTF-8 时保存为文本 否则保存为 BLOB
+fn path_value(path: &Path) -> Value {
+    match path.to_str() {
+        Some(path) => Value::Text(path.to_string()),
+        None => Value::Blob(encode_path(path)),
+    }
+}
+
+fn path_from(value: ValueRef<'_>) -> rusqlite::Result<PathBuf> {
+    match value {
+        ValueRef::Text(text) => Ok(PathBuf::from(String::from_utf8_lossy(text).into_owned())),
+        ValueRef::Blob(bytes) => Ok(decode_path(bytes)),
+        value => Err(rusqlite::Error::InvalidColumnType(1, "path".into(), value.data_type())),
+    }
+}
+
+/// 原始字节
+#[cfg(unix)]
+fn encode_path(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn decode_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// UTF-16LE 保留不成对的代理项
+#[cfg(windows)]
+fn encode_path(path: &Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str().encode_wide().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(windows)]
+fn decode_path(bytes: &[u8]) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+
+    let wide: Vec<u16> = bytes.chunks_exact(2).map(|u| u16::from_le_bytes([u[0], u[1]])).collect();
+    PathBuf::from(std::ffi::OsString::from_wide(&wide))
+}
+
+fn name(state: &TaskState) -> &'static str {
+    match state {
+        TaskState::Scheduled(_) => "scheduled",
+        TaskState::Queued => "queued",
+        TaskState::Running => "running",
+        TaskState::Paused => "paused",
+        TaskState::Completed => "completed",
+        TaskState::Failed(_) => "failed",
+        TaskState::Cancelled => "cancelled",
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fmt, ops::Range, str::FromStr};
+
+use crate::DownloadError;
+
+/// 已下载的区间 按起点排序 互不重叠也不相邻
+///
+/// 反序列化时逐个插入 重叠或相邻的区间会被合并
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "Vec<Range<u64>>")
+)]
+pub struct Ranges(Vec<Range<u64>>);
+
+impl From<Vec<Range<u64>>> for Ranges {
+    fn from(ranges: Vec<Range<u64>>) -> Self {
+        let mut this = Self::new();
+        ranges.into_iter().for_each(|range| this.insert(range));
+        this
+    }
+}
+
+impl Ranges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 0 开始连续下载了 offset 字节
+    pub fn prefix(offset: u64) -> Self {
+        let mut ranges = Self::new();
+        ranges.insert(0..offset);
+        ranges
+    }
+
+    /// 插入区间 与已有区间重叠或相邻时合并
+    pub fn insert(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        let (mut start, mut end) = (range.start, range.end);
+        let i = self.0.partition_point(|r| r.end < start);
+        let j = self.0.partition_point(|r| r.start <= end);
+        if i < j {
+            start = start.min(self.0[i].start);
+            end = end.max(self.0[j - 1].end);
+        }
+        self.0.splice(i..j, std::iter::once(start..end));
+    }
+
+    /// 移除区间 用于丢弃校验失败的数据
+    pub fn remove(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        let mut rest = Vec::with_capacity(self.0.len() + 1);
+        for r in self.0.drain(..) {
+            if r.end <= range.start || range.end <= r.start {
+                rest.push(r);
+                continue;
+            }
+            if r.start < range.start {
+                rest.push(r.start..range.start);
+            }
+            if range.end < r.end {
+                rest.push(range.end..r.end);
+            }
+        }
+        self.0 = rest;
+    }
+
+    /// 区间是否已全部下载
+    pub fn contains(&self, range: &Range<u64>) -> bool {
+        range.is_empty() || self.0.iter().any(|r| r.start <= range.start && range.end <= r.end)
+    }
+
+    /// 从 0 开始连续下载的长度
+    pub fn offset(&self) -> u64 {
+        self.0.first().filter(|r| r.start == 0).map_or(0, |r| r.end)
+    }
+
+    /// 已下载的总字节数
+    pub fn downloaded(&self) -> u64 {
+        self.0.iter().map(|r| r.end - r.start).sum()
+    }
+
+    /// [0, size) 中还未下载的区间
+    pub fn missing(&self, size: u64) -> Vec<Range<u64>> {
+        let mut missing = vec![];
+        let mut pos = 0;
+        for r in self.0.iter().take_while(|r| r.start < size) {
+            if r.start > pos {
+                missing.push(pos..r.start);
+            }
+            pos = r.end;
+        }
+        if pos < size {
+            missing.push(pos..size);
+        }
+        missing
+    }
+
+    /// 是否只有从 0 开始的一段 即顺序下载
+    pub fn is_prefix(&self) -> bool {
+        self.0.is_empty() || (self.0.len() == 1 && self.0[0].start == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<u64>> {
+        self.0.iter()
+    }
+}
+
+/// 格式 `0-100,200-300`
+impl fmt::Display for Ranges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, r) in self.0.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(f, "{sep}{}-{}", r.start, r.end)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Ranges {
+    type Err = DownloadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Self::new();
+        for r in s.split(',').filter(|r| !r.is_empty()) {
+            let (start, end) = r.split_once('-').ok_or(DownloadError::MetadataCorrupt)?;
+            let start = start.parse().map_err(|_| DownloadError::MetadataCorrupt)?;
+            let end = end.parse().map_err(|_| DownloadError::MetadataCorrupt)?;
+            ranges.insert(start..end);
+        }
+        Ok(ranges)
+    }
+}
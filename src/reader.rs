@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, ReadBuf},
+    sync::watch,
+};
+
+use crate::{Downloading, Result};
+
+/// 边下载边读取 从头顺序读取已经写入文件的连续部分
+///
+/// 读到下载位置时等待新的数据 而不是返回结束 可以用于预览视频或解压正在下载的压缩包
+///
+/// 下载完成后读到文件末尾结束 下载在完成前被丢弃时返回 `UnexpectedEof`
+pub struct DownloadReader {
+    file:     File,
+    pos:      u64,
+    /// 大小未知时为 None
+    size:     Option<u64>,
+    readable: watch::Receiver<u64>,
+    /// 等待新的数据 写入方丢弃时返回 Err
+    changed:  Option<BoxFuture<'static, Result<(), watch::error::RecvError>>>,
+    closed:   bool,
+    /// 加密存放时读取后解密
+    #[cfg(feature = "encrypt")]
+    sealer:   Option<crate::encrypt::Sealer>,
+}
+
+impl DownloadReader {
+    /// 已经读取的字节数
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+}
+
+impl AsyncRead for DownloadReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let readable = *this.readable.borrow_and_update();
+            if this.pos < readable {
+                let max = buf.remaining().min((readable - this.pos) as usize);
+                let mut limited = ReadBuf::new(buf.initialize_unfilled_to(max));
+                ready!(Pin::new(&mut this.file).poll_read(cx, &mut limited))?;
+                let n = limited.filled().len();
+                #[cfg(feature = "encrypt")]
+                if let Some(sealer) = &this.sealer {
+                    sealer.apply(this.pos, limited.filled_mut());
+                }
+                buf.advance(n);
+                this.pos += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            if this.size == Some(this.pos) || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if this.closed {
+                return match this.size {
+                    Some(_) => Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    None => Poll::Ready(Ok(())),
+                };
+            }
+
+            let changed = this.changed.get_or_insert_with(|| {
+                let mut readable = this.readable.clone();
+                Box::pin(async move { readable.changed().await })
+            });
+            let result = ready!(changed.as_mut().poll(cx));
+            this.changed = None;
+            this.closed = result.is_err();
+        }
+    }
+}
+
+impl fmt::Debug for DownloadReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadReader")
+            .field("pos", &self.pos)
+            .field("size", &self.size)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Downloading {
+    /// 打开一个从头读取的 [`DownloadReader`] 可以打开多个
+    ///
+    /// 只读取从头开始连续下载的部分 分段下载时要等前面的区间下载完成
+    pub async fn reader(&self) -> Result<DownloadReader> {
+        let size = (!self.meta.growing).then_some(self.meta.size);
+        Ok(DownloadReader {
+            file: File::open(&self.path).await?,
+            pos: 0,
+            size,
+            readable: self.progress.subscribe_readable(),
+            changed: None,
+            closed: false,
+            #[cfg(feature = "encrypt")]
+            sealer: self.at_rest.clone(),
+        })
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::Display;
+#[cfg(feature = "http3")]
+use std::sync::atomic::Ordering;
+
+use reqwest::{
+    header::{self, HeaderName},
+    Method, Response, StatusCode, Url,
+};
+
+#[cfg(feature = "http3")]
+use reqwest::Version;
+
+use crate::{http::HttpDownloader, DownloadError, Result};
+
+/// 重定向策略
+///
+/// 内置客户端不自动跟随重定向 由下载器按策略处理 使用自定义客户端时以客户端的设置为准
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// 最多跟随的次数 0 为不跟随
+    pub max_hops:   usize,
+    /// 是否允许重定向到其他源 协议 主机或端口任意一个不同都是其他源
+    pub cross_host: bool,
+    /// 重定向到其他源时去掉 Authorization 和 Cookie 也不再附加 `auth` 的认证
+    pub strip_auth: bool,
+    /// 是否允许从 https 重定向到 http
+    pub downgrade:  bool,
+}
+
+impl Default for RedirectPolicy {
+    /// 最多 10 次 允许跨源 跨源时去掉认证信息 不允许降级到 http
+    fn default() -> Self {
+        Self { max_hops: 10, cross_host: true, strip_auth: true, downgrade: false }
+    }
+}
+
+/// 跨源时去掉的请求头
+const SENSITIVE: [HeaderName; 3] =
+    [header::AUTHORIZATION, header::COOKIE, header::PROXY_AUTHORIZATION];
+
+impl HttpDownloader {
+    /// 发送请求并按重定向策略跟随 返回最终的响应 `Response::url` 为最终地址
+    pub(crate) async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[(HeaderName, &str)],
+    ) -> Result<Response> {
+        let policy = &self.redirect;
+        let mut url = Url::parse(url).map_err(invalid_url)?;
+        let (mut method, mut hops, mut strip) = (method, 0, false);
+        let auth = self.auth.as_ref();
+        let mut token = match auth {
+            Some(auth) => auth.token(false).await?,
+            None => None,
+        };
+        let mut refreshed = false;
+        if let Some(hosts) = &self.hosts {
+            hosts.pace(url.as_str(), self.builder.cancel.as_ref()).await?;
+        }
+        loop {
+            let mut request = self.client.request(method.clone(), url.clone());
+            let custom = self.headers.iter().map(|(name, value)| (name, value.as_str()));
+            for (name, value) in custom.chain(headers.iter().map(|(name, value)| (name, *value))) {
+                if !(strip && SENSITIVE.contains(name)) {
+                    request = request.header(name, value);
+                }
+            }
+            if let Some(auth) = auth.filter(|_| !strip) {
+                request = auth.apply(request, token.as_deref())?;
+            }
+            #[cfg(feature = "http3")]
+            let http3 = self.http3.as_ref().filter(|http3| http3.load(Ordering::Relaxed));
+            #[cfg(feature = "http3")]
+            if http3.is_some() {
+                request = request.version(Version::HTTP_3);
+            }
+            let response = match request.send().await {
+                // QUIC 握手超时等错误不一定能归类为连接错误 没有响应就改用 TCP 重发
+                #[cfg(feature = "http3")]
+                Err(_) if http3.is_some() => {
+                    http3.into_iter().for_each(|http3| http3.store(false, Ordering::Relaxed));
+                    continue;
+                }
+                response => response?,
+            };
+            // token 可能已过期 刷新后重发一次
+            if response.status() == StatusCode::UNAUTHORIZED && token.is_some() && !strip {
+                if let Some(auth) = auth.filter(|_| !refreshed) {
+                    token = auth.token(true).await?;
+                    refreshed = true;
+                    continue;
+                }
+            }
+            let location = response.headers().get(header::LOCATION);
+            let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+                return Ok(response);
+            };
+            if hops >= policy.max_hops {
+                return Err(DownloadError::TooManyRedirects);
+            }
+            hops += 1;
+
+            let next = url.join(location.to_str().map_err(invalid_url)?).map_err(invalid_url)?;
+            if url.scheme() == "https" && next.scheme() == "http" && !policy.downgrade {
+                return Err(DownloadError::RedirectRejected(next.to_string()));
+            }
+            // 协议 主机和端口 (省略时为协议的默认端口) 都相同才是同源
+            if next.origin() != url.origin() {
+                if !policy.cross_host {
+                    return Err(DownloadError::RedirectRejected(next.to_string()));
+                }
+                strip |= policy.strip_auth;
+            }
+            if response.status() == StatusCode::SEE_OTHER && method != Method::HEAD {
+                method = Method::GET;
+            }
+            url = next;
+        }
+    }
+}
+
+fn invalid_url(e: impl Display) -> DownloadError {
+    DownloadError::InvalidUrl(e.to_string())
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    time::Duration,
+};
+
+use crate::{cancellable, manager::Observer, CancellationToken, DownloadError, Result};
+
+/// 哪些错误需要重试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOn {
+    /// 5xx 响应 以及 FTP 的 4xx 响应
+    pub server_error: bool,
+    /// 请求超时或长时间没有收到数据
+    pub timeout:      bool,
+    /// 连接失败
+    pub connect:      bool,
+    /// 传输过程中连接被重置或提前结束
+    pub reset:        bool,
+}
+
+impl Default for RetryOn {
+    fn default() -> Self {
+        Self { server_error: true, timeout: true, connect: true, reset: true }
+    }
+}
+
+/// 下载的重试策略 每次重试都从已保存的进度继续
+///
+/// 第 n 次重试前等待 `base * 2^(n-1)` 不超过 `max_delay` 开启 jitter 时在 [0, delay] 中随机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// 包括第一次在内的最多尝试次数
+    pub max_attempts: u32,
+    pub base:         Duration,
+    pub max_delay:    Duration,
+    pub jitter:       bool,
+    pub retry_on:     RetryOn,
+}
+
+impl Default for RetryPolicy {
+    /// 最多 5 次 从 500ms 开始 最长等待 30s
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base:         Duration::from_millis(500),
+            max_delay:    Duration::from_secs(30),
+            jitter:       true,
+            retry_on:     RetryOn::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 不重试
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// 第 attempt 次重试前的等待时间 从 1 开始
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base.saturating_mul(factor).min(self.max_delay);
+        match self.jitter {
+            true => delay.mul_f64(random()),
+            false => delay,
+        }
+    }
+
+    /// 错误是否属于需要重试的类型
+    pub fn should_retry(&self, e: &DownloadError) -> bool {
+        let on = &self.retry_on;
+        match e {
+            #[cfg(feature = "http")]
+            DownloadError::Http(e) => {
+                (on.server_error && e.status().is_some_and(|s| s.is_server_error()))
+                    || (on.timeout && e.is_timeout())
+                    || (on.connect && e.is_connect())
+                    || (on.reset && (e.is_body() || e.is_request()))
+            }
+            DownloadError::Io(e) => match e.kind() {
+                ErrorKind::TimedOut => on.timeout,
+                ErrorKind::ConnectionRefused => on.connect,
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => on.reset,
+                ErrorKind::UnexpectedEof => on.reset,
+                _ => false,
+            },
+            // 4xx 为暂时性的错误 例如连接数过多
+            #[cfg(feature = "ftp")]
+            DownloadError::Ftp { code, .. } => on.server_error && *code / 100 == 4,
+            DownloadError::Stalled => on.timeout,
+            DownloadError::ConnectionClosed => on.reset,
+            // 损坏的块已丢弃 重试时只重新下载该块
+            DownloadError::PieceMismatch(_) => on.reset,
+            // 重试时只重新下载损坏的块
+            DownloadError::Corrupted(_) => on.reset,
+            // 重试时会丢弃旧的进度从头下载
+            DownloadError::ResourceChanged => on.reset,
+            _ => false,
+        }
+    }
+
+    /// 执行 f 失败时按策略重试 等待期间取消返回 `DownloadError::Cancelled`
+    ///
+    /// 重试前通知 observer
+    pub(crate) async fn run<F, Fut, T>(
+        &self,
+        cancel: Option<&CancellationToken>,
+        observer: Option<&Observer>,
+        f: F,
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let future = f();
+            #[cfg(feature = "tracing")]
+            let future = tracing::Instrument::instrument(
+                future,
+                tracing::info_span!("attempt", attempt),
+            );
+            match future.await {
+                Err(e) if attempt < self.max_attempts && self.should_retry(&e) => {
+                    if let Some(observer) = observer {
+                        observer.retrying(attempt, &e);
+                    }
+                    #[cfg(feature = "metrics")]
+                    crate::telemetry::retry();
+                    let delay = self.delay(attempt);
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt, ?delay, error = %e, "重试");
+                    cancellable(cancel, tokio::time::sleep(delay)).await?;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// [0, 1) 之间的随机数
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
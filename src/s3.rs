@@ -0,0 +1,276 @@
+use std::{fmt, time::SystemTime};
+
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, Request,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{Auth, Signer},
+    cloud::{base64_hex, uri_encode, Utc},
+    filename::percent_decode,
+    hash::{hex, Algorithm},
+    http::HttpDownloader,
+    DownloadBuilder, DownloadError, Result,
+};
+
+/// GET 和 HEAD 请求不对请求体签名
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// AWS 的访问凭证
+#[derive(Clone)]
+pub struct Credentials {
+    access_key:    String,
+    secret_key:    String,
+    /// STS 临时凭证的 token
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(access_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+        Self { access_key: access_key.into(), secret_key: secret_key.into(), session_token: None }
+    }
+
+    /// 临时凭证的 session token
+    pub fn session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = Some(token.into());
+        self
+    }
+
+    /// 读取 `AWS_ACCESS_KEY_ID` `AWS_SECRET_ACCESS_KEY` 和 `AWS_SESSION_TOKEN`
+    pub fn from_env() -> Option<Self> {
+        let env = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let credentials = Self::new(env("AWS_ACCESS_KEY_ID")?, env("AWS_SECRET_ACCESS_KEY")?);
+        Some(Self { session_token: env("AWS_SESSION_TOKEN"), ..credentials })
+    }
+}
+
+/// 不输出密钥
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials").field("access_key", &self.access_key).finish_non_exhaustive()
+    }
+}
+
+/// AWS Signature Version 4 配合 `Auth::signer` 使用
+///
+/// 签名 host 和所有 `x-amz-` 请求头 Range 等其他请求头不参与签名
+#[derive(Debug, Clone)]
+pub struct SigV4 {
+    credentials: Credentials,
+    region:      String,
+    service:     String,
+}
+
+impl SigV4 {
+    /// 默认服务为 `s3`
+    pub fn new(credentials: Credentials, region: impl Into<String>) -> Self {
+        Self { credentials, region: region.into(), service: "s3".to_string() }
+    }
+
+    pub fn service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    fn sign_at(&self, request: &mut Request, now: SystemTime) -> Result<()> {
+        let now = Utc::new(now);
+        let (date, time) = (now.date(), now.basic());
+        let credentials = &self.credentials;
+        let headers = request.headers_mut();
+        headers.insert("x-amz-date", header_value(&time)?);
+        headers.insert("x-amz-content-sha256", HeaderValue::from_static(UNSIGNED_PAYLOAD));
+        if let Some(token) = &credentials.session_token {
+            headers.insert("x-amz-security-token", header_value(token)?);
+        }
+
+        let url = request.url();
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut signed = vec![("host".to_string(), host)];
+        for (name, value) in request.headers() {
+            if name.as_str().starts_with("x-amz-") {
+                let value = value.to_str().map_err(invalid_header)?.trim();
+                signed.push((name.as_str().to_string(), value.to_string()));
+            }
+        }
+        signed.sort();
+        let names = signed.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_headers: String =
+            signed.iter().map(|(name, value)| format!("{name}:{value}\n")).collect();
+
+        let path = url.path().split('/').map(|segment| {
+            uri_encode(&percent_decode(segment).unwrap_or_else(|| segment.to_string()))
+        });
+        let mut query: Vec<_> =
+            url.query_pairs().map(|(key, value)| (uri_encode(&key), uri_encode(&value))).collect();
+        query.sort();
+        let query = query.iter().map(|(key, value)| format!("{key}={value}"));
+        let canonical = format!(
+            "{}\n{}\n{}\n{canonical_headers}\n{names}\n{UNSIGNED_PAYLOAD}",
+            request.method(),
+            path.collect::<Vec<_>>().join("/"),
+            query.collect::<Vec<_>>().join("&"),
+        );
+
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let to_sign =
+            format!("AWS4-HMAC-SHA256\n{time}\n{scope}\n{}", hex(&Sha256::digest(canonical)));
+        let key = [date.as_str(), &self.region, &self.service, "aws4_request"]
+            .into_iter()
+            .fold(format!("AWS4{}", credentials.secret_key).into_bytes(), |key, part| {
+                hmac(&key, part.as_bytes())
+            });
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={names}, Signature={}",
+            credentials.access_key,
+            hex(&hmac(&key, to_sign.as_bytes())),
+        );
+        request.headers_mut().insert("authorization", header_value(&authorization)?);
+        Ok(())
+    }
+}
+
+impl Signer for SigV4 {
+    fn sign(&self, request: &mut Request) -> Result<()> {
+        self.sign_at(request, SystemTime::now())
+    }
+}
+
+/// S3 中的对象 也可以用于 MinIO 和 Cloudflare R2 等兼容 S3 的服务
+///
+/// 通过带 Range 的 GetObject 下载 可以配合 `Segmented` 多连接下载
+#[derive(Debug, Clone)]
+pub struct S3Object {
+    bucket:     String,
+    key:        String,
+    region:     String,
+    endpoint:   Option<String>,
+    /// 使用 `endpoint/bucket/key` 形式的地址
+    path_style: bool,
+    version:    Option<String>,
+}
+
+impl S3Object {
+    pub fn new(
+        bucket: impl Into<String>,
+        key: impl Into<String>,
+        region: impl Into<String>,
+    ) -> Self {
+        Self {
+            bucket:     bucket.into(),
+            key:        key.into(),
+            region:     region.into(),
+            endpoint:   None,
+            path_style: false,
+            version:    None,
+        }
+    }
+
+    /// 兼容 S3 的服务地址 例如 `http://localhost:9000` 同时改用路径形式的地址
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_string());
+        self.path_style = true;
+        self
+    }
+
+    /// 是否使用 `endpoint/bucket/key` 形式的地址 名称中带点的 bucket 需要开启
+    pub fn path_style(mut self, path_style: bool) -> Self {
+        self.path_style = path_style;
+        self
+    }
+
+    /// 下载指定版本
+    pub fn version_id(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// 对象的地址
+    pub fn url(&self) -> String {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("https://s3.{}.amazonaws.com", self.region),
+        };
+        let key = self.key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let mut url = match self.path_style {
+            true => format!("{endpoint}/{}/{key}", self.bucket),
+            false => match endpoint.split_once("://") {
+                Some((scheme, host)) => format!("{scheme}://{}.{host}/{key}", self.bucket),
+                None => format!("{}.{endpoint}/{key}", self.bucket),
+            },
+        };
+        if let Some(version) = &self.version {
+            url.push_str(&format!("?versionId={}", uri_encode(version)));
+        }
+        url
+    }
+
+    /// 下载到 path 的下载器 使用 SigV4 签名
+    ///
+    /// 先用 HeadObject 获取对象的 SHA-256 或 SHA-1 校验和 没有时使用单次上传的 ETag (MD5)
+    /// 都没有时返回 `DownloadError::NoChecksum` 可以改用 `downloader_with_hash`
+    pub async fn downloader(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        credentials: Credentials,
+    ) -> Result<HttpDownloader> {
+        let mut http = self.downloader_with_hash(path, credentials, "");
+        let response = http.send(Method::HEAD, &http.urls[0], &[]).await?.error_for_status()?;
+        let (algorithm, hash) = checksum(response.headers()).ok_or(DownloadError::NoChecksum)?;
+        http.builder = http.builder.hash(hash);
+        Ok(http.algorithm(algorithm))
+    }
+
+    /// 使用已知 hash 的下载器 默认按 SHA-256 校验
+    pub fn downloader_with_hash(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        credentials: Credentials,
+        hash: impl Into<String>,
+    ) -> HttpDownloader {
+        let builder = DownloadBuilder::new(path).hash(hash);
+        HttpDownloader::with_builder(self.url(), builder)
+            .auth(Auth::signer(SigV4::new(credentials, &self.region)))
+            .header(HeaderName::from_static("x-amz-checksum-mode"), "ENABLED")
+    }
+}
+
+/// 响应中可以用于校验的 hash 分段上传的组合校验和与 ETag 不是整个文件的 hash
+fn checksum(headers: &HeaderMap) -> Option<(Algorithm, String)> {
+    let base64 = |name: &str| base64_hex(headers.get(name)?.to_str().ok()?);
+    if let Some(hash) = base64("x-amz-checksum-sha256") {
+        return Some((Algorithm::Sha256, hash));
+    }
+    if let Some(hash) = base64("x-amz-checksum-sha1") {
+        return Some((Algorithm::Sha1, hash));
+    }
+    // 使用 KMS 或客户提供的密钥 (SSE-C) 加密的对象 ETag 不是 MD5
+    let sse = headers.get("x-amz-server-side-encryption");
+    if sse.is_some_and(|value| value.as_bytes().starts_with(b"aws:kms"))
+        || headers.contains_key("x-amz-server-side-encryption-customer-algorithm")
+    {
+        return None;
+    }
+    let etag = headers.get("etag")?.to_str().ok()?.trim_matches('"').to_ascii_lowercase();
+    let md5 = etag.len() == 32 && etag.bytes().all(|b| b.is_ascii_hexdigit());
+    md5.then_some((Algorithm::Md5, etag))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC 接受任意长度的密钥");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(invalid_header)
+}
+
+fn invalid_header(e: impl fmt::Display) -> DownloadError {
+    DownloadError::InvalidHeader(e.to_string())
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Downloading, Metadata, Result};
+
+/// 目录中找到的可以继续下载的 downloading 文件
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResumableEntry {
+    /// downloading 文件路径
+    pub path:       PathBuf,
+    /// 下载完成后的文件路径
+    pub target:     PathBuf,
+    pub hash:       String,
+    pub size:       u64,
+    /// 已下载的字节数
+    pub downloaded: u64,
+    pub percent:    f64,
+}
+
+impl ResumableEntry {
+    /// 打开继续下载
+    pub async fn resume(&self) -> Result<Downloading> {
+        Downloading::reopen(self.path.clone(), self.target.clone()).await
+    }
+}
+
+/// 递归查找目录下默认命名的 `*.downloading` 文件并读取元数据
+///
+/// 元数据缺失或损坏的文件会被跳过 结果按路径排序
+pub async fn scan_dir(path: impl AsRef<Path>) -> Result<Vec<ResumableEntry>> {
+    let mut entries = vec![];
+    let mut dirs = vec![path.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != "downloading") {
+                continue;
+            }
+            let Ok(meta) = Metadata::load(&path).await else {
+                continue;
+            };
+            entries.push(ResumableEntry {
+                target:     path.with_extension(""),
+                path,
+                hash:       meta.hash.clone(),
+                size:       meta.size,
+                downloaded: meta.ranges.downloaded(),
+                percent:    meta.percent(),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
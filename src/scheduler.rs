@@ -0,0 +1,162 @@
+use std::{collections::HashMap, ops::Range};
+
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{DownloadError, Downloading, Result};
+
+/// 分配区间 每个连接持有自己剩余的 [pos, end)
+pub(crate) struct Scheduler {
+    min_split: u64,
+    inner:     std::sync::Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id:   u64,
+    pending:   Vec<Range<u64>>,
+    active:    HashMap<u64, Range<u64>>,
+    /// 每个损坏的块重新下载的次数
+    refetched: HashMap<usize, u32>,
+    /// 等待退出的连接数 这些连接领取区间时得到 None
+    retiring:  usize,
+}
+
+/// 同一块最多重新下载的次数
+const MAX_REFETCH: u32 = 3;
+
+impl Scheduler {
+    pub(crate) fn new(mut pending: Vec<Range<u64>>, connections: usize, min_split: u64) -> Self {
+        while pending.len() < connections {
+            let Some((i, r)) = pending.iter().enumerate().max_by_key(|(_, r)| r.end - r.start)
+            else {
+                break;
+            };
+            if r.end - r.start < min_split * 2 {
+                break;
+            }
+            let mid = r.start + (r.end - r.start) / 2;
+            let tail = mid..r.end;
+            pending[i].end = mid;
+            pending.push(tail);
+        }
+        pending.sort_by_key(|r| std::cmp::Reverse(r.start));
+        let inner = Inner { pending, ..Default::default() };
+        Self { min_split, inner: std::sync::Mutex::new(inner) }
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// 取下一个区间 没有待下载的区间时接管剩余最多的连接的后半段
+    pub(crate) fn next(&self) -> Option<(u64, Range<u64>)> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.retiring > 0 {
+            inner.retiring -= 1;
+            return None;
+        }
+        let range = match inner.pending.pop() {
+            Some(range) => range,
+            None => {
+                let victim = inner.active.values_mut().max_by_key(|r| r.end - r.start)?;
+                if victim.end - victim.start < self.min_split * 2 {
+                    return None;
+                }
+                let mid = victim.start + (victim.end - victim.start) / 2;
+                let range = mid..victim.end;
+                victim.end = mid;
+                range
+            }
+        };
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.active.insert(id, range.clone());
+        Some((id, range))
+    }
+
+    /// 减少一个连接 剩余最多的区间放回待下载 它的连接收到下一块数据时结束
+    ///
+    /// 之后第一个领取区间的连接退出 放回的区间由其他连接继续
+    #[cfg(feature = "http")]
+    pub(crate) fn retire(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(range) = inner.active.values_mut().max_by_key(|r| r.end - r.start) {
+            let rest = range.clone();
+            range.end = range.start;
+            inner.pending.push(rest);
+        }
+        inner.retiring += 1;
+    }
+
+    /// 收到 len 字节 返回其中属于该连接的字节数以及区间是否已经收完
+    fn advance(&self, id: u64, len: u64) -> (u64, bool) {
+        let mut inner = self.inner.lock().unwrap();
+        let range = inner.active.get_mut(&id).expect("区间不存在");
+        let n = len.min(range.end - range.start);
+        range.start += n;
+        (n, range.is_empty())
+    }
+
+    /// 重新下载校验失败的块 超过次数时返回 false
+    fn requeue(&self, index: usize, range: Range<u64>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let refetched = inner.refetched.entry(index).or_default();
+        *refetched += 1;
+        if *refetched > MAX_REFETCH {
+            return false;
+        }
+        inner.pending.push(range);
+        true
+    }
+
+    /// 把收到的 chunk 中属于该连接的部分写入 pos 处 返回区间是否已经收完
+    ///
+    /// 校验失败的块交给之后空闲的连接重新下载 超过次数时返回 `DownloadError::PieceMismatch`
+    pub(crate) async fn write(
+        &self,
+        downloading: &Mutex<Downloading>,
+        id: u64,
+        pos: &mut u64,
+        chunk: &[u8],
+    ) -> Result<bool> {
+        let (n, finished) = self.advance(id, chunk.len() as u64);
+        if n > 0 {
+            let mut downloading = downloading.lock().await;
+            match downloading.write_at(*pos, &chunk[..n as usize]).await {
+                Err(DownloadError::PieceMismatch(index)) => {
+                    let (pieces, size) = (&downloading.meta().pieces, downloading.meta().size);
+                    let range = pieces.as_ref().expect("有分块 hash").range(index, size);
+                    if !self.requeue(index, range) {
+                        return Err(DownloadError::PieceMismatch(index));
+                    }
+                }
+                result => drop(result?),
+            }
+            *pos += n;
+        }
+        Ok(finished)
+    }
+
+    /// 移除区间 返回是否已完整下载
+    pub(crate) fn finish(&self, id: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active.remove(&id).is_none_or(|range| range.is_empty())
+    }
+}
+
+/// 等待所有连接结束 某个连接出错时停止其他连接并返回该错误
+///
+/// 持有锁时才停止 不会中断正在进行的写入 否则元数据可能只写入一半
+pub(crate) async fn join(
+    mut workers: JoinSet<Result<()>>,
+    downloading: &Mutex<Downloading>,
+) -> Result<()> {
+    while let Some(result) = workers.join_next().await {
+        if let Err(e) = result.map_err(DownloadError::from).and_then(|result| result) {
+            let _guard = downloading.lock().await;
+            workers.shutdown().await;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::{ops::Range, path::Path, sync::Arc};
+
+use reqwest::{header, Method, StatusCode};
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{
+    hosts::HostLimits,
+    http::{content_range, is_remote, Downloaded, HttpDownloader},
+    limit::RateLimiter,
+    manager::{Observer, TaskSpec},
+    proxy::ProxyConfig,
+    scheduler::{self, Scheduler},
+    tuner::{self, Tuner},
+    BufferPool, CancellationToken, DownloadError, Downloading, Result,
+};
+
+/// 多连接分段下载
+///
+/// 把未下载的部分切分成多个区间并发请求 通过 `Downloading::write_at` 写入同一个文件
+///
+/// 某个连接完成后会接管剩余最多的区间的后半段 快的连接帮慢的连接分担
+#[derive(Debug, Clone)]
+pub struct Segmented {
+    http:        HttpDownloader,
+    connections: usize,
+    min_split:   u64,
+    adaptive:    bool,
+}
+
+impl Segmented {
+    /// 默认 4 个连接 区间小于 1MiB 不再切分
+    pub fn new(http: HttpDownloader) -> Self {
+        Self { http, connections: 4, min_split: 1024 * 1024, adaptive: false }
+    }
+
+    /// 代理 见 `HttpDownloader::proxy`
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.http = self.http.proxy(proxy);
+        self
+    }
+
+    pub(crate) fn default_proxy(&mut self, proxy: &ProxyConfig) {
+        self.http.default_proxy(proxy);
+    }
+
+    pub(crate) fn default_host_limits(&mut self, limits: &HostLimits) {
+        self.http.default_host_limits(limits);
+    }
+
+    pub(crate) fn add_limiter(&mut self, limiter: RateLimiter) {
+        self.http.builder.limiters.push(limiter);
+    }
+
+    pub(crate) fn default_buffer_pool(&mut self, pool: &BufferPool) {
+        self.http.builder.default_buffer_pool(pool);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Observer) {
+        self.http.builder.observer = Some(observer);
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.http.builder.path
+    }
+
+    pub(crate) fn task_spec(&self) -> TaskSpec {
+        self.http.task_spec(self.connections)
+    }
+
+    /// 最大连接数
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// 从一个连接开始 按总速度自动调整连接数 `connections` 为上限
+    ///
+    /// 每隔几秒增加一个连接 总速度没有明显提高时撤回 过一段时间再尝试
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// 区间小于该大小时不再切分
+    pub fn min_split(mut self, min_split: u64) -> Self {
+        self.min_split = min_split.max(1);
+        self
+    }
+
+    /// 取消令牌 见 `DownloadBuilder::cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.http = self.http.cancel(token);
+        self
+    }
+
+    /// 下载并校验 服务端不支持 Range 时退化为单连接下载
+    ///
+    /// 按 `HttpDownloader::retry` 的策略重试 重试时只下载还缺少的区间
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "download",
+            skip_all,
+            fields(
+                url = %self.http.urls[0],
+                path = %self.http.builder.path.display(),
+                connections = self.connections,
+            ),
+            err(level = "warn"),
+        )
+    )]
+    pub async fn download(&self) -> Result<Downloaded> {
+        let builder = &self.http.builder;
+        let (cancel, observer) = (builder.cancel.as_ref(), builder.observer.as_ref());
+        let download = self.http.retry.run(cancel, observer, || self.attempt());
+        let download = self.http.within_deadline(download);
+        #[cfg(feature = "metrics")]
+        let download = crate::telemetry::measure(download);
+        download.await
+    }
+
+    async fn attempt(&self) -> Result<Downloaded> {
+        let probe = self.http.probe().await?;
+        // 大小未知时无法切分区间
+        let Some(size) = probe.size.filter(|_| probe.ranges && self.connections > 1) else {
+            return self.http.attempt().await;
+        };
+
+        let mut downloading = self.http.open(&probe).await?;
+        // 保存的 ETag 或 Last-Modified 与远程不一致 资源已变化
+        let stored = &downloading.meta().validator;
+        if stored.is_some() && *stored != probe.validator {
+            downloading.restart().await?;
+        }
+        downloading.set_validator(probe.validator.clone()).await?;
+        let meta = downloading.meta();
+        let (missing, downloaded) = (meta.ranges.missing(size), meta.ranges.downloaded());
+        let connections = if self.adaptive { 1 } else { self.connections };
+        let scheduler = Arc::new(Scheduler::new(missing, connections, self.min_split));
+        let downloading = Arc::new(Mutex::new(downloading));
+
+        let mut spawned = 0;
+        let mut spawn = |workers: &mut JoinSet<_>| {
+            let worker = Worker {
+                mirror:      spawned % self.http.urls.len(),
+                validator:   probe.validator.clone(),
+                http:        self.http.clone(),
+                scheduler:   scheduler.clone(),
+                downloading: downloading.clone(),
+            };
+            workers.spawn(worker.run());
+            spawned += 1;
+        };
+        let mut workers = JoinSet::new();
+        for _ in 0..connections.min(scheduler.pending()) {
+            spawn(&mut workers);
+        }
+        match self.adaptive {
+            true => {
+                let tuner = Tuner::new(self.connections, downloaded);
+                tuner::join(workers, &downloading, &scheduler, tuner, spawn).await?
+            }
+            false => scheduler::join(workers, &downloading).await?,
+        }
+
+        let downloading = Arc::into_inner(downloading).expect("所有连接都已结束").into_inner();
+        self.http.complete(probe, downloading).await
+    }
+}
+
+struct Worker {
+    /// 起始使用的地址 连接分散到各个镜像
+    mirror:      usize,
+    /// 作为 If-Range 发送 区间请求期间资源变化时返回 `DownloadError::ResourceChanged`
+    validator:   Option<String>,
+    http:        HttpDownloader,
+    scheduler:   Arc<Scheduler>,
+    downloading: Arc<Mutex<Downloading>>,
+}
+
+impl Worker {
+    async fn run(self) -> Result<()> {
+        loop {
+            // 先等到可用的流再领取区间 等待期间区间可以交给其他连接
+            let permit = self.http.stream_permit().await?;
+            let host = self.http.host_permit(&self.http.urls[self.mirror]).await?;
+            let Some((id, range)) = self.scheduler.next() else {
+                return Ok(());
+            };
+            let result = self.fetch(id, range).await;
+            drop((permit, host));
+            if !self.scheduler.finish(id) {
+                return result.and(Err(DownloadError::ConnectionClosed));
+            }
+            result?;
+        }
+    }
+
+    /// 下载区间 停滞时从收到的位置重新连接 远程出错时切换镜像
+    ///
+    /// 所有地址都没有进展时返回最后的错误
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "segment",
+            skip_all,
+            fields(id = id, start = range.start, end = range.end),
+            err(level = "debug"),
+        )
+    )]
+    async fn fetch(&self, id: u64, range: Range<u64>) -> Result<()> {
+        let urls = &self.http.urls;
+        let (mut pos, mut mirror, mut failures) = (range.start, self.mirror, 0);
+        loop {
+            let start = pos;
+            // 起始地址的主机名额在领取区间前已经拿到
+            let _host = match mirror == self.mirror {
+                true => None,
+                false => self.http.host_permit(&urls[mirror]).await?,
+            };
+            match self.transfer(&urls[mirror], id, &mut pos, range.end).await {
+                Err(DownloadError::Stalled) if pos > start => {}
+                Err(e) if is_remote(&e) => {
+                    failures = if pos > start { 0 } else { failures + 1 };
+                    if failures >= urls.len() {
+                        return Err(e);
+                    }
+                    mirror = (mirror + 1) % urls.len();
+                }
+                result => {
+                    #[cfg(feature = "tracing")]
+                    if result.is_ok() {
+                        tracing::debug!(bytes = pos - range.start, "分段完成");
+                    }
+                    if let (Ok(()), Some(observer)) = (&result, &self.http.builder.observer) {
+                        observer.segment_completed(range.start..pos);
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// 请求 [pos, end) 连接在区间收完前结束时返回 `DownloadError::ConnectionClosed`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(url = %url, offset = *pos), err(level = "debug"))
+    )]
+    async fn transfer(&self, url: &str, id: u64, pos: &mut u64, end: u64) -> Result<()> {
+        let bytes = format!("bytes={}-{}", pos, end - 1);
+        let mut headers = vec![(header::RANGE, bytes.as_str())];
+        if let Some(validator) = &self.validator {
+            headers.push((header::IF_RANGE, validator.as_str()));
+        }
+        let response = self.http.send(Method::GET, url, &headers).await?;
+        let mut response = response.error_for_status()?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return match self.validator {
+                Some(_) => Err(DownloadError::ResourceChanged),
+                None => Err(DownloadError::RangeNotSupported),
+            };
+        }
+        // 返回的区间与请求的不同时写入会错位
+        match content_range(response.headers()) {
+            Some((start, last)) if start == *pos && last < end => {}
+            _ => return Err(DownloadError::RangeNotSupported),
+        }
+
+        while let Some(chunk) = self.http.chunk(&mut response).await? {
+            if self.scheduler.write(&self.downloading, id, pos, &chunk).await? {
+                return Ok(());
+            }
+        }
+        Err(DownloadError::ConnectionClosed)
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::SeekFrom::Start,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    fs::File,
+    io::{self, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+};
+
+use crate::{CancellationToken, Downloading, Result};
+
+/// 请求头的最大长度
+const MAX_HEAD: u64 = 8 * 1024;
+/// 每次发送的最大字节数
+const CHUNK: usize = 64 * 1024;
+
+/// 通过 HTTP 提供正在下载的文件 让本地播放器边下边播
+///
+/// 只提供从头连续下载的部分 请求的范围还没有下载时等待 不会读到末尾的元数据
+///
+/// 每个连接只处理一个请求 任意路径都返回同一个文件 丢弃或 `shutdown` 后停止服务
+#[derive(Debug)]
+pub struct Server {
+    addr:   SocketAddr,
+    name:   String,
+    cancel: CancellationToken,
+}
+
+impl Server {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 带文件名的地址 部分播放器根据扩展名判断格式
+    pub fn url(&self) -> String {
+        format!("http://{}/{}", self.addr, self.name)
+    }
+
+    /// 停止接受新连接并断开已有连接
+    pub fn shutdown(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// 提供的文件
+#[derive(Debug)]
+struct Source {
+    path:     PathBuf,
+    /// 下载完成后改名为目标文件
+    target:   PathBuf,
+    /// 大小未知时为 None
+    size:     Option<u64>,
+    mime:     &'static str,
+    readable: watch::Receiver<u64>,
+    /// 加密存放时读取 downloading 文件后解密
+    #[cfg(feature = "encrypt")]
+    sealer:   Option<crate::encrypt::Sealer>,
+}
+
+impl Downloading {
+    /// 在 addr 上启动 HTTP 服务 见 [`Server`] 端口为 0 时随机分配
+    pub async fn serve(&self, addr: impl ToSocketAddrs) -> Result<Server> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let name = self.target.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let source = Arc::new(Source {
+            path:     self.path.clone(),
+            target:   self.target.clone(),
+            size:     (!self.meta.growing).then_some(self.meta.size),
+            mime:     mime(&self.target),
+            readable: self.progress.subscribe_readable(),
+            #[cfg(feature = "encrypt")]
+            sealer:   self.at_rest.clone(),
+        });
+
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        tokio::spawn(token.clone().run_until_cancelled_owned(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let source = source.clone();
+                tokio::spawn(token.clone().run_until_cancelled_owned(handle(stream, source)));
+            }
+        }));
+        Ok(Server { addr, name, cancel })
+    }
+}
+
+/// 处理一个请求 出错时直接断开
+async fn handle(mut stream: TcpStream, source: Arc<Source>) -> io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader.take(MAX_HEAD));
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let method = line.split_whitespace().next().unwrap_or_default().to_string();
+    let mut range = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut writer, "405 Method Not Allowed", "Content-Length: 0\r\n").await;
+    }
+
+    let mime = source.mime;
+    let Some(size) = source.size else {
+        // 大小未知 不支持 Range 发送到连接关闭为止
+        let head = format!("Content-Type: {mime}\r\nAccept-Ranges: none\r\n");
+        respond(&mut writer, "200 OK", &head).await?;
+        return match method.as_str() {
+            "GET" => copy(&source, 0, None, &mut writer).await,
+            _ => Ok(()),
+        };
+    };
+    let (status, start, end) = match range.map(|range| parse_range(&range, size)) {
+        None => ("200 OK", 0, size),
+        Some(Some((start, end))) => ("206 Partial Content", start, end),
+        Some(None) => {
+            let head = format!("Content-Range: bytes */{size}\r\nContent-Length: 0\r\n");
+            return respond(&mut writer, "416 Range Not Satisfiable", &head).await;
+        }
+    };
+    let mut head = format!(
+        "Content-Type: {mime}\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\n",
+        end - start
+    );
+    if start != 0 || end != size {
+        head.push_str(&format!("Content-Range: bytes {start}-{}/{size}\r\n", end - 1));
+    }
+    respond(&mut writer, status, &head).await?;
+    match method.as_str() {
+        "GET" => copy(&source, start, Some(end), &mut writer).await,
+        _ => Ok(()),
+    }
+}
+
+async fn respond(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: &str,
+    head: &str,
+) -> io::Result<()> {
+    let head = format!("HTTP/1.1 {status}\r\n{head}Connection: close\r\n\r\n");
+    writer.write_all(head.as_bytes()).await
+}
+
+/// 发送 [start, end) 还没有下载到时等待 下载被丢弃后发送完已有的部分结束
+async fn copy(
+    source: &Source,
+    start: u64,
+    end: Option<u64>,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> io::Result<()> {
+    #[cfg_attr(not(feature = "encrypt"), allow(unused_variables))]
+    let (mut file, sealed) = match File::open(&source.path).await {
+        Ok(file) => (file, true),
+        Err(_) => (File::open(&source.target).await?, false),
+    };
+    file.seek(Start(start)).await?;
+    let mut readable = source.readable.clone();
+    let (mut pos, mut buf) = (start, vec![0; CHUNK]);
+    while end != Some(pos) {
+        let available = *readable.borrow_and_update();
+        if pos >= available {
+            if readable.changed().await.is_err() && *readable.borrow() <= pos {
+                break;
+            }
+            continue;
+        }
+        let max = (available - pos).min(end.unwrap_or(u64::MAX) - pos).min(CHUNK as u64);
+        let n = file.read(&mut buf[..max as usize]).await?;
+        if n == 0 {
+            break;
+        }
+        #[cfg(feature = "encrypt")]
+        if let Some(sealer) = source.sealer.as_ref().filter(|_| sealed) {
+            sealer.apply(pos, &mut buf[..n]);
+        }
+        writer.write_all(&buf[..n]).await?;
+        pos += n as u64;
+    }
+    writer.flush().await
+}
+
+/// 解析单个范围 `bytes=start-end` `bytes=start-` `bytes=-suffix` 返回 [start, end)
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), size),
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.saturating_add(1).min(size)),
+    };
+    (start < end).then_some((start, end))
+}
+
+/// 按扩展名猜测常见媒体的类型
+fn mime(path: &Path) -> &'static str {
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+    match ext.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "avi" => "video/x-msvideo",
+        "mov" => "video/quicktime",
+        "ts" => "video/mp2t",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
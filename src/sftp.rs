@@ -0,0 +1,295 @@
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    io::{self, ErrorKind, SeekFrom::Start},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use russh::{
+    client::{self, AuthResult, Handle},
+    keys::{self, HashAlg, PrivateKey, PrivateKeyWithHashAlg, PublicKeyOrCertificate},
+};
+use russh_sftp::client::{fs::File, SftpSession};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{transport::Transport, DownloadError, Result};
+
+/// 每次读取的最大字节数
+const CHUNK: usize = 64 * 1024;
+
+/// SFTP 的 [`Transport`] 交给 `TransportDownloader` 下载
+///
+/// ```ignore
+/// let sftp = SftpTransport::new("bastion.example.com", "deploy", "/data/build.tar.zst")
+///     .private_key_file("~/.ssh/id_ed25519", None)?;
+/// TransportDownloader::new(sftp, builder).connections(4).download().await?;
+/// ```
+///
+/// `open` 时建立一个 SSH 连接 每个区间在同一个连接上打开远程文件 seek 到起始位置后读取
+///
+/// 默认使用 `~/.ssh/known_hosts` 校验服务器公钥 文件修改时间变化时丢弃已下载的进度
+#[derive(Clone)]
+pub struct SftpTransport {
+    host:     String,
+    port:     u16,
+    user:     String,
+    path:     String,
+    auth:     Credential,
+    host_key: HostKey,
+    timeout:  Duration,
+    session:  Arc<Mutex<Option<Arc<Session>>>>,
+}
+
+#[derive(Clone)]
+enum Credential {
+    Password(String),
+    Key(Arc<PrivateKey>),
+}
+
+#[derive(Clone)]
+enum HostKey {
+    /// None 为 `~/.ssh/known_hosts`
+    KnownHosts(Option<PathBuf>),
+    /// `SHA256:` 开头的公钥指纹 与 `ssh-keygen -lf` 的输出一致
+    Fingerprint(String),
+    Any,
+}
+
+/// SSH 连接和其上的 SFTP 会话
+struct Session {
+    sftp:    SftpSession,
+    /// 丢弃后断开连接
+    _handle: Handle<Client>,
+}
+
+impl SftpTransport {
+    /// 默认端口 22 没有设置密码或私钥时登录失败
+    pub fn new(host: impl Into<String>, user: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            host:     host.into(),
+            port:     22,
+            user:     user.into(),
+            path:     path.into(),
+            auth:     Credential::Password(String::new()),
+            host_key: HostKey::KnownHosts(None),
+            timeout:  Duration::from_secs(60),
+            session:  Arc::default(),
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// 密码登录
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.auth = Credential::Password(password.into());
+        self
+    }
+
+    /// OpenSSH 格式的私钥 加密的私钥需要 passphrase
+    pub fn private_key(mut self, key: &str, passphrase: Option<&str>) -> Result<Self> {
+        let key = keys::decode_secret_key(key, passphrase).map_err(ssh)?;
+        self.auth = Credential::Key(Arc::new(key));
+        Ok(self)
+    }
+
+    /// 从文件读取私钥 见 `private_key` 开头的 `~` 展开为 HOME
+    pub fn private_key_file(
+        self,
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let path = match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+            (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+            _ => path.to_path_buf(),
+        };
+        self.private_key(&std::fs::read_to_string(path)?, passphrase)
+    }
+
+    /// 使用指定的 known_hosts 文件校验服务器公钥
+    pub fn known_hosts(mut self, path: impl Into<PathBuf>) -> Self {
+        self.host_key = HostKey::KnownHosts(Some(path.into()));
+        self
+    }
+
+    /// 只接受该指纹的服务器公钥 例如 `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`
+    pub fn host_key_fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.host_key = HostKey::Fingerprint(fingerprint.into());
+        self
+    }
+
+    /// 不校验服务器公钥 只应在可信的网络中使用
+    pub fn danger_accept_any_host_key(mut self) -> Self {
+        self.host_key = HostKey::Any;
+        self
+    }
+
+    /// 建立连接和等待响应的超时 默认 60 秒 超时返回 `DownloadError::Stalled`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 建立 SSH 连接 登录后打开 SFTP 子系统
+    async fn connect(&self) -> Result<Session> {
+        let timeout = Some(self.timeout);
+        let config = Arc::new(client::Config { inactivity_timeout: timeout, ..Default::default() });
+        let key = self.host_key.clone();
+        let client = Client { host: self.host.clone(), port: self.port, key };
+        let connect = client::connect(config, (self.host.as_str(), self.port), client);
+        // 连接失败保留 IO 错误 以便按重试策略重试
+        let mut handle = self.within(connect).await?.map_err(|e| match e {
+            russh::Error::IO(e) => DownloadError::Io(e),
+            e => ssh(e),
+        })?;
+
+        let result = match &self.auth {
+            Credential::Password(password) => {
+                handle.authenticate_password(&self.user, password).await
+            }
+            Credential::Key(key) => {
+                let hash = handle.best_supported_rsa_hash().await.map_err(ssh)?.flatten();
+                let key = PrivateKeyWithHashAlg::new(key.clone(), hash);
+                handle.authenticate_publickey(&self.user, key).await
+            }
+        };
+        if let AuthResult::Failure { .. } = result.map_err(ssh)? {
+            return Err(DownloadError::Ssh(format!("{} 登录失败", self.user)));
+        }
+
+        let channel = handle.channel_open_session().await.map_err(ssh)?;
+        channel.request_subsystem(true, "sftp").await.map_err(ssh)?;
+        let sftp = self.within(SftpSession::new(channel.into_stream())).await?.map_err(ssh)?;
+        sftp.set_timeout(self.timeout.as_secs().max(1));
+        Ok(Session { sftp, _handle: handle })
+    }
+
+    /// `open` 建立的连接
+    fn session(&self) -> Result<Arc<Session>> {
+        self.session.lock().unwrap().clone().ok_or(DownloadError::ConnectionClosed)
+    }
+
+    async fn within<F: Future>(&self, future: F) -> Result<F::Output> {
+        tokio::time::timeout(self.timeout, future).await.map_err(|_| DownloadError::Stalled)
+    }
+}
+
+impl Transport for SftpTransport {
+    /// 每次尝试重新建立连接 之前的连接在所有区间结束后断开
+    fn open(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let session = self.connect().await?;
+            *self.session.lock().unwrap() = Some(Arc::new(session));
+            Ok(())
+        })
+    }
+
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move {
+            let metadata = self.session()?.sftp.metadata(&self.path).await.map_err(ssh)?;
+            Ok(metadata.size)
+        })
+    }
+
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let session = self.session()?;
+            let mut file = session.sftp.open(&self.path).await.map_err(ssh)?;
+            file.seek(Start(range.start)).await?;
+
+            let read = Read { file, remain: range.end - range.start, _session: session };
+            let stream = futures::stream::try_unfold(read, |mut read| async move {
+                let chunk = read.next().await?;
+                Ok(chunk.map(|chunk| (chunk, read)))
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let metadata = self.session()?.sftp.metadata(&self.path).await.map_err(ssh)?;
+            Ok(metadata.mtime.map(|mtime| mtime.to_string()))
+        })
+    }
+}
+
+impl fmt::Debug for SftpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SftpTransport")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 读取一个区间 持有连接直到读完
+struct Read {
+    file:     File,
+    remain:   u64,
+    _session: Arc<Session>,
+}
+
+impl Read {
+    async fn next(&mut self) -> Result<Option<Bytes>> {
+        if self.remain == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; CHUNK.min(self.remain.try_into().unwrap_or(CHUNK))];
+        // SFTP 请求失败时视为传输中断 按重试策略从已下载的位置继续
+        let n = self.file.read(&mut buf).await.map_err(|e| match e.kind() {
+            ErrorKind::Other => io::Error::new(ErrorKind::ConnectionAborted, e),
+            _ => e,
+        })?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        self.remain -= n as u64;
+        Ok(Some(Bytes::from(buf)))
+    }
+}
+
+/// 校验服务器公钥
+struct Client {
+    host: String,
+    port: u16,
+    key:  HostKey,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let PublicKeyOrCertificate::PublicKey { key, .. } = key else {
+            return Ok(false);
+        };
+        match &self.key {
+            HostKey::KnownHosts(None) => Ok(keys::check_known_hosts(&self.host, self.port, key)?),
+            HostKey::KnownHosts(Some(path)) => {
+                Ok(keys::check_known_hosts_path(&self.host, self.port, key, path)?)
+            }
+            HostKey::Fingerprint(fingerprint) => {
+                Ok(key.fingerprint(HashAlg::Sha256).to_string() == *fingerprint)
+            }
+            HostKey::Any => Ok(true),
+        }
+    }
+}
+
+fn ssh(e: impl Display) -> DownloadError {
+    DownloadError::Ssh(e.to_string())
+}
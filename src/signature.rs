@@ -0,0 +1,99 @@
+use std::{
+    fmt,
+    io::{Read, SeekFrom::Start},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{fs::File, io::AsyncSeekExt, process::Command};
+
+use crate::{DownloadError, Result};
+
+/// 读取时的缓冲大小
+const CHUNK: usize = 1024 * 1024;
+
+/// 分离签名 `complete` 在 hash 校验通过后校验签名 不通过时返回 `DownloadError::InvalidSignature`
+///
+/// minisign 直接校验 GPG 调用 `gpgv` 需要已安装 GnuPG
+#[derive(Clone)]
+pub struct Signature(Kind);
+
+#[derive(Clone)]
+enum Kind {
+    Minisign { key: minisign_verify::PublicKey, signature: Arc<minisign_verify::Signature> },
+    Gpg { keyring: PathBuf, signature: PathBuf },
+}
+
+impl Signature {
+    /// public_key 为 `minisign.pub` 文件内容或其中的 base64 signature 为 `.minisig` 文件内容
+    ///
+    /// 只支持默认的预先 hash 的签名 不支持 `minisign -l` 生成的旧格式
+    pub fn minisign(public_key: &str, signature: &str) -> Result<Self> {
+        let key = minisign_verify::PublicKey::decode(public_key)
+            .or_else(|_| minisign_verify::PublicKey::from_base64(public_key.trim()))
+            .map_err(invalid)?;
+        let signature = minisign_verify::Signature::decode(signature).map_err(invalid)?;
+        Ok(Self(Kind::Minisign { key, signature: Arc::new(signature) }))
+    }
+
+    /// keyring 为 `gpg --export` 导出的公钥 signature 为 `.sig` 或 `.asc` 文件
+    pub fn gpg(keyring: impl AsRef<Path>, signature: impl AsRef<Path>) -> Self {
+        // 不带目录的 keyring 会被 gpgv 当作 GnuPG 主目录中的文件
+        let keyring = match keyring.as_ref().parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new(".").join(keyring),
+            _ => keyring.as_ref().to_path_buf(),
+        };
+        Self(Kind::Gpg { keyring, signature: signature.as_ref().to_path_buf() })
+    }
+
+    /// 校验 path 处的 file 调用前已截去元数据
+    pub(crate) async fn verify(&self, file: &mut File, path: &Path) -> Result<()> {
+        match &self.0 {
+            Kind::Minisign { key, signature } => {
+                file.seek(Start(0)).await?;
+                let mut file = file.try_clone().await?.into_std().await;
+                let (key, signature) = (key.clone(), signature.clone());
+                let task = tokio::task::spawn_blocking(move || -> Result<()> {
+                    let mut verifier = key.verify_stream(&signature).map_err(invalid)?;
+                    let mut buf = vec![0; CHUNK];
+                    loop {
+                        match file.read(&mut buf)? {
+                            0 => return verifier.finalize().map_err(invalid),
+                            n => verifier.update(&buf[..n]),
+                        }
+                    }
+                });
+                task.await?
+            }
+            Kind::Gpg { keyring, signature } => {
+                let mut command = Command::new("gpgv");
+                let output = command.arg("--keyring").arg(keyring).arg(signature).arg(path);
+                let output = output.kill_on_drop(true).output().await?;
+                match output.status.success() {
+                    true => Ok(()),
+                    false => Err(invalid(String::from_utf8_lossy(&output.stderr).trim())),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::Minisign { signature, .. } => f
+                .debug_struct("Minisign")
+                .field("trusted_comment", &signature.trusted_comment())
+                .finish_non_exhaustive(),
+            Kind::Gpg { keyring, signature } => f
+                .debug_struct("Gpg")
+                .field("keyring", keyring)
+                .field("signature", signature)
+                .finish(),
+        }
+    }
+}
+
+fn invalid(e: impl fmt::Display) -> DownloadError {
+    DownloadError::InvalidSignature(e.to_string())
+}
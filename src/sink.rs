@@ -0,0 +1,83 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::Sink;
+use tokio::io::AsyncWrite;
+
+use crate::{DownloadError, Downloading};
+
+/// `Sink<Bytes>` 适配器 将 HTTP 响应流直接转发到 downloading 文件
+///
+/// ```ignore
+/// let mut sink = downloading.into_sink();
+/// response.bytes_stream().map_err(DownloadError::from).forward(&mut sink).await?;
+/// sink.into_inner().complete_with(Algorithm::Sha256).await?;
+/// ```
+///
+/// 基于 `AsyncWrite` 实现 `flush` 和 `close` 时写入元数据
+#[derive(Debug)]
+pub struct DownloadSink {
+    downloading: Downloading,
+    /// 还未写入文件的数据
+    pending:     Bytes,
+}
+
+impl DownloadSink {
+    pub fn new(downloading: Downloading) -> Self {
+        Self { downloading, pending: Bytes::new() }
+    }
+
+    pub fn get_ref(&self) -> &Downloading {
+        &self.downloading
+    }
+
+    /// 取回 downloading 之前先 `flush` 或 `close` 否则未写入的数据会丢失
+    pub fn into_inner(self) -> Downloading {
+        self.downloading
+    }
+
+    /// 写完 pending 中的数据
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), DownloadError>> {
+        while !self.pending.is_empty() {
+            let n = ready!(Pin::new(&mut self.downloading).poll_write(cx, &self.pending))?;
+            self.pending.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<Bytes> for DownloadSink {
+    type Error = DownloadError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_drain(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        debug_assert!(self.pending.is_empty(), "start_send 之前需要 poll_ready");
+        self.get_mut().pending = item;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut this.downloading).poll_flush(cx))?))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut this.downloading).poll_shutdown(cx))?))
+    }
+}
+
+impl Downloading {
+    /// 转换为 `Sink<Bytes>` 见 [`DownloadSink`]
+    pub fn into_sink(self) -> DownloadSink {
+        DownloadSink::new(self)
+    }
+}
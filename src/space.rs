@@ -0,0 +1,103 @@
+use std::io;
+
+use fs4::fs_std::FileExt;
+
+use crate::{DownloadError, Downloading, Result};
+
+/// 开始下载前对磁盘空间的处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpaceCheck {
+    /// 不检查
+    Skip,
+    /// 剩余空间不足以写完时返回 `DownloadError::InsufficientSpace`
+    #[default]
+    Check,
+    /// 检查后预先分配 downloading 文件的空间 之后的写入不会因空间不足失败
+    ///
+    /// Linux 和 macOS 等使用 fallocate Windows 设置分配大小 减少多连接下载产生的碎片
+    /// 文件系统不支持时只检查
+    Preallocate,
+    /// 检查后以稀疏文件存放 多连接乱序写入时未写入的区间不占用磁盘
+    ///
+    /// Unix 上文件默认就是稀疏的 Windows 上设置 NTFS 的稀疏属性
+    Sparse,
+}
+
+impl Downloading {
+    /// 检查剩余空间并预先分配整个文件 用于 `Downloading::new` 打开的下载
+    /// 构建器中使用 `DownloadBuilder::space_check`
+    pub async fn preallocate(&mut self) -> Result<()> {
+        self.check_space(SpaceCheck::Preallocate).await
+    }
+
+    /// 按文件最终的长度减去已占用的空间检查 大小未知或无法获取剩余空间时不检查
+    pub(crate) async fn check_space(&mut self, policy: SpaceCheck) -> Result<()> {
+        if policy == SpaceCheck::Skip || self.meta.growing {
+            return Ok(());
+        }
+        self.meta.resize();
+        let len = if self.sidecar.is_some() { self.meta.size } else { self.meta.len };
+        let (path, file) = (self.path.clone(), self.file.try_clone().await?.into_std().await);
+        let task = tokio::task::spawn_blocking(move || {
+            Ok::<_, io::Error>((fs4::available_space(path)?, file.allocated_size()?, file))
+        });
+        let (available, allocated, file) = match task.await? {
+            Ok(stat) => stat,
+            Err(_) => return Ok(()),
+        };
+        let required = len.saturating_sub(allocated);
+        if required > available {
+            return Err(DownloadError::InsufficientSpace { required, available });
+        }
+        let task = match policy {
+            SpaceCheck::Preallocate => tokio::task::spawn_blocking(move || file.allocate(len)),
+            SpaceCheck::Sparse => tokio::task::spawn_blocking(move || sparse(&file)),
+            _ => return Ok(()),
+        };
+        match task.await? {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::StorageFull => {
+                Err(DownloadError::InsufficientSpace { required, available })
+            }
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 数据文件实际占用的磁盘空间
+    pub(crate) fn allocated(&self) -> Option<u64> {
+        self.writer.data.allocated_size().ok()
+    }
+}
+
+/// 未写入的区间不占用磁盘 Unix 上 `set_len` 扩展的部分本来就不分配
+#[cfg(not(windows))]
+fn sparse(_file: &std::fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn sparse(file: &std::fs::File) -> io::Result<()> {
+    use std::{os::windows::io::AsRawHandle, ptr::null_mut};
+
+    use windows_sys::Win32::{System::Ioctl::FSCTL_SET_SPARSE, System::IO::DeviceIoControl};
+
+    let mut returned = 0;
+    // SAFETY: 句柄在 file 存活期间有效 FSCTL_SET_SPARSE 没有输入输出缓冲区
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            FSCTL_SET_SPARSE,
+            null_mut(),
+            0,
+            null_mut(),
+            0,
+            &mut returned,
+            null_mut(),
+        )
+    };
+    match ok {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,250 @@
+//! Linux 上把明文 HTTP 的响应体通过 splice 从 socket 经管道转移到文件 数据不经过用户态
+
+use std::{fs::File, io, os::fd::OwnedFd, sync::Arc, time::Duration};
+
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    StatusCode, Url,
+};
+use rustix::pipe::{self, PipeFlags, SpliceFlags};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::TcpStream,
+};
+
+use crate::{
+    cancellable,
+    http::{content_range, validator, HttpDownloader},
+    DownloadError, Downloading, Result,
+};
+
+/// 管道的大小 每次最多转移这么多数据
+const PIPE_SIZE: usize = 1024 * 1024;
+/// 响应头的上限 超过时交给 reqwest
+const MAX_HEAD: usize = 64 * 1024;
+
+impl HttpDownloader {
+    /// 自己发送请求并把响应体 splice 到文件 不满足 `zero_copy` 的条件时返回 false
+    ///
+    /// 响应不是预期的 206 或 200 例如重定向 错误 分块编码或压缩时同样返回 false
+    /// 由 reqwest 重新请求处理
+    pub(crate) async fn splice(
+        &self,
+        url: &str,
+        downloading: &mut Downloading,
+        headers: &[(HeaderName, &str)],
+    ) -> Result<bool> {
+        let Some((connect, read)) = self.spliceable(url, downloading) else {
+            return Ok(false);
+        };
+        let Some(request) = self.request_head(url, headers) else {
+            return Ok(false);
+        };
+        let url = Url::parse(url).map_err(|e| DownloadError::InvalidUrl(e.to_string()))?;
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Ok(false);
+        };
+        let cancel = self.builder.cancel.as_ref();
+        if let Some(hosts) = &self.hosts {
+            hosts.pace(url.as_str(), cancel).await?;
+        }
+        let connecting = TcpStream::connect((host, port));
+        let mut stream = match connect {
+            Some(timeout) => cancellable(cancel, tokio::time::timeout(timeout, connecting))
+                .await?
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??,
+            None => cancellable(cancel, connecting).await??,
+        };
+        stream.write_all(request.as_bytes()).await?;
+
+        let timeout = self.stall.or(read);
+        let Some((status, headers, mut body)) = read_head(&mut stream, timeout).await? else {
+            return Ok(false);
+        };
+        let meta = downloading.meta();
+        let (offset, stored) = (meta.offset, meta.validator.clone());
+        let start = match status {
+            StatusCode::PARTIAL_CONTENT => content_range(&headers).map(|(start, _)| start),
+            StatusCode::OK if offset == 0 => Some(0),
+            _ => None,
+        };
+        let length = headers.get(header::CONTENT_LENGTH);
+        let length = length.and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+        let encoded = headers
+            .get(header::CONTENT_ENCODING)
+            .is_some_and(|v| !v.as_bytes().eq_ignore_ascii_case(b"identity"));
+        let (Some(length), false, false) =
+            (length, encoded, headers.contains_key(header::TRANSFER_ENCODING))
+        else {
+            return Ok(false);
+        };
+        if start != Some(offset) || offset + length > meta.size {
+            return Ok(false);
+        }
+
+        downloading.set_validator(validator(&headers).or(stored)).await?;
+        // 与响应头一起读到的开头部分照常写入
+        body.truncate(length as usize);
+        if !body.is_empty() {
+            downloading.write_at(offset, &body).await?;
+        }
+        let (pos, length) = (offset + body.len() as u64, length - body.len() as u64);
+        downloading.splice_from(&stream, pos, length, self.stall).await?;
+        Ok(true)
+    }
+
+    /// 明文 HTTP 没有代理 认证和 cookies 写入前不需要处理 返回连接和读取的超时
+    fn spliceable(
+        &self,
+        url: &str,
+        downloading: &Downloading,
+    ) -> Option<(Option<Duration>, Option<Duration>)> {
+        let meta = downloading.meta();
+        let plain = url.starts_with("http://") && self.auth.is_none();
+        if !self.zero_copy || !plain || meta.growing || downloading.transforms() {
+            return None;
+        }
+        self.config.as_ref()?.direct()
+    }
+
+    /// GET 请求头 请求头的值包含换行等无效字符时返回 None
+    fn request_head(&self, url: &str, headers: &[(HeaderName, &str)]) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        let mut head = format!("GET {}", url.path());
+        if let Some(query) = url.query() {
+            head += &format!("?{query}");
+        }
+        head += &format!(" HTTP/1.1\r\nhost: {}", url.host_str()?);
+        if let Some(port) = url.port() {
+            head += &format!(":{port}");
+        }
+        head += "\r\naccept-encoding: identity\r\nconnection: close\r\n";
+        let custom = self.headers.iter().map(|(name, value)| (name, value.as_str()));
+        for (name, value) in custom.chain(headers.iter().map(|(name, value)| (name, *value))) {
+            // 只接受未编码的响应
+            if name == header::ACCEPT_ENCODING {
+                continue;
+            }
+            HeaderValue::from_str(value).ok()?;
+            head += &format!("{name}: {value}\r\n");
+        }
+        Some(head + "\r\n")
+    }
+}
+
+/// 读取响应头 返回状态码 响应头和一起读到的响应体的开头部分
+///
+/// 响应头过大或无法解析时返回 None
+async fn read_head(
+    stream: &mut TcpStream,
+    timeout: Option<Duration>,
+) -> Result<Option<(StatusCode, HeaderMap, Vec<u8>)>> {
+    let (mut buf, mut len) = (vec![0; MAX_HEAD], 0);
+    let end = loop {
+        if len == buf.len() {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buf[len..]);
+        let n = match timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, read).await.map_err(|_| DownloadError::Stalled)??
+            }
+            None => read.await?,
+        };
+        if n == 0 {
+            return Err(DownloadError::ConnectionClosed);
+        }
+        len += n;
+        if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+    };
+    let Some((status, headers)) = parse_head(&buf[..end]) else {
+        return Ok(None);
+    };
+    buf.truncate(len);
+    Ok(Some((status, headers, buf.split_off(end + 4))))
+}
+
+fn parse_head(head: &[u8]) -> Option<(StatusCode, HeaderMap)> {
+    let mut lines = std::str::from_utf8(head).ok()?.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?;
+    let status = StatusCode::from_bytes(status.as_bytes()).ok()?;
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).ok()?;
+        headers.append(name, HeaderValue::from_str(value.trim()).ok()?);
+    }
+    Some((status, headers))
+}
+
+impl Downloading {
+    /// 把 socket 中接下来的 len 字节 splice 到文件 pos 处 每次转移后记录进度
+    ///
+    /// 无法更新增量 hash 完成时重新读取文件计算
+    pub(crate) async fn splice_from(
+        &mut self,
+        socket: &TcpStream,
+        mut pos: u64,
+        len: u64,
+        stall: Option<Duration>,
+    ) -> Result<()> {
+        self.flush_runs().await?;
+        self.file.flush().await?;
+        self.meta.state = None;
+        let (reader, writer) = pipe::pipe_with(PipeFlags::CLOEXEC).map_err(io::Error::from)?;
+        // 管道默认 64 KiB 调大后每次转移更多数据 失败时保持默认
+        let _ = pipe::fcntl_setpipe_size(&writer, PIPE_SIZE);
+        let reader = Arc::new(reader);
+        let end = pos + len;
+        while pos < end {
+            if self.is_cancelled() {
+                if self.writer.dirty {
+                    self.save().await?;
+                }
+                return Err(DownloadError::Cancelled);
+            }
+            let (readable, cancel) = (socket.readable(), self.cancel.as_ref());
+            match stall {
+                Some(stall) => cancellable(cancel, tokio::time::timeout(stall, readable))
+                    .await?
+                    .map_err(|_| DownloadError::Stalled)??,
+                None => cancellable(cancel, readable).await??,
+            }
+            let want = (end - pos).min(PIPE_SIZE as u64) as usize;
+            let flags = SpliceFlags::MOVE | SpliceFlags::NONBLOCK;
+            let moved = socket.try_io(Interest::READABLE, || {
+                Ok(pipe::splice(socket, None, &writer, None, want, flags)?)
+            });
+            let n = match moved {
+                Ok(0) => return Err(DownloadError::ConnectionClosed),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let (reader, data) = (reader.clone(), self.writer.data.clone());
+            tokio::task::spawn_blocking(move || drain(&reader, &data, pos, n)).await??;
+            let delay = self.reserve(n as u64);
+            if !delay.is_zero() {
+                cancellable(self.cancel.as_ref(), tokio::time::sleep(delay)).await?;
+            }
+            self.commit(pos..pos + n as u64).await?;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// 把管道中的 n 字节全部 splice 到文件 pos 处
+fn drain(pipe: &OwnedFd, file: &File, mut pos: u64, mut n: usize) -> io::Result<()> {
+    while n > 0 {
+        match pipe::splice(pipe, None, file, Some(&mut pos), n, SpliceFlags::MOVE) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(moved) => n -= moved,
+            Err(rustix::io::Errno::INTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
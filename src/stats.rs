@@ -0,0 +1,86 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::Downloading;
+
+/// 计算平均速度使用的时间窗口
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// 下载速度统计
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// 最近一段时间的平均速度 字节/秒
+    pub average:   f64,
+    /// 峰值速度 字节/秒
+    pub peak:      f64,
+    /// 按平均速度估算的剩余时间 速度为 0 时为 None
+    pub eta:       Option<Duration>,
+    /// downloading 文件实际占用的磁盘空间 稀疏文件中未写入的区间不占用 无法获取时为 None
+    pub allocated: Option<u64>,
+}
+
+/// 记录最近的写入 样本为 (时间, 字节数)
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    samples: VecDeque<(Instant, u64)>,
+    /// 窗口内的总字节数
+    bytes:   u64,
+    peak:    f64,
+}
+
+impl Sampler {
+    pub(crate) fn new() -> Self {
+        Self { samples: VecDeque::new(), bytes: 0, peak: 0.0 }
+    }
+
+    pub(crate) fn record(&mut self, n: u64) {
+        let now = Instant::now();
+        self.samples.push_back((now, n));
+        self.bytes += n;
+        while let Some(&(time, n)) = self.samples.front() {
+            if now.duration_since(time) <= WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+            self.bytes -= n;
+        }
+        self.peak = self.peak.max(self.average(now));
+    }
+
+    /// 窗口内的平均速度 第一个样本的字节数算在它之前的时间里 不计入
+    fn average(&self, now: Instant) -> f64 {
+        let Some(&(first, n)) = self.samples.front() else {
+            return 0.0;
+        };
+        let elapsed = now.duration_since(first).as_secs_f64();
+        match elapsed > 0.0 {
+            true => (self.bytes - n) as f64 / elapsed,
+            false => 0.0,
+        }
+    }
+
+    pub(crate) fn stats(&self, remain: u64) -> Stats {
+        let average = self.average(Instant::now());
+        let eta = match remain {
+            0 => Some(Duration::ZERO),
+            _ if average > 0.0 => Some(Duration::from_secs_f64(remain as f64 / average)),
+            _ => None,
+        };
+        Stats { average, peak: self.peak, eta, allocated: None }
+    }
+}
+
+impl Downloading {
+    /// 下载速度 峰值速度 剩余时间和占用的磁盘空间 大小未知时没有剩余时间
+    pub fn stats(&self) -> Stats {
+        let remain = self.meta.size.saturating_sub(self.meta.ranges.downloaded());
+        let stats = Stats { allocated: self.allocated(), ..self.stats.stats(remain) };
+        match self.meta.growing {
+            true => Stats { eta: None, ..stats },
+            false => stats,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{Sink, Stream};
+use tokio::sync::mpsc;
+use tokio_util::sync::PollSender;
+
+use crate::{DownloadError, DownloadSink, Downloading};
+
+/// 同时写入 downloading 文件和转发给消费者的 `Sink<Bytes>`
+///
+/// ```ignore
+/// let (mut tee, stream) = downloading.tee(16);
+/// tokio::spawn(player.play(stream));
+/// response.bytes_stream().map_err(DownloadError::from).forward(&mut tee).await?;
+/// tee.into_inner().complete_with(Algorithm::Sha256).await?;
+/// ```
+///
+/// 数据写入文件后才转发 消费者跟不上时缓冲区满后写入也会等待 消费者丢弃后只写入文件
+#[derive(Debug)]
+pub struct Tee {
+    sink:    DownloadSink,
+    sender:  PollSender<Bytes>,
+    /// 已交给文件还未转发的数据
+    pending: Option<Bytes>,
+}
+
+impl Tee {
+    pub fn get_ref(&self) -> &Downloading {
+        self.sink.get_ref()
+    }
+
+    /// 取回 downloading 之前先 `flush` 或 `close` 否则未写入的数据会丢失 消费者的流随之结束
+    pub fn into_inner(self) -> Downloading {
+        self.sink.into_inner()
+    }
+
+    /// 写完文件后转发 pending
+    fn poll_forward(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), DownloadError>> {
+        ready!(Pin::new(&mut self.sink).poll_ready(cx))?;
+        if let Some(item) = &self.pending {
+            // 消费者已丢弃时直接丢掉
+            if ready!(self.sender.poll_reserve(cx)).is_ok() {
+                let _ = self.sender.send_item(item.clone());
+            }
+            self.pending = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<Bytes> for Tee {
+    type Error = DownloadError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_forward(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        Pin::new(&mut this.sink).start_send(item.clone())?;
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_forward(cx))?;
+        Pin::new(&mut this.sink).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_forward(cx))?;
+        ready!(Pin::new(&mut this.sink).poll_close(cx))?;
+        this.sender.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Downloading {
+    /// 转换为 [`Tee`] 返回的流按顺序产出写入的数据 buffer 为最多缓冲的块数
+    pub fn tee(self, buffer: usize) -> (Tee, impl Stream<Item = Bytes>) {
+        let (sender, receiver) = mpsc::channel(buffer.max(1));
+        let tee = Tee { sink: self.into_sink(), sender: PollSender::new(sender), pending: None };
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            Some((receiver.recv().await?, receiver))
+        });
+        (tee, stream)
+    }
+}
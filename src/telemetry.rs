@@ -0,0 +1,101 @@
+use std::{future::Future, time::Instant};
+
+use metrics::{counter, gauge, histogram};
+
+use crate::{DownloadError, Result};
+
+/// 写入文件的字节数
+const BYTES: &str = "downloader_bytes_total";
+/// 进行中的下载数
+const ACTIVE: &str = "downloader_active_downloads";
+/// 结束的下载数 按 result 区分 completed failed cancelled
+const DOWNLOADS: &str = "downloader_downloads_total";
+/// 下载的耗时 包括重试 秒
+const DURATION: &str = "downloader_download_duration_seconds";
+/// 重试次数
+const RETRIES: &str = "downloader_retries_total";
+/// hash 分块 hash 或签名校验失败的次数
+const VERIFICATION_FAILURES: &str = "downloader_verification_failures_total";
+/// 每次打开 downloading 文件期间的平均速度 字节/秒
+const THROUGHPUT: &str = "downloader_throughput_bytes_per_second";
+
+/// 统计一次下载 进行期间计入活跃下载数 结束时按结果计数
+///
+/// 指标通过 `metrics` 门面发送 由使用方安装的 recorder 导出 例如 Prometheus
+pub(crate) async fn measure<T>(download: impl Future<Output = Result<T>>) -> Result<T> {
+    let active = Active::new();
+    let result = download.await;
+    let label = match &result {
+        Ok(_) => "completed",
+        Err(DownloadError::Cancelled) => "cancelled",
+        Err(_) => "failed",
+    };
+    if let Err(e) = &result {
+        if is_verification(e) {
+            counter!(VERIFICATION_FAILURES).increment(1);
+        }
+    }
+    counter!(DOWNLOADS, "result" => label).increment(1);
+    histogram!(DURATION).record(active.start.elapsed().as_secs_f64());
+    result
+}
+
+pub(crate) fn retry() {
+    counter!(RETRIES).increment(1);
+}
+
+/// 校验失败的错误 重试耗尽后返回的也计入
+fn is_verification(e: &DownloadError) -> bool {
+    matches!(
+        e,
+        DownloadError::HashMismatch { .. }
+            | DownloadError::PieceMismatch(_)
+            | DownloadError::Corrupted(_)
+            | DownloadError::InvalidSignature(_)
+    )
+}
+
+/// 丢弃时减去活跃下载数 下载的 future 被丢弃时同样生效
+struct Active {
+    start: Instant,
+}
+
+impl Active {
+    fn new() -> Self {
+        gauge!(ACTIVE).increment(1.0);
+        Self { start: Instant::now() }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        gauge!(ACTIVE).decrement(1.0);
+    }
+}
+
+/// `Downloading` 打开期间写入的字节数 关闭时记录平均速度
+#[derive(Debug)]
+pub(crate) struct Meter {
+    opened: Instant,
+    bytes:  u64,
+}
+
+impl Meter {
+    pub(crate) fn new() -> Self {
+        Self { opened: Instant::now(), bytes: 0 }
+    }
+
+    pub(crate) fn record(&mut self, n: u64) {
+        counter!(BYTES).increment(n);
+        self.bytes += n;
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        let elapsed = self.opened.elapsed().as_secs_f64();
+        if self.bytes > 0 && elapsed > 0.0 {
+            histogram!(THROUGHPUT).record(self.bytes as f64 / elapsed);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt::Display, sync::Arc};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{aws_lc_rs, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, DigitallySignedStruct, SignatureScheme,
+};
+use rustls_platform_verifier::Verifier;
+use sha2::{Digest, Sha256};
+
+use crate::{hash::unhex, DownloadError, Result};
+
+/// TLS 选项 默认只使用系统的根证书
+///
+/// 证书在设置时解析 错误立即返回
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    roots:    Vec<CertificateDer<'static>>,
+    /// 客户端证书链和私钥
+    identity: Option<Arc<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>>,
+    /// 证书公钥 (SPKI) 的 SHA-256
+    pins:     Vec<[u8; 32]>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在系统根证书之外信任的 CA 证书 PEM 中可以包含多个证书
+    pub fn root_pem(mut self, pem: &[u8]) -> Result<Self> {
+        let certs = CertificateDer::pem_slice_iter(pem).collect::<Result<Vec<_>, _>>();
+        let certs = certs.map_err(invalid)?;
+        if certs.is_empty() {
+            return Err(invalid("PEM 中没有证书"));
+        }
+        self.roots.extend(certs);
+        Ok(self)
+    }
+
+    /// DER 格式的 CA 证书 见 `root_pem`
+    pub fn root_der(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.roots.push(CertificateDer::from(der.into()));
+        self
+    }
+
+    /// 双向认证使用的客户端证书 PEM 中包含证书链和私钥
+    pub fn identity_pem(mut self, pem: &[u8]) -> Result<Self> {
+        let certs = CertificateDer::pem_slice_iter(pem).collect::<Result<Vec<_>, _>>();
+        let certs = certs.map_err(invalid)?;
+        let key = PrivateKeyDer::from_pem_slice(pem).map_err(invalid)?;
+        if certs.is_empty() {
+            return Err(invalid("PEM 中没有证书"));
+        }
+        provider().key_provider.load_private_key(key.clone_key()).map_err(invalid)?;
+        self.identity = Some(Arc::new((certs, key)));
+        Ok(self)
+    }
+
+    /// 固定证书公钥 hex 为证书链中某个证书 SubjectPublicKeyInfo 的 SHA-256
+    ///
+    /// 设置后证书链中至少有一个证书匹配才允许连接 证书本身仍然需要通过校验
+    ///
+    /// 可以用 `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum` 计算
+    pub fn pin_sha256(mut self, hex: &str) -> Result<Self> {
+        let pin = unhex(hex.trim()).and_then(|pin| pin.try_into().ok());
+        self.pins.push(pin.ok_or_else(|| invalid(format!("无效的 SHA-256: {hex}")))?);
+        Ok(self)
+    }
+
+    pub(crate) fn build(&self) -> Result<rustls::ClientConfig> {
+        let provider = provider();
+        let verifier = Verifier::new_with_extra_roots(self.roots.clone(), provider.clone());
+        let mut verifier: Arc<dyn ServerCertVerifier> = Arc::new(verifier.map_err(invalid)?);
+        if !self.pins.is_empty() {
+            verifier = Arc::new(Pinned { inner: verifier, pins: self.pins.clone() });
+        }
+
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+        let mut config = match &self.identity {
+            Some(identity) => {
+                let (certs, key) = identity.as_ref();
+                builder.with_client_auth_cert(certs.clone(), key.clone_key()).map_err(invalid)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(aws_lc_rs::default_provider())
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Tls(e.to_string())
+}
+
+/// 通过 inner 校验后再检查证书公钥
+#[derive(Debug)]
+struct Pinned {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins:  Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let pinned = std::iter::once(end_entity).chain(intermediates).any(|cert| {
+            let Ok(cert) = webpki::EndEntityCert::try_from(cert) else {
+                return false;
+            };
+            let spki = Sha256::digest(cert.subject_public_key_info());
+            self.pins.iter().any(|pin| pin[..] == spki[..])
+        });
+        match pinned {
+            true => Ok(verified),
+            false => Err(CertificateError::ApplicationVerificationFailure.into()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
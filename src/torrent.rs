@@ -0,0 +1,356 @@
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    filename::{percent_decode, sanitize},
+    hash::{hex, Algorithm},
+    transport::{Transport, TransportDownloader},
+    DownloadBuilder, DownloadError, Pieces, Result,
+};
+
+/// 单文件种子的 info 字典 多文件种子不支持
+///
+/// 种子没有整个文件的 hash 写入时逐块校验 SHA-1 完成时用 `Downloading::complete_pieces` 校验
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Torrent {
+    pub name:         String,
+    pub length:       u64,
+    pub piece_length: u64,
+    /// 每块 SHA-1 的小写十六进制
+    pub pieces:       Vec<String>,
+    /// info 字典的 SHA-1 小写十六进制
+    pub info_hash:    String,
+    /// announce 和 announce-list 中的地址 按优先级排列
+    pub trackers:     Vec<String>,
+}
+
+/// 磁力链接 `magnet:?xt=urn:btih:...` 需要 [`PieceSource::metadata`] 从 peer 获取 info 字典
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    /// 小写十六进制 base32 编码的会转换为十六进制
+    pub info_hash: String,
+    pub name:      Option<String>,
+    pub size:      Option<u64>,
+    pub trackers:  Vec<String>,
+}
+
+/// BitTorrent 引擎的接入点 负责连接 tracker 和 peer 按块提供数据
+///
+/// 写入 校验 续传和分段调度由 [`TransportDownloader`] 完成 多个连接会同时请求不同的块
+pub trait PieceSource: Send + Sync + 'static {
+    /// 开始下载前调用 例如向 tracker 宣告
+    fn open<'a>(&'a self, _torrent: &'a Torrent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// 第 index 块的完整数据 校验失败的块会重新请求
+    fn piece<'a>(&'a self, torrent: &'a Torrent, index: usize) -> BoxFuture<'a, Result<Bytes>>;
+
+    /// 按 BEP 9 从 peer 获取 bencode 编码的 info 字典 用于磁力链接 默认不支持
+    fn metadata<'a>(&'a self, _magnet: &'a Magnet) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async { Err(invalid("不支持获取磁力链接的元数据")) })
+    }
+}
+
+impl Torrent {
+    /// 解析 `.torrent` 文件
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let root = Value::parse_all(bytes)?;
+        let info = root.get(b"info").ok_or_else(|| invalid("缺少 info"))?;
+        let mut trackers = vec![];
+        if let Some(tiers) = root.get(b"announce-list").and_then(Value::list) {
+            let urls = tiers.iter().filter_map(Value::list).flatten().filter_map(Value::str);
+            trackers.extend(urls.map(str::to_string));
+        }
+        if let Some(announce) = root.get(b"announce").and_then(Value::str) {
+            if !trackers.iter().any(|url| url == announce) {
+                trackers.insert(0, announce.to_string());
+            }
+        }
+        Self::from_info(info, trackers)
+    }
+
+    /// 从 bencode 编码的 info 字典创建 用于磁力链接
+    pub fn from_info_bytes(info: &[u8], trackers: Vec<String>) -> Result<Self> {
+        Self::from_info(&Value::parse_all(info)?, trackers)
+    }
+
+    fn from_info(info: &Value<'_>, trackers: Vec<String>) -> Result<Self> {
+        let Value::Dict(_, raw) = info else {
+            return Err(invalid("info 不是字典"));
+        };
+        if info.get(b"files").is_some() {
+            return Err(invalid("不支持多文件种子"));
+        }
+        let name = info.get(b"name").and_then(Value::str).unwrap_or_default().to_string();
+        let length = info.get(b"length").and_then(Value::int).ok_or_else(|| invalid("缺少 length"))?;
+        let piece_length = info.get(b"piece length").and_then(Value::int).filter(|&n| n > 0);
+        let piece_length = piece_length.ok_or_else(|| invalid("缺少 piece length"))?;
+        let pieces = info.get(b"pieces").and_then(Value::bytes).unwrap_or_default();
+        let count = length.div_ceil(piece_length);
+        if !pieces.len().is_multiple_of(20) || (pieces.len() / 20) as u64 != count {
+            return Err(invalid("pieces 的数量与文件大小不一致"));
+        }
+        Ok(Self {
+            name,
+            length,
+            piece_length,
+            pieces: pieces.chunks(20).map(hex).collect(),
+            info_hash: hex(&Sha1::digest(raw)),
+            trackers,
+        })
+    }
+
+    /// 用于逐块校验的分块 hash
+    pub fn to_pieces(&self) -> Pieces {
+        Pieces::new(Algorithm::Sha1, self.piece_length, &self.pieces)
+    }
+
+    /// 保存到 dir 下种子中的文件名 没有文件名时使用 info hash
+    ///
+    /// 设置了大小和分块 hash 没有整个文件的 hash
+    pub fn builder(&self, dir: impl AsRef<Path>) -> DownloadBuilder {
+        let name = match self.name.trim().is_empty() {
+            true => self.info_hash.clone(),
+            false => sanitize(&self.name),
+        };
+        let path = dir.as_ref().join(name);
+        DownloadBuilder::new(path).size(self.length).pieces(self.to_pieces())
+    }
+
+    /// 通过 source 下载到 dir 默认 4 个连接 区间不小于一块
+    pub fn downloader(
+        self,
+        source: impl PieceSource,
+        dir: impl AsRef<Path>,
+    ) -> TransportDownloader {
+        let builder = self.builder(dir);
+        let piece_length = self.piece_length;
+        let transport = TorrentTransport { torrent: self, source: Arc::new(source) };
+        TransportDownloader::new(transport, builder).connections(4).min_split(piece_length)
+    }
+}
+
+impl Magnet {
+    /// 解析 `magnet:?xt=urn:btih:<hex 或 base32>&dn=...&tr=...&xl=...`
+    pub fn parse(uri: &str) -> Result<Self> {
+        let query = uri.strip_prefix("magnet:?").ok_or_else(|| invalid("不是磁力链接"))?;
+        let (mut info_hash, mut name, mut size, mut trackers) = (None, None, None, vec![]);
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(&value.replace('+', " "));
+            let value = value.ok_or_else(|| invalid(format!("无效的参数: {pair}")))?;
+            match key {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(btih(hash).ok_or_else(|| invalid("无效的 btih"))?);
+                    }
+                }
+                "dn" => name = Some(value),
+                "xl" => size = value.parse().ok(),
+                key if key == "tr" || key.starts_with("tr.") => trackers.push(value),
+                _ => {}
+            }
+        }
+        let info_hash = info_hash.ok_or_else(|| invalid("缺少 xt=urn:btih"))?;
+        Ok(Self { info_hash, name, size, trackers })
+    }
+
+    /// 通过 source 获取 info 字典 校验 info hash 后转换为 `Torrent`
+    pub async fn resolve(&self, source: &impl PieceSource) -> Result<Torrent> {
+        let info = source.metadata(self).await?;
+        let torrent = Torrent::from_info_bytes(&info, self.trackers.clone())?;
+        if torrent.info_hash != self.info_hash {
+            return Err(invalid("info 字典与 info hash 不一致"));
+        }
+        Ok(torrent)
+    }
+}
+
+/// 把区间映射到块的 [`Transport`] 每块请求完整的数据后截取区间内的部分
+pub struct TorrentTransport {
+    torrent: Torrent,
+    source:  Arc<dyn PieceSource>,
+}
+
+impl TorrentTransport {
+    pub fn new(torrent: Torrent, source: impl PieceSource) -> Self {
+        Self { torrent, source: Arc::new(source) }
+    }
+}
+
+impl Transport for TorrentTransport {
+    fn open(&self) -> BoxFuture<'_, Result<()>> {
+        self.source.open(&self.torrent)
+    }
+
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(Some(self.torrent.length)) })
+    }
+
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let length = self.torrent.piece_length;
+            let end = range.end.min(self.torrent.length);
+            let pieces = range.start / length..end.div_ceil(length);
+            let stream = futures::stream::iter(pieces).then(move |index| async move {
+                let data = self.source.piece(&self.torrent, index as usize).await?;
+                let start = index * length;
+                let from = range.start.saturating_sub(start).min(data.len() as u64);
+                let to = (end - start).min(data.len() as u64).max(from);
+                Ok(data.slice(from as usize..to as usize))
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    /// 同一路径下载了别的种子时丢弃进度
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(Some(self.torrent.info_hash.clone())) })
+    }
+}
+
+impl fmt::Debug for TorrentTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TorrentTransport").field("torrent", &self.torrent).finish_non_exhaustive()
+    }
+}
+
+/// bencode 的值 字典保留原始字节用于计算 info hash
+enum Value<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    Dict(Vec<(&'a [u8], Value<'a>)>, &'a [u8]),
+}
+
+/// 嵌套的最大层数
+const MAX_DEPTH: usize = 64;
+
+impl<'a> Value<'a> {
+    /// 整个输入是一个值
+    fn parse_all(input: &'a [u8]) -> Result<Self> {
+        match Self::parse(input, 0) {
+            Some((value, [])) => Ok(value),
+            _ => Err(invalid("无效的 bencode")),
+        }
+    }
+
+    /// 返回值和剩余的输入
+    fn parse(input: &'a [u8], depth: usize) -> Option<(Self, &'a [u8])> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match input.first()? {
+            b'i' => {
+                let end = input.iter().position(|&b| b == b'e')?;
+                let n = std::str::from_utf8(&input[1..end]).ok()?.parse().ok()?;
+                Some((Self::Int(n), &input[end + 1..]))
+            }
+            b'l' => {
+                let (mut items, mut rest) = (vec![], &input[1..]);
+                while *rest.first()? != b'e' {
+                    let (item, next) = Self::parse(rest, depth + 1)?;
+                    items.push(item);
+                    rest = next;
+                }
+                Some((Self::List(items), &rest[1..]))
+            }
+            b'd' => {
+                let (mut entries, mut rest) = (vec![], &input[1..]);
+                while *rest.first()? != b'e' {
+                    let (Self::Bytes(key), next) = Self::parse(rest, depth + 1)? else {
+                        return None;
+                    };
+                    let (value, next) = Self::parse(next, depth + 1)?;
+                    entries.push((key, value));
+                    rest = next;
+                }
+                let raw = &input[..input.len() - rest.len() + 1];
+                Some((Self::Dict(entries, raw), &rest[1..]))
+            }
+            b'0'..=b'9' => {
+                let colon = input.iter().position(|&b| b == b':')?;
+                let digits = &input[..colon];
+                // 只允许数字 除了 0 本身不能以 0 开头
+                if !digits.iter().all(u8::is_ascii_digit) || digits.len() > 1 && digits[0] == b'0' {
+                    return None;
+                }
+                let len: usize = std::str::from_utf8(digits).ok()?.parse().ok()?;
+                let end = (colon + 1).checked_add(len)?;
+                let bytes = input.get(colon + 1..end)?;
+                Some((Self::Bytes(bytes), &input[end..]))
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&Self> {
+        match self {
+            Self::Dict(entries, _) => entries.iter().find(|(k, _)| *k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn int(&self) -> Option<u64> {
+        match self {
+            Self::Int(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn str(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.bytes()?).ok()
+    }
+
+    fn list(&self) -> Option<&[Self]> {
+        match self {
+            Self::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// 40 位十六进制或 32 位 base32 转换为小写十六进制
+fn btih(hash: &str) -> Option<String> {
+    match hash.len() {
+        40 if hash.bytes().all(|b| b.is_ascii_hexdigit()) => Some(hash.to_ascii_lowercase()),
+        32 => {
+            let (mut bits, mut acc, mut out) = (0, 0u64, vec![]);
+            for b in hash.bytes() {
+                let v = match b.to_ascii_uppercase() {
+                    c @ b'A'..=b'Z' => c - b'A',
+                    c @ b'2'..=b'7' => c - b'2' + 26,
+                    _ => return None,
+                };
+                acc = acc << 5 | v as u64;
+                bits += 5;
+                if bits >= 8 {
+                    bits -= 8;
+                    out.push((acc >> bits) as u8);
+                }
+            }
+            Some(hex(&out))
+        }
+        _ => None,
+    }
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::Torrent(e.to_string())
+}
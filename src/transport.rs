@@ -0,0 +1,292 @@
+use std::{
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{
+    cancellable,
+    hash::Algorithm,
+    limit::RateLimiter,
+    manager::Observer,
+    retry::RetryPolicy,
+    scheduler::{self, Scheduler},
+    BufferPool, CancellationToken, DownloadBuilder, DownloadError, Downloading, Result,
+};
+
+/// 可插拔的下载协议 实现后交给 [`TransportDownloader`] 复用续传 分段 重试和校验
+///
+/// 分段下载时多个连接会同时调用 `read_range`
+pub trait Transport: Send + Sync + 'static {
+    /// 建立连接或登录 每次尝试开始时调用
+    fn open(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// 远程文件大小 未知时返回 None
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>>;
+
+    /// 读取 [start, end) 大小未知时 end 为 `u64::MAX` 读到末尾结束
+    ///
+    /// 返回的数据超出 end 时丢弃多余的部分 提前结束时视为连接中断
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>>;
+
+    /// 能否从任意位置开始读取 不支持时只从 0 开始读取 中断后从头下载
+    fn supports_resume(&self) -> bool {
+        true
+    }
+
+    /// 远程资源的版本 例如修改时间 与保存的不一致时丢弃进度从头下载
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// 本地文件的路径 返回时直接在文件之间复制 不再调用 `read_range`
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// 通过 [`Transport`] 下载到文件
+///
+/// 大小已知并且支持续传时按 `connections` 分段下载 否则单连接顺序下载
+///
+/// 构建器没有 hash 只有分块 hash 时用 `Downloading::complete_pieces` 校验
+#[derive(Clone)]
+pub struct TransportDownloader {
+    transport:   Arc<dyn Transport>,
+    builder:     DownloadBuilder,
+    algorithm:   Algorithm,
+    retry:       RetryPolicy,
+    connections: usize,
+    min_split:   u64,
+}
+
+impl TransportDownloader {
+    /// 默认 SHA-256 校验 单连接 不重试 文件大小由 `Transport::size` 获取
+    pub fn new(transport: impl Transport, builder: DownloadBuilder) -> Self {
+        Self::from_arc(Arc::new(transport), builder)
+    }
+
+    /// 多个下载共享同一个 transport
+    pub fn from_arc(transport: Arc<dyn Transport>, builder: DownloadBuilder) -> Self {
+        Self {
+            transport,
+            builder,
+            algorithm:   Algorithm::Sha256,
+            retry:       RetryPolicy::none(),
+            connections: 1,
+            min_split:   1024 * 1024,
+        }
+    }
+
+    /// 校验 hash 使用的算法
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// 重试策略 每次重试重新调用 `Transport::open`
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// 最大连接数
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// 区间小于该大小时不再切分
+    pub fn min_split(mut self, min_split: u64) -> Self {
+        self.min_split = min_split.max(1);
+        self
+    }
+
+    /// 取消令牌 见 `DownloadBuilder::cancel`
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.builder = self.builder.cancel(token);
+        self
+    }
+
+    pub(crate) fn add_limiter(&mut self, limiter: RateLimiter) {
+        self.builder.limiters.push(limiter);
+    }
+
+    pub(crate) fn default_buffer_pool(&mut self, pool: &BufferPool) {
+        self.builder.default_buffer_pool(pool);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Observer) {
+        self.builder.observer = Some(observer);
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.builder.path
+    }
+
+    /// 下载并校验 返回目标文件路径 中断后再次调用会从已下载的位置继续
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "download",
+            skip_all,
+            fields(path = %self.builder.path.display()),
+            err(level = "warn"),
+        )
+    )]
+    pub async fn download(&self) -> Result<PathBuf> {
+        let (cancel, observer) = (self.builder.cancel.as_ref(), self.builder.observer.as_ref());
+        let download = self.retry.run(cancel, observer, || self.attempt());
+        #[cfg(feature = "metrics")]
+        let download = crate::telemetry::measure(download);
+        download.await
+    }
+
+    async fn attempt(&self) -> Result<PathBuf> {
+        let transport = &self.transport;
+        transport.open().await?;
+        let size = transport.size().await?;
+        let validator = transport.validator().await?;
+        let mut downloading = match size {
+            Some(size) => self.builder.clone().size(size).open().await?,
+            None => self.builder.clone().unknown_size().open().await?,
+        };
+
+        let meta = downloading.meta();
+        let changed = meta.validator.is_some() && meta.validator != validator;
+        if changed || (!transport.supports_resume() && meta.ranges.downloaded() > 0) {
+            downloading.restart().await?;
+        }
+        downloading.set_validator(validator).await?;
+
+        let path = downloading.target().to_path_buf();
+        let split = transport.supports_resume() && self.connections > 1;
+        let downloading = match (transport.local_path(), size.filter(|_| split)) {
+            (Some(source), _) if size.is_some() => self.copy(downloading, source).await?,
+            (_, Some(size)) => self.concurrent(downloading, size).await?,
+            _ => self.sequential(downloading).await?,
+        };
+        match self.builder.hash.is_empty() && self.builder.pieces.is_some() {
+            true => downloading.complete_pieces().await?,
+            false => downloading.complete_with(self.algorithm).await?,
+        }
+        self.builder.finish(path, self.algorithm, None).await
+    }
+
+    /// 单连接依次下载各个缺口
+    async fn sequential(&self, mut downloading: Downloading) -> Result<Downloading> {
+        while downloading.meta().growing || !downloading.is_finished() {
+            let meta = downloading.meta();
+            let end = match meta.growing {
+                true => u64::MAX,
+                false => meta.ranges.missing(meta.size)[0].end,
+            };
+            let (mut pos, cancel) = (meta.offset, self.builder.cancel.as_ref());
+            let mut stream = self.transport.read_range(pos..end).await?;
+            while let Some(chunk) = cancellable(cancel, stream.next()).await? {
+                let chunk = chunk?;
+                let n = chunk.len().min((end - pos) as usize);
+                downloading.write_at(pos, &chunk[..n]).await?;
+                pos += n as u64;
+                if pos == end {
+                    break;
+                }
+            }
+            if downloading.meta().growing {
+                break;
+            }
+            if pos < end {
+                return Err(DownloadError::ConnectionClosed);
+            }
+        }
+        Ok(downloading)
+    }
+
+    /// 从本地文件复制缺少的部分 见 `Downloading::copy_from`
+    async fn copy(&self, mut downloading: Downloading, source: &Path) -> Result<Downloading> {
+        let source = tokio::fs::File::open(source).await?.into_std().await;
+        let meta = downloading.meta();
+        for range in meta.ranges.missing(meta.size) {
+            downloading.copy_from(&source, range).await?;
+        }
+        Ok(downloading)
+    }
+
+    /// 把缺少的部分切分成多个区间并发读取 见 `Segmented`
+    async fn concurrent(&self, downloading: Downloading, size: u64) -> Result<Downloading> {
+        let missing = downloading.meta().ranges.missing(size);
+        let scheduler = Arc::new(Scheduler::new(missing, self.connections, self.min_split));
+        let downloading = Arc::new(Mutex::new(downloading));
+
+        let mut workers = JoinSet::new();
+        for _ in 0..self.connections.min(scheduler.pending()) {
+            let worker = Worker {
+                transport:   self.transport.clone(),
+                cancel:      self.builder.cancel.clone(),
+                scheduler:   scheduler.clone(),
+                downloading: downloading.clone(),
+            };
+            workers.spawn(worker.run());
+        }
+        scheduler::join(workers, &downloading).await?;
+        Ok(Arc::into_inner(downloading).expect("所有连接都已结束").into_inner())
+    }
+}
+
+impl fmt::Debug for TransportDownloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransportDownloader")
+            .field("builder", &self.builder)
+            .field("algorithm", &self.algorithm)
+            .field("connections", &self.connections)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Worker {
+    transport:   Arc<dyn Transport>,
+    cancel:      Option<CancellationToken>,
+    scheduler:   Arc<Scheduler>,
+    downloading: Arc<Mutex<Downloading>>,
+}
+
+impl Worker {
+    async fn run(self) -> Result<()> {
+        while let Some((id, range)) = self.scheduler.next() {
+            let result = self.fetch(id, range).await;
+            if !self.scheduler.finish(id) {
+                return result.and(Err(DownloadError::ConnectionClosed));
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    /// 读取区间 区间被其他连接接管一部分后读到新的结束位置为止
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "segment",
+            skip_all,
+            fields(id = id, start = range.start, end = range.end),
+            err(level = "debug"),
+        )
+    )]
+    async fn fetch(&self, id: u64, range: Range<u64>) -> Result<()> {
+        let mut pos = range.start;
+        let mut stream = self.transport.read_range(range).await?;
+        while let Some(chunk) = cancellable(self.cancel.as_ref(), stream.next()).await? {
+            if self.scheduler.write(&self.downloading, id, &mut pos, &chunk?).await? {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{scheduler::Scheduler, DownloadError, Downloading, Result};
+
+/// 两次调整之间的间隔 新连接需要时间度过慢启动
+const INTERVAL: Duration = Duration::from_secs(2);
+/// 增加连接后总速度至少提高的比例 否则撤回
+const GAIN: f64 = 1.1;
+/// 撤回后等待的间隔数 之后再尝试增加 网络状况可能已经变化
+const HOLD: u32 = 5;
+
+/// 按总速度调整连接数 从一个连接开始 增加连接带来明显提升时继续增加
+#[derive(Debug)]
+pub(crate) struct Tuner {
+    max:        usize,
+    downloaded: u64,
+    time:       Instant,
+    /// 上次增加连接前的速度
+    baseline:   Option<f64>,
+    hold:       u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tune {
+    Add,
+    Remove,
+    Keep,
+}
+
+impl Tuner {
+    pub(crate) fn new(max: usize, downloaded: u64) -> Self {
+        Self { max, downloaded, time: Instant::now(), baseline: None, hold: 0 }
+    }
+
+    /// 根据上次调用以来的速度决定下一步
+    pub(crate) fn tune(&mut self, downloaded: u64, connections: usize) -> Tune {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.time).as_secs_f64().max(f64::EPSILON);
+        let speed = downloaded.saturating_sub(self.downloaded) as f64 / elapsed;
+        (self.downloaded, self.time) = (downloaded, now);
+        match self.baseline.take() {
+            Some(baseline) if speed < baseline * GAIN && connections > 1 => {
+                self.hold = HOLD;
+                Tune::Remove
+            }
+            _ if self.hold > 0 => {
+                self.hold -= 1;
+                Tune::Keep
+            }
+            _ if connections < self.max => {
+                self.baseline = Some(speed);
+                Tune::Add
+            }
+            _ => Tune::Keep,
+        }
+    }
+}
+
+/// 与 `scheduler::join` 相同 同时按 tuner 的结果用 spawn 增加连接或让 scheduler 减少连接
+pub(crate) async fn join(
+    mut workers: JoinSet<Result<()>>,
+    downloading: &Mutex<Downloading>,
+    scheduler: &Scheduler,
+    mut tuner: Tuner,
+    mut spawn: impl FnMut(&mut JoinSet<Result<()>>),
+) -> Result<()> {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + INTERVAL,
+        INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            result = workers.join_next() => {
+                let Some(result) = result else {
+                    return Ok(());
+                };
+                if let Err(e) = result.map_err(DownloadError::from).and_then(|result| result) {
+                    let _guard = downloading.lock().await;
+                    workers.shutdown().await;
+                    return Err(e);
+                }
+            }
+            _ = interval.tick() => {
+                let downloaded = downloading.lock().await.meta().ranges.downloaded();
+                match tuner.tune(downloaded, workers.len()) {
+                    Tune::Add => spawn(&mut workers),
+                    Tune::Remove => scheduler.retire(),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! 使用 io_uring 写入数据文件
+//!
+//! 所有下载共用一个后台线程和一个 io_uring 实例 同时到达的写入一次提交
+//! 小块写入较多时比 tokio 每次写入占用一个阻塞线程的系统调用少
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io,
+    os::fd::AsRawFd,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, OnceLock,
+    },
+};
+
+use io_uring::{opcode, types, IoUring};
+use tokio::sync::oneshot;
+
+/// 同时提交的写入数
+const ENTRIES: u32 = 256;
+
+/// 一次写入 缓冲区和文件在完成前由后台线程持有
+struct Op {
+    file:    Arc<File>,
+    buf:     Vec<u8>,
+    pos:     u64,
+    /// 已写入的字节数 写入不完整时继续提交剩余的部分
+    written: usize,
+    done:    oneshot::Sender<io::Result<Vec<u8>>>,
+}
+
+/// 内核是否支持 io_uring 容器中可能被 seccomp 禁止
+pub(crate) fn available() -> bool {
+    ring().is_some()
+}
+
+/// 第一次使用时创建 内核不支持或被禁止时返回 None
+fn ring() -> Option<&'static Sender<Op>> {
+    static RING: OnceLock<Option<Sender<Op>>> = OnceLock::new();
+    RING.get_or_init(|| {
+        let ring = IoUring::new(ENTRIES).ok()?;
+        let (tx, rx) = mpsc::channel();
+        let thread = std::thread::Builder::new().name("downloader-uring".into());
+        thread.spawn(move || run(ring, rx)).ok()?;
+        Some(tx)
+    })
+    .as_ref()
+}
+
+/// 交给后台线程写入 等待写入完成后取回缓冲区
+pub(crate) async fn write_all_at(file: Arc<File>, buf: Vec<u8>, pos: u64) -> io::Result<Vec<u8>> {
+    let ring = ring().ok_or(io::ErrorKind::Unsupported)?;
+    let (done, result) = oneshot::channel();
+    let op = Op { file, buf, pos, written: 0, done };
+    ring.send(op).map_err(|_| io::Error::other("io_uring 线程已退出"))?;
+    result.await.map_err(|_| io::Error::other("io_uring 线程已退出"))?
+}
+
+/// 没有进行中的写入时阻塞等待请求 否则等待至少一个写入完成后再收取新的请求
+fn run(mut ring: IoUring, rx: Receiver<Op>) {
+    let (mut ops, mut waiting) = (Vec::<Option<Op>>::new(), VecDeque::new());
+    let mut inflight = 0;
+    loop {
+        if inflight == 0 && waiting.is_empty() {
+            match rx.recv() {
+                Ok(op) => waiting.push_back(op),
+                Err(_) => return,
+            }
+        }
+        waiting.extend(rx.try_iter());
+        while inflight < ENTRIES as usize {
+            let Some(op) = waiting.pop_front() else { break };
+            let key = match ops.iter().position(Option::is_none) {
+                Some(key) => key,
+                None => {
+                    ops.push(None);
+                    ops.len() - 1
+                }
+            };
+            let rest = &op.buf[op.written..];
+            let len = rest.len().min(u32::MAX as usize) as u32;
+            let fd = types::Fd(op.file.as_raw_fd());
+            let sqe = opcode::Write::new(fd, rest.as_ptr(), len)
+                .offset(op.pos + op.written as u64)
+                .build()
+                .user_data(key as u64);
+            // SAFETY: 缓冲区和文件存放在 ops 中 直到取得对应的完成事件才释放
+            // 进行中的写入不超过 ENTRIES 提交队列不会满
+            unsafe { ring.submission().push(&sqe).expect("提交队列已满") };
+            ops[key] = Some(op);
+            inflight += 1;
+        }
+        if let Err(e) = ring.submit_and_wait(1) {
+            if e.kind() != io::ErrorKind::Interrupted {
+                // 还未提交的请求直接失败 已提交的仍会完成
+                for op in waiting.drain(..) {
+                    let _ = op.done.send(Err(io::Error::new(e.kind(), e.to_string())));
+                }
+            }
+            continue;
+        }
+        let completed: Vec<_> = ring.completion().map(|c| (c.user_data(), c.result())).collect();
+        for (key, result) in completed {
+            let Some(mut op) = ops[key as usize].take() else { continue };
+            inflight -= 1;
+            match result {
+                n if n < 0 => {
+                    let e = io::Error::from_raw_os_error(-n);
+                    match e.kind() {
+                        io::ErrorKind::Interrupted => waiting.push_front(op),
+                        _ => drop(op.done.send(Err(e))),
+                    }
+                }
+                0 => drop(op.done.send(Err(io::ErrorKind::WriteZero.into()))),
+                n => {
+                    op.written += n as usize;
+                    match op.written < op.buf.len() {
+                        true => waiting.push_front(op),
+                        false => drop(op.done.send(Ok(op.buf))),
+                    }
+                }
+            }
+        }
+    }
+}
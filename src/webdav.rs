@@ -0,0 +1,140 @@
+use std::{
+    fmt::Display,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream};
+use quick_xml::{escape::unescape, events::Event, Reader};
+use reqwest::{
+    header::{self, HeaderName},
+    Method, StatusCode,
+};
+
+use crate::{
+    http::HttpDownloader,
+    transport::{Transport, TransportDownloader},
+    DownloadError, Result,
+};
+
+/// WebDAV 的 [`Transport`] 例如 Nextcloud 和 ownCloud
+///
+/// ```ignore
+/// let http = HttpDownloader::new(url, "backup.tar", hash).auth(Auth::basic(user, app_password));
+/// WebDav::new(http).downloader().connections(4).download().await?;
+/// ```
+///
+/// 通过 `PROPFIND` 获取大小和 ETag 再用带 If-Match 的 Range 请求读取各个区间
+///
+/// 使用 `HttpDownloader` 的客户端 认证 请求头和重定向设置 只使用主地址
+#[derive(Debug, Clone)]
+pub struct WebDav {
+    http:  HttpDownloader,
+    props: Arc<Mutex<Props>>,
+}
+
+/// `PROPFIND` 返回的属性
+#[derive(Debug, Clone, Default)]
+struct Props {
+    size:     Option<u64>,
+    etag:     Option<String>,
+    modified: Option<String>,
+}
+
+impl WebDav {
+    pub fn new(http: HttpDownloader) -> Self {
+        Self { http, props: Arc::default() }
+    }
+
+    /// 使用 `HttpDownloader` 的构建器 hash 算法和重试策略下载
+    pub fn downloader(self) -> TransportDownloader {
+        let http = &self.http;
+        let (builder, algorithm, retry) = (http.builder.clone(), http.algorithm, http.retry);
+        TransportDownloader::new(self, builder).algorithm(algorithm).retry(retry)
+    }
+}
+
+impl Transport for WebDav {
+    fn open(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let method = Method::from_bytes(b"PROPFIND").expect("有效的方法");
+            let depth = [(HeaderName::from_static("depth"), "0")];
+            let response = self.http.send(method, &self.http.urls[0], &depth).await?;
+            let xml = response.error_for_status()?.text().await?;
+            *self.props.lock().unwrap() = Props::parse(&xml)?;
+            Ok(())
+        })
+    }
+
+    fn size(&self) -> BoxFuture<'_, Result<Option<u64>>> {
+        Box::pin(async move { Ok(self.props.lock().unwrap().size) })
+    }
+
+    /// 服务端忽略 Range 从头返回时跳过区间之前的部分
+    fn read_range(&self, range: Range<u64>) -> BoxFuture<'_, Result<BoxStream<'_, Result<Bytes>>>> {
+        Box::pin(async move {
+            let value = match range.end {
+                u64::MAX => format!("bytes={}-", range.start),
+                end => format!("bytes={}-{}", range.start, end - 1),
+            };
+            let etag = self.props.lock().unwrap().etag.clone();
+            let mut headers = vec![(header::RANGE, value.as_str())];
+            if let Some(etag) = &etag {
+                headers.push((header::IF_MATCH, etag.as_str()));
+            }
+            let response = self.http.send(Method::GET, &self.http.urls[0], &headers).await?;
+            if response.status() == StatusCode::PRECONDITION_FAILED {
+                return Err(DownloadError::ResourceChanged);
+            }
+            let response = response.error_for_status()?;
+            let skip = match response.status() {
+                StatusCode::PARTIAL_CONTENT => 0,
+                _ => range.start,
+            };
+            Ok(self.http.stream(response, skip))
+        })
+    }
+
+    /// ETag 没有时使用修改时间
+    fn validator(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let props = self.props.lock().unwrap();
+            Ok(props.etag.clone().or_else(|| props.modified.clone()))
+        })
+    }
+}
+
+impl Props {
+    /// 解析 207 Multi-Status 只取第一个 response 弱 ETag 不能用于 If-Match
+    fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut props = Self::default();
+        loop {
+            match reader.read_event().map_err(invalid)? {
+                Event::Start(e) => {
+                    let name = e.local_name();
+                    let name = name.as_ref();
+                    if !matches!(name, "getcontentlength" | "getetag" | "getlastmodified") {
+                        continue;
+                    }
+                    let text = reader.read_text(e.name()).map_err(invalid)?;
+                    let text = unescape(text.trim()).map_err(invalid)?.into_owned();
+                    match name {
+                        "getcontentlength" => props.size = Some(text.parse().map_err(invalid)?),
+                        "getetag" if !text.starts_with("W/") => props.etag = Some(text),
+                        "getlastmodified" => props.modified = Some(text),
+                        _ => {}
+                    }
+                }
+                Event::End(e) if e.local_name().as_ref() == "response" => return Ok(props),
+                Event::Eof => return Ok(props),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn invalid(e: impl Display) -> DownloadError {
+    DownloadError::WebDav(e.to_string())
+}
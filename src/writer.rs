@@ -0,0 +1,399 @@
+use std::{
+    borrow::Cow,
+    future::Future,
+    io::SeekFrom::*,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+
+use tokio::{
+    fs::File,
+    io::{self, AsyncSeek, AsyncWrite},
+    task::JoinHandle,
+    time::Sleep,
+};
+
+use crate::{BufferPool, DownloadError, Downloading, PersistPolicy, Result, SyncPolicy};
+
+/// `AsyncWrite` 的状态
+#[derive(Debug)]
+pub(crate) struct Writer {
+    /// 数据文件当前的位置 未知时为 None
+    pub(crate) cursor:  Option<u64>,
+    /// 已有未持久化的进度
+    pub(crate) dirty:   bool,
+    seeking:            bool,
+    /// 元数据所在文件 用于在阻塞线程中写入元数据
+    trailer:            std::fs::File,
+    /// 数据文件 用于定位写入和查询实际占用的空间
+    pub(crate) data:    Arc<std::fs::File>,
+    flushing:           Option<JoinHandle<io::Result<()>>>,
+    /// 本次写入已经从限速器取走令牌
+    reserved:           bool,
+    throttle:           Option<Pin<Box<Sleep>>>,
+    pub(crate) sync:    SyncPolicy,
+    /// 距上次同步写入的字节数
+    pub(crate) behind:  u64,
+    pub(crate) persist: PersistPolicy,
+    /// 距上次写入元数据写入的字节数
+    pub(crate) unsaved: u64,
+    /// 上次写入元数据的时间
+    pub(crate) saved:   Instant,
+    /// `write_at` 缓冲的大小 0 为不缓冲
+    pub(crate) buffer:  usize,
+    /// 缓冲中的区间 (开始位置, 数据) 还没有写入文件
+    pub(crate) runs:    Vec<(u64, Vec<u8>)>,
+    pub(crate) pool:    Option<BufferPool>,
+    /// 以 O_DIRECT 打开的数据文件 只用于对齐的写入
+    pub(crate) direct:  Option<Arc<std::fs::File>>,
+    /// `write_at` 写入内存映射
+    #[cfg(feature = "mmap")]
+    pub(crate) mmap:    bool,
+    #[cfg(feature = "mmap")]
+    pub(crate) mapping: Option<crate::mmap::Mapping>,
+}
+
+impl Writer {
+    pub(crate) async fn new(data: &File, sidecar: Option<&File>) -> Result<Self> {
+        let trailer = sidecar.unwrap_or(data).try_clone().await?.into_std().await;
+        Ok(Self {
+            cursor:   None,
+            dirty:    false,
+            seeking:  false,
+            trailer,
+            data:     Arc::new(data.try_clone().await?.into_std().await),
+            flushing: None,
+            reserved: false,
+            throttle: None,
+            sync:     SyncPolicy::default(),
+            behind:   0,
+            persist:  PersistPolicy::default(),
+            unsaved:  0,
+            saved:    Instant::now(),
+            buffer:   0,
+            runs:     vec![],
+            pool:     None,
+            direct:   None,
+            #[cfg(feature = "mmap")]
+            mmap:     false,
+            #[cfg(feature = "mmap")]
+            mapping:  None,
+        })
+    }
+}
+
+/// 顺序写入 从 `offset` 开始填充第一个未下载的区间
+///
+/// 写入只更新内存中的进度 `flush` 时才写入元数据 `shutdown` 同 `flush`
+///
+/// 与 `write_at` 等方法混用前先 `flush`
+///
+/// 取消后的写入会先写入元数据再返回 `DownloadError::Cancelled`
+impl AsyncWrite for Downloading {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_flushing(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // 上次写入已在后台完成 之前的数据可以读取
+        ready!(Pin::new(&mut this.file).poll_flush(cx))?;
+        this.progress.readable(this.meta.offset);
+        // 取消时先保存已写入的进度
+        if this.is_cancelled() {
+            ready!(Pin::new(&mut *this).poll_flush(cx))?;
+            return Poll::Ready(Err(DownloadError::Cancelled.into()));
+        }
+        if this.sync_pending() || (this.writer.dirty && this.persist_due()) {
+            ready!(Pin::new(&mut *this).poll_flush(cx))?;
+        }
+
+        let pos = this.meta.offset;
+        let end = this.meta.ranges.iter().map(|r| r.start).find(|&start| start > pos);
+        let end = end.unwrap_or(if this.meta.growing { u64::MAX } else { this.meta.size });
+        if end <= pos {
+            return Poll::Ready(Err(DownloadError::Overflow.into()));
+        }
+        let buf = &buf[..buf.len().min((end - pos) as usize)];
+        ready!(this.poll_throttle(cx, buf.len() as u64));
+
+        ready!(this.poll_seek(cx, pos))?;
+        let (plain, written) = this.transform(pos, buf);
+        let n = ready!(Pin::new(&mut this.file).poll_write(cx, &written))?;
+        this.writer.reserved = false;
+        this.writer.cursor = Some(pos + n as u64);
+        if let Some(state) = &mut this.meta.state {
+            state.update(&plain[..n]);
+        }
+        this.meta.ranges.insert(pos..pos + n as u64);
+        this.meta.offset = this.meta.ranges.offset();
+        this.meta.size = this.meta.size.max(this.meta.offset);
+        this.record(n as u64);
+        this.writer.dirty = true;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flushing(cx))?;
+        ready!(Pin::new(&mut this.file).poll_flush(cx))?;
+        this.progress.readable(this.meta.offset);
+        if this.writer.dirty {
+            let (len, writes, grows) = this.trailer();
+            let file = this.writer.trailer.try_clone()?;
+            let data = this.sync_due().then(|| this.writer.data.clone());
+            #[cfg(feature = "mmap")]
+            if data.is_some() {
+                this.flush_mapping()?;
+            }
+            this.writer.flushing = Some(tokio::task::spawn_blocking(move || {
+                if let Some(data) = &data {
+                    data.sync_data()?;
+                }
+                file.set_len(len)?;
+                for (n, (pos, slot)) in writes.into_iter().enumerate() {
+                    if n > 0 && grows {
+                        file.sync_data()?;
+                    }
+                    write_all_at(&file, &slot, pos)?;
+                }
+                match data {
+                    Some(_) => file.sync_data(),
+                    None => Ok(()),
+                }
+            }));
+            this.writer.dirty = false;
+            // 与数据文件共享位置
+            this.writer.cursor = None;
+        }
+        this.poll_flushing(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// 丢弃时还有未写入的进度 例如持有者 panic 同步写入元数据 尽力而为 失败时忽略
+impl Drop for Downloading {
+    fn drop(&mut self) {
+        if !self.writer.dirty {
+            return;
+        }
+        let (len, writes, grows) = self.trailer();
+        let sync = self.sync_due();
+        let file = &self.writer.trailer;
+        let _ = (if sync { self.sync_data() } else { Ok(()) })
+            .and_then(|_| file.set_len(len))
+            .and_then(|_| {
+                writes.iter().enumerate().try_for_each(|(n, (pos, slot))| {
+                    if n > 0 && grows {
+                        file.sync_data()?;
+                    }
+                    write_all_at(file, slot, *pos)
+                })
+            })
+            .and_then(|_| if sync { file.sync_data() } else { Ok(()) });
+    }
+}
+
+impl Downloading {
+    /// 从 offset 开始依次写入数据文件 设置了 `DownloadBuilder::mmap` 时直接复制到映射中
+    ///
+    /// 设置了 `DownloadBuilder::direct_io` 时对齐的部分不经过页缓存
+    pub(crate) async fn write_data(
+        &mut self,
+        offset: u64,
+        bufs: Vec<Cow<'_, [u8]>>,
+    ) -> io::Result<()> {
+        #[cfg(feature = "mmap")]
+        if let Some(mapping) = self.mapping()? {
+            if mapping.write(offset, &bufs) {
+                return Ok(());
+            }
+        }
+        if self.write_direct(offset, &bufs).await? {
+            return Ok(());
+        }
+        // 写入前需要复制到拥有的缓冲区 解密或加密后的数据已经是拥有的
+        let bufs = bufs.into_iter().map(|buf| match buf {
+            Cow::Borrowed(buf) => {
+                let mut owned = self.take_buffer(buf.len());
+                owned.extend_from_slice(buf);
+                owned
+            }
+            Cow::Owned(buf) => buf,
+        });
+        let bufs = bufs.collect();
+        let bufs = write_at(self.writer.data.clone(), bufs, offset).await?;
+        self.recycle(bufs);
+        Ok(())
+    }
+
+    /// 同步数据文件 包括映射中的修改
+    fn sync_data(&self) -> io::Result<()> {
+        #[cfg(feature = "mmap")]
+        self.flush_mapping()?;
+        self.writer.data.sync_data()
+    }
+
+    /// 编码元数据 返回 (元数据所在文件的长度, [(写入位置, 槽的内容)], 两次写入之间是否同步)
+    ///
+    /// 见 `Metadata::encode` 和 `Metadata::grows`
+    fn trailer(&mut self) -> (u64, Vec<(u64, Vec<u8>)>, bool) {
+        self.meta.resize();
+        let base = if self.sidecar.is_some() { 0 } else { self.meta.size };
+        let (len, writes) = self.meta.encode(base);
+        let grows = self.meta.grows(base);
+        self.meta.written(base);
+        self.persisted();
+        (len, writes, grows)
+    }
+
+    /// 等待写入元数据的任务结束
+    fn poll_flushing(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(task) = &mut self.writer.flushing else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(Pin::new(task).poll(cx));
+        self.writer.flushing = None;
+        Poll::Ready(result.map_err(io::Error::other).and_then(|result| result))
+    }
+
+    /// 从限速器取走 n 个令牌并等待 等待期间重复调用不会再次取走
+    fn poll_throttle(&mut self, cx: &mut Context<'_>, n: u64) -> Poll<()> {
+        if !self.writer.reserved {
+            let delay = self.reserve(n);
+            self.writer.throttle = (!delay.is_zero()).then(|| Box::pin(tokio::time::sleep(delay)));
+            self.writer.reserved = true;
+        }
+        if let Some(sleep) = &mut self.writer.throttle {
+            ready!(sleep.as_mut().poll(cx));
+            self.writer.throttle = None;
+        }
+        Poll::Ready(())
+    }
+
+    /// 位置未知或不一致时 seek 到 pos
+    fn poll_seek(&mut self, cx: &mut Context<'_>, pos: u64) -> Poll<io::Result<()>> {
+        if self.writer.cursor == Some(pos) {
+            return Poll::Ready(Ok(()));
+        }
+        let mut file = Pin::new(&mut self.file);
+        if !self.writer.seeking {
+            ready!(file.as_mut().poll_complete(cx))?;
+            file.as_mut().start_seek(Start(pos))?;
+            self.writer.seeking = true;
+        }
+        let result = ready!(file.poll_complete(cx));
+        self.writer.seeking = false;
+        self.writer.cursor = Some(result?);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// 在阻塞线程中从 pos 开始依次定位写入 返回写完的缓冲区以便重用
+///
+/// 开启 `uring` 时 Linux 上每个缓冲区各自交给 io_uring 内核不支持时同样使用阻塞线程
+pub(crate) async fn write_at(
+    file: Arc<std::fs::File>,
+    bufs: Vec<Vec<u8>>,
+    pos: u64,
+) -> io::Result<Vec<Vec<u8>>> {
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    if crate::uring::available() {
+        let (mut writes, mut empty, mut pos) = (vec![], vec![], pos);
+        for buf in bufs {
+            // 长度为 0 的写入会被当作 `WriteZero`
+            if buf.is_empty() {
+                empty.push(buf);
+                continue;
+            }
+            let len = buf.len() as u64;
+            writes.push(crate::uring::write_all_at(file.clone(), buf, pos));
+            pos += len;
+        }
+        let mut bufs = futures::future::try_join_all(writes).await?;
+        bufs.extend(empty);
+        return Ok(bufs);
+    }
+    let task = move || write_vectored_all_at(&file, &bufs, pos).map(|_| bufs);
+    tokio::task::spawn_blocking(task).await?
+}
+
+/// 一次 pwritev 最多的缓冲区数
+#[cfg(target_os = "linux")]
+const IOV_MAX: usize = 1024;
+
+/// 定位写入多个缓冲区 不完整时继续写入剩余的部分
+#[cfg(target_os = "linux")]
+fn write_vectored_all_at(file: &std::fs::File, bufs: &[Vec<u8>], mut pos: u64) -> io::Result<()> {
+    use std::io::IoSlice;
+
+    let mut slices: Vec<_> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+    let mut slices = &mut slices[..];
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        let n = slices.len().min(IOV_MAX);
+        match rustix::io::pwritev(file, &slices[..n], pos) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                IoSlice::advance_slices(&mut slices, n);
+                pos += n as u64;
+            }
+            Err(rustix::io::Errno::INTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_vectored_all_at(file: &std::fs::File, bufs: &[Vec<u8>], mut pos: u64) -> io::Result<()> {
+    for buf in bufs {
+        write_all_at(file, buf, pos)?;
+        pos += buf.len() as u64;
+    }
+    Ok(())
+}
+
+/// 定位写入 不使用文件当前的位置 数据文件可能还有未完成的写入
+///
+/// 多个任务可以同时写入同一个文件的不同位置
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &std::fs::File, buf: &[u8], pos: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, pos)
+}
+
+/// `seek_write` 会移动文件位置
+#[cfg(windows)]
+pub(crate) fn write_all_at(file: &std::fs::File, mut buf: &[u8], mut pos: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_write(buf, pos) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                pos += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn write_all_at(mut file: &std::fs::File, buf: &[u8], pos: u64) -> io::Result<()> {
+    use std::io::{Seek, Write};
+
+    file.seek(Start(pos))?;
+    file.write_all(buf)
+}